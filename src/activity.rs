@@ -0,0 +1,122 @@
+//! Interference history per RF channel
+//!
+//! An [`ActivityLog`](struct.ActivityLog.html) samples `CD`/`RPD` on
+//! one channel per [`pump()`](struct.ActivityLog.html#method.pump) and
+//! keeps an exponentially decaying average of the hits in an array
+//! owned by the caller. Calling it from the main loop between regular
+//! traffic builds an evolving picture of which channels are busy, for
+//! picking a channel or a hopping sequence.
+
+use crate::channels::{Channel, ChannelMask, CHANNEL_COUNT};
+use crate::Nrf24l01;
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Default weight of the history, each sample counts `1 / 2^3`
+pub const DEFAULT_DECAY_SHIFT: u8 = 3;
+
+/// Activity per channel, `0` for never busy to `255` for always busy
+pub type Activity = [u8; CHANNEL_COUNT as usize];
+
+/// Round-robin carrier sampler over a set of channels
+pub struct ActivityLog<'a> {
+    activity: &'a mut Activity,
+    mask: ChannelMask,
+    next: u8,
+    decay_shift: u8,
+}
+
+impl<'a> ActivityLog<'a> {
+    /// Sample the channels in `mask`, accumulating into `activity`
+    ///
+    /// `activity` is kept as it is, so a history saved earlier carries
+    /// on.
+    pub fn new(activity: &'a mut Activity, mask: ChannelMask) -> Self {
+        ActivityLog {
+            activity,
+            mask,
+            next: 0,
+            decay_shift: DEFAULT_DECAY_SHIFT,
+        }
+    }
+
+    /// Weight each new sample with `1 / 2^shift`, `1..=7`
+    ///
+    /// Larger values remember longer but react slower.
+    pub fn set_decay_shift(&mut self, shift: u8) {
+        assert!((1..=7).contains(&shift));
+        self.decay_shift = shift;
+    }
+
+    pub fn mask(&self) -> &ChannelMask {
+        &self.mask
+    }
+
+    /// Activity of `channel`
+    pub fn activity(&self, channel: u8) -> u8 {
+        self.activity[usize::from(channel)]
+    }
+
+    pub fn activities(&self) -> &Activity {
+        self.activity
+    }
+
+    /// The sampled channel with the least activity, the lowest on a tie
+    pub fn quietest(&self) -> Option<u8> {
+        self.mask
+            .channels()
+            .min_by_key(|&channel| self.activity(channel))
+    }
+
+    /// Forget the history
+    pub fn clear(&mut self) {
+        for activity in self.activity.iter_mut() {
+            *activity = 0;
+        }
+    }
+
+    /// Sample the next channel
+    ///
+    /// Uses [`Nrf24l01::cca()`](../struct.Nrf24l01.html#method.cca),
+    /// which waits for the TX FIFO to drain and afterwards returns to
+    /// the previous channel and mode. Returns the channel sampled,
+    /// `None` if the mask is empty.
+    pub fn pump<Ce, Csn, Spi, E, SpiE, D>(
+        &mut self,
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        delay: &mut D,
+    ) -> Result<Option<u8>, SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+        D: DelayUs<u32>,
+    {
+        let channel = match (self.next..CHANNEL_COUNT)
+            .chain(0..self.next)
+            .find(|&channel| self.mask.is_allowed(channel))
+        {
+            Some(channel) => channel,
+            None => return Ok(None),
+        };
+        let busy = !radio.cca(Channel::new(channel), 0, delay)?;
+        self.record(channel, busy);
+        self.next = (channel + 1) % CHANNEL_COUNT;
+        Ok(Some(channel))
+    }
+
+    fn record(&mut self, channel: u8, busy: bool) {
+        let weight = 1u16 << self.decay_shift;
+        let activity = &mut self.activity[usize::from(channel)];
+        let kept = u16::from(*activity) * (weight - 1);
+        // Round towards the sample so that both ends can be reached
+        *activity = match busy {
+            true => (kept + 255).div_ceil(weight),
+            false => kept / weight,
+        } as u8;
+    }
+}
@@ -0,0 +1,412 @@
+//! Link parameters that follow the link quality
+//!
+//! [`AdaptiveRate`](struct.AdaptiveRate.html) watches the retransmit
+//! count of every packet and steps the data rate down when the link
+//! gets weak, and back up when it is clean again. Each change is
+//! announced to the [`AdaptiveRatePeer`](struct.AdaptiveRatePeer.html)
+//! with a control packet first. Should the link break down anyway,
+//! both sides fall back to the slowest rate. Every packet carries one
+//! type byte in front of the user data.
+//!
+//! [`RetransmitTuner`](struct.RetransmitTuner.html) adjusts the
+//! auto-retransmit delay and count to the observed loss and to the
+//! ack payloads that have to fit into the delay.
+
+use crate::device::Device;
+use crate::registers::{ObserveTx, SetupRetr};
+use crate::timing::min_retransmit_delay;
+use crate::{Clock, Configuration, DataRate, Nrf24l01, Payload, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_DATA: u8 = 0;
+const TYPE_RATE: u8 = 1;
+/// Maximum user data per packet
+pub const MAX_DATA_LEN: usize = PAYLOAD_LEN - 1;
+/// Rates from slowest to fastest
+const RATES: [DataRate; 3] = [DataRate::R250Kbps, DataRate::R1Mbps, DataRate::R2Mbps];
+/// Average retransmits, times 16, above which the rate goes down
+const STEP_DOWN_AVG: u16 = 3 * 16;
+/// Average retransmits, times 16, below which the rate may go up
+const STEP_UP_AVG: u16 = 8;
+/// Clean packets before trying a faster rate
+const STEP_UP_AFTER: u16 = 64;
+/// Consecutive losses before falling back to the slowest rate
+const FALLBACK_FAILURES: u8 = 5;
+/// Time for the peer's acknowledgement to go out before switching
+const SWITCH_DELAY_US: u64 = 1000;
+/// Packets per retransmit tuning step
+const TUNING_WINDOW: u16 = 32;
+
+fn rate_index(rate: DataRate) -> usize {
+    match rate {
+        DataRate::R250Kbps => 0,
+        DataRate::R1Mbps => 1,
+        DataRate::R2Mbps => 2,
+    }
+}
+
+/// Slowest rate supported by the chip
+fn slowest<Ce, Csn, Spi, E, SpiE>(radio: &Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> usize
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    match radio.chip_variant().supports_data_rate(DataRate::R250Kbps) {
+        true => 0,
+        false => 1,
+    }
+}
+
+/// Sending side, decides on the rate
+pub struct AdaptiveRate<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    peer: [u8; 5],
+    rate: usize,
+    min_rate: usize,
+    /// Moving average of retransmits per packet, times 16
+    average: u16,
+    clean: u16,
+    failures: u8,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> AdaptiveRate<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Both sides must start out with the same rate
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        peer: [u8; 5],
+    ) -> Result<Self, SpiE> {
+        let rate = rate_index(radio.get_data_rate()?);
+        let min_rate = slowest(radio);
+        Ok(AdaptiveRate {
+            radio,
+            peer,
+            rate,
+            min_rate,
+            average: 0,
+            clean: 0,
+            failures: 0,
+        })
+    }
+
+    pub fn rate(&self) -> DataRate {
+        RATES[self.rate]
+    }
+
+    /// Send `data`, adapting the rate afterwards
+    ///
+    /// Returns whether it has been acknowledged.
+    pub fn send(&mut self, data: &[u8]) -> Result<bool, SpiE> {
+        assert!(data.len() <= MAX_DATA_LEN);
+        let mut packet = [0; PAYLOAD_LEN];
+        packet[0] = TYPE_DATA;
+        packet[1..1 + data.len()].copy_from_slice(data);
+        let acked = self.radio.send_to(&self.peer, &packet[0..1 + data.len()])?;
+        let (_, observe) = self.radio.device().read_register::<ObserveTx>()?;
+        self.update(acked, observe.arc_cnt())?;
+        Ok(acked)
+    }
+
+    fn update(&mut self, acked: bool, retransmits: u8) -> Result<(), SpiE> {
+        self.average = self.average - self.average / 8 + u16::from(retransmits) * 2;
+        if !acked {
+            self.failures = self.failures.saturating_add(1);
+            self.clean = 0;
+            if self.failures >= FALLBACK_FAILURES && self.rate != self.min_rate {
+                // The peer falls back on its own once it stops hearing us
+                self.switch(self.min_rate)?;
+            }
+            return Ok(());
+        }
+        self.failures = 0;
+        if retransmits == 0 {
+            self.clean = self.clean.saturating_add(1);
+        }
+        if self.average > STEP_DOWN_AVG && self.rate > self.min_rate {
+            self.propose(self.rate - 1)?;
+        } else if self.average < STEP_UP_AVG
+            && self.clean >= STEP_UP_AFTER
+            && self.rate + 1 < RATES.len()
+        {
+            self.propose(self.rate + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the peer, and switch once it has acknowledged
+    fn propose(&mut self, rate: usize) -> Result<(), SpiE> {
+        if self.radio.send_to(&self.peer, &[TYPE_RATE, rate as u8])? {
+            self.switch(rate)?;
+        } else {
+            self.clean = 0;
+        }
+        Ok(())
+    }
+
+    fn switch(&mut self, rate: usize) -> Result<(), SpiE> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_data_rate(RATES[rate])?;
+        self.rate = rate;
+        self.average = 0;
+        self.clean = 0;
+        self.failures = 0;
+        Ok(())
+    }
+}
+
+/// Receiving side, follows the sender
+pub struct AdaptiveRatePeer<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    rate: usize,
+    min_rate: usize,
+    timeout_us: u64,
+    last_rx_us: Option<u64>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> AdaptiveRatePeer<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Fall back to the slowest rate after hearing nothing for `timeout_us`
+    ///
+    /// The timeout must be longer than the sender's send interval.
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        timeout_us: u64,
+    ) -> Result<Self, SpiE> {
+        let rate = rate_index(radio.get_data_rate()?);
+        let min_rate = slowest(radio);
+        Ok(AdaptiveRatePeer {
+            radio,
+            rate,
+            min_rate,
+            timeout_us,
+            last_rx_us: None,
+        })
+    }
+
+    pub fn rate(&self) -> DataRate {
+        RATES[self.rate]
+    }
+
+    /// Poll for user data, handling rate changes on the way
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<Option<Payload>, SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            let now = clock.now_us();
+            self.last_rx_us = Some(now);
+            match packet.first() {
+                Some(&TYPE_DATA) => return Ok(Some(Payload::new(&packet[1..]))),
+                Some(&TYPE_RATE) if packet.len() == 2 && usize::from(packet[1]) < RATES.len() => {
+                    while clock.now_us() < now + SWITCH_DELAY_US {}
+                    self.switch(usize::from(packet[1]))?;
+                }
+                _ => {}
+            }
+        }
+        let now = clock.now_us();
+        let last_rx_us = *self.last_rx_us.get_or_insert(now);
+        if now - last_rx_us > self.timeout_us && self.rate != self.min_rate {
+            self.switch(self.min_rate)?;
+            self.last_rx_us = Some(now);
+        }
+        Ok(None)
+    }
+
+    fn switch(&mut self, rate: usize) -> Result<(), SpiE> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_data_rate(RATES[rate])?;
+        self.rate = rate;
+        Ok(())
+    }
+}
+
+/// Limits for the [`RetransmitTuner`](struct.RetransmitTuner.html),
+/// in `SETUP_RETR` units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetransmitBounds {
+    /// `ARD`, `0..=15`, the delay is `250 µs * (ARD + 1)`
+    pub min_delay: u8,
+    pub max_delay: u8,
+    /// `ARC`, `0..=15`
+    pub min_count: u8,
+    pub max_count: u8,
+}
+
+impl Default for RetransmitBounds {
+    fn default() -> Self {
+        RetransmitBounds {
+            min_delay: 0,
+            max_delay: 15,
+            min_count: 1,
+            max_count: 15,
+        }
+    }
+}
+
+/// Adjusts `SETUP_RETR` after every packet
+///
+/// Loss raises the retransmit count, and the delay when retransmits
+/// pile up; a clean link lowers both again. The delay never drops
+/// below what the longest recent ack payload needs.
+pub struct RetransmitTuner {
+    bounds: RetransmitBounds,
+    delay: u8,
+    count: u8,
+    /// Longest ack payload of the previous window
+    ack_len: usize,
+    window_ack_len: usize,
+    packets: u16,
+    lost: u16,
+    retransmits: u16,
+}
+
+impl RetransmitTuner {
+    /// Start from the current setting of `radio`
+    pub fn new<Ce, Csn, Spi, E, SpiE>(
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        bounds: RetransmitBounds,
+    ) -> Result<Self, SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        assert!(bounds.min_delay <= bounds.max_delay && bounds.max_delay < 16);
+        assert!(bounds.min_count <= bounds.max_count && bounds.max_count < 16);
+        let (_, setup) = radio.device().read_register::<SetupRetr>()?;
+        Ok(RetransmitTuner {
+            bounds,
+            delay: setup.ard().clamp(bounds.min_delay, bounds.max_delay),
+            count: setup.arc().clamp(bounds.min_count, bounds.max_count),
+            ack_len: 0,
+            window_ack_len: 0,
+            packets: 0,
+            lost: 0,
+            retransmits: 0,
+        })
+    }
+
+    /// Current `(ARD, ARC)`
+    pub fn setting(&self) -> (u8, u8) {
+        (self.delay, self.count)
+    }
+
+    /// Account for the packet just sent and retune
+    ///
+    /// `ack_payload_len` is the length of the ack payload that came
+    /// back, if any.
+    pub fn update<Ce, Csn, Spi, E, SpiE>(
+        &mut self,
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        acked: bool,
+        ack_payload_len: usize,
+    ) -> Result<(), SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        let (_, observe) = radio.device().read_register::<ObserveTx>()?;
+        self.packets += 1;
+        self.retransmits += u16::from(observe.arc_cnt());
+        if !acked {
+            self.lost += 1;
+        }
+        self.window_ack_len = self.window_ack_len.max(ack_payload_len);
+
+        let (delay, count) = (self.delay, self.count);
+        if self.window_ack_len > self.ack_len {
+            // Too short a delay silently drops longer ack payloads
+            self.ack_len = self.window_ack_len;
+            self.delay = self.delay.max(self.min_delay(radio)?);
+        }
+        if self.packets >= TUNING_WINDOW {
+            self.tune();
+            self.ack_len = self.window_ack_len;
+            self.delay = self.delay.max(self.min_delay(radio)?);
+            self.window_ack_len = 0;
+            self.packets = 0;
+            self.lost = 0;
+            self.retransmits = 0;
+        }
+        if (delay, count) != (self.delay, self.count) {
+            nb::block!(radio.wait_tx_empty())?;
+            radio.set_auto_retransmit(self.delay, self.count)?;
+        }
+        Ok(())
+    }
+
+    fn tune(&mut self) {
+        let bounds = self.bounds;
+        let average = self.retransmits / self.packets;
+        if self.lost > 0 {
+            self.count = (self.count + 2).min(bounds.max_count);
+            // Retries in quick succession keep colliding, spread them out
+            if average * 2 >= u16::from(self.count) {
+                self.delay = (self.delay + 1).min(bounds.max_delay);
+            }
+        } else if average * 4 < u16::from(self.count) {
+            self.count = self.count.saturating_sub(1).max(bounds.min_count);
+            self.delay = self.delay.saturating_sub(1).max(bounds.min_delay);
+        }
+    }
+
+    /// Shortest delay for the ack payloads seen, within the bounds
+    fn min_delay<Ce, Csn, Spi, E, SpiE>(
+        &self,
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    ) -> Result<u8, SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        let address_width = radio.get_address_width()?;
+        let crc = radio.get_crc()?;
+        let rate = radio.get_data_rate()?;
+        let delay = min_retransmit_delay(self.ack_len, address_width, crc, rate)
+            .unwrap_or(self.bounds.max_delay);
+        Ok(delay.clamp(self.bounds.min_delay, self.bounds.max_delay))
+    }
+}
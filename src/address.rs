@@ -0,0 +1,205 @@
+use crate::{Pipe, MAX_ADDR_BYTES, PIPES_COUNT, RX_ADDR_PREFIX_LEN};
+use core::ops::Deref;
+
+/// A 3 to 5 byte radio address
+///
+/// The chip takes addresses over SPI least significant byte first, but
+/// puts them on air most significant byte first. Datasheets and other
+/// stacks usually quote the on-air order, which is what
+/// [`from_msb_first()`](#method.from_msb_first) and
+/// [`from_u64()`](#method.from_u64) take. It derefs to the bytes in SPI
+/// order, so `&address` can be passed wherever an address slice is
+/// expected, like [`set_rx_addr()`](trait.Configuration.html#method.set_rx_addr)
+/// or [`send_to()`](struct.Nrf24l01.html#method.send_to).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    bytes: [u8; MAX_ADDR_BYTES],
+    len: u8,
+}
+
+impl Address {
+    /// Shortest address width supported by the chip
+    pub const MIN_LEN: usize = 3;
+
+    /// From bytes in SPI order, least significant first
+    ///
+    /// Panics if the length is not 3 to 5.
+    pub fn new(lsb_first: &[u8]) -> Self {
+        Self::try_new(lsb_first).expect("address must be 3 to 5 bytes")
+    }
+
+    pub fn try_new(lsb_first: &[u8]) -> Option<Self> {
+        let len = lsb_first.len();
+        if !(Self::MIN_LEN..=MAX_ADDR_BYTES).contains(&len) {
+            return None;
+        }
+        let mut bytes = [0; MAX_ADDR_BYTES];
+        bytes[0..len].copy_from_slice(lsb_first);
+        Some(Address {
+            bytes,
+            len: len as u8,
+        })
+    }
+
+    /// From bytes in on-air order, most significant first
+    pub fn from_msb_first(msb_first: &[u8]) -> Self {
+        let mut address = Self::new(msb_first);
+        address.bytes[0..msb_first.len()].reverse();
+        address
+    }
+
+    /// From the lowest `len` bytes of `value`, e.g. `0xE7E7E7E7E7`
+    pub fn from_u64(value: u64, len: usize) -> Self {
+        Self::new(&value.to_le_bytes()[0..len])
+    }
+
+    pub fn to_u64(&self) -> u64 {
+        let mut value = [0; 8];
+        value[0..self.len()].copy_from_slice(self.as_bytes());
+        u64::from_le_bytes(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.into()
+    }
+
+    /// Always `false`, for symmetry with [`len()`](#method.len)
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Bytes in SPI order, least significant first
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[0..self.len()]
+    }
+
+    /// Bytes in on-air order, most significant first
+    pub fn to_msb_first(&self) -> [u8; MAX_ADDR_BYTES] {
+        let mut bytes = [0; MAX_ADDR_BYTES];
+        let len = self.len();
+        bytes[0..len].copy_from_slice(self.as_bytes());
+        bytes[0..len].reverse();
+        bytes
+    }
+
+    /// Least significant byte, the only one pipes 2 to 5 can change
+    pub fn lsb(&self) -> u8 {
+        self.bytes[0]
+    }
+}
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Deref for Address {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Level shifts an address needs on air, out of 39 possible in 40 bits
+const MIN_TRANSITIONS: u32 = 12;
+/// Default pipe LSBs, as used by the multiceiver hub
+const FAMILY_LSB: [u8; PIPES_COUNT] = [0xC3, 0x3C, 0x33, 0xCE, 0x3E, 0xE3];
+
+/// Why an [`AddressFamily`](struct.AddressFamily.html) was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressError {
+    /// A byte is `0xAA` or `0x55`, which continues the preamble
+    Preamble,
+    /// The address of `pipe` has too few level shifts to stand out
+    /// from noise
+    FewTransitions { pipe: Pipe },
+    /// Two pipes share their least significant byte
+    DuplicateLsb,
+}
+
+/// Addresses of all six pipes of a receiver: a shared 4-byte prefix
+/// and one distinct least significant byte per pipe
+///
+/// The datasheet advises against addresses that continue the
+/// `0xAA`/`0x55` preamble or change level only a few times, as noise
+/// then matches them more often. [`new()`](#method.new) checks for
+/// both, [`from_network_id()`](#method.from_network_id) derives a
+/// family that passes. Transmitters reach a pipe at
+/// [`address()`](#method.address).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AddressFamily {
+    prefix: [u8; RX_ADDR_PREFIX_LEN],
+    lsbs: [u8; PIPES_COUNT],
+}
+
+impl AddressFamily {
+    /// `prefix` in SPI order, like [`Config::rx_prefix()`](struct.Config.html#method.rx_prefix)
+    pub fn new(
+        prefix: [u8; RX_ADDR_PREFIX_LEN],
+        lsbs: [u8; PIPES_COUNT],
+    ) -> Result<Self, AddressError> {
+        let family = AddressFamily { prefix, lsbs };
+        family.validate()?;
+        Ok(family)
+    }
+
+    /// The same network id always gives the same family
+    pub fn from_network_id(network_id: u32) -> Self {
+        // xorshift32 needs a non-zero state
+        let mut state = network_id ^ 0x9E37_79B9;
+        if state == 0 {
+            state = 1;
+        }
+        loop {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if let Ok(family) = Self::new(state.to_le_bytes(), FAMILY_LSB) {
+                return family;
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), AddressError> {
+        let is_preamble = |byte: &u8| *byte == 0xAA || *byte == 0x55;
+        if self.prefix.iter().chain(self.lsbs.iter()).any(is_preamble) {
+            return Err(AddressError::Preamble);
+        }
+        for (i, lsb) in self.lsbs.iter().enumerate() {
+            if self.lsbs[i + 1..].contains(lsb) {
+                return Err(AddressError::DuplicateLsb);
+            }
+        }
+        for &pipe in &Pipe::ALL {
+            let bits = self.address(pipe).to_u64();
+            // Neighbouring bits that differ, within the 40 bits
+            let transitions = ((bits ^ (bits >> 1)) & 0x7F_FFFF_FFFF).count_ones();
+            if transitions < MIN_TRANSITIONS {
+                return Err(AddressError::FewTransitions { pipe });
+            }
+        }
+        Ok(())
+    }
+
+    /// Shared upper bytes in SPI order, for
+    /// [`Config::rx_prefix()`](struct.Config.html#method.rx_prefix)
+    pub fn prefix(&self) -> [u8; RX_ADDR_PREFIX_LEN] {
+        self.prefix
+    }
+
+    /// Least significant byte of `pipe`, for
+    /// [`Config::rx()`](struct.Config.html#method.rx)
+    pub fn lsb(&self, pipe: Pipe) -> u8 {
+        self.lsbs[pipe.index()]
+    }
+
+    /// Full address of `pipe`, which a transmitter sends to
+    pub fn address(&self, pipe: Pipe) -> Address {
+        let mut bytes = [0; MAX_ADDR_BYTES];
+        bytes[0] = self.lsb(pipe);
+        bytes[1..].copy_from_slice(&self.prefix);
+        Address::new(&bytes)
+    }
+}
@@ -0,0 +1,326 @@
+//! Dynamic pipe allocation for star networks
+//!
+//! An [`Allocator`](struct.Allocator.html) runs on top of a
+//! [`MulticeiverHub`](../multiceiver/struct.MulticeiverHub.html):
+//! pipe 0 is the well-known join pipe, pipes 1 to 5 are leased to
+//! nodes identified by a unique non-zero 32-bit id. A
+//! [`Client`](struct.Client.html) sends a join request, then polls
+//! until the offer comes back in an ack payload. Every packet on the
+//! leased pipe renews the lease.
+
+use crate::multiceiver::{pipe_address, MulticeiverHub};
+use crate::{Clock, Configuration, Nrf24l01, Payload, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_JOIN: u8 = 0xA1;
+const TYPE_POLL: u8 = 0xA2;
+const TYPE_OFFER: u8 = 0xA3;
+const REQUEST_LEN: usize = 5;
+const OFFER_LEN: usize = 13;
+/// Encoded length of the lease table
+pub const TABLE_LEN: usize = 4 * (PIPES_COUNT - 1);
+
+/// Pipe and address granted to a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub pipe: usize,
+    pub address: [u8; 5],
+    /// Send at least once in this period to keep the lease
+    pub duration_s: u16,
+}
+
+impl Lease {
+    fn encode(&self, uid: u32, buf: &mut [u8]) {
+        buf[0] = TYPE_OFFER;
+        buf[1..5].copy_from_slice(&uid.to_le_bytes());
+        buf[5] = self.pipe as u8;
+        buf[6..8].copy_from_slice(&self.duration_s.to_le_bytes());
+        buf[8..13].copy_from_slice(&self.address);
+    }
+
+    fn decode(buf: &[u8], uid: u32) -> Option<Self> {
+        if buf.len() < OFFER_LEN || buf[0] != TYPE_OFFER || buf[1..5] != uid.to_le_bytes() {
+            return None;
+        }
+        let mut address = [0; 5];
+        address.copy_from_slice(&buf[8..13]);
+        Some(Lease {
+            pipe: usize::from(buf[5]),
+            address,
+            duration_s: u16::from_le_bytes([buf[6], buf[7]]),
+        })
+    }
+}
+
+/// Non-volatile storage for the lease table, e.g. a flash page
+pub trait LeaseStore {
+    type Error;
+
+    /// Fill `buf` with the stored table, `false` if there is none
+    fn load(&mut self, buf: &mut [u8; TABLE_LEN]) -> Result<bool, Self::Error>;
+    fn store(&mut self, data: &[u8; TABLE_LEN]) -> Result<(), Self::Error>;
+}
+
+/// Data received on a leased pipe
+pub struct Packet {
+    pub uid: u32,
+    pub pipe: usize,
+    pub payload: Payload,
+}
+
+#[derive(Debug)]
+pub enum Error<SpiE, StoreE> {
+    Spi(SpiE),
+    Store(StoreE),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    uid: u32,
+    expires_us: u64,
+}
+
+/// Hands out pipes 1 to 5 of a hub
+pub struct Allocator<'a, Ce, Csn, Spi, E, SpiE, S>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    S: LeaseStore,
+{
+    hub: MulticeiverHub<'a, Ce, Csn, Spi, E, SpiE>,
+    base: [u8; 4],
+    store: S,
+    lease_us: u64,
+    entries: [Option<Entry>; PIPES_COUNT],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE, S> Allocator<'a, Ce, Csn, Spi, E, SpiE, S>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    S: LeaseStore,
+{
+    /// Listen on the hub addresses derived from `base`
+    ///
+    /// Leases found in `store` are restored with a fresh lease period.
+    pub fn new<C: Clock>(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        base: [u8; 4],
+        mut store: S,
+        lease_s: u16,
+        clock: &C,
+    ) -> Result<Self, Error<SpiE, S::Error>> {
+        let hub = MulticeiverHub::new(radio, base).map_err(Error::Spi)?;
+        let lease_us = u64::from(lease_s) * 1_000_000;
+        let mut entries = [None; PIPES_COUNT];
+        let mut table = [0; TABLE_LEN];
+        if store.load(&mut table).map_err(Error::Store)? {
+            let expires_us = clock.now_us() + lease_us;
+            for (entry, uid) in entries[1..].iter_mut().zip(table.chunks(4)) {
+                let uid = u32::from_le_bytes([uid[0], uid[1], uid[2], uid[3]]);
+                if uid != 0 {
+                    *entry = Some(Entry { uid, expires_us });
+                }
+            }
+        }
+        Ok(Allocator {
+            hub,
+            base,
+            store,
+            lease_us,
+            entries,
+        })
+    }
+
+    pub fn hub(&mut self) -> &mut MulticeiverHub<'a, Ce, Csn, Spi, E, SpiE> {
+        &mut self.hub
+    }
+
+    /// Node id holding the lease on `pipe`
+    pub fn uid_of(&self, pipe: usize) -> Option<u32> {
+        self.entries[pipe].map(|entry| entry.uid)
+    }
+
+    pub fn pipe_of(&self, uid: u32) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.is_some_and(|entry| entry.uid == uid))
+    }
+
+    /// Serve join requests, expire leases, and poll for data
+    ///
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<Option<Packet>, Error<SpiE, S::Error>> {
+        let now = clock.now_us();
+        self.expire(now)?;
+        while let Some((pipe, payload)) = self.hub.receive().map_err(Error::Spi)? {
+            if pipe == 0 {
+                self.handle_request(&payload, now)?;
+                continue;
+            }
+            if let Some(ref mut entry) = self.entries[pipe] {
+                entry.expires_us = now + self.lease_us;
+                return Ok(Some(Packet {
+                    uid: entry.uid,
+                    pipe,
+                    payload,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn handle_request(&mut self, request: &[u8], now: u64) -> Result<(), Error<SpiE, S::Error>> {
+        if request.len() < REQUEST_LEN || request[0] != TYPE_JOIN {
+            return Ok(());
+        }
+        let uid = u32::from_le_bytes([request[1], request[2], request[3], request[4]]);
+        if uid == 0 {
+            return Ok(());
+        }
+        let pipe = match self.pipe_of(uid) {
+            Some(pipe) => pipe,
+            None => match (1..PIPES_COUNT).find(|&pipe| self.entries[pipe].is_none()) {
+                Some(pipe) => pipe,
+                None => return Ok(()),
+            },
+        };
+        self.entries[pipe] = Some(Entry {
+            uid,
+            expires_us: now + self.lease_us,
+        });
+        self.save()?;
+        let lease = Lease {
+            pipe,
+            address: pipe_address(&self.base, pipe),
+            duration_s: (self.lease_us / 1_000_000) as u16,
+        };
+        let mut offer = [0; OFFER_LEN];
+        lease.encode(uid, &mut offer);
+        self.hub.queue_ack(0, &offer).map_err(Error::Spi)?;
+        Ok(())
+    }
+
+    fn expire(&mut self, now: u64) -> Result<(), Error<SpiE, S::Error>> {
+        let mut changed = false;
+        for entry in self.entries.iter_mut() {
+            if entry.is_some_and(|entry| now >= entry.expires_us) {
+                *entry = None;
+                changed = true;
+            }
+        }
+        if changed {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<(), Error<SpiE, S::Error>> {
+        let mut table = [0; TABLE_LEN];
+        for (entry, uid) in self.entries[1..].iter().zip(table.chunks_mut(4)) {
+            if let Some(entry) = entry {
+                uid.copy_from_slice(&entry.uid.to_le_bytes());
+            }
+        }
+        self.store.store(&table).map_err(Error::Store)
+    }
+}
+
+/// Node side of the join protocol
+pub struct Client<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    base: [u8; 4],
+    uid: u32,
+    lease: Option<Lease>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Client<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// `uid` must be non-zero and unique, e.g. derived from a chip serial number
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        base: [u8; 4],
+        uid: u32,
+    ) -> Result<Self, SpiE> {
+        assert!(uid != 0);
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_address_width(5)?;
+        radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+        radio.set_ack_payloads(true)?;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        Ok(Client {
+            radio,
+            base,
+            uid,
+            lease: None,
+        })
+    }
+
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// Request a lease, polling for the offer for up to `timeout_ms`
+    pub fn join<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<Lease>, SpiE> {
+        let join_address = pipe_address(&self.base, 0);
+        let mut request = [0; REQUEST_LEN];
+        request[0] = TYPE_JOIN;
+        request[1..].copy_from_slice(&self.uid.to_le_bytes());
+        if !self.radio.send_to(&join_address, &request)? {
+            return Ok(None);
+        }
+        let mut poll = request;
+        poll[0] = TYPE_POLL;
+        for _ in 0..timeout_ms {
+            delay.delay_us(1000);
+            if !self.radio.send_to(&join_address, &poll)? {
+                continue;
+            }
+            if let Some(offer) = self.radio.read_ack_payload()? {
+                if let Some(lease) = Lease::decode(&offer, self.uid) {
+                    self.lease = Some(lease);
+                    return Ok(Some(lease));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Send `data` on the leased pipe, renewing the lease
+    ///
+    /// Returns whether the hub acknowledged it. Join again when this
+    /// keeps failing.
+    pub fn send(&mut self, data: &[u8]) -> Result<bool, SpiE> {
+        assert!(data.len() <= PAYLOAD_LEN);
+        let lease = self.lease.expect("no lease, call join() first");
+        self.radio.send_to(&lease.address, data)
+    }
+}
@@ -0,0 +1,95 @@
+//! Async counterparts of the waits, for executors such as embassy
+//!
+//! The waits for the oscillator and for the chip go through the
+//! [`DelayUs`](trait.DelayUs.html) and [`IrqPin`](../trait.IrqPin.html)
+//! traits, which mirror `embedded_hal_async::delay::DelayNs` and
+//! `embedded_hal_async::digital::Wait`. With embassy, enable the
+//! `embassy` feature for [`EmbassyDelay`](struct.EmbassyDelay.html)
+//! and the `embedded-hal-async` feature to pass an `ExtiInput` as the
+//! IRQ pin. A receive task then loops over
+//! [`receive_async()`](../struct.Nrf24l01.html#method.receive_async)
+//! and forwards each packet into a channel.
+//!
+//! CE handling stays with the driver, including the hold times
+//! between mode changes.
+
+use crate::device::Device;
+use crate::{Event, IrqPin, Nrf24l01, Payload, Pipe};
+use core::fmt::Debug;
+use core::future::Future;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// An async delay
+pub trait DelayUs {
+    fn delay_us(&mut self, us: u32) -> impl Future<Output = ()>;
+}
+
+/// [`DelayUs`](trait.DelayUs.html) through `embassy_time::Timer`
+#[cfg(feature = "embassy")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbassyDelay;
+
+#[cfg(feature = "embassy")]
+impl DelayUs for EmbassyDelay {
+    async fn delay_us(&mut self, us: u32) {
+        embassy_time::Timer::after_micros(us.into()).await
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Like [`power_up()`](#method.power_up), awaiting the oscillator
+    /// start-up instead of blocking
+    pub async fn power_up_async<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), SpiE> {
+        let powered_up = self.device.update_config(|config| {
+            let powered_up = config.pwr_up();
+            config.set_pwr_up(true);
+            powered_up
+        })?;
+        if !powered_up {
+            delay.delay_us(self.power_up_delay_us()).await;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next packet, sleeping on the IRQ pin in between
+    pub async fn receive_async<P: IrqPin>(&mut self, irq: &mut P) -> Result<(Pipe, Payload), SpiE> {
+        loop {
+            match self.wait_rx_ready() {
+                Ok(pipe) => return Ok((pipe, nb::block!(self.read())?)),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            // Release the IRQ line so that it falls for the next packet
+            let flags = self.take_interrupts()?;
+            if flags.pipe.is_none() {
+                irq.wait_for_low().await;
+            }
+        }
+    }
+
+    /// Send `packet` and wait for its outcome on the IRQ pin
+    ///
+    /// Returns whether it was acknowledged.
+    pub async fn send_async<P: IrqPin>(
+        &mut self,
+        irq: &mut P,
+        packet: &[u8],
+    ) -> Result<bool, SpiE> {
+        nb::block!(self.send(packet))?;
+        loop {
+            match self.poll()? {
+                Event::SendComplete { .. } => return Ok(true),
+                Event::SendFailed => return Ok(false),
+                Event::PacketReceived(_) | Event::Idle => irq.wait_for_low().await,
+            }
+        }
+    }
+}
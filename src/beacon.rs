@@ -0,0 +1,280 @@
+//! Periodic one-to-many broadcast
+//!
+//! A [`Beacon`](struct.Beacon.html) loads its packet once with
+//! `REUSE_TX_PL` and sends it again on every CE pulse, without
+//! auto-ack, so each beacon costs a single pulse and no SPI transfer
+//! of the payload. A [`Tracker`](struct.Tracker.html) on the receiving
+//! side counts beacons that did not arrive from the gaps between those
+//! that did, which is all presence detection needs.
+
+use crate::command::{ReuseTxPl, WriteTxPayload};
+use crate::device::Device;
+use crate::registers::EnAa;
+use crate::{Clock, Configuration, Interrupts, Nrf24l01, Payload, Pipe, CE_PULSE_US, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Missed beacons after which a [`Tracker`](struct.Tracker.html)
+/// considers the sender gone
+pub const DEFAULT_MAX_MISSED: u32 = 3;
+
+/// Sends the same packet at a fixed interval
+///
+/// Borrows the radio until [`finish()`](#method.finish) or drop, which
+/// flush the packet and restore auto-ack.
+pub struct Beacon<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    interval_us: u32,
+    next_us: Option<u64>,
+    sent: u32,
+    previous_en_aa: EnAa,
+    active: bool,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Beacon<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Send `packet` to `address` every `interval_us`
+    ///
+    /// Moves off a channel blocked by the radio's
+    /// [channel mask](../struct.Nrf24l01.html#method.set_channel_mask).
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+        interval_us: u32,
+        packet: &[u8],
+    ) -> Result<Self, SpiE> {
+        assert!(interval_us > 0);
+        nb::block!(radio.wait_tx_empty())?;
+        radio.enforce_channel_mask()?;
+        let (_, previous_en_aa) = radio.device.read_register::<EnAa>()?;
+        radio.set_auto_ack(&[false; PIPES_COUNT])?;
+        radio.set_tx_addr(address)?;
+        let mut beacon = Beacon {
+            radio,
+            interval_us,
+            next_us: None,
+            sent: 0,
+            previous_en_aa,
+            active: true,
+        };
+        beacon.set_packet(packet)?;
+        Ok(beacon)
+    }
+
+    /// Beacons sent so far
+    pub fn sent(&self) -> u32 {
+        self.sent
+    }
+
+    pub fn interval_us(&self) -> u32 {
+        self.interval_us
+    }
+
+    /// Send `packet` from the next beacon on
+    pub fn set_packet(&mut self, packet: &[u8]) -> Result<(), SpiE> {
+        let radio = &mut *self.radio;
+        radio.standby();
+        radio.tx()?;
+        // Ends `REUSE_TX_PL` for the previous packet
+        radio.flush_tx()?;
+        radio.device.send_command(&WriteTxPayload::new(packet))?;
+        radio.device.send_command(&ReuseTxPl)?;
+        Ok(())
+    }
+
+    /// Send the beacon if it is due, returning whether it was
+    ///
+    /// Beacons that fell due while this was not called are skipped
+    /// rather than sent in a burst.
+    pub fn poll<C: Clock, D: DelayUs<u32>>(
+        &mut self,
+        clock: &C,
+        delay: &mut D,
+    ) -> Result<bool, SpiE> {
+        let now = clock.now_us();
+        if self.next_us.is_some_and(|next| now < next) {
+            return Ok(false);
+        }
+        let interval = u64::from(self.interval_us);
+        self.next_us = Some(match self.next_us {
+            Some(next) if now - next < interval => next + interval,
+            _ => now + interval,
+        });
+        let radio = &mut *self.radio;
+        radio.device.ce_enable();
+        delay.delay_us(CE_PULSE_US);
+        radio.device.ce_disable();
+        // The packet stays in the FIFO, so `TX_DS` has to be cleared here
+        nb::block!(radio.wait_tx_done())?;
+        radio.clear(Interrupts::new().set_tx_ds())?;
+        self.sent = self.sent.wrapping_add(1);
+        Ok(true)
+    }
+
+    /// Stop sending, flush the packet and restore auto-ack
+    pub fn finish(mut self) -> Result<(), SpiE> {
+        self.leave()
+    }
+
+    fn leave(&mut self) -> Result<(), SpiE> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+        let radio = &mut *self.radio;
+        radio.standby();
+        radio.tx()?;
+        radio.flush_tx()?;
+        radio.clear(Interrupts::new().set_tx_ds())?;
+        radio.device.write_register(self.previous_en_aa.clone())?;
+        radio.standby();
+        Ok(())
+    }
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Drop for Beacon<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    fn drop(&mut self) {
+        // Errors cannot be reported here, use `finish()` to see them
+        let _ = self.leave();
+    }
+}
+
+/// Receives beacons and keeps track of those lost
+pub struct Tracker<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    interval_us: u32,
+    max_missed: u32,
+    last_seen_us: Option<u64>,
+    received: u32,
+    missed: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Tracker<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen on pipe 1 at `address`, without auto-ack, for beacons
+    /// sent every `interval_us`
+    ///
+    /// Moves off a blocked channel the same way as
+    /// [`Beacon::new()`](struct.Beacon.html#method.new).
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+        interval_us: u32,
+    ) -> Result<Self, SpiE> {
+        assert!(interval_us > 0);
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[1] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.enforce_channel_mask()?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_auto_ack(&[false; PIPES_COUNT])?;
+        radio.set_rx_addr(Pipe::P1, address)?;
+        Ok(Tracker {
+            radio,
+            interval_us,
+            max_missed: DEFAULT_MAX_MISSED,
+            last_seen_us: None,
+            received: 0,
+            missed: 0,
+        })
+    }
+
+    /// Consider the sender gone after `max_missed` beacons in a row
+    pub fn set_max_missed(&mut self, max_missed: u32) {
+        self.max_missed = max_missed;
+    }
+
+    /// Beacons received so far
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Beacons lost between two received ones
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+
+    /// Lost beacons in percent
+    pub fn loss_percent(&self) -> u8 {
+        let total = u64::from(self.received) + u64::from(self.missed);
+        match total {
+            0 => 0,
+            total => (u64::from(self.missed) * 100 / total) as u8,
+        }
+    }
+
+    /// Local time the last beacon arrived
+    pub fn last_seen_us(&self) -> Option<u64> {
+        self.last_seen_us
+    }
+
+    /// Whether the last beacon is recent enough
+    pub fn is_present<C: Clock>(&self, clock: &C) -> bool {
+        let window = u64::from(self.interval_us) * (u64::from(self.max_missed) + 1);
+        self.last_seen_us
+            .is_some_and(|last| clock.now_us().saturating_sub(last) <= window)
+    }
+
+    /// Forget the sender, for example when switching to another one
+    pub fn reset(&mut self) {
+        self.last_seen_us = None;
+        self.received = 0;
+        self.missed = 0;
+    }
+
+    /// Poll for a beacon
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<Option<Payload>, SpiE> {
+        match self.radio.wait_rx_ready() {
+            Ok(_) => {}
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        }
+        let packet = nb::block!(self.radio.read())?;
+        let now = packet.timestamp_us().unwrap_or_else(|| clock.now_us());
+        if let Some(last) = self.last_seen_us {
+            let interval = u64::from(self.interval_us);
+            // Rounded, to allow for jitter on both sides
+            let intervals = (now.saturating_sub(last) + interval / 2) / interval;
+            let missed = intervals.saturating_sub(1).min(u64::from(u32::MAX)) as u32;
+            self.missed = self.missed.saturating_add(missed);
+        }
+        self.last_seen_us = Some(now);
+        self.received = self.received.wrapping_add(1);
+        Ok(Some(packet))
+    }
+}
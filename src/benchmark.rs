@@ -0,0 +1,121 @@
+//! Throughput measurement with the current radio settings
+//!
+//! Keeps the TX FIFO full for a given time and counts what gets
+//! acknowledged, so that the effect of auto-retransmit delay and
+//! count, data rate and payload length can be measured.
+
+use crate::command::{FlushTx, WriteTxPayload};
+use crate::device::Device;
+use crate::registers::{FifoStatus, ObserveTx, Status};
+use crate::{Clock, Configuration, Nrf24l01, Pipe, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Outcome of a [`run()`](fn.run.html)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Time from the first packet until the last one completed
+    pub duration_us: u64,
+    pub payload_len: usize,
+    pub sent: u32,
+    pub acked: u32,
+    /// Packets dropped after reaching the retransmit limit
+    pub dropped: u32,
+    pub retransmits: u32,
+}
+
+impl Report {
+    /// Acknowledged packets per second
+    pub fn packets_per_second(&self) -> u32 {
+        match self.duration_us {
+            0 => 0,
+            us => (u64::from(self.acked) * 1_000_000 / us) as u32,
+        }
+    }
+
+    /// Acknowledged payload bytes per second
+    pub fn bytes_per_second(&self) -> u32 {
+        match self.duration_us {
+            0 => 0,
+            us => (u64::from(self.acked) * self.payload_len as u64 * 1_000_000 / us) as u32,
+        }
+    }
+}
+
+/// Send `payload_len` byte packets to `address` for `duration_us`
+///
+/// Dropping a packet flushes the whole TX FIFO, so dropped packets
+/// are an estimate when the link is poor.
+pub fn run<Ce, Csn, Spi, E, SpiE, C>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    clock: &C,
+    address: &[u8],
+    payload_len: usize,
+    duration_us: u64,
+) -> Result<Report, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    C: Clock,
+{
+    assert!(payload_len > 0 && payload_len <= PAYLOAD_LEN);
+    let mut payload = [0; PAYLOAD_LEN];
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let payload = &payload[0..payload_len];
+
+    nb::block!(radio.wait_tx_empty())?;
+    radio.set_tx_addr(address)?;
+    radio.set_rx_addr(Pipe::P0, address)?;
+    radio.clear_interrupts()?;
+
+    let mut report = Report {
+        payload_len,
+        ..Report::default()
+    };
+    let start = clock.now_us();
+    let end = start + duration_us;
+    let device = radio.device();
+    loop {
+        let (status, observe) = device.read_register::<ObserveTx>()?;
+        let mut clear = Status(0);
+        if status.tx_ds() {
+            report.acked += 1;
+            report.retransmits += u32::from(observe.arc_cnt());
+            clear.set_tx_ds(true);
+        }
+        if status.max_rt() {
+            let in_flight = report.sent - report.acked - report.dropped;
+            report.dropped += in_flight;
+            report.retransmits += u32::from(observe.arc_cnt());
+            device.send_command(&FlushTx)?;
+            clear.set_max_rt(true);
+        }
+        if clear.0 != 0 {
+            device.write_register(clear)?;
+        }
+
+        if clock.now_us() < end {
+            if !status.tx_full() {
+                device.send_command(&WriteTxPayload::new(payload))?;
+                device.ce_enable();
+                report.sent += 1;
+            }
+        } else if !status.tx_ds() && !status.max_rt() {
+            let (_, fifo_status) = device.read_register::<FifoStatus>()?;
+            if fifo_status.tx_empty() {
+                break;
+            }
+        }
+    }
+    report.duration_us = clock.now_us() - start;
+    // Completions may have been merged between two polls
+    report.acked = report.sent - report.dropped;
+    device.ce_disable();
+    Ok(report)
+}
@@ -0,0 +1,303 @@
+//! Binding a node to a hub at the press of a button
+//!
+//! Like an RC transmitter and its receiver: while a
+//! [`Hub`](struct.Hub.html) is in pairing mode it listens on
+//! [`BIND_CHANNEL`](constant.BIND_CHANNEL.html) at
+//! [`BIND_ADDRESS`](constant.BIND_ADDRESS.html). An unbound
+//! [`Node`](struct.Node.html) sends a join request there and polls
+//! until the hub's answer, the address and channel to use from now on,
+//! comes back in an ack payload. The node persists the
+//! [`Binding`](struct.Binding.html) in a
+//! [`BindingStore`](trait.BindingStore.html) and restores it on the
+//! next start.
+//!
+//! Both sides may be given a pairing code, and the hub ignores
+//! requests carrying another one. The code travels in plain text: it
+//! keeps neighbouring systems apart, it does not keep attackers out.
+
+use crate::channels::Channel;
+use crate::{Configuration, Nrf24l01, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_REQUEST: u8 = 0xB1;
+const TYPE_POLL: u8 = 0xB2;
+const TYPE_BINDING: u8 = 0xB3;
+const REQUEST_LEN: usize = 9;
+const ANSWER_LEN: usize = 5 + BINDING_LEN;
+/// Channel both sides meet on while binding
+pub const BIND_CHANNEL: Channel = Channel::new(2);
+/// Address the hub listens on while binding, in SPI order
+pub const BIND_ADDRESS: [u8; 5] = [0xB4, 0x6D, 0x3C, 0x92, 0xE1];
+/// Encoded length of a [`Binding`](struct.Binding.html)
+pub const BINDING_LEN: usize = 6;
+
+/// Where a bound node talks to its hub
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    /// Address of the hub pipe assigned to the node, in SPI order
+    pub address: [u8; 5],
+    pub channel: Channel,
+}
+
+impl Binding {
+    pub fn to_bytes(&self) -> [u8; BINDING_LEN] {
+        let mut bytes = [0; BINDING_LEN];
+        bytes[0..5].copy_from_slice(&self.address);
+        bytes[5] = self.channel.number();
+        bytes
+    }
+
+    /// `None` for an invalid channel
+    pub fn from_bytes(bytes: &[u8; BINDING_LEN]) -> Option<Self> {
+        let mut address = [0; 5];
+        address.copy_from_slice(&bytes[0..5]);
+        Some(Binding {
+            address,
+            channel: Channel::try_new(bytes[5])?,
+        })
+    }
+}
+
+/// Non-volatile storage for the binding of a node, e.g. a flash page
+pub trait BindingStore {
+    type Error;
+
+    /// Fill `buf` with the stored binding, `false` if there is none
+    fn load(&mut self, buf: &mut [u8; BINDING_LEN]) -> Result<bool, Self::Error>;
+    fn store(&mut self, data: &[u8; BINDING_LEN]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum Error<SpiE, StoreE> {
+    Spi(SpiE),
+    Store(StoreE),
+}
+
+impl<SpiE, StoreE> From<SpiE> for Error<SpiE, StoreE> {
+    fn from(e: SpiE) -> Self {
+        Error::Spi(e)
+    }
+}
+
+fn encode_request(kind: u8, uid: u32, code: u32) -> [u8; REQUEST_LEN] {
+    let mut request = [0; REQUEST_LEN];
+    request[0] = kind;
+    request[1..5].copy_from_slice(&uid.to_le_bytes());
+    request[5..9].copy_from_slice(&code.to_le_bytes());
+    request
+}
+
+/// The node side, binding once and restoring the binding afterwards
+pub struct Node<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    uid: u32,
+    code: u32,
+    binding: Option<Binding>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Node<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// `uid` must be non-zero and unique, `code` must match the hub's
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>, uid: u32, code: u32) -> Self {
+        assert!(uid != 0);
+        Node {
+            radio,
+            uid,
+            code,
+            binding: None,
+        }
+    }
+
+    pub fn binding(&self) -> Option<&Binding> {
+        self.binding.as_ref()
+    }
+
+    /// Apply the binding found in `store`, if any
+    pub fn restore<S: BindingStore>(
+        &mut self,
+        store: &mut S,
+    ) -> Result<Option<Binding>, Error<SpiE, S::Error>> {
+        let mut buf = [0; BINDING_LEN];
+        if !store.load(&mut buf).map_err(Error::Store)? {
+            return Ok(None);
+        }
+        let binding = match Binding::from_bytes(&buf) {
+            Some(binding) => binding,
+            None => return Ok(None),
+        };
+        self.apply(&binding)?;
+        Ok(Some(binding))
+    }
+
+    /// Ask a hub in pairing mode for a binding, for up to `timeout_ms`
+    ///
+    /// On success the binding is stored and applied: the radio sends to
+    /// the assigned address on the assigned channel. Otherwise the
+    /// radio is left on the binding channel.
+    pub fn bind<S: BindingStore, D: DelayUs<u32>>(
+        &mut self,
+        store: &mut S,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<Binding>, Error<SpiE, S::Error>> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_channel(BIND_CHANNEL)?;
+        self.radio.set_address_width(5)?;
+        self.radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+        self.radio.set_ack_payloads(true)?;
+        let request = encode_request(TYPE_REQUEST, self.uid, self.code);
+        let poll = encode_request(TYPE_POLL, self.uid, self.code);
+        let mut sent = false;
+        for _ in 0..timeout_ms {
+            let packet = match sent {
+                false => &request,
+                true => &poll,
+            };
+            if self.radio.send_to(&BIND_ADDRESS, packet)? {
+                if let Some(answer) = self.radio.read_ack_payload()? {
+                    if let Some(binding) = self.decode(&answer) {
+                        store.store(&binding.to_bytes()).map_err(Error::Store)?;
+                        self.apply(&binding)?;
+                        return Ok(Some(binding));
+                    }
+                }
+                sent = true;
+            }
+            delay.delay_us(1000);
+        }
+        Ok(None)
+    }
+
+    fn decode(&self, answer: &[u8]) -> Option<Binding> {
+        if answer.len() < ANSWER_LEN
+            || answer[0] != TYPE_BINDING
+            || answer[1..5] != self.uid.to_le_bytes()
+        {
+            return None;
+        }
+        let mut bytes = [0; BINDING_LEN];
+        bytes.copy_from_slice(&answer[5..ANSWER_LEN]);
+        Binding::from_bytes(&bytes)
+    }
+
+    fn apply(&mut self, binding: &Binding) -> Result<(), SpiE> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_channel(binding.channel)?;
+        self.radio.set_tx_addr(&binding.address)?;
+        self.radio.set_rx_addr(Pipe::P0, &binding.address)?;
+        self.binding = Some(*binding);
+        Ok(())
+    }
+}
+
+/// The hub side in pairing mode
+///
+/// Created when the pairing button is pressed, and dropped or
+/// [`finish()`](#method.finish)ed some time later. Meanwhile the radio
+/// listens only for join requests.
+pub struct Hub<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    code: u32,
+    previous_channel: u8,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Hub<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Enter pairing mode, answering requests that carry `code`
+    ///
+    /// Moves to the binding channel and points pipe 0 at the binding
+    /// address, with auto-ack and ack payloads.
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>, code: u32) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        let previous_channel = radio.get_frequency()?;
+        radio.set_channel(BIND_CHANNEL)?;
+        radio.set_address_width(5)?;
+        radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+        radio.set_ack_payloads(true)?;
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_rx_addr(Pipe::P0, &BIND_ADDRESS)?;
+        Ok(Hub {
+            radio,
+            code,
+            previous_channel,
+        })
+    }
+
+    /// Answer join requests
+    ///
+    /// `assign` picks the binding for a node id, or `None` to turn the
+    /// node away. Returns the node id and binding handed out, if any.
+    /// The answer goes out with the acknowledgement of the node's next
+    /// poll.
+    pub fn poll<F>(&mut self, mut assign: F) -> Result<Option<(u32, Binding)>, SpiE>
+    where
+        F: FnMut(u32) -> Option<Binding>,
+    {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            if packet.len() < REQUEST_LEN
+                || packet[0] != TYPE_REQUEST
+                || packet[5..9] != self.code.to_le_bytes()
+            {
+                continue;
+            }
+            let uid = u32::from_le_bytes([packet[1], packet[2], packet[3], packet[4]]);
+            let binding = match assign(uid) {
+                Some(binding) => binding,
+                None => continue,
+            };
+            let mut answer = [0; ANSWER_LEN];
+            answer[0] = TYPE_BINDING;
+            answer[1..5].copy_from_slice(&uid.to_le_bytes());
+            answer[5..].copy_from_slice(&binding.to_bytes());
+            // Drop answers to earlier requests that were never polled
+            self.radio.flush_tx()?;
+            self.radio.write_ack_payload(Pipe::P0, &answer)?;
+            return Ok(Some((uid, binding)));
+        }
+    }
+
+    /// Leave pairing mode, going back to the previous channel
+    ///
+    /// Pipe 0 and the other pipes are left as pairing set them up.
+    pub fn finish(self) -> Result<(), SpiE> {
+        self.radio.set_frequency(self.previous_channel)
+    }
+}
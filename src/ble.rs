@@ -0,0 +1,196 @@
+//! Bluetooth Low Energy advertisements
+//!
+//! The nRF24L01+ can produce non-connectable BLE advertising packets
+//! when running in 1 Mbps ShockBurst mode on the three advertising
+//! channels: the access address and every payload byte are sent
+//! bit-reversed, and CRC24 plus whitening are done in software.
+//! Any phone scanning for BLE devices can pick them up.
+
+use crate::device::Device;
+use crate::registers::Feature;
+use crate::{Configuration, CrcMode, DataRate, Nrf24l01, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Advertising channel indices and their RF channels
+const CHANNELS: [(u8, u8); 3] = [(37, 2), (38, 26), (39, 80)];
+/// Advertising access address `0x8E89BED6`, bit-reversed, LSB first
+const ACCESS_ADDRESS: [u8; 4] = [0x71, 0x91, 0x7D, 0x6B];
+/// ADV_NONCONN_IND with a random device address
+const PDU_TYPE: u8 = 0x42;
+const CRC_LEN: usize = 3;
+const MAC_LEN: usize = 6;
+/// PDU header and device address
+const PDU_HEADER_LEN: usize = 2 + MAC_LEN;
+/// Room for AD structures
+pub const MAX_AD_LEN: usize = PAYLOAD_LEN - CRC_LEN - PDU_HEADER_LEN;
+/// Longest `manufacturer_data` that
+/// [`advertise()`](struct.Advertiser.html#method.advertise) takes,
+/// company identifier included
+///
+/// What is left of [`MAX_AD_LEN`](constant.MAX_AD_LEN.html) after the
+/// flags and the AD structure header.
+pub const MAX_MANUFACTURER_DATA_LEN: usize = MAX_AD_LEN - 3 - 2;
+
+const AD_FLAGS: u8 = 0x01;
+const AD_SHORTENED_NAME: u8 = 0x08;
+const AD_COMPLETE_NAME: u8 = 0x09;
+const AD_MANUFACTURER_DATA: u8 = 0xFF;
+/// LE General Discoverable, BR/EDR not supported
+const FLAGS: u8 = 0x06;
+
+/// Broadcasts BLE advertisements
+///
+/// Reconfigures the radio for BLE: restore your own settings before
+/// using it for nRF24 traffic again.
+pub struct Advertiser<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    mac: [u8; MAC_LEN],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Advertiser<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// `mac` is a random static device address, most significant byte first
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        mac: [u8; MAC_LEN],
+    ) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_crc(CrcMode::Disabled)?;
+        radio.set_auto_ack(&[false; PIPES_COUNT])?;
+        radio.set_auto_retransmit(0, 0)?;
+        radio.set_rf(&DataRate::R1Mbps, 3)?;
+        radio.set_pipes_rx_lengths(&[Some(PAYLOAD_LEN as u8); PIPES_COUNT])?;
+        // ShockBurst without packet control field
+        radio
+            .device()
+            .update_register::<Feature, _, _>(|feature| feature.set_en_dpl(false))?;
+        radio.set_address_width(ACCESS_ADDRESS.len() as u8)?;
+        radio.set_tx_addr(&ACCESS_ADDRESS)?;
+        Ok(Advertiser { radio, mac })
+    }
+
+    /// Send one advertisement on each of the three advertising channels
+    ///
+    /// `manufacturer_data` starts with the 16-bit company identifier,
+    /// little-endian. `name` is shortened to fit the remaining space.
+    ///
+    /// Panics if `manufacturer_data` is longer than
+    /// [`MAX_MANUFACTURER_DATA_LEN`](constant.MAX_MANUFACTURER_DATA_LEN.html).
+    pub fn advertise(&mut self, name: &str, manufacturer_data: &[u8]) -> Result<(), SpiE> {
+        let mut pdu = [0; PAYLOAD_LEN];
+        let len = self.encode_pdu(&mut pdu, name, manufacturer_data);
+
+        let previous_channel = self.radio.get_frequency()?;
+        for &(index, rf_channel) in CHANNELS.iter() {
+            let mut packet = pdu;
+            encode_packet(&mut packet[0..len], index);
+            nb::block!(self.radio.wait_tx_empty())?;
+            self.radio.set_frequency(rf_channel)?;
+            nb::block!(self.radio.send(&packet[0..len]))?;
+            nb::block!(self.radio.wait_tx_done())?;
+        }
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_frequency(previous_channel)
+    }
+
+    /// Fill in the PDU, returning the length including room for the CRC
+    fn encode_pdu(&self, pdu: &mut [u8], name: &str, manufacturer_data: &[u8]) -> usize {
+        let mut len = PDU_HEADER_LEN;
+        let mut push_ad = |ad_type: u8, data: &[u8]| {
+            pdu[len] = data.len() as u8 + 1;
+            pdu[len + 1] = ad_type;
+            pdu[len + 2..len + 2 + data.len()].copy_from_slice(data);
+            len += 2 + data.len();
+        };
+
+        push_ad(AD_FLAGS, &[FLAGS]);
+        let used = 3 + if manufacturer_data.is_empty() {
+            0
+        } else {
+            2 + manufacturer_data.len()
+        };
+        assert!(used <= MAX_AD_LEN, "manufacturer data too long");
+        if !manufacturer_data.is_empty() {
+            push_ad(AD_MANUFACTURER_DATA, manufacturer_data);
+        }
+        let room = MAX_AD_LEN - used;
+        if !name.is_empty() && room > 2 {
+            let name = name.as_bytes();
+            if name.len() <= room - 2 {
+                push_ad(AD_COMPLETE_NAME, name);
+            } else {
+                push_ad(AD_SHORTENED_NAME, &name[0..room - 2]);
+            }
+        }
+
+        pdu[0] = PDU_TYPE;
+        pdu[1] = (len - 2) as u8;
+        for (i, byte) in self.mac.iter().rev().enumerate() {
+            pdu[2 + i] = *byte;
+        }
+        len + CRC_LEN
+    }
+}
+
+/// Append CRC24, whiten for channel `index`, and bit-reverse for the nRF24
+fn encode_packet(packet: &mut [u8], index: u8) {
+    let data_len = packet.len() - CRC_LEN;
+    let (data, crc) = packet.split_at_mut(data_len);
+    crc.copy_from_slice(&[0x55; CRC_LEN]);
+    crc24(data, crc);
+    for byte in crc.iter_mut() {
+        *byte = byte.reverse_bits();
+    }
+    whiten(packet, index.reverse_bits() | 2);
+    for byte in packet.iter_mut() {
+        *byte = byte.reverse_bits();
+    }
+}
+
+/// BLE CRC24, `crc` holds the initial value
+fn crc24(data: &[u8], crc: &mut [u8]) {
+    for &byte in data {
+        let mut d = byte;
+        for _ in 0..8 {
+            let t = crc[0] >> 7;
+            crc[0] = (crc[0] << 1) | (crc[1] >> 7);
+            crc[1] = (crc[1] << 1) | (crc[2] >> 7);
+            crc[2] <<= 1;
+            if t != d & 1 {
+                crc[2] ^= 0x5B;
+                crc[1] ^= 0x06;
+            }
+            d >>= 1;
+        }
+    }
+}
+
+/// Data whitening with the LFSR shifted left by one bit
+fn whiten(data: &mut [u8], mut coefficient: u8) {
+    for byte in data.iter_mut() {
+        let mut mask = 1u8;
+        while mask != 0 {
+            if coefficient & 0x80 != 0 {
+                coefficient ^= 0x11;
+                *byte ^= mask;
+            }
+            coefficient <<= 1;
+            mask <<= 1;
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{Nrf24l01, Payload, Pipe};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Pause between two polls of the chip
+const POLL_INTERVAL_US: u32 = 50;
+
+/// Blocking API without `nb`, built with the `blocking` feature
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Send `packet` and wait for its outcome
+    ///
+    /// Returns whether it was acknowledged.
+    pub fn send_blocking<D: DelayUs<u32>>(
+        &mut self,
+        packet: &[u8],
+        delay: &mut D,
+    ) -> Result<bool, SpiE> {
+        loop {
+            match self.send(packet) {
+                Ok(()) => break,
+                Err(nb::Error::WouldBlock) => delay.delay_us(POLL_INTERVAL_US),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+        }
+        loop {
+            match self.wait_tx_done() {
+                Ok(acked) => return Ok(acked),
+                Err(nb::Error::WouldBlock) => delay.delay_us(POLL_INTERVAL_US),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Wait up to `timeout_us` for a packet, forever if `None`
+    pub fn recv_blocking<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_us: Option<u32>,
+    ) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        let mut waited_us = 0;
+        loop {
+            match self.wait_rx_ready() {
+                Ok(pipe) => return Ok(Some((pipe, nb::block!(self.read())?))),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            if timeout_us.is_some_and(|timeout_us| waited_us >= timeout_us) {
+                return Ok(None);
+            }
+            delay.delay_us(POLL_INTERVAL_US);
+            waited_us = waited_us.saturating_add(POLL_INTERVAL_US);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! A shared broadcast address next to unicast addresses
+//!
+//! Every node listens on one pipe at the same broadcast address, with
+//! auto-ack disabled there so that no two nodes answer the same packet,
+//! and on its other pipes at its own unicast addresses as before.
+//! Broadcasts are sent without asking for an acknowledgement.
+
+use crate::device::Device;
+use crate::registers::{EnAa, EnRxaddr, TxAddr};
+use crate::{Configuration, Nrf24l01, Pipe};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen for broadcasts on `pipe` at `address`, without auto-ack
+    ///
+    /// The other pipes are left alone. Pipes 2 to 5 only take the least
+    /// significant byte, see
+    /// [`set_rx_addr()`](trait.Configuration.html#method.set_rx_addr).
+    pub fn set_broadcast_pipe(&mut self, pipe: Pipe, address: &[u8]) -> Result<(), SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        self.set_dyn_ack(true)?;
+        self.set_rx_addr(pipe, address)?;
+        let (_, mut en_aa) = self.device.read_register::<EnAa>()?;
+        en_aa.set_enaa_p(pipe.index(), false);
+        self.device.write_register(en_aa)?;
+        let (_, mut en_rxaddr) = self.device.read_register::<EnRxaddr>()?;
+        en_rxaddr.set_erx_p(pipe.index(), true);
+        self.device.write_register(en_rxaddr)?;
+        Ok(())
+    }
+
+    /// Send `packet` to the broadcast `address` without acknowledgement
+    ///
+    /// `TX_ADDR` is switched for this one packet and restored afterwards.
+    pub fn send_broadcast(&mut self, address: &[u8], packet: &[u8]) -> Result<(), SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        let (_, tx_addr) = self.device.read_register::<TxAddr>()?;
+        self.set_dyn_ack(true)?;
+        self.set_tx_addr(address)?;
+        nb::block!(self.send_no_ack(packet))?;
+        nb::block!(self.wait_tx_done())?;
+        self.device.write_register(tx_addr)?;
+        Ok(())
+    }
+}
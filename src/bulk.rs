@@ -0,0 +1,296 @@
+//! Bulk transfer with a sliding window and selective acknowledgements
+//!
+//! The [`Sender`](struct.Sender.html) sends up to `window` data packets
+//! without asking for hardware acknowledgements, then a poll packet
+//! whose acknowledgement carries the receiver's selective ack: the
+//! next sequence number it expects plus a bitmap of the packets it
+//! already holds beyond that. Packets missing from it go out again on
+//! the next [`poll()`](struct.Sender.html#method.poll).
+//!
+//! The [`Receiver`](struct.Receiver.html) keeps its current selective
+//! ack loaded as the only ack payload and delivers data in order.
+
+use crate::{Configuration, Nrf24l01, Payload, Pipe, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_DATA: u8 = 0x91;
+const TYPE_POLL: u8 = 0x92;
+const TYPE_SACK: u8 = 0x93;
+const HEADER_LEN: usize = 2;
+const SACK_LEN: usize = 4;
+/// Maximum number of data bytes per packet
+pub const DATA_LEN: usize = PAYLOAD_LEN - HEADER_LEN;
+/// Largest window, limited by the selective ack bitmap
+pub const MAX_WINDOW: usize = 16;
+
+/// Packets held per direction, indexed by sequence number
+struct Slots {
+    data: [[u8; DATA_LEN]; MAX_WINDOW],
+    lens: [usize; MAX_WINDOW],
+    /// Sender: acknowledged, receiver: received
+    done: [bool; MAX_WINDOW],
+}
+
+impl Slots {
+    const fn new() -> Self {
+        Slots {
+            data: [[0; DATA_LEN]; MAX_WINDOW],
+            lens: [0; MAX_WINDOW],
+            done: [false; MAX_WINDOW],
+        }
+    }
+
+    fn store(&mut self, seq: u8, data: &[u8]) {
+        let index = usize::from(seq) % MAX_WINDOW;
+        self.data[index][0..data.len()].copy_from_slice(data);
+        self.lens[index] = data.len();
+    }
+
+    fn get(&self, seq: u8) -> &[u8] {
+        let index = usize::from(seq) % MAX_WINDOW;
+        &self.data[index][0..self.lens[index]]
+    }
+}
+
+/// Sending side of a bulk transfer
+pub struct Sender<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    window: u8,
+    /// Oldest unacknowledged sequence number
+    base: u8,
+    next_seq: u8,
+    /// Sequence numbers from `base` up to this one have been sent
+    sent_until: u8,
+    slots: Slots,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Sender<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Send to `peer_address` with up to `window` unacknowledged packets
+    ///
+    /// Panics unless `window` is between 1 and
+    /// [`MAX_WINDOW`](constant.MAX_WINDOW.html).
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        peer_address: &[u8],
+        window: usize,
+    ) -> Result<Self, SpiE> {
+        assert!((1..=MAX_WINDOW).contains(&window));
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_ack_payloads(true)?;
+        radio.set_dyn_ack(true)?;
+        radio.set_tx_addr(peer_address)?;
+        radio.set_rx_addr(Pipe::P0, peer_address)?;
+        Ok(Sender {
+            radio,
+            window: window as u8,
+            base: 0,
+            next_seq: 0,
+            sent_until: 0,
+            slots: Slots::new(),
+        })
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Packets queued or sent but not acknowledged yet
+    pub fn in_flight(&self) -> usize {
+        usize::from(self.next_seq.wrapping_sub(self.base))
+    }
+
+    /// Everything has been acknowledged
+    pub fn is_idle(&self) -> bool {
+        self.base == self.next_seq
+    }
+
+    /// Queue `data`, returning `false` while the window is full
+    ///
+    /// Panics if `data` is longer than [`DATA_LEN`](constant.DATA_LEN.html).
+    pub fn send(&mut self, data: &[u8]) -> bool {
+        assert!(data.len() <= DATA_LEN);
+        if self.in_flight() >= usize::from(self.window) {
+            return false;
+        }
+        self.slots.store(self.next_seq, data);
+        self.slots.done[usize::from(self.next_seq) % MAX_WINDOW] = false;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        true
+    }
+
+    /// Send the packets not sent yet, then ask for a selective ack
+    ///
+    /// Returns whether the receiver answered.
+    pub fn poll(&mut self) -> Result<bool, SpiE> {
+        let mut seq = self.sent_until;
+        while seq != self.next_seq {
+            let index = usize::from(seq) % MAX_WINDOW;
+            if !self.slots.done[index] {
+                let data = self.slots.get(seq);
+                let mut packet = [0; PAYLOAD_LEN];
+                packet[0] = TYPE_DATA;
+                packet[1] = seq;
+                packet[HEADER_LEN..HEADER_LEN + data.len()].copy_from_slice(data);
+                nb::block!(self.radio.send_no_ack(&packet[0..HEADER_LEN + data.len()]))?;
+                nb::block!(self.radio.wait_tx_done())?;
+            }
+            seq = seq.wrapping_add(1);
+        }
+        self.sent_until = self.next_seq;
+
+        nb::block!(self.radio.send(&[TYPE_POLL]))?;
+        if !nb::block!(self.radio.wait_tx_done())? {
+            return Ok(false);
+        }
+        match self.radio.read_ack_payload()? {
+            Some(sack) => match *sack {
+                [TYPE_SACK, next_expected, lo, hi] => {
+                    self.apply_sack(next_expected, u16::from_le_bytes([lo, hi]));
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn apply_sack(&mut self, next_expected: u8, bitmap: u16) {
+        let in_flight = self.in_flight();
+        let acked_until = usize::from(next_expected.wrapping_sub(self.base));
+        if acked_until > in_flight {
+            // Stale or foreign
+            return;
+        }
+        for offset in 0..in_flight {
+            let seq = self.base.wrapping_add(offset as u8);
+            let index = usize::from(seq) % MAX_WINDOW;
+            if offset < acked_until {
+                self.slots.done[index] = true;
+            } else if offset > acked_until && offset - acked_until < MAX_WINDOW {
+                let bit = offset - acked_until - 1;
+                self.slots.done[index] |= bitmap & (1 << bit) != 0;
+            }
+        }
+        while self.base != self.next_seq && self.slots.done[usize::from(self.base) % MAX_WINDOW] {
+            self.base = self.base.wrapping_add(1);
+        }
+        // Send whatever the receiver is still missing again
+        self.sent_until = self.base;
+    }
+}
+
+/// Receiving side of a bulk transfer
+pub struct Receiver<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    pipe: Pipe,
+    next_expected: u8,
+    slots: Slots,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Receiver<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Receive on `pipe`, which must have auto-ack enabled
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>, pipe: Pipe) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_ack_payloads(true)?;
+        // Enter RX mode before the SACK ack payload occupies the TX FIFO
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        let mut receiver = Receiver {
+            radio,
+            pipe,
+            next_expected: 0,
+            slots: Slots::new(),
+        };
+        receiver.load_sack()?;
+        Ok(receiver)
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Poll for the next packet in order
+    pub fn poll(&mut self) -> Result<Option<Payload>, SpiE> {
+        loop {
+            if let Some(payload) = self.take_next()? {
+                return Ok(Some(payload));
+            }
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            if packet.len() < HEADER_LEN || packet[0] != TYPE_DATA {
+                continue;
+            }
+            let seq = packet[1];
+            let offset = usize::from(seq.wrapping_sub(self.next_expected));
+            let index = usize::from(seq) % MAX_WINDOW;
+            // Duplicates of delivered packets only need the ack refreshed
+            if offset < MAX_WINDOW && !self.slots.done[index] {
+                self.slots.store(seq, &packet[HEADER_LEN..]);
+                self.slots.done[index] = true;
+            }
+            self.load_sack()?;
+        }
+    }
+
+    fn take_next(&mut self) -> Result<Option<Payload>, SpiE> {
+        let index = usize::from(self.next_expected) % MAX_WINDOW;
+        if !self.slots.done[index] {
+            return Ok(None);
+        }
+        let payload = Payload::new(self.slots.get(self.next_expected));
+        self.slots.done[index] = false;
+        self.next_expected = self.next_expected.wrapping_add(1);
+        self.load_sack()?;
+        Ok(Some(payload))
+    }
+
+    /// Replace the ack payload with the current selective ack
+    fn load_sack(&mut self) -> Result<(), SpiE> {
+        let mut bitmap = 0u16;
+        for bit in 0..MAX_WINDOW - 1 {
+            let seq = self.next_expected.wrapping_add(1 + bit as u8);
+            if self.slots.done[usize::from(seq) % MAX_WINDOW] {
+                bitmap |= 1 << bit;
+            }
+        }
+        let [lo, hi] = bitmap.to_le_bytes();
+        let sack: [u8; SACK_LEN] = [TYPE_SACK, self.next_expected, lo, hi];
+        self.radio.flush_tx()?;
+        self.radio.write_ack_payload(self.pipe, &sack)
+    }
+}
@@ -0,0 +1,173 @@
+//! RF channel sets and WiFi coexistence
+//!
+//! RF channel `n` is centered at `2400 + n` MHz. A 2.4 GHz WiFi
+//! channel `w` is centered at `2407 + 5 * w` MHz (`2484` for channel
+//! 14) and occupies about 22 MHz, so the usual WiFi channels 1, 6 and
+//! 11 leave only a few gaps below 2474 MHz.
+
+use core::ops::RangeInclusive;
+
+/// Number of RF channels, `0..=125`
+pub const CHANNEL_COUNT: u8 = 126;
+/// Half of the occupied WiFi bandwidth, in MHz
+const WIFI_HALF_WIDTH: u8 = 11;
+
+/// RF channels overlapped by WiFi channel `wifi` (`1..=14`)
+pub fn wifi_channel_range(wifi: u8) -> RangeInclusive<u8> {
+    assert!((1..=14).contains(&wifi));
+    let center = match wifi {
+        14 => 84,
+        _ => 7 + 5 * wifi,
+    };
+    center - WIFI_HALF_WIDTH..=center + WIFI_HALF_WIDTH
+}
+
+/// A valid RF channel
+///
+/// The constructors are `const fn`, so a constant channel out of range
+/// fails to compile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Channel(u8);
+
+impl Channel {
+    /// Lowest frequency, channel 0
+    pub const BASE_MHZ: u16 = 2400;
+
+    /// Panics if `channel` is not in `0..=125`
+    pub const fn new(channel: u8) -> Self {
+        match Self::try_new(channel) {
+            Some(channel) => channel,
+            None => panic!("RF channel out of range"),
+        }
+    }
+
+    pub const fn try_new(channel: u8) -> Option<Self> {
+        if channel < CHANNEL_COUNT {
+            Some(Channel(channel))
+        } else {
+            None
+        }
+    }
+
+    /// Panics if `mhz` is not in `2400..=2525`
+    pub const fn from_mhz(mhz: u16) -> Self {
+        match Self::try_from_mhz(mhz) {
+            Some(channel) => channel,
+            None => panic!("frequency out of range"),
+        }
+    }
+
+    pub const fn try_from_mhz(mhz: u16) -> Option<Self> {
+        if mhz >= Self::BASE_MHZ && mhz < Self::BASE_MHZ + CHANNEL_COUNT as u16 {
+            Some(Channel((mhz - Self::BASE_MHZ) as u8))
+        } else {
+            None
+        }
+    }
+
+    /// Value of the `RF_CH` register
+    pub const fn number(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn mhz(&self) -> u16 {
+        Self::BASE_MHZ + self.0 as u16
+    }
+}
+
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> u8 {
+        channel.0
+    }
+}
+
+impl core::convert::TryFrom<u8> for Channel {
+    type Error = u8;
+
+    fn try_from(channel: u8) -> Result<Self, u8> {
+        Channel::try_new(channel).ok_or(channel)
+    }
+}
+
+/// Set of allowed RF channels
+///
+/// Applies to the whole driver through
+/// [`Nrf24l01::set_channel_mask()`](../struct.Nrf24l01.html#method.set_channel_mask),
+/// to gazell through [`Params::channel_mask()`](../gazell/struct.Params.html#method.channel_mask).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask(u128);
+
+impl Default for ChannelMask {
+    fn default() -> Self {
+        ChannelMask::all()
+    }
+}
+
+impl ChannelMask {
+    pub const fn all() -> Self {
+        ChannelMask((1 << CHANNEL_COUNT) - 1)
+    }
+
+    pub const fn none() -> Self {
+        ChannelMask(0)
+    }
+
+    /// Everything clear of WiFi channels 1, 6 and 11
+    ///
+    /// Leaves RF channels 0, 24, 25, 49, 50 and 74 to 125.
+    pub fn avoid_common_wifi() -> Self {
+        ChannelMask::all()
+            .block_wifi(1)
+            .block_wifi(6)
+            .block_wifi(11)
+    }
+
+    pub fn allow(mut self, channel: u8) -> Self {
+        assert!(channel < CHANNEL_COUNT);
+        self.0 |= 1 << channel;
+        self
+    }
+
+    pub fn block(mut self, channel: u8) -> Self {
+        assert!(channel < CHANNEL_COUNT);
+        self.0 &= !(1 << channel);
+        self
+    }
+
+    pub fn block_range(self, channels: RangeInclusive<u8>) -> Self {
+        channels.fold(self, |mask, channel| mask.block(channel))
+    }
+
+    /// Block everything overlapped by WiFi channel `wifi`
+    pub fn block_wifi(self, wifi: u8) -> Self {
+        let range = wifi_channel_range(wifi);
+        let end = (*range.end()).min(CHANNEL_COUNT - 1);
+        self.block_range(*range.start()..=end)
+    }
+
+    /// Block every channel in `blacklist`
+    pub fn block_all(self, blacklist: &[u8]) -> Self {
+        blacklist
+            .iter()
+            .fold(self, |mask, &channel| mask.block(channel))
+    }
+
+    pub fn is_allowed(&self, channel: u8) -> bool {
+        channel < CHANNEL_COUNT && self.0 & (1 << channel) != 0
+    }
+
+    pub fn count(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// Allowed channels in ascending order
+    pub fn channels(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..CHANNEL_COUNT).filter(move |&channel| self.is_allowed(channel))
+    }
+
+    /// The allowed channel closest to `channel`, preferring the lower one
+    pub fn nearest(&self, channel: u8) -> Option<u8> {
+        self.channels()
+            .min_by_key(|&allowed| (allowed as i16 - channel as i16).abs())
+    }
+}
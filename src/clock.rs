@@ -0,0 +1,8 @@
+/// Monotonic time source for the timing-based protocols
+///
+/// Wrap a hardware timer or a tick counter. Wrap-around of the `u64`
+/// microsecond counter is not handled.
+pub trait Clock {
+    /// Microseconds since an arbitrary starting point
+    fn now_us(&self) -> u64;
+}
@@ -1,13 +1,42 @@
+//! Byte-level encoding of the SPI commands
+//!
+//! Each [`Command`](trait.Command.html) knows its length, writes its
+//! bytes into the transfer buffer, and decodes the response from the
+//! same buffer afterwards, whose first byte is always `STATUS`. Host
+//! tools, fuzz targets and simulated devices can use these to produce
+//! and parse exactly what the driver puts on the wire.
+
 pub use crate::payload::Payload;
 use crate::registers::Register;
 use core::marker::PhantomData;
 
+// Commands are never empty, there is always the command byte
+#[allow(clippy::len_without_is_empty)]
 pub trait Command {
+    /// Bytes on the wire, command byte included
     fn len(&self) -> usize;
+    /// Fill `data[0..len()]` with what is sent to the chip
     fn encode(&self, data: &mut [u8]);
 
     type Response;
+    /// Parse what the chip sent back, `STATUS` in `data[0]` included
     fn decode_response(data: &[u8]) -> Self::Response;
+
+    /// Removes packets from the RX FIFO, so that the `STATUS` sent
+    /// along no longer describes it afterwards
+    fn pops_rx() -> bool {
+        false
+    }
+
+    /// Adds a packet to the TX FIFO, which may fill it up
+    fn pushes_tx() -> bool {
+        false
+    }
+
+    /// Empties the TX FIFO
+    fn flushes_tx() -> bool {
+        false
+    }
 }
 
 pub struct ReadRegister<R: Register> {
@@ -37,6 +66,12 @@ impl<R: Register> Command for ReadRegister<R> {
     }
 }
 
+impl<R: Register> Default for ReadRegister<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct WriteRegister<R: Register> {
     register: R,
 }
@@ -61,6 +96,60 @@ impl<R: Register> Command for WriteRegister<R> {
     fn decode_response(_: &[u8]) -> Self::Response {}
 }
 
+/// `R_REGISTER` by address, for registers without a type
+pub struct ReadRawRegister {
+    addr: u8,
+}
+
+impl ReadRawRegister {
+    pub fn new(addr: u8) -> Self {
+        assert!(addr < 0x20);
+        ReadRawRegister { addr }
+    }
+}
+
+impl Command for ReadRawRegister {
+    fn len(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.addr;
+    }
+
+    type Response = u8;
+    fn decode_response(data: &[u8]) -> Self::Response {
+        data[1]
+    }
+}
+
+/// `W_REGISTER` by address, for registers without a type
+pub struct WriteRawRegister {
+    addr: u8,
+    value: u8,
+}
+
+impl WriteRawRegister {
+    pub fn new(addr: u8, value: u8) -> Self {
+        assert!(addr < 0x20);
+        WriteRawRegister { addr, value }
+    }
+}
+
+impl Command for WriteRawRegister {
+    fn len(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = 0b10_0000 | self.addr;
+        buf[1] = self.value;
+    }
+
+    type Response = ();
+    fn decode_response(_: &[u8]) -> Self::Response {}
+}
+
 pub struct ReadRxPayload {
     payload_width: usize,
 }
@@ -84,6 +173,10 @@ impl Command for ReadRxPayload {
     fn decode_response(data: &[u8]) -> Self::Response {
         Payload::new(&data[1..])
     }
+
+    fn pops_rx() -> bool {
+        true
+    }
 }
 
 pub struct WriteTxPayload<'a> {
@@ -108,6 +201,67 @@ impl<'a> Command for WriteTxPayload<'a> {
 
     type Response = ();
     fn decode_response(_: &[u8]) -> Self::Response {}
+
+    fn pushes_tx() -> bool {
+        true
+    }
+}
+
+pub struct WriteTxPayloadNoAck<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> WriteTxPayloadNoAck<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        WriteTxPayloadNoAck { data }
+    }
+}
+
+impl<'a> Command for WriteTxPayloadNoAck<'a> {
+    fn len(&self) -> usize {
+        1 + self.data.len()
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = 0b1011_0000;
+        buf[1..].copy_from_slice(self.data);
+    }
+
+    type Response = ();
+    fn decode_response(_: &[u8]) -> Self::Response {}
+
+    fn pushes_tx() -> bool {
+        true
+    }
+}
+
+pub struct WriteAckPayload<'a> {
+    pipe: u8,
+    data: &'a [u8],
+}
+
+impl<'a> WriteAckPayload<'a> {
+    pub fn new(pipe: u8, data: &'a [u8]) -> Self {
+        WriteAckPayload { pipe, data }
+    }
+}
+
+impl<'a> Command for WriteAckPayload<'a> {
+    fn len(&self) -> usize {
+        1 + self.data.len()
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = 0b1010_1000 | self.pipe;
+        buf[1..].copy_from_slice(self.data);
+    }
+
+    type Response = ();
+    fn decode_response(_: &[u8]) -> Self::Response {}
+
+    fn pushes_tx() -> bool {
+        true
+    }
 }
 
 pub struct ReadRxPayloadWidth;
@@ -140,6 +294,10 @@ impl Command for FlushRx {
 
     type Response = ();
     fn decode_response(_: &[u8]) -> Self::Response {}
+
+    fn pops_rx() -> bool {
+        true
+    }
 }
 
 pub struct FlushTx;
@@ -155,6 +313,27 @@ impl Command for FlushTx {
 
     type Response = ();
     fn decode_response(_: &[u8]) -> Self::Response {}
+
+    fn flushes_tx() -> bool {
+        true
+    }
+}
+
+/// Keeps sending the last packet on every CE pulse until a new one is
+/// written or the TX FIFO is flushed
+pub struct ReuseTxPl;
+
+impl Command for ReuseTxPl {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = 0b1110_0011;
+    }
+
+    type Response = ();
+    fn decode_response(_: &[u8]) -> Self::Response {}
 }
 
 pub struct Nop;
@@ -171,3 +350,21 @@ impl Command for Nop {
     type Response = ();
     fn decode_response(_: &[u8]) -> Self::Response {}
 }
+
+/// Unlocks `FEATURE`, `DYNPD` and the related commands on the original
+/// nRF24L01. Sending it again locks them.
+pub struct Activate;
+
+impl Command for Activate {
+    fn len(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = 0b0101_0000;
+        buf[1] = 0x73;
+    }
+
+    type Response = ();
+    fn decode_response(_: &[u8]) -> Self::Response {}
+}
@@ -0,0 +1,279 @@
+//! LZSS compression for messages larger than a packet
+//!
+//! Only built with the `compression` feature.
+//! [`pack()`](fn.pack.html) prefixes a message with a header byte and
+//! compresses it if that makes it shorter; [`unpack()`](fn.unpack.html)
+//! undoes it either way. Text-heavy telemetry and configuration blobs
+//! typically shrink to well under half their size.
+//!
+//! The compressed format is a flag byte per eight items, one bit each:
+//! set for a literal byte, clear for a two-byte back reference with a
+//! 12-bit offset and a 4-bit length.
+
+use crate::framing;
+use crate::Nrf24l01;
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Length of the header [`pack()`](fn.pack.html) adds
+pub const HEADER_LEN: usize = 1;
+/// Header bit of compressed messages
+pub const FLAG_COMPRESSED: u8 = 0x01;
+/// Farthest back a reference can reach
+const WINDOW_LEN: usize = 1 << 12;
+/// Shorter matches are stored as literals
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + 0xF;
+
+/// Space [`compress()`](fn.compress.html) may need for `len` bytes
+pub const fn max_compressed_len(len: usize) -> usize {
+    len + len.div_ceil(8)
+}
+
+/// Compress `data` into `out`
+///
+/// Returns the compressed length, or `None` if `out` is too short.
+pub fn compress(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut len = 0;
+    while read < data.len() {
+        let flags_index = len;
+        *out.get_mut(flags_index)? = 0;
+        len += 1;
+        for bit in 0..8 {
+            if read >= data.len() {
+                break;
+            }
+            let (offset, match_len) = longest_match(data, read);
+            if match_len >= MIN_MATCH {
+                let offset = offset - 1;
+                *out.get_mut(len)? = (offset >> 4) as u8;
+                *out.get_mut(len + 1)? =
+                    ((offset & 0xF) << 4) as u8 | (match_len - MIN_MATCH) as u8;
+                len += 2;
+                read += match_len;
+            } else {
+                out[flags_index] |= 1 << bit;
+                *out.get_mut(len)? = data[read];
+                len += 1;
+                read += 1;
+            }
+        }
+    }
+    Some(len)
+}
+
+/// Offset and length of the longest earlier match for `data[pos..]`
+fn longest_match(data: &[u8], pos: usize) -> (usize, usize) {
+    let max_len = MAX_MATCH.min(data.len() - pos);
+    let mut best = (0, 0);
+    for start in pos.saturating_sub(WINDOW_LEN)..pos {
+        // Matches may run into the bytes they produce
+        let len = (0..max_len)
+            .take_while(|&i| data[start + i] == data[pos + i])
+            .count();
+        if len > best.1 {
+            best = (pos - start, len);
+            if len == max_len {
+                break;
+            }
+        }
+    }
+    best
+}
+
+/// Decompress `data` into `out`
+///
+/// Returns the decompressed length, or `None` if `data` is malformed
+/// or `out` too short.
+pub fn decompress(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut len = 0;
+    while read < data.len() {
+        let flags = data[read];
+        read += 1;
+        for bit in 0..8 {
+            if read >= data.len() {
+                break;
+            }
+            if flags & (1 << bit) != 0 {
+                *out.get_mut(len)? = data[read];
+                len += 1;
+                read += 1;
+                continue;
+            }
+            let (high, low) = (*data.get(read)?, *data.get(read + 1)?);
+            read += 2;
+            let offset = (usize::from(high) << 4 | usize::from(low >> 4)) + 1;
+            let match_len = usize::from(low & 0xF) + MIN_MATCH;
+            if offset > len {
+                return None;
+            }
+            for _ in 0..match_len {
+                *out.get_mut(len)? = out[len - offset];
+                len += 1;
+            }
+        }
+    }
+    Some(len)
+}
+
+/// Header byte plus `data`, compressed if that is shorter
+///
+/// `out` must hold [`max_compressed_len()`](fn.max_compressed_len.html)
+/// plus `HEADER_LEN` bytes to be safe, returns `None` otherwise.
+pub fn pack(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let body = out.get_mut(HEADER_LEN..)?;
+    match compress(data, body) {
+        Some(len) if len < data.len() => {
+            out[0] = FLAG_COMPRESSED;
+            Some(HEADER_LEN + len)
+        }
+        _ => {
+            out.get_mut(HEADER_LEN..HEADER_LEN + data.len())?
+                .copy_from_slice(data);
+            out[0] = 0;
+            Some(HEADER_LEN + data.len())
+        }
+    }
+}
+
+/// Undo [`pack()`](fn.pack.html)
+pub fn unpack(packed: &[u8], out: &mut [u8]) -> Option<usize> {
+    let (&header, body) = packed.split_first()?;
+    match header & FLAG_COMPRESSED {
+        0 => {
+            out.get_mut(0..body.len())?.copy_from_slice(body);
+            Some(body.len())
+        }
+        _ => decompress(body, out),
+    }
+}
+
+/// [`pack()`](fn.pack.html) `data` and send it with
+/// [`framing::send_frame()`](../framing/fn.send_frame.html)
+///
+/// The receiver passes each frame from its
+/// [`Deframer`](../framing/struct.Deframer.html) to
+/// [`unpack()`](fn.unpack.html).
+pub fn send_frame<Ce, Csn, Spi, E, SpiE>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    data: &[u8],
+    packed: &mut [u8],
+    encoded: &mut [u8],
+) -> Result<bool, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    let len = pack(data, packed).expect("packing buffer too short");
+    framing::send_frame(radio, &packed[0..len], encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> usize {
+        let mut compressed = [0; 6000];
+        let len = compress(data, &mut compressed).unwrap();
+        assert!(len <= max_compressed_len(data.len()));
+        let mut decompressed = [0; 6000];
+        let decompressed_len = decompress(&compressed[0..len], &mut decompressed).unwrap();
+        assert_eq!(&decompressed[0..decompressed_len], data);
+        len
+    }
+
+    /// Bytes without repeats worth a reference
+    fn noise(data: &mut [u8]) {
+        let mut state = 0x1234_5678u32;
+        for byte in data.iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *byte = (state >> 24) as u8;
+        }
+    }
+
+    #[test]
+    fn known_vectors() {
+        let vectors: [(&[u8], &[u8]); 4] = [
+            (b"", b""),
+            (b"abc", b"\x07abc"),
+            (b"aaaaaa", b"\x01a\x00\x02"),
+            (b"\0\0\0\0", b"\x01\0\x00\x00"),
+        ];
+        for (data, expected) in vectors.iter() {
+            let mut compressed = [0; 16];
+            let len = compress(data, &mut compressed).unwrap();
+            assert_eq!(&compressed[0..len], *expected);
+            round_trip(data);
+        }
+    }
+
+    #[test]
+    fn text_shrinks() {
+        let text = b"temperature=21.5;humidity=40;temperature=21.6;humidity=41;";
+        assert!(round_trip(text) < text.len());
+    }
+
+    #[test]
+    fn input_longer_than_the_window() {
+        let mut data = [0; 5000];
+        noise(&mut data[0..100]);
+        for i in 100..data.len() {
+            data[i] = data[i % 100];
+        }
+        assert!(round_trip(&data) < 1000);
+    }
+
+    #[test]
+    fn incompressible_data() {
+        let mut data = [0; 1000];
+        noise(&mut data);
+        round_trip(&data);
+        let mut packed = [0; HEADER_LEN + max_compressed_len(1000)];
+        let len = pack(&data, &mut packed).unwrap();
+        assert_eq!(packed[0], 0);
+        assert_eq!(len, HEADER_LEN + data.len());
+        let mut unpacked = [0; 1000];
+        assert_eq!(unpack(&packed[0..len], &mut unpacked), Some(data.len()));
+        assert_eq!(unpacked, data);
+    }
+
+    #[test]
+    fn max_compressed_len_is_exact() {
+        let mut data = [0; 256];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut compressed = [0; max_compressed_len(256)];
+        assert_eq!(compress(&data, &mut compressed), Some(compressed.len()));
+        assert_eq!(compress(&data, &mut compressed[1..]), None);
+    }
+
+    #[test]
+    fn pack_compresses_when_shorter() {
+        let data = [b'x'; 100];
+        let mut packed = [0; 128];
+        let len = pack(&data, &mut packed).unwrap();
+        assert_eq!(packed[0], FLAG_COMPRESSED);
+        assert!(len < data.len());
+        let mut unpacked = [0; 100];
+        assert_eq!(unpack(&packed[0..len], &mut unpacked), Some(100));
+        assert_eq!(unpacked, data);
+        assert_eq!(unpack(&packed[0..len], &mut unpacked[0..99]), None);
+    }
+
+    #[test]
+    fn malformed_input() {
+        let mut out = [0; 16];
+        // Reference before the start
+        assert_eq!(decompress(b"\x00\x00\x00", &mut out), None);
+        // Truncated reference
+        assert_eq!(decompress(b"\x01a\x00", &mut out), None);
+        assert_eq!(unpack(b"", &mut out), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::channels::Channel;
 use crate::command::{FlushRx, FlushTx, Nop};
 use crate::device::Device;
 use crate::registers::{
@@ -16,12 +17,187 @@ pub enum DataRate {
     R2Mbps,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for DataRate {
     fn default() -> DataRate {
         DataRate::R1Mbps
     }
 }
 
+/// RF output power in TX mode
+///
+/// The levels above 0 dBm, -4 dBm and -18 dBm exist on only some chips,
+/// see [`level()`](#method.level).
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum OutputPower {
+    /// nRF24L01(+) only
+    Neg18dBm,
+    Neg12dBm,
+    Neg6dBm,
+    /// SI24R1 only
+    Neg4dBm,
+    ZeroDbm,
+    /// SI24R1 only
+    Pos1dBm,
+    /// SI24R1 only
+    Pos3dBm,
+    /// SI24R1 only
+    Pos4dBm,
+    /// SI24R1 only
+    Pos7dBm,
+}
+
+impl OutputPower {
+    /// Power in dBm
+    pub fn dbm(&self) -> i8 {
+        match *self {
+            OutputPower::Neg18dBm => -18,
+            OutputPower::Neg12dBm => -12,
+            OutputPower::Neg6dBm => -6,
+            OutputPower::Neg4dBm => -4,
+            OutputPower::ZeroDbm => 0,
+            OutputPower::Pos1dBm => 1,
+            OutputPower::Pos3dBm => 3,
+            OutputPower::Pos4dBm => 4,
+            OutputPower::Pos7dBm => 7,
+        }
+    }
+
+    /// Raw level for [`set_rf()`](trait.Configuration.html#method.set_rf),
+    /// `None` if `variant` does not support this power
+    pub fn level(&self, variant: ChipVariant) -> Option<u8> {
+        let levels = Self::levels(variant);
+        levels
+            .iter()
+            .position(|power| power == self)
+            .map(|level| level as u8)
+    }
+
+    /// Inverse of [`level()`](#method.level)
+    pub fn from_level(variant: ChipVariant, level: u8) -> Option<Self> {
+        Self::levels(variant).get(usize::from(level)).copied()
+    }
+
+    fn levels(variant: ChipVariant) -> &'static [OutputPower] {
+        match variant {
+            ChipVariant::Si24r1 => &[
+                OutputPower::Neg12dBm,
+                OutputPower::Neg6dBm,
+                OutputPower::Neg4dBm,
+                OutputPower::ZeroDbm,
+                OutputPower::Pos1dBm,
+                OutputPower::Pos3dBm,
+                OutputPower::Pos4dBm,
+                OutputPower::Pos7dBm,
+            ],
+            _ => &[
+                OutputPower::Neg18dBm,
+                OutputPower::Neg12dBm,
+                OutputPower::Neg6dBm,
+                OutputPower::ZeroDbm,
+            ],
+        }
+    }
+}
+
+impl core::convert::TryFrom<u8> for OutputPower {
+    type Error = u8;
+
+    /// Raw nRF24L01(+) level, as formerly passed to `set_rf()`
+    fn try_from(level: u8) -> Result<Self, u8> {
+        OutputPower::from_level(ChipVariant::Plus, level).ok_or(level)
+    }
+}
+
+impl From<OutputPower> for u8 {
+    /// Raw nRF24L01(+) level, panics for SI24R1 only powers
+    fn from(power: OutputPower) -> u8 {
+        power
+            .level(ChipVariant::Plus)
+            .expect("not supported by the nRF24L01")
+    }
+}
+
+/// RX data pipe
+#[derive(Debug, PartialEq, Eq, Copy, Clone, PartialOrd, Ord, Hash)]
+pub enum Pipe {
+    P0,
+    P1,
+    P2,
+    P3,
+    P4,
+    P5,
+}
+
+impl Pipe {
+    pub const ALL: [Pipe; PIPES_COUNT] =
+        [Pipe::P0, Pipe::P1, Pipe::P2, Pipe::P3, Pipe::P4, Pipe::P5];
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+}
+
+impl From<Pipe> for u8 {
+    fn from(pipe: Pipe) -> u8 {
+        pipe as u8
+    }
+}
+
+impl From<Pipe> for usize {
+    fn from(pipe: Pipe) -> usize {
+        pipe.index()
+    }
+}
+
+impl core::convert::TryFrom<u8> for Pipe {
+    type Error = u8;
+
+    fn try_from(pipe: u8) -> Result<Self, u8> {
+        Pipe::from_index(pipe.into()).ok_or(pipe)
+    }
+}
+
+/// Chip generations and clones that need different handling
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ChipVariant {
+    /// nRF24L01+
+    #[default]
+    Plus,
+    /// The original nRF24L01: no 250 Kbps, `FEATURE`/`DYNPD` locked
+    /// until `ACTIVATE`, and carrier detect instead of RPD
+    NonPlus,
+    /// SI24R1 clone: eight power levels from -12 dBm to 7 dBm using
+    /// all three low bits of `RF_SETUP`
+    Si24r1,
+}
+
+impl ChipVariant {
+    pub fn supports_data_rate(&self, rate: DataRate) -> bool {
+        !(*self == ChipVariant::NonPlus && rate == DataRate::R250Kbps)
+    }
+
+    /// Number of output power levels accepted by [`set_rf()`](trait.Configuration.html#method.set_rf)
+    pub fn power_levels(&self) -> u8 {
+        match *self {
+            ChipVariant::Si24r1 => 8,
+            _ => 4,
+        }
+    }
+
+    /// Time in RX mode before the `CD`/`RPD` register is valid
+    pub fn carrier_detect_delay_us(&self) -> u32 {
+        match *self {
+            ChipVariant::Plus | ChipVariant::Si24r1 => 170,
+            ChipVariant::NonPlus => 258,
+        }
+    }
+}
+
 /// Supported CRC modes
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum CrcMode {
@@ -75,6 +251,22 @@ pub trait Configuration {
         Ok(freq_offset)
     }
 
+    /// Get the RF channel, `None` if `RF_CH` holds 126 or 127
+    fn get_channel(
+        &mut self,
+    ) -> Result<Option<Channel>, <<Self as Configuration>::Inner as Device>::Error> {
+        let freq_offset = self.get_frequency()?;
+        Ok(Channel::try_new(freq_offset))
+    }
+
+    /// Set the RF channel
+    fn set_channel(
+        &mut self,
+        channel: Channel,
+    ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
+        self.set_frequency(channel.number())
+    }
+
     /// Set frequency offset (channel)
     fn set_frequency(
         &mut self,
@@ -90,14 +282,26 @@ pub trait Configuration {
     }
 
     /// power: `0`: -18 dBm, `3`: 0 dBm
+    ///
+    /// On the SI24R1 power ranges from `0`: -12 dBm to `7`: 7 dBm.
+    /// [`OutputPower`](enum.OutputPower.html) converts between the two.
     fn set_rf(
         &mut self,
         rate: &DataRate,
         power: u8,
     ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
-        assert!(power < 0b100);
+        let variant = self.device().chip_variant();
+        assert!(power < variant.power_levels());
         let mut register = RfSetup(0);
-        register.set_rf_pwr(power);
+        match variant {
+            ChipVariant::Plus => register.set_rf_pwr(power),
+            // Keep the LNA gain at its reset value
+            ChipVariant::NonPlus => {
+                register.set_rf_pwr(power);
+                register.set_lna_hcurr(true);
+            }
+            ChipVariant::Si24r1 => register.set_si24r1_pwr(power),
+        }
 
         let (dr_low, dr_high) = match *rate {
             DataRate::R250Kbps => (true, false),
@@ -111,6 +315,72 @@ pub trait Configuration {
         Ok(())
     }
 
+    /// Set the output power, keeping the air data rate
+    ///
+    /// Panics if the chip variant does not support `power`.
+    fn set_output_power(
+        &mut self,
+        power: OutputPower,
+    ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
+        let rate = self.get_data_rate()?;
+        let variant = self.device().chip_variant();
+        let level = power.level(variant).expect("unsupported output power");
+        self.set_rf(&rate, level)
+    }
+
+    /// Get the output power
+    fn get_output_power(
+        &mut self,
+    ) -> Result<OutputPower, <<Self as Configuration>::Inner as Device>::Error> {
+        let variant = self.device().chip_variant();
+        let (_, register) = self.device().read_register::<RfSetup>()?;
+        let level = match variant {
+            ChipVariant::Si24r1 => register.si24r1_pwr(),
+            _ => register.rf_pwr(),
+        };
+        Ok(OutputPower::from_level(variant, level).unwrap())
+    }
+
+    /// Change the air data rate, keeping the output power
+    fn set_data_rate(
+        &mut self,
+        rate: DataRate,
+    ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
+        let (dr_low, dr_high) = match rate {
+            DataRate::R250Kbps => (true, false),
+            DataRate::R1Mbps => (false, false),
+            DataRate::R2Mbps => (false, true),
+        };
+        self.device().update_register::<RfSetup, _, _>(|register| {
+            register.set_rf_dr_low(dr_low);
+            register.set_rf_dr_high(dr_high);
+        })
+    }
+
+    /// Get the air data rate
+    fn get_data_rate(
+        &mut self,
+    ) -> Result<DataRate, <<Self as Configuration>::Inner as Device>::Error> {
+        let (_, register) = self.device().read_register::<RfSetup>()?;
+        let rate = match (register.rf_dr_low(), register.rf_dr_high()) {
+            (true, _) => DataRate::R250Kbps,
+            (false, false) => DataRate::R1Mbps,
+            (false, true) => DataRate::R2Mbps,
+        };
+        Ok(rate)
+    }
+
+    /// Get CRC mode
+    fn get_crc(&mut self) -> Result<CrcMode, <<Self as Configuration>::Inner as Device>::Error> {
+        let (_, register) = self.device().read_register::<Config>()?;
+        let mode = match (register.en_crc(), register.crco()) {
+            (false, _) => CrcMode::Disabled,
+            (true, false) => CrcMode::OneByte,
+            (true, true) => CrcMode::TwoBytes,
+        };
+        Ok(mode)
+    }
+
     /// Set CRC mode
     fn set_crc(
         &mut self,
@@ -146,32 +416,33 @@ pub trait Configuration {
         Ok(())
     }
 
-    /// Set address `addr` of pipe number `pipe_no`
+    /// Set address `addr` of `pipe`
+    ///
+    /// Pipes 2 to 5 only take the least significant byte.
     fn set_rx_addr(
         &mut self,
-        pipe_no: usize,
+        pipe: Pipe,
         addr: &[u8],
     ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
         macro_rules! w {
-            ( $($no: expr, $name: ident);+ ) => (
-                match pipe_no {
+            ( $($no: ident, $name: ident);+ ) => (
+                match pipe {
                     $(
-                        $no => {
+                        Pipe::$no => {
                             use crate::registers::$name;
                             let register = $name::new(addr);
                             self.device().write_register(register)?;
                         }
                     )+
-                        _ => panic!("No such pipe {}", pipe_no)
                 }
             )
         }
-        w!(0, RxAddrP0;
-           1, RxAddrP1;
-           2, RxAddrP2;
-           3, RxAddrP3;
-           4, RxAddrP4;
-           5, RxAddrP5);
+        w!(P0, RxAddrP0;
+           P1, RxAddrP1;
+           P2, RxAddrP2;
+           P3, RxAddrP3;
+           P4, RxAddrP4;
+           P5, RxAddrP5);
         Ok(())
     }
 
@@ -187,7 +458,10 @@ pub trait Configuration {
 
     /// Configure auto-retransmit
     ///
-    /// To disable, call as `set_auto_retransmit(0, 0)`.
+    /// `delay` is the raw `ARD` value, see
+    /// [`timing::retransmit_delay_ard()`](timing/fn.retransmit_delay_ard.html)
+    /// for converting from microseconds. To disable, call as
+    /// `set_auto_retransmit(0, 0)`.
     fn set_auto_retransmit(
         &mut self,
         delay: u8,
@@ -264,6 +538,32 @@ pub trait Configuration {
         Ok(())
     }
 
+    /// Enable or disable payloads in acknowledgement packets
+    ///
+    /// Ack payloads require dynamic payload lengths, which get enabled
+    /// along.
+    fn set_ack_payloads(
+        &mut self,
+        enable: bool,
+    ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
+        self.device().update_register::<Feature, _, _>(|feature| {
+            feature.set_en_ack_pay(enable);
+            if enable {
+                feature.set_en_dpl(true);
+            }
+        })
+    }
+
+    /// Allow sending packets that ask for no acknowledgement, see
+    /// [`send_no_ack()`](struct.Nrf24l01.html#method.send_no_ack)
+    fn set_dyn_ack(
+        &mut self,
+        enable: bool,
+    ) -> Result<(), <<Self as Configuration>::Inner as Device>::Error> {
+        self.device()
+            .update_register::<Feature, _, _>(|feature| feature.set_en_dyn_ack(enable))
+    }
+
     /// ## `bools`
     /// * `None`: Dynamic payload length
     /// * `Some(len)`: Static payload length `len`
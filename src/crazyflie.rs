@@ -0,0 +1,265 @@
+//! Crazyflie CRTP over ESB, as spoken by the Crazyradio
+//!
+//! The ground station is the PTX and polls the Crazyflie; everything
+//! the Crazyflie has to say travels back in ack payloads.
+
+use crate::{Config, Configuration, CrcMode, DataRate, Nrf24l01, Pipe, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Default radio address
+pub const DEFAULT_ADDRESS: [u8; 5] = [0xE7; 5];
+/// Default RF channel
+pub const DEFAULT_CHANNEL: u8 = 80;
+/// Maximum CRTP data length after the header byte
+pub const MAX_DATA_LEN: usize = PAYLOAD_LEN - 1;
+/// Header of the empty packet used to poll for downlink data
+const NULL_HEADER: u8 = 0xFF;
+
+/// CRTP ports
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Port {
+    Console = 0,
+    Param = 2,
+    Commander = 3,
+    Mem = 4,
+    Log = 5,
+    Localization = 6,
+    GenericSetpoint = 7,
+    Platform = 13,
+    LinkCtrl = 15,
+}
+
+impl Port {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(Port::Console),
+            2 => Some(Port::Param),
+            3 => Some(Port::Commander),
+            4 => Some(Port::Mem),
+            5 => Some(Port::Log),
+            6 => Some(Port::Localization),
+            7 => Some(Port::GenericSetpoint),
+            13 => Some(Port::Platform),
+            15 => Some(Port::LinkCtrl),
+            _ => None,
+        }
+    }
+}
+
+/// One CRTP packet
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub port: Port,
+    /// `0..=3`
+    pub channel: u8,
+    data: [u8; MAX_DATA_LEN],
+    len: usize,
+}
+
+impl Packet {
+    pub fn new(port: Port, channel: u8, data: &[u8]) -> Self {
+        assert!(channel < 4);
+        assert!(data.len() <= MAX_DATA_LEN);
+        let mut packet = Packet {
+            port,
+            channel,
+            data: [0; MAX_DATA_LEN],
+            len: data.len(),
+        };
+        packet.data[0..data.len()].copy_from_slice(data);
+        packet
+    }
+
+    /// Legacy commander setpoint: angles in degrees, yaw rate in °/s
+    pub fn setpoint(roll: f32, pitch: f32, yaw_rate: f32, thrust: u16) -> Self {
+        let mut data = [0; 14];
+        data[0..4].copy_from_slice(&roll.to_le_bytes());
+        data[4..8].copy_from_slice(&pitch.to_le_bytes());
+        data[8..12].copy_from_slice(&yaw_rate.to_le_bytes());
+        data[12..14].copy_from_slice(&thrust.to_le_bytes());
+        Packet::new(Port::Commander, 0, &data)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[0..self.len]
+    }
+
+    /// Header byte: port in bits 7..4, link bits 3..2 set, channel in bits 1..0
+    pub fn header(&self) -> u8 {
+        ((self.port as u8) << 4) | 0b1100 | self.channel
+    }
+
+    /// Serialize into `buf`, returning the length
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.header();
+        buf[1..1 + self.len].copy_from_slice(self.data());
+        1 + self.len
+    }
+
+    /// `None` for null packets and unknown ports
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (&header, data) = buf.split_first()?;
+        if header == NULL_HEADER || data.len() > MAX_DATA_LEN {
+            return None;
+        }
+        let port = Port::from_bits(header >> 4)?;
+        Some(Packet::new(port, header & 0b11, data))
+    }
+}
+
+/// Outcome of a packet sent by the [`GroundStation`](struct.GroundStation.html)
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// Not acknowledged
+    Lost,
+    /// Acknowledged without a payload
+    Empty,
+    /// Acknowledged with a downlink packet
+    Packet(Packet),
+}
+
+/// Settings matching the Crazyradio defaults
+pub fn config() -> Config {
+    Config::default()
+        .frequency(DEFAULT_CHANNEL)
+        .data_rate(DataRate::R2Mbps)
+        .crc_mode(CrcMode::TwoBytes)
+        .auto_retransmit_delay(1)
+        .auto_retransmit_count(3)
+}
+
+fn enable_ack_payloads<Ce, Csn, Spi, E, SpiE>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    address: &[u8],
+) -> Result<(), SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    let mut pipes = [false; PIPES_COUNT];
+    pipes[0] = true;
+    nb::block!(radio.wait_tx_empty())?;
+    radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+    radio.set_ack_payloads(true)?;
+    radio.set_auto_ack(&pipes)?;
+    radio.set_pipes_rx_enable(&pipes)?;
+    radio.set_rx_addr(Pipe::P0, address)?;
+    radio.set_tx_addr(address)
+}
+
+/// Crazyradio-style PTX side
+pub struct GroundStation<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> GroundStation<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+    ) -> Result<Self, SpiE> {
+        enable_ack_payloads(radio, address)?;
+        Ok(GroundStation { radio })
+    }
+
+    pub fn send(&mut self, packet: &Packet) -> Result<Reply, SpiE> {
+        let mut buf = [0; PAYLOAD_LEN];
+        let len = packet.encode(&mut buf);
+        self.send_raw(&buf[0..len])
+    }
+
+    /// Send a null packet to fetch pending downlink data
+    pub fn poll(&mut self) -> Result<Reply, SpiE> {
+        self.send_raw(&[NULL_HEADER])
+    }
+
+    fn send_raw(&mut self, buf: &[u8]) -> Result<Reply, SpiE> {
+        nb::block!(self.radio.send(buf))?;
+        if !nb::block!(self.radio.wait_tx_done())? {
+            return Ok(Reply::Lost);
+        }
+        let reply = match self.radio.read_ack_payload()? {
+            Some(payload) => match Packet::decode(&payload) {
+                Some(packet) => Reply::Packet(packet),
+                None => Reply::Empty,
+            },
+            None => Reply::Empty,
+        };
+        Ok(reply)
+    }
+}
+
+/// Crazyflie-style PRX side, answering through ack payloads
+pub struct Peripheral<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Peripheral<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+    ) -> Result<Self, SpiE> {
+        enable_ack_payloads(radio, address)?;
+        // Enter RX mode before ack payloads start occupying the TX FIFO
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        Ok(Peripheral { radio })
+    }
+
+    /// Queue `packet` for the next acknowledgement
+    ///
+    /// Up to three packets fit into the TX FIFO.
+    pub fn queue(&mut self, packet: &Packet) -> Result<(), SpiE> {
+        let mut buf = [0; PAYLOAD_LEN];
+        let len = packet.encode(&mut buf);
+        self.radio.write_ack_payload(Pipe::P0, &buf[0..len])
+    }
+
+    /// Poll for an uplink packet, skipping null packets
+    pub fn receive(&mut self) -> Result<Option<Packet>, SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let payload = nb::block!(self.radio.read())?;
+            if let Some(packet) = Packet::decode(&payload) {
+                return Ok(Some(packet));
+            }
+        }
+    }
+}
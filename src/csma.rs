@@ -0,0 +1,113 @@
+use crate::Clock;
+use core::fmt;
+
+/// Listen-before-talk settings and backoff state for
+/// [`Nrf24l01::set_csma()`](struct.Nrf24l01.html#method.set_csma)
+///
+/// Timing is measured with `clock`, not in calls: after entering RX,
+/// `send()` returns `WouldBlock` until the carrier detect delay
+/// (170 µs, 258 µs on the nRF24L01) has passed, and a busy channel
+/// defers it for a random number of such slots. Polling `send()` in a
+/// busy loop or from a timer both work.
+#[derive(Clone)]
+pub struct Csma {
+    clock: &'static dyn Clock,
+    max_backoff: u16,
+    max_attempts: u8,
+    rng: u32,
+    attempts: u8,
+    /// When listening for carriers started
+    listening_since_us: Option<u64>,
+    backoff_until_us: u64,
+}
+
+impl fmt::Debug for Csma {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Csma")
+            .field("max_backoff", &self.max_backoff)
+            .field("max_attempts", &self.max_attempts)
+            .field("attempts", &self.attempts)
+            .field("backoff_until_us", &self.backoff_until_us)
+            .finish()
+    }
+}
+
+impl Csma {
+    /// `seed` must be non-zero and should differ between nodes
+    pub fn new(seed: u32, clock: &'static dyn Clock) -> Self {
+        assert!(seed != 0);
+        Csma {
+            clock,
+            max_backoff: 64,
+            max_attempts: 8,
+            rng: seed,
+            attempts: 0,
+            listening_since_us: None,
+            backoff_until_us: 0,
+        }
+    }
+
+    /// Upper bound of the backoff window, in carrier detect delays
+    pub fn max_backoff(mut self, slots: u16) -> Self {
+        assert!(slots > 0);
+        self.max_backoff = slots;
+        self
+    }
+
+    /// Transmit anyway after finding the channel busy this many times
+    pub fn max_attempts(mut self, attempts: u8) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    pub(crate) fn now_us(&self) -> u64 {
+        self.clock.now_us()
+    }
+
+    /// Whether a pending backoff has not run out yet
+    pub(crate) fn defer(&self, now_us: u64) -> bool {
+        now_us < self.backoff_until_us
+    }
+
+    /// The radio has just entered RX
+    pub(crate) fn listening(&mut self, now_us: u64) {
+        self.listening_since_us = Some(now_us);
+    }
+
+    /// Whether the radio has listened for `settle_us`, starting the
+    /// count now if it had not been started
+    pub(crate) fn settled(&mut self, now_us: u64, settle_us: u32) -> bool {
+        let since_us = *self.listening_since_us.get_or_insert(now_us);
+        now_us.saturating_sub(since_us) >= u64::from(settle_us)
+    }
+
+    /// Channel found busy, returns whether to back off
+    ///
+    /// The window of `slot_us` slots doubles with every attempt.
+    pub(crate) fn busy(&mut self, now_us: u64, slot_us: u32) -> bool {
+        if self.attempts >= self.max_attempts {
+            self.reset();
+            return false;
+        }
+        self.attempts += 1;
+        let window = (1u32 << self.attempts.min(15)).min(u32::from(self.max_backoff));
+        let slots = self.next_random() % window + 1;
+        self.backoff_until_us = now_us + u64::from(slots) * u64::from(slot_us);
+        true
+    }
+
+    /// Done with this packet, the next one listens afresh
+    pub(crate) fn reset(&mut self) {
+        self.attempts = 0;
+        self.backoff_until_us = 0;
+        self.listening_since_us = None;
+    }
+
+    /// xorshift32
+    fn next_random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+}
@@ -0,0 +1,81 @@
+//! Suppression of duplicate packets
+//!
+//! When an acknowledgement gets lost the transmitter sends the packet
+//! again, and the receiver sees it twice. With a sequence byte in each
+//! packet, a [`Dedup`](struct.Dedup.html) cache on the receiving side
+//! recognizes the repeats so that non-idempotent commands run once.
+//! Senders are told apart by any byte, usually the pipe or a node id.
+
+/// Largest supported cache depth
+pub const MAX_DEPTH: usize = 32;
+
+/// Remembers the last `(sender, seq)` pairs seen
+#[derive(Debug, Clone)]
+pub struct Dedup {
+    entries: [(u8, u8); MAX_DEPTH],
+    depth: usize,
+    len: usize,
+    /// Slot to overwrite next
+    next: usize,
+    duplicates: u32,
+}
+
+impl Dedup {
+    /// Remember up to `depth` packets
+    ///
+    /// Panics unless `depth` is between 1 and
+    /// [`MAX_DEPTH`](constant.MAX_DEPTH.html).
+    pub fn new(depth: usize) -> Self {
+        assert!((1..=MAX_DEPTH).contains(&depth));
+        Dedup {
+            entries: [(0, 0); MAX_DEPTH],
+            depth,
+            len: 0,
+            next: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// Returns `true` for a packet not seen recently, and remembers it
+    pub fn accept(&mut self, sender: u8, seq: u8) -> bool {
+        if self.contains(sender, seq) {
+            self.duplicates = self.duplicates.wrapping_add(1);
+            return false;
+        }
+        self.entries[self.next] = (sender, seq);
+        self.next = (self.next + 1) % self.depth;
+        self.len = (self.len + 1).min(self.depth);
+        true
+    }
+
+    pub fn contains(&self, sender: u8, seq: u8) -> bool {
+        self.entries[0..self.len].contains(&(sender, seq))
+    }
+
+    /// Forget everything `sender` sent, e.g. after it restarted
+    pub fn forget(&mut self, sender: u8) {
+        let entries = self.entries;
+        let start = self.next + self.depth - self.len;
+        let mut kept = 0;
+        // Oldest first, so that the overwrite order is kept
+        for i in 0..self.len {
+            let entry = entries[(start + i) % self.depth];
+            if entry.0 != sender {
+                self.entries[kept] = entry;
+                kept += 1;
+            }
+        }
+        self.len = kept;
+        self.next = kept % self.depth;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    /// Packets rejected as duplicates so far
+    pub fn duplicates(&self) -> u32 {
+        self.duplicates
+    }
+}
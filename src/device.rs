@@ -1,10 +1,29 @@
 use crate::command::{Command, ReadRegister, WriteRegister};
-use crate::registers::{Config, Register, SetupAw, Status};
-use crate::Error;
+use crate::power::{PowerState, PowerTracker};
+use crate::registers::{
+    Config, Dynpd, EnAa, EnRxaddr, Feature, FifoStatus, Register, RfSetup, SetupAw, Status,
+};
+#[cfg(feature = "trace")]
+use crate::trace::{Trace, TraceEvent};
+use crate::{ChipVariant, Clock, CsnTiming, Error, Pipe, RetryPolicy, MAX_ADDR_BYTES};
 use core::fmt::Debug;
-use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::blocking::spi::{Transfer, Write};
 use embedded_hal::digital::v2::OutputPin;
 
+/// Registers that only change when written, kept in
+/// [`DeviceImpl`](struct.DeviceImpl.html) to save the read of a
+/// read-modify-write
+fn cache_slot(addr: u8) -> Option<usize> {
+    [
+        EnAa::addr(),
+        EnRxaddr::addr(),
+        Dynpd::addr(),
+        Feature::addr(),
+    ]
+    .iter()
+    .position(|&cached| cached == addr)
+}
+
 pub struct DeviceImpl<
     Ce: OutputPin<Error = E>,
     Csn: OutputPin<Error = E>,
@@ -15,6 +34,27 @@ pub struct DeviceImpl<
     csn: Csn,
     spi: Spi,
     config: Config,
+    cache: [Option<u8>; 4],
+    chip_variant: ChipVariant,
+    last_status: Status,
+    rx_status_valid: bool,
+    tx_status_valid: bool,
+    /// Packets queued since the TX FIFO was last known to be empty
+    tx_queued: u8,
+    ce_high: bool,
+    ce_tied_high: bool,
+    power: Option<PowerTracker>,
+    verify_writes: bool,
+    /// First mismatch seen by `verify_writes`: register, written, read
+    verification_failure: Option<(u8, u8, u8)>,
+    csn_timing: Option<CsnTiming>,
+    retry: Option<RetryPolicy<Spi::Error>>,
+    /// Transactions repeated under `retry`
+    spi_retries: u32,
+    /// Attempts used up by the last failed transaction, if `retry` gave up
+    exhausted_attempts: Option<u8>,
+    #[cfg(feature = "trace")]
+    trace: Option<&'static dyn Trace>,
 }
 
 impl<
@@ -26,7 +66,15 @@ impl<
     > DeviceImpl<Ce, Csn, Spi, E>
 {
     /// Construct a new driver instance.
-    pub fn new(mut ce: Ce, mut csn: Csn, spi: Spi) -> Result<Self, Error<SpiE>> {
+    ///
+    /// `csn_timing` already applies to the connection check.
+    pub fn new(
+        mut ce: Ce,
+        mut csn: Csn,
+        spi: Spi,
+        chip_variant: ChipVariant,
+        csn_timing: Option<CsnTiming>,
+    ) -> Result<Self, Error<SpiE>> {
         ce.set_low().unwrap();
         csn.set_high().unwrap();
 
@@ -40,6 +88,23 @@ impl<
             csn,
             spi,
             config,
+            cache: [None; 4],
+            chip_variant,
+            last_status: Status(0),
+            rx_status_valid: false,
+            tx_status_valid: false,
+            tx_queued: 0,
+            ce_high: false,
+            ce_tied_high: false,
+            power: None,
+            verify_writes: false,
+            verification_failure: None,
+            csn_timing,
+            retry: None,
+            spi_retries: 0,
+            exhausted_attempts: None,
+            #[cfg(feature = "trace")]
+            trace: None,
         };
 
         match device.is_connected()? {
@@ -48,14 +113,278 @@ impl<
         }
     }
 
+    pub fn release(self) -> (Ce, Csn, Spi) {
+        (self.ce, self.csn, self.spi)
+    }
+
+    /// CE is hard-wired high, the chip is active whenever `PWR_UP` is set
+    pub fn set_ce_tied_high(&mut self) {
+        self.ce_tied_high = true;
+        self.ce_high = true;
+        self.update_power_state();
+    }
+
+    pub fn ce_tied_high(&self) -> bool {
+        self.ce_tied_high
+    }
+
+    pub fn set_csn_timing(&mut self, csn_timing: Option<CsnTiming>) {
+        self.csn_timing = csn_timing;
+    }
+
+    pub fn set_retry(&mut self, retry: Option<RetryPolicy<SpiE>>) {
+        self.retry = retry;
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn set_trace(&mut self, trace: Option<&'static dyn Trace>) {
+        self.trace = trace;
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn trace(&self, event: TraceEvent) {
+        if let Some(trace) = self.trace {
+            trace.event(event);
+        }
+    }
+
+    /// Transactions repeated after a transient error so far
+    pub fn spi_retries(&self) -> u32 {
+        self.spi_retries
+    }
+
+    /// Attempts the retry policy used up before the last SPI error was
+    /// returned, `None` if it was not retried to the end
+    pub fn take_exhausted_attempts(&mut self) -> Option<u8> {
+        self.exhausted_attempts.take()
+    }
+
+    /// Read back every register written, see
+    /// [`take_verification_failure()`](#method.take_verification_failure)
+    ///
+    /// Doubles the SPI traffic of register writes. Returns the previous
+    /// setting.
+    pub fn set_verify_writes(&mut self, verify_writes: bool) -> bool {
+        core::mem::replace(&mut self.verify_writes, verify_writes)
+    }
+
+    /// The first write that did not read back as written since the last
+    /// call, as `Error::VerificationFailed`
+    pub fn take_verification_failure(&mut self) -> Option<Error<SpiE>> {
+        self.verification_failure
+            .take()
+            .map(|(reg, wrote, read)| Error::VerificationFailed { reg, wrote, read })
+    }
+
+    /// Write `CONFIG` and the cached registers again, power cycling
+    /// through `PWR_UP`
+    pub fn rewrite_registers(&mut self) -> Result<(), SpiE> {
+        self.rewrite_cached::<EnAa>()?;
+        self.rewrite_cached::<EnRxaddr>()?;
+        self.rewrite_cached::<Feature>()?;
+        self.rewrite_cached::<Dynpd>()?;
+        let mut config = self.config.clone();
+        config.set_pwr_up(false);
+        self.write_register(config)?;
+        let config = self.config.clone();
+        self.write_register(config)?;
+        Ok(())
+    }
+
+    fn rewrite_cached<R: Register>(&mut self) -> Result<(), SpiE> {
+        let cached = cache_slot(R::addr()).and_then(|slot| self.cache[slot]);
+        if let Some(value) = cached {
+            self.write_register(R::decode(&[value]))?;
+        }
+        Ok(())
+    }
+
+    /// Send a command through a caller-owned buffer
+    ///
+    /// The response is left in `buf[0..command.len()]`.
+    pub fn send_command_in<C: Command>(
+        &mut self,
+        command: &C,
+        buf: &mut [u8],
+    ) -> Result<Status, SpiE> {
+        let buf = &mut buf[0..command.len()];
+        // FIFO accesses may have happened before the error
+        let repeatable = !C::pops_rx() && !C::pushes_tx();
+        let mut attempt = 1;
+        loop {
+            // Serialize the command, again after a failed transfer
+            command.encode(buf);
+
+            // Spi transaction
+            self.select();
+            let transfer_result = self.spi.transfer(buf).map(|_| {});
+            self.deselect();
+            // Propagate Err only after csn.set_high():
+            match transfer_result {
+                Ok(()) => break,
+                Err(e) => match self.retry {
+                    Some(retry) if repeatable && retry.retry(attempt, &e) => {
+                        attempt += 1;
+                        self.spi_retries = self.spi_retries.wrapping_add(1);
+                        trace!(self, TraceEvent::SpiRetry { attempt });
+                    }
+                    retry => {
+                        self.exhausted_attempts = retry
+                            .filter(|retry| repeatable && retry.exhausted(attempt, &e))
+                            .map(|_| attempt);
+                        return Err(e);
+                    }
+                },
+            }
+        }
+
+        let status = Status(buf[0]);
+        self.last_status = status.clone();
+        self.rx_status_valid = !C::pops_rx();
+        self.tx_status_valid = !C::pushes_tx();
+        self.track_tx_queue::<C>();
+        Ok(status)
+    }
+
+    fn track_tx_queue<C: Command>(&mut self) {
+        if C::flushes_tx() {
+            self.tx_queued = 0;
+        } else if C::pushes_tx() {
+            self.tx_queued = self.tx_queued.saturating_add(1);
+        }
+    }
+
+    /// Packets queued since the TX FIFO was last known to be empty
+    pub fn tx_queued(&self) -> u8 {
+        self.tx_queued
+    }
+
+    /// Packets in the TX FIFO as far as `FIFO_STATUS` and the packets
+    /// queued since it was last empty tell
+    ///
+    /// Packets only leave on their own, so this may be one too high,
+    /// but never too low.
+    pub fn tx_fifo_count(&mut self) -> Result<u8, SpiE> {
+        let (_, fifo_status) = self.read_register::<FifoStatus>()?;
+        self.tx_queued = if fifo_status.tx_empty() {
+            0
+        } else if fifo_status.tx_full() {
+            3
+        } else {
+            self.tx_queued.clamp(1, 2)
+        };
+        Ok(self.tx_queued)
+    }
+
+    /// Pull CSN low, allowing for the setup time
+    fn select(&mut self) {
+        self.csn.set_low().unwrap();
+        if let Some(timing) = self.csn_timing {
+            timing.setup();
+        }
+    }
+
+    /// Raise CSN, allowing for the hold time on both sides
+    fn deselect(&mut self) {
+        if let Some(timing) = self.csn_timing {
+            timing.hold();
+        }
+        self.csn.set_high().unwrap();
+        if let Some(timing) = self.csn_timing {
+            timing.hold();
+        }
+    }
+
+    /// `CONFIG` as the driver last wrote it
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Forget the cached registers so that they are read from the chip again
+    pub fn invalidate_cache(&mut self) {
+        self.cache = [None; 4];
+    }
+
+    /// Switch quirks after the chip has been identified
+    pub fn set_chip_variant(&mut self, chip_variant: ChipVariant) {
+        self.chip_variant = chip_variant;
+    }
+
+    /// Start estimating the charge drawn, see [`PowerTracker`]
+    pub fn track_power(&mut self, clock: &'static dyn Clock) -> Result<(), SpiE> {
+        let (_, rf_setup) = self.read_register::<RfSetup>()?;
+        self.power = Some(PowerTracker::new(clock, self.power_state(), &rf_setup));
+        Ok(())
+    }
+
+    /// Charge drawn since [`track_power()`](#method.track_power), in pC
+    pub fn charge_pc(&self) -> Option<u64> {
+        self.power.as_ref().map(PowerTracker::charge_pc)
+    }
+
+    pub fn power_state(&self) -> PowerState {
+        match (self.config.pwr_up(), self.ce_high, self.config.prim_rx()) {
+            (false, _, _) => PowerState::PowerDown,
+            (true, false, _) => PowerState::Standby,
+            (true, true, false) => PowerState::Tx,
+            (true, true, true) => PowerState::Rx,
+        }
+    }
+
+    fn update_power_state(&mut self) {
+        let state = self.power_state();
+        if let Some(ref mut power) = self.power {
+            power.transition(state);
+        }
+    }
+
     /// Reads and validates content of the `SETUP_AW` register.
+    ///
+    /// Clones may return junk in the reserved bits, only the address
+    /// width is checked for them.
     pub fn is_connected(&mut self) -> Result<bool, SpiE> {
         let (_, setup_aw) = self.read_register::<SetupAw>()?;
-        let valid = setup_aw.aw() <= 3;
+        let valid = match self.chip_variant {
+            ChipVariant::Si24r1 => setup_aw.0 != 0xFF && setup_aw.aw() != 0,
+            _ => setup_aw.0 <= 3 && setup_aw.aw() != 0,
+        };
         Ok(valid)
     }
 }
 
+impl<
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    > DeviceImpl<Ce, Csn, Spi, E>
+{
+    /// Send a command without reading back `STATUS` or a response
+    ///
+    /// Lets the HAL skip MISO, and e.g. hand the transfer to DMA.
+    pub fn write_command<C: Command<Response = ()>>(&mut self, command: &C) -> Result<(), SpiE> {
+        let mut buf_storage = [0; 33];
+        let len = command.len();
+        let buf = &mut buf_storage[0..len];
+        command.encode(buf);
+
+        self.select();
+        let write_result = self.spi.write(buf);
+        self.deselect();
+        write_result?;
+
+        if C::pops_rx() {
+            self.rx_status_valid = false;
+        }
+        if C::pushes_tx() {
+            self.tx_status_valid = false;
+        }
+        self.track_tx_queue::<C>();
+        Ok(())
+    }
+}
+
 impl<
         Ce: OutputPin<Error = E>,
         Csn: OutputPin<Error = E>,
@@ -66,12 +395,26 @@ impl<
 {
     type Error = SpiE;
 
+    fn chip_variant(&self) -> ChipVariant {
+        self.chip_variant
+    }
+
     fn ce_enable(&mut self) {
+        if self.ce_tied_high {
+            return;
+        }
         self.ce.set_high().unwrap();
+        self.ce_high = true;
+        self.update_power_state();
     }
 
     fn ce_disable(&mut self) {
+        if self.ce_tied_high {
+            return;
+        }
         self.ce.set_low().unwrap();
+        self.ce_high = false;
+        self.update_power_state();
     }
 
     fn send_command<C: Command>(
@@ -82,30 +425,81 @@ impl<
         let mut buf_storage = [0; 33];
         let len = command.len();
         let buf = &mut buf_storage[0..len];
-        // Serialize the command
-        command.encode(buf);
-
-        // Spi transaction
-        self.csn.set_low().unwrap();
-        let transfer_result = self.spi.transfer(buf).map(|_| {});
-        self.csn.set_high().unwrap();
-        // Propagate Err only after csn.set_high():
-        transfer_result?;
+        let status = self.send_command_in(command, buf)?;
 
         // Parse response
-        let status = Status(buf[0]);
         let response = C::decode_response(buf);
-
         Ok((status, response))
     }
 
     fn write_register<R: Register>(&mut self, register: R) -> Result<Status, Self::Error> {
+        if R::addr() == RfSetup::addr() {
+            if let Some(ref mut power) = self.power {
+                let mut rf_setup = [0];
+                register.encode(&mut rf_setup);
+                power.set_rf(&RfSetup::decode(&rf_setup));
+            }
+        }
+        let mut written = [0; MAX_ADDR_BYTES];
+        let len = register.write_len();
+        register.encode(&mut written[0..len]);
         let (status, ()) = self.send_command(&WriteRegister::new(register))?;
+        if R::addr() != Status::addr() {
+            trace!(
+                self,
+                TraceEvent::RegisterWrite {
+                    reg: R::addr(),
+                    value: written[0],
+                }
+            );
+        }
+        if let Some(slot) = cache_slot(R::addr()) {
+            self.cache[slot] = Some(written[0]);
+        }
+        // `STATUS` bits are cleared by writing 1
+        if self.verify_writes && R::addr() != Status::addr() {
+            let (_, readback) = self.send_command(&ReadRegister::<R>::new())?;
+            let mut read = [0; MAX_ADDR_BYTES];
+            readback.encode(&mut read[0..R::read_len()]);
+            let mismatch = written[0..len]
+                .iter()
+                .zip(read.iter())
+                .find(|(wrote, read)| wrote != read);
+            if let Some((&wrote, &read)) = mismatch {
+                self.verification_failure
+                    .get_or_insert((R::addr(), wrote, read));
+            }
+        }
         Ok(status)
     }
 
     fn read_register<R: Register>(&mut self) -> Result<(Status, R), Self::Error> {
-        self.send_command(&ReadRegister::new())
+        let slot = cache_slot(R::addr());
+        if let Some(value) = slot.and_then(|slot| self.cache[slot]) {
+            return Ok((self.last_status.clone(), R::decode(&[value])));
+        }
+        let (status, register) = self.send_command(&ReadRegister::<R>::new())?;
+        if let Some(slot) = slot {
+            let mut cached = [0];
+            register.encode(&mut cached);
+            self.cache[slot] = Some(cached[0]);
+        }
+        Ok((status, register))
+    }
+
+    fn last_status(&self) -> Status {
+        self.last_status.clone()
+    }
+
+    fn pending_rx_pipe(&self) -> Option<Pipe> {
+        match self.rx_status_valid {
+            true => self.last_status.rx_p_no(),
+            false => None,
+        }
+    }
+
+    fn tx_ready(&self) -> bool {
+        self.tx_status_valid && !self.last_status.tx_full() && !self.last_status.max_rt()
     }
 
     fn update_config<F, R>(&mut self, f: F) -> Result<R, Self::Error>
@@ -119,6 +513,7 @@ impl<
         if self.config != old_config {
             let config = self.config.clone();
             self.write_register(config)?;
+            self.update_power_state();
         }
         Ok(result)
     }
@@ -130,6 +525,9 @@ pub trait Device {
     /// Error from the Spi implementation
     type Error;
 
+    /// Chip variant given in the configuration
+    fn chip_variant(&self) -> ChipVariant;
+
     /// Set Ce pin high
     fn ce_enable(&mut self);
     /// Set Ce pin low
@@ -154,8 +552,30 @@ pub trait Device {
     /// Send `W_REGISTER` command
     fn write_register<R: Register>(&mut self, register: R) -> Result<Status, Self::Error>;
     /// Send `R_REGISTER` command
+    ///
+    /// `EN_AA`, `EN_RXADDR`, `DYNPD` and `FEATURE` come from a cache
+    /// once read or written, along with the last `STATUS`.
     fn read_register<R: Register>(&mut self) -> Result<(Status, R), Self::Error>;
 
+    /// Send `R_REGISTER` command, bypassing the cache
+    fn read_register_uncached<R: Register>(&mut self) -> Result<(Status, R), Self::Error> {
+        self.send_command(&ReadRegister::new())
+    }
+
+    /// `STATUS` as sent along with the last command
+    fn last_status(&self) -> Status;
+    /// Pipe of the packet at the head of the RX FIFO, if the last
+    /// `STATUS` showed one and nothing has been read out since
+    ///
+    /// Packets only ever arrive on their own, so this is still true.
+    fn pending_rx_pipe(&self) -> Option<Pipe>;
+    /// The last `STATUS` showed room in the TX FIFO and no `MAX_RT`,
+    /// and nothing has been queued since
+    ///
+    /// Only the chip sending packets can change the first, so there
+    /// still is room.
+    fn tx_ready(&self) -> bool;
+
     /// Read, and modify a register, and write it back if it has been changed.
     fn update_register<Reg, F, R>(&mut self, f: F) -> Result<R, Self::Error>
     where
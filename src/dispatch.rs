@@ -0,0 +1,96 @@
+//! Routing received packets to a handler per pipe
+//!
+//! A multi-peer hub registers one [`Handler`](trait.Handler.html) for
+//! each pipe it listens on, then calls
+//! [`Dispatcher::poll()`](struct.Dispatcher.html#method.poll) from its
+//! main loop. Closures taking `(Pipe, &[u8])` are handlers, too.
+
+use crate::{Nrf24l01, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Receives the packets of one or more pipes
+pub trait Handler {
+    fn handle(&mut self, pipe: Pipe, packet: &[u8]);
+}
+
+impl<F: FnMut(Pipe, &[u8])> Handler for F {
+    fn handle(&mut self, pipe: Pipe, packet: &[u8]) {
+        self(pipe, packet)
+    }
+}
+
+/// Reads all pending packets and hands each to the handler of its pipe
+pub struct Dispatcher<'a, 'h, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    handlers: [Option<&'h mut dyn Handler>; PIPES_COUNT],
+    unhandled: u32,
+}
+
+impl<'a, 'h, Ce, Csn, Spi, E, SpiE> Dispatcher<'a, 'h, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Starts without handlers, the pipes themselves are set up on `radio`
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> Self {
+        Dispatcher {
+            radio,
+            handlers: [None, None, None, None, None, None],
+            unhandled: 0,
+        }
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Route the packets of `pipe` to `handler`, returning the previous one
+    pub fn register(
+        &mut self,
+        pipe: Pipe,
+        handler: &'h mut dyn Handler,
+    ) -> Option<&'h mut dyn Handler> {
+        self.handlers[pipe.index()].replace(handler)
+    }
+
+    pub fn unregister(&mut self, pipe: Pipe) -> Option<&'h mut dyn Handler> {
+        self.handlers[pipe.index()].take()
+    }
+
+    /// Packets dropped for lack of a handler
+    pub fn unhandled(&self) -> u32 {
+        self.unhandled
+    }
+
+    /// Drain the RX FIFO, returning the number of packets handled
+    pub fn poll(&mut self) -> Result<usize, SpiE> {
+        let mut handled = 0;
+        loop {
+            let pipe = match self.radio.wait_rx_ready() {
+                Ok(pipe) => pipe,
+                Err(nb::Error::WouldBlock) => return Ok(handled),
+                Err(nb::Error::Other(e)) => return Err(e),
+            };
+            let packet = nb::block!(self.radio.read())?;
+            match self.handlers[pipe.index()] {
+                Some(ref mut handler) => {
+                    handler.handle(pipe, &packet);
+                    handled += 1;
+                }
+                None => self.unhandled = self.unhandled.wrapping_add(1),
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Antenna diversity with two modules
+//!
+//! Two radios with antennas placed or oriented differently rarely fade
+//! at the same time. A [`Diversity`](struct.Diversity.html) sends from
+//! whichever has the better
+//! [`link_quality()`](../struct.Nrf24l01.html#method.link_quality),
+//! retrying a lost packet on the other, and receives on both,
+//! dropping the second copy of every packet with a
+//! [`Dedup`](../dedup/struct.Dedup.html) cache. Packets must carry a
+//! sequence number in their first byte for that.
+//!
+//! Both radios have to be set up with the same channel, data rate and
+//! addresses beforehand. Both acknowledge every packet they receive;
+//! the two acks go out at nearly the same time and overlap on the air.
+//! The radios have the same type, so use type-erased pins for CE and
+//! CSN and a shared-bus SPI proxy.
+
+use crate::dedup::Dedup;
+use crate::{Nrf24l01, Payload, Pipe};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Better score by which the other radio has to lead to take over
+const HYSTERESIS: u8 = 32;
+/// Score assumed for a radio that has not sent anything yet
+const UNKNOWN_QUALITY: u8 = 128;
+
+/// Two radios acting as one
+pub struct Diversity<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radios: [&'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>; 2],
+    /// Index of the radio sending next
+    active: usize,
+    dedup: Dedup,
+    /// Where the next receive poll starts, alternating for fairness
+    next_rx: usize,
+    received: [u32; 2],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Diversity<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Combine two identically configured radios, recognizing
+    /// duplicates among the last `dedup_depth` packets
+    pub fn new(
+        first: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        second: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        dedup_depth: usize,
+    ) -> Self {
+        Diversity {
+            radios: [first, second],
+            active: 0,
+            dedup: Dedup::new(dedup_depth),
+            next_rx: 0,
+            received: [0; 2],
+        }
+    }
+
+    pub fn radio(&mut self, index: usize) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radios[index]
+    }
+
+    /// Index of the radio that sends next
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Packets each radio delivered first
+    pub fn received(&self) -> [u32; 2] {
+        self.received
+    }
+
+    /// Duplicates dropped so far
+    pub fn duplicates(&self) -> u32 {
+        self.dedup.duplicates()
+    }
+
+    /// Send `packet` and wait for the outcome
+    ///
+    /// A packet the active radio could not deliver is sent again from
+    /// the other one. Returns whether either got it through.
+    pub fn send(&mut self, packet: &[u8]) -> Result<bool, SpiE> {
+        let first = self.active;
+        let mut acked = false;
+        for index in [first, 1 - first] {
+            let radio = &mut *self.radios[index];
+            nb::block!(radio.send(packet))?;
+            if nb::block!(radio.wait_tx_done())? {
+                acked = true;
+                break;
+            }
+        }
+        self.select();
+        Ok(acked)
+    }
+
+    /// Poll both radios for a packet not seen before
+    pub fn receive(&mut self) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        for offset in 0..2 {
+            let index = (self.next_rx + offset) % 2;
+            while let Some((pipe, packet)) = Self::poll_radio(self.radios[index])? {
+                let fresh = match packet.first() {
+                    Some(&seq) => self.dedup.accept(pipe.index() as u8, seq),
+                    None => true,
+                };
+                if fresh {
+                    self.received[index] = self.received[index].wrapping_add(1);
+                    self.next_rx = 1 - index;
+                    return Ok(Some((pipe, packet)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn poll_radio(
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    ) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        let pipe = match radio.wait_rx_ready() {
+            Ok(pipe) => pipe,
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        };
+        Ok(Some((pipe, nb::block!(radio.read())?)))
+    }
+
+    /// Switch to the other radio if it clearly does better
+    fn select(&mut self) {
+        let quality = |radio: &Nrf24l01<Ce, Csn, Spi, E, SpiE>| {
+            radio.link_quality().unwrap_or(UNKNOWN_QUALITY)
+        };
+        let active = quality(self.radios[self.active]);
+        let other = quality(self.radios[1 - self.active]);
+        if other > active.saturating_add(HYSTERESIS) || (other > active && active == 0) {
+            self.active = 1 - self.active;
+        }
+    }
+}
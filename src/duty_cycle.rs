@@ -0,0 +1,131 @@
+//! Transmit duty-cycle enforcement
+//!
+//! [`DutyCycle`](struct.DutyCycle.html) tracks time on air over a
+//! rolling window and refuses to send once the budget is used up.
+//! Only packets sent through it are accounted for.
+
+use crate::device::Device;
+use crate::registers::ObserveTx;
+use crate::timing::air_time_us;
+use crate::{Clock, Configuration, CrcMode, DataRate, Error, Nrf24l01};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Resolution of the rolling window
+const BUCKETS: usize = 16;
+
+/// Sends through the radio while within the transmit budget
+pub struct DutyCycle<'a, Ce, Csn, Spi, E, SpiE, C>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    C: Clock,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    clock: &'a C,
+    budget_us: u64,
+    bucket_us: u64,
+    buckets: [u64; BUCKETS],
+    current: usize,
+    bucket_start_us: u64,
+    address_width: u8,
+    crc: CrcMode,
+    rate: DataRate,
+    /// Time on air of the packet in flight
+    in_flight_us: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE, C> DutyCycle<'a, Ce, Csn, Spi, E, SpiE, C>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    C: Clock,
+{
+    /// Allow `percent` time on air within any `window_us`
+    ///
+    /// Reads the address width, CRC mode and data rate to compute the
+    /// time on air: recreate it after changing those.
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        clock: &'a C,
+        percent: f32,
+        window_us: u64,
+    ) -> Result<Self, SpiE> {
+        assert!(percent > 0.0 && percent <= 100.0);
+        assert!(window_us >= BUCKETS as u64);
+        let address_width = radio.get_address_width()?;
+        let crc = radio.get_crc()?;
+        let rate = radio.get_data_rate()?;
+        let bucket_start_us = clock.now_us();
+        Ok(DutyCycle {
+            radio,
+            clock,
+            budget_us: (window_us as f32 * percent / 100.0) as u64,
+            bucket_us: window_us / BUCKETS as u64,
+            buckets: [0; BUCKETS],
+            current: 0,
+            bucket_start_us,
+            address_width,
+            crc,
+            rate,
+            in_flight_us: 0,
+        })
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Time on air left in the current window
+    pub fn remaining_us(&mut self) -> u64 {
+        self.advance();
+        self.budget_us.saturating_sub(self.buckets.iter().sum())
+    }
+
+    /// Like [`Nrf24l01::send()`](../struct.Nrf24l01.html#method.send),
+    /// but fails with `DutyCycleExceeded` when the packet does not fit
+    /// into the budget
+    pub fn send(&mut self, packet: &[u8]) -> nb::Result<(), Error<SpiE>> {
+        let air_us = air_time_us(packet.len(), self.address_width, self.crc, self.rate);
+        if u64::from(air_us) > self.remaining_us() {
+            return Err(nb::Error::Other(Error::DutyCycleExceeded));
+        }
+        self.radio.send(packet).map_err(|e| e.map(Error::Spi))?;
+        self.buckets[self.current] += u64::from(air_us);
+        self.in_flight_us = air_us;
+        Ok(())
+    }
+
+    /// Like [`Nrf24l01::wait_tx_done()`](../struct.Nrf24l01.html#method.wait_tx_done),
+    /// charging retransmits to the budget
+    pub fn wait_tx_done(&mut self) -> nb::Result<bool, SpiE> {
+        let acked = self.radio.wait_tx_done()?;
+        let (_, observe) = self.radio.device().read_register::<ObserveTx>()?;
+        self.advance();
+        self.buckets[self.current] += u64::from(observe.arc_cnt()) * u64::from(self.in_flight_us);
+        self.in_flight_us = 0;
+        Ok(acked)
+    }
+
+    /// Drop buckets that have left the window
+    fn advance(&mut self) {
+        let now = self.clock.now_us();
+        let elapsed = now.saturating_sub(self.bucket_start_us) / self.bucket_us;
+        if elapsed >= BUCKETS as u64 {
+            self.buckets = [0; BUCKETS];
+        } else {
+            for _ in 0..elapsed {
+                self.current = (self.current + 1) % BUCKETS;
+                self.buckets[self.current] = 0;
+            }
+        }
+        self.bucket_start_us += elapsed * self.bucket_us;
+    }
+}
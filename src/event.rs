@@ -0,0 +1,155 @@
+use crate::command::{FlushTx, Nop};
+use crate::device::Device;
+use crate::registers::{FifoStatus, ObserveTx, Status};
+use crate::{Mode, Nrf24l01, Pipe};
+use core::fmt::Debug;
+use core::future::Future;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// What [`Nrf24l01::poll()`](struct.Nrf24l01.html#method.poll) found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A packet is waiting in the RX FIFO, fetch it with
+    /// [`read()`](struct.Nrf24l01.html#method.read)
+    PacketReceived(Pipe),
+    /// A packet has been acknowledged after `retries` retransmits
+    SendComplete {
+        retries: u8,
+    },
+    /// A packet has been dropped after the maximum number of
+    /// retransmits, the TX FIFO has been flushed
+    SendFailed,
+    Idle,
+}
+
+/// Interrupt flags returned by
+/// [`Nrf24l01::take_interrupts()`](struct.Nrf24l01.html#method.take_interrupts)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterruptFlags {
+    /// A packet has been received
+    pub rx_dr: bool,
+    /// A packet has been sent, and acknowledged if requested
+    pub tx_ds: bool,
+    /// A packet has reached the maximum number of retransmits
+    pub max_rt: bool,
+    /// Pipe of the packet at the head of the RX FIFO
+    pub pipe: Option<Pipe>,
+}
+
+impl InterruptFlags {
+    /// None of the flags is set
+    pub fn is_empty(&self) -> bool {
+        !(self.rx_dr || self.tx_ds || self.max_rt)
+    }
+}
+
+/// An IRQ pin that can be awaited
+///
+/// Mirrors `wait_for_low()` of `embedded_hal_async::digital::Wait`.
+/// With the `embedded-hal-async` feature, every such pin is an
+/// `IrqPin`. The IRQ line is active low and stays low while any
+/// interrupt flag is set.
+pub trait IrqPin {
+    fn wait_for_low(&mut self) -> impl Future<Output = ()>;
+}
+
+/// A pin error counts as a wake-up, the flags are read either way
+#[cfg(feature = "embedded-hal-async")]
+impl<P: embedded_hal_async::digital::Wait> IrqPin for P {
+    async fn wait_for_low(&mut self) {
+        let _ = embedded_hal_async::digital::Wait::wait_for_low(self).await;
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Read `STATUS` and clear exactly the interrupt flags that are set
+    ///
+    /// Flags raised after the read stay set, so no interrupt is lost
+    /// between the two SPI transactions. The second one is skipped if
+    /// no flag is set.
+    pub fn take_interrupts(&mut self) -> Result<InterruptFlags, SpiE> {
+        let (status, ()) = self.device.send_command(&Nop)?;
+        let flags = InterruptFlags {
+            rx_dr: status.rx_dr(),
+            tx_ds: status.tx_ds(),
+            max_rt: status.max_rt(),
+            pipe: status.rx_p_no(),
+        };
+        if !flags.is_empty() {
+            let mut clear = Status(0);
+            clear.set_rx_dr(flags.rx_dr);
+            clear.set_tx_ds(flags.tx_ds);
+            clear.set_max_rt(flags.max_rt);
+            self.device.write_register(clear)?;
+        }
+        Ok(flags)
+    }
+
+    /// Wait for the IRQ pin, then take the interrupt flags
+    ///
+    /// Needs no polling at all while nothing happens. Interrupts must
+    /// not be masked in `CONFIG`.
+    pub async fn wait_event<P: IrqPin>(&mut self, irq: &mut P) -> Result<InterruptFlags, SpiE> {
+        loop {
+            irq.wait_for_low().await;
+            let flags = self.take_interrupts()?;
+            if !flags.is_empty() {
+                return Ok(flags);
+            }
+        }
+    }
+
+    /// Check for the next thing to handle, clearing its interrupt flag
+    ///
+    /// Transmit outcomes come first. Packets are reported as long as
+    /// they are in the RX FIFO, without changing between RX and TX
+    /// mode. Costs a single two-byte SPI transaction when idle.
+    ///
+    /// In RX mode, `TX_DS` only means that an ack payload went out: it
+    /// is cleared and CE stays high.
+    pub fn poll(&mut self) -> Result<Event, SpiE> {
+        let (status, observe_tx) = self.device.read_register::<ObserveTx>()?;
+        let transmitting = self.mode == Mode::Tx;
+        if transmitting && status.max_rt() {
+            self.device.send_command(&FlushTx)?;
+            let mut clear = Status(0);
+            clear.set_max_rt(true);
+            self.device.write_register(clear)?;
+            self.standby();
+            self.record_tx_failed();
+            return Ok(Event::SendFailed);
+        }
+        if status.tx_ds() {
+            let mut clear = Status(0);
+            clear.set_tx_ds(true);
+            self.device.write_register(clear)?;
+        }
+        if transmitting && status.tx_ds() {
+            let (_, fifo_status) = self.device.read_register::<FifoStatus>()?;
+            if fifo_status.tx_empty() {
+                self.standby();
+            }
+            self.record_tx_done(observe_tx.arc_cnt());
+            return Ok(Event::SendComplete {
+                retries: observe_tx.arc_cnt(),
+            });
+        }
+        if let Some(pipe) = status.rx_p_no() {
+            if status.rx_dr() {
+                let mut clear = Status(0);
+                clear.set_rx_dr(true);
+                self.device.write_register(clear)?;
+            }
+            return Ok(Event::PacketReceived(pipe));
+        }
+        Ok(Event::Idle)
+    }
+}
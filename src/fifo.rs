@@ -0,0 +1,54 @@
+use crate::Nrf24l01;
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Fill level of a FIFO, which holds three packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FifoLevel {
+    Empty,
+    One,
+    Two,
+    Full,
+}
+
+impl FifoLevel {
+    fn from_count(count: u8) -> Self {
+        match count {
+            0 => FifoLevel::Empty,
+            1 => FifoLevel::One,
+            2 => FifoLevel::Two,
+            _ => FifoLevel::Full,
+        }
+    }
+
+    /// Packets in the FIFO
+    pub fn count(&self) -> usize {
+        *self as usize
+    }
+
+    /// Packets that can still be queued
+    pub fn free(&self) -> usize {
+        3 - self.count()
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// How full the TX FIFO is
+    ///
+    /// The chip only flags empty and full, so in between this counts the
+    /// packets queued since the FIFO was last seen empty. A packet sent
+    /// meanwhile may leave it one too high, never too low, so a level
+    /// below `Full` always means there is room. Costs one register read.
+    pub fn tx_fifo_level(&mut self) -> Result<FifoLevel, SpiE> {
+        let count = self.device.tx_fifo_count()?;
+        Ok(FifoLevel::from_count(count))
+    }
+}
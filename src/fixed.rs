@@ -0,0 +1,55 @@
+use crate::{Error, Nrf24l01, Payload, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// The data of a packet from [`send_fixed()`](struct.Nrf24l01.html#method.send_fixed),
+/// without the length byte and the padding
+///
+/// `None` if the length byte points past the end of `packet`.
+pub fn unpad_fixed(packet: &[u8]) -> Option<&[u8]> {
+    let (&len, data) = packet.split_first()?;
+    data.get(0..usize::from(len))
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Queue `data` as a packet of exactly `N` bytes, for a peer whose
+    /// pipe has a static payload length of `N`
+    ///
+    /// The first byte carries the length of `data`, zeros fill up the
+    /// rest. Fails with `TooLong` if `data` is longer than `N - 1`.
+    /// Panics if `N` exceeds [`PAYLOAD_LEN`](constant.PAYLOAD_LEN.html).
+    pub fn send_fixed<const N: usize>(&mut self, data: &[u8]) -> nb::Result<(), Error<SpiE>> {
+        assert!(N <= PAYLOAD_LEN);
+        if data.len() >= N {
+            return Err(nb::Error::Other(Error::TooLong {
+                len: data.len(),
+                max: N.saturating_sub(1),
+            }));
+        }
+        let mut packet = [0; PAYLOAD_LEN];
+        packet[0] = data.len() as u8;
+        packet[1..=data.len()].copy_from_slice(data);
+        self.send(&packet[0..N]).map_err(|e| e.map(Error::Spi))
+    }
+
+    /// Take a packet from [`send_fixed()`](#method.send_fixed) out of
+    /// the RX FIFO, returning just its data
+    ///
+    /// `None` for a packet with an impossible length byte.
+    pub fn read_fixed(&mut self) -> Result<Option<Payload>, nb::Error<SpiE>> {
+        let packet = self.read()?;
+        Ok(unpad_fixed(&packet).map(|data| {
+            let mut payload = Payload::new(data);
+            payload.set_timestamp_us(packet.timestamp_us());
+            payload
+        }))
+    }
+}
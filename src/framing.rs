@@ -0,0 +1,436 @@
+//! COBS framing for byte streams carried over packets
+//!
+//! Consistent Overhead Byte Stuffing removes every zero byte from a
+//! frame, so a single zero can mark its end. A stream split into
+//! packets by [`send_frame()`](fn.send_frame.html) survives lost
+//! packets: the [`Deframer`](struct.Deframer.html) picks up again at
+//! the next delimiter.
+//!
+//! The hardware CRC only covers single packets, and a frame with a
+//! lost packet in the middle often still decodes. The `_checked`
+//! variants append a CRC-32 to the whole frame, which
+//! [`check()`](fn.check.html) verifies after reassembly, so that
+//! such frames are discarded.
+
+use crate::{Nrf24l01, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Ends every encoded frame
+pub const DELIMITER: u8 = 0;
+/// Length of the CRC-32 appended by [`encode_checked()`](fn.encode_checked.html)
+pub const CRC_LEN: usize = 4;
+/// Longest run of non-zero bytes a code byte covers
+const MAX_RUN: usize = 254;
+
+/// Space [`encode()`](fn.encode.html) needs for `len` bytes, delimiter
+/// included
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / MAX_RUN + 2
+}
+
+/// COBS encode `data` into `out`, followed by the delimiter
+///
+/// Returns the number of bytes written, or `None` if `out` is too short.
+pub fn encode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    encode_bytes(data.iter().cloned(), out)
+}
+
+/// Like [`encode()`](fn.encode.html), with the CRC-32 of `data`
+/// appended before encoding
+///
+/// `out` must hold [`max_encoded_len()`](fn.max_encoded_len.html) of
+/// `data.len() + CRC_LEN` bytes.
+pub fn encode_checked(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let crc = crc32(data).to_le_bytes();
+    encode_bytes(data.iter().chain(crc.iter()).cloned(), out)
+}
+
+fn encode_bytes<I: Iterator<Item = u8>>(data: I, out: &mut [u8]) -> Option<usize> {
+    let mut code_index = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+    for byte in data {
+        if byte != DELIMITER {
+            *out.get_mut(len)? = byte;
+            len += 1;
+            code += 1;
+        }
+        if byte == DELIMITER || usize::from(code) == MAX_RUN + 1 {
+            *out.get_mut(code_index)? = code;
+            code_index = len;
+            len += 1;
+            code = 1;
+        }
+    }
+    *out.get_mut(code_index)? = code;
+    *out.get_mut(len)? = DELIMITER;
+    Some(len + 1)
+}
+
+/// Decode one COBS frame, without its delimiter, into `out`
+///
+/// Returns the decoded length, or `None` if the frame is malformed or
+/// `out` too short.
+pub fn decode(frame: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut len = 0;
+    while read < frame.len() {
+        let code = usize::from(frame[read]);
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        read += 1;
+        for i in 0..code - 1 {
+            *out.get_mut(len)? = frame[read + i];
+            len += 1;
+        }
+        read += code - 1;
+        if code != MAX_RUN + 1 && read < frame.len() {
+            *out.get_mut(len)? = DELIMITER;
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+/// A decoded frame does not match its CRC-32
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegrityError;
+
+/// CRC-32 (IEEE 802.3) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continue `crc`, the CRC-32 of what came before, over `data`
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// Verify and strip the CRC-32 of a frame sent with
+/// [`encode_checked()`](fn.encode_checked.html)
+pub fn check(frame: &[u8]) -> Result<&[u8], IntegrityError> {
+    if frame.len() < CRC_LEN {
+        return Err(IntegrityError);
+    }
+    let (data, crc) = frame.split_at(frame.len() - CRC_LEN);
+    match crc32(data).to_le_bytes() == crc {
+        true => Ok(data),
+        false => Err(IntegrityError),
+    }
+}
+
+/// Reassembles frames from a stream of received bytes
+pub struct Deframer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    /// The current frame did not fit and is being skipped
+    overflow: bool,
+    errors: u32,
+}
+
+impl<'b> Deframer<'b> {
+    /// Frames longer than `buf` are dropped
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Deframer {
+            buf,
+            len: 0,
+            overflow: false,
+            errors: 0,
+        }
+    }
+
+    /// Frames dropped for being malformed or too long
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// Forget the partial frame, e.g. after a lost packet
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+    }
+
+    /// Add received bytes, calling `on_frame` with each decoded frame
+    pub fn feed<F: FnMut(&[u8])>(&mut self, bytes: &[u8], mut on_frame: F) {
+        for &byte in bytes {
+            if byte != DELIMITER {
+                match self.buf.get_mut(self.len) {
+                    Some(slot) if !self.overflow => {
+                        *slot = byte;
+                        self.len += 1;
+                    }
+                    _ => self.overflow = true,
+                }
+                continue;
+            }
+            if self.len > 0 || self.overflow {
+                let decoded_len = match self.overflow {
+                    true => None,
+                    false => decode_in_place(&mut self.buf[0..self.len]),
+                };
+                match decoded_len {
+                    Some(len) => on_frame(&self.buf[0..len]),
+                    None => self.errors = self.errors.wrapping_add(1),
+                }
+            }
+            self.reset();
+        }
+    }
+
+    /// Like [`feed()`](#method.feed) for frames sent with a CRC-32,
+    /// which is verified and stripped
+    pub fn feed_checked<F>(&mut self, bytes: &[u8], mut on_frame: F)
+    where
+        F: FnMut(Result<&[u8], IntegrityError>),
+    {
+        self.feed(bytes, |frame| on_frame(check(frame)))
+    }
+}
+
+/// Like [`decode()`](fn.decode.html), with the decoded data replacing
+/// the frame
+///
+/// Decoding never grows the data, so no second buffer is needed.
+pub fn decode_in_place(frame: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut len = 0;
+    while read < frame.len() {
+        let code = usize::from(frame[read]);
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        read += 1;
+        frame.copy_within(read..read + code - 1, len);
+        len += code - 1;
+        read += code - 1;
+        if code != MAX_RUN + 1 && read < frame.len() {
+            frame[len] = DELIMITER;
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+/// Encode `data` into `scratch` and send it in as many packets as needed
+///
+/// `scratch` must hold [`max_encoded_len()`](fn.max_encoded_len.html)
+/// bytes. Returns whether every packet was acknowledged. Without a
+/// CRC, the receiving [`Deframer`](struct.Deframer.html) may deliver a
+/// frame with a gap, use [`send_frame_checked()`](fn.send_frame_checked.html)
+/// to have it discarded.
+pub fn send_frame<Ce, Csn, Spi, E, SpiE>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    data: &[u8],
+    scratch: &mut [u8],
+) -> Result<bool, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    let len = encode(data, scratch).expect("scratch buffer too short");
+    send_encoded(radio, &scratch[0..len])
+}
+
+/// Like [`send_frame()`](fn.send_frame.html), with a CRC-32 over the
+/// whole frame
+///
+/// `scratch` must hold [`max_encoded_len()`](fn.max_encoded_len.html)
+/// of `data.len() + CRC_LEN` bytes.
+pub fn send_frame_checked<Ce, Csn, Spi, E, SpiE>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    data: &[u8],
+    scratch: &mut [u8],
+) -> Result<bool, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    let len = encode_checked(data, scratch).expect("scratch buffer too short");
+    send_encoded(radio, &scratch[0..len])
+}
+
+fn send_encoded<Ce, Csn, Spi, E, SpiE>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    encoded: &[u8],
+) -> Result<bool, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    let mut acked = true;
+    for packet in encoded.chunks(PAYLOAD_LEN) {
+        nb::block!(radio.send(packet))?;
+        acked &= nb::block!(radio.wait_tx_done())?;
+    }
+    Ok(acked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) {
+        let mut encoded = [0xAA; 1100];
+        let len = encode(data, &mut encoded).unwrap();
+        assert!(len <= max_encoded_len(data.len()));
+        assert_eq!(encoded[len - 1], DELIMITER);
+        assert!(!encoded[0..len - 1].contains(&DELIMITER));
+        let mut decoded = [0; 1100];
+        let decoded_len = decode(&encoded[0..len - 1], &mut decoded).unwrap();
+        assert_eq!(&decoded[0..decoded_len], data);
+        let decoded_len = decode_in_place(&mut encoded[0..len - 1]).unwrap();
+        assert_eq!(&encoded[0..decoded_len], data);
+    }
+
+    #[test]
+    fn known_vectors() {
+        let vectors: [(&[u8], &[u8]); 6] = [
+            (&[], &[0x01, 0x00]),
+            (&[0x00], &[0x01, 0x01, 0x00]),
+            (&[0x00, 0x00], &[0x01, 0x01, 0x01, 0x00]),
+            (
+                &[0x11, 0x22, 0x00, 0x33],
+                &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00],
+            ),
+            (
+                &[0x11, 0x22, 0x33, 0x44],
+                &[0x05, 0x11, 0x22, 0x33, 0x44, 0x00],
+            ),
+            (
+                &[0x11, 0x00, 0x00, 0x00],
+                &[0x02, 0x11, 0x01, 0x01, 0x01, 0x00],
+            ),
+        ];
+        for (data, expected) in vectors.iter() {
+            let mut encoded = [0; 16];
+            let len = encode(data, &mut encoded).unwrap();
+            assert_eq!(&encoded[0..len], *expected);
+            round_trip(data);
+        }
+    }
+
+    #[test]
+    fn long_runs() {
+        let mut data = [0; 1000];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i % 255) as u8 + 1;
+        }
+        for len in [253, 254, 255, 508, 1000] {
+            round_trip(&data[0..len]);
+        }
+        data[254] = 0;
+        round_trip(&data);
+    }
+
+    #[test]
+    fn max_encoded_len_is_enough() {
+        let data = [0x55; 1000];
+        let mut encoded = [0; max_encoded_len(1000)];
+        assert!(encode(&data, &mut encoded).is_some());
+        let mut short = [0; 1000];
+        assert_eq!(encode(&data, &mut short), None);
+    }
+
+    #[test]
+    fn malformed_frames() {
+        let mut out = [0; 16];
+        assert_eq!(decode(&[0x05, 0x11, 0x22], &mut out), None);
+        assert_eq!(decode(&[0x02, 0x00, 0x11], &mut out), None);
+        assert_eq!(decode(&[0x03, 0x11, 0x22], &mut out[0..1]), None);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn checked_frames() {
+        let mut encoded = [0; 32];
+        let len = encode_checked(b"hello\0world", &mut encoded).unwrap();
+        let mut decoded = [0; 32];
+        let decoded_len = decode(&encoded[0..len - 1], &mut decoded).unwrap();
+        assert_eq!(check(&decoded[0..decoded_len]), Ok(&b"hello\0world"[..]));
+        decoded[0] ^= 1;
+        assert_eq!(check(&decoded[0..decoded_len]), Err(IntegrityError));
+        assert_eq!(check(&[1, 2, 3]), Err(IntegrityError));
+    }
+
+    #[test]
+    fn deframer_splits_stream() {
+        let mut stream = [0; 64];
+        let first = encode(b"ab\0c", &mut stream).unwrap();
+        let second = encode(b"", &mut stream[first..]).unwrap();
+        let third = encode(b"xyz", &mut stream[first + second..]).unwrap();
+        let mut buf = [0; 16];
+        let mut deframer = Deframer::new(&mut buf);
+        let mut frames = 0;
+        for chunk in stream[0..first + second + third].chunks(3) {
+            deframer.feed(chunk, |frame| {
+                let expected: &[u8] = [&b"ab\0c"[..], b"", b"xyz"][frames];
+                assert_eq!(frame, expected);
+                frames += 1;
+            });
+        }
+        assert_eq!(frames, 3);
+        assert_eq!(deframer.errors(), 0);
+    }
+
+    #[test]
+    fn deframer_drops_overlong_frames() {
+        let mut stream = [0; 32];
+        let first = encode(&[7; 20], &mut stream).unwrap();
+        let second = encode(b"ok", &mut stream[first..]).unwrap();
+        let mut buf = [0; 8];
+        let mut deframer = Deframer::new(&mut buf);
+        let mut frames = 0;
+        deframer.feed(&stream[0..first + second], |frame| {
+            assert_eq!(frame, b"ok");
+            frames += 1;
+        });
+        assert_eq!(frames, 1);
+        assert_eq!(deframer.errors(), 1);
+    }
+
+    #[test]
+    fn checked_deframer_discards_gaps() {
+        let mut data = [0; 100];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut stream = [0; max_encoded_len(100 + CRC_LEN)];
+        let len = encode_checked(&data, &mut stream).unwrap();
+        let mut buf = [0; 128];
+        let mut deframer = Deframer::new(&mut buf);
+        for (i, packet) in stream[0..len].chunks(PAYLOAD_LEN).enumerate() {
+            if i == 1 {
+                continue;
+            }
+            deframer.feed_checked(packet, |frame| assert!(frame.is_err()));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Nordic Gazell link layer compatibility
+//!
+//! Gazell hops between the channels of a shared channel table. The
+//! host (PRX) moves on to the next channel every
+//! `timeslots_per_channel` timeslots, and a device (PTX) that loses
+//! track of the host dwells on each channel long enough for the host to
+//! pass by. Devices address the host through one of its pipes; replies
+//! travel back in ack payloads.
+
+use crate::channels::ChannelMask;
+use crate::device::Device as _;
+use crate::{Config, Configuration, CrcMode, DataRate, Nrf24l01, Payload, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Maximum number of entries in the channel table
+pub const MAX_CHANNEL_TABLE_SIZE: usize = 16;
+
+/// Gazell link parameters, must match on host and devices
+#[derive(Debug, Clone)]
+pub struct Params {
+    channel_table: [u8; MAX_CHANNEL_TABLE_SIZE],
+    channel_table_len: usize,
+    timeslot_period_us: u32,
+    timeslots_per_channel: u16,
+    timeslots_per_channel_out_of_sync: u16,
+    max_tx_attempts: u16,
+    base_address_0: u32,
+    base_address_1: u32,
+    prefixes: [u8; PIPES_COUNT],
+}
+
+impl Default for Params {
+    /// The defaults of Nordic's `nrf_gzll`
+    fn default() -> Self {
+        let mut channel_table = [0; MAX_CHANNEL_TABLE_SIZE];
+        channel_table[0..5].copy_from_slice(&[4, 25, 42, 63, 77]);
+        Params {
+            channel_table,
+            channel_table_len: 5,
+            timeslot_period_us: 600,
+            timeslots_per_channel: 2,
+            timeslots_per_channel_out_of_sync: 15,
+            max_tx_attempts: 100,
+            base_address_0: 0x0102_0304,
+            base_address_1: 0x0506_0708,
+            prefixes: [0xE7, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6],
+        }
+    }
+}
+
+impl Params {
+    pub fn channel_table(mut self, channels: &[u8]) -> Self {
+        assert!(!channels.is_empty());
+        assert!(channels.len() <= MAX_CHANNEL_TABLE_SIZE);
+        self.channel_table[0..channels.len()].copy_from_slice(channels);
+        self.channel_table_len = channels.len();
+        self
+    }
+    /// Move every channel of the table that `mask` blocks to the nearest allowed one
+    ///
+    /// Duplicates are dropped, so the table may get shorter.
+    pub fn channel_mask(mut self, mask: &ChannelMask) -> Self {
+        let mut table = [0; MAX_CHANNEL_TABLE_SIZE];
+        let mut len = 0;
+        for &channel in self.channels() {
+            let channel = mask.nearest(channel).expect("no channel allowed");
+            if !table[0..len].contains(&channel) {
+                table[len] = channel;
+                len += 1;
+            }
+        }
+        self.channel_table = table;
+        self.channel_table_len = len;
+        self
+    }
+    pub fn timeslot_period_us(mut self, period: u32) -> Self {
+        self.timeslot_period_us = period;
+        self
+    }
+    pub fn timeslots_per_channel(mut self, timeslots: u16) -> Self {
+        self.timeslots_per_channel = timeslots;
+        self
+    }
+    /// Device dwell time on each channel while it has lost the host
+    pub fn timeslots_per_channel_out_of_sync(mut self, timeslots: u16) -> Self {
+        self.timeslots_per_channel_out_of_sync = timeslots;
+        self
+    }
+    /// Timeslots a device tries before giving up on a packet, `0` for no limit
+    pub fn max_tx_attempts(mut self, attempts: u16) -> Self {
+        self.max_tx_attempts = attempts;
+        self
+    }
+    /// `base_address_0` is used by pipe 0, `base_address_1` by pipes 1 to 5
+    pub fn base_addresses(mut self, base_address_0: u32, base_address_1: u32) -> Self {
+        self.base_address_0 = base_address_0;
+        self.base_address_1 = base_address_1;
+        self
+    }
+    pub fn prefixes(mut self, prefixes: [u8; PIPES_COUNT]) -> Self {
+        self.prefixes = prefixes;
+        self
+    }
+
+    pub fn channels(&self) -> &[u8] {
+        &self.channel_table[0..self.channel_table_len]
+    }
+
+    /// Radio address of `pipe`: prefix byte, then the base address LSB first
+    pub fn pipe_address(&self, pipe: Pipe) -> [u8; 5] {
+        let base = match pipe {
+            Pipe::P0 => self.base_address_0,
+            _ => self.base_address_1,
+        };
+        let base = base.to_le_bytes();
+        [
+            self.prefixes[pipe.index()],
+            base[0],
+            base[1],
+            base[2],
+            base[3],
+        ]
+    }
+}
+
+/// Radio settings used by Gazell
+pub fn config() -> Config {
+    Config::default()
+        .data_rate(DataRate::R2Mbps)
+        .crc_mode(CrcMode::TwoBytes)
+        .auto_retransmit_delay(0)
+        .auto_retransmit_count(0)
+}
+
+fn configure<Ce, Csn, Spi, E, SpiE>(radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> Result<(), SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    nb::block!(radio.wait_tx_empty())?;
+    radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+    radio.set_ack_payloads(true)?;
+    radio.set_auto_ack(&[true; PIPES_COUNT])?;
+    // One hardware attempt per timeslot, retries happen in software
+    radio.set_auto_retransmit(0, 0)
+}
+
+/// Gazell host, listening on all pipes
+pub struct Host<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    params: Params,
+    channel_index: usize,
+    timeslot: u16,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Host<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        params: Params,
+    ) -> Result<Self, SpiE> {
+        configure(radio)?;
+        radio.set_pipes_rx_enable(&[true; PIPES_COUNT])?;
+        radio.set_rx_addr(Pipe::P0, &params.pipe_address(Pipe::P0))?;
+        radio.set_rx_addr(Pipe::P1, &params.pipe_address(Pipe::P1))?;
+        for &pipe in &Pipe::ALL[2..] {
+            radio.set_rx_addr(pipe, &params.pipe_address(pipe)[0..1])?;
+        }
+        radio.set_frequency(params.channels()[0])?;
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        Ok(Host {
+            radio,
+            params,
+            channel_index: 0,
+            timeslot: 0,
+        })
+    }
+
+    /// Call once every timeslot period to follow the hopping schedule
+    pub fn tick(&mut self) -> Result<(), SpiE> {
+        self.timeslot += 1;
+        if self.timeslot < self.params.timeslots_per_channel {
+            return Ok(());
+        }
+        self.timeslot = 0;
+        self.channel_index = (self.channel_index + 1) % self.params.channel_table_len;
+        let channel = self.params.channels()[self.channel_index];
+        self.radio.device().ce_disable();
+        self.radio.set_frequency(channel)?;
+        self.radio.device().ce_enable();
+        Ok(())
+    }
+
+    /// Poll for a packet, returning it with its pipe
+    pub fn receive(&mut self) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        let pipe = match self.radio.wait_rx_ready() {
+            Ok(pipe) => pipe,
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        };
+        let payload = nb::block!(self.radio.read())?;
+        // A device just found us here, stay for a full period
+        self.timeslot = 0;
+        Ok(Some((pipe, payload)))
+    }
+
+    /// Queue a reply for the next packet from a device on `pipe`
+    pub fn write_ack_payload(&mut self, pipe: Pipe, data: &[u8]) -> Result<(), SpiE> {
+        self.radio.write_ack_payload(pipe, data)
+    }
+}
+
+/// Gazell device, transmitting to one host pipe
+pub struct Device<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    params: Params,
+    pipe: Option<Pipe>,
+    channel_index: usize,
+    in_sync: bool,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Device<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        params: Params,
+    ) -> Result<Self, SpiE> {
+        configure(radio)?;
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_frequency(params.channels()[0])?;
+        Ok(Device {
+            radio,
+            params,
+            pipe: None,
+            channel_index: 0,
+            in_sync: false,
+        })
+    }
+
+    /// Did the last transmission succeed?
+    pub fn in_sync(&self) -> bool {
+        self.in_sync
+    }
+
+    /// Send `data` to host `pipe`, hopping channels until acknowledged
+    ///
+    /// Starts on the channel that last worked. Returns `false` after
+    /// `max_tx_attempts` timeslots without acknowledgement.
+    pub fn send<D: DelayUs<u32>>(
+        &mut self,
+        pipe: Pipe,
+        data: &[u8],
+        delay: &mut D,
+    ) -> Result<bool, SpiE> {
+        if self.pipe != Some(pipe) {
+            let address = self.params.pipe_address(pipe);
+            nb::block!(self.radio.wait_tx_empty())?;
+            self.radio.set_tx_addr(&address)?;
+            self.radio.set_rx_addr(Pipe::P0, &address)?;
+            self.pipe = Some(pipe);
+        }
+
+        let mut attempts = 0u16;
+        let mut on_channel = 0u16;
+        loop {
+            nb::block!(self.radio.send(data))?;
+            if nb::block!(self.radio.wait_tx_done())? {
+                self.in_sync = true;
+                return Ok(true);
+            }
+            attempts += 1;
+            if self.params.max_tx_attempts != 0 && attempts >= self.params.max_tx_attempts {
+                self.in_sync = false;
+                return Ok(false);
+            }
+            on_channel += 1;
+            let dwell = match self.in_sync {
+                true => self.params.timeslots_per_channel,
+                false => self.params.timeslots_per_channel_out_of_sync,
+            };
+            if on_channel >= dwell {
+                on_channel = 0;
+                self.in_sync = false;
+                self.channel_index = (self.channel_index + 1) % self.params.channel_table_len;
+                let channel = self.params.channels()[self.channel_index];
+                nb::block!(self.radio.wait_tx_empty())?;
+                self.radio.set_frequency(channel)?;
+            }
+            delay.delay_us(self.params.timeslot_period_us);
+        }
+    }
+
+    /// Read a reply that the host sent in an ack payload
+    pub fn read(&mut self) -> Result<Option<Payload>, SpiE> {
+        self.radio.read_ack_payload()
+    }
+}
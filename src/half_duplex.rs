@@ -0,0 +1,189 @@
+//! Alternating send windows on a shared schedule
+//!
+//! Time is cut into windows of equal length. The
+//! [`Leader`](enum.Role.html#variant.Leader) sends in the even ones
+//! and listens in the odd ones, the follower the other way round, so
+//! neither ever sends while the other does. The schedule runs on the
+//! leader's clock; the follower maps its own through an
+//! [`Estimate`](../time_sync/struct.Estimate.html) from the
+//! [`time_sync`](../time_sync/index.html) module and stays silent
+//! until it has one.
+//!
+//! Each window starts with a guard for clock error and the RX/TX
+//! settling time. A packet is only started if its transaction fits
+//! into the rest of the window.
+
+use crate::time_sync::Estimate;
+use crate::timing::{transaction_time_us, SETTLING_US};
+use crate::{Clock, Configuration, Nrf24l01, Payload, Pipe, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Guard at the start of each window in addition to the settling time
+pub const DEFAULT_GUARD_US: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Owns the schedule, sends in even windows
+    Leader,
+    /// Follows the leader's clock, sends in odd windows
+    Follower,
+}
+
+/// Where in the schedule the link is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// This side may send for `remaining_us`
+    Tx { remaining_us: u32 },
+    /// The peer sends for `remaining_us`
+    Rx { remaining_us: u32 },
+}
+
+/// One end of a half-duplex link
+pub struct HalfDuplexLink<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    role: Role,
+    peer_address: [u8; 5],
+    window_us: u32,
+    guard_us: u32,
+    /// Time a packet needs from `send()` to its ack
+    send_us: u32,
+    estimate: Option<Estimate>,
+    /// Window a packet has already been sent in
+    last_sent: Option<u64>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> HalfDuplexLink<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Talk to `peer_address`, receiving on pipe 1 at `address`, in
+    /// windows of `window_us`
+    ///
+    /// Panics if a full-size packet does not fit into a window.
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        role: Role,
+        address: [u8; 5],
+        peer_address: [u8; 5],
+        window_us: u32,
+    ) -> Result<Self, SpiE> {
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        pipes[1] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_rx_addr(Pipe::P1, &address)?;
+        radio.set_tx_addr(&peer_address)?;
+        radio.set_rx_addr(Pipe::P0, &peer_address)?;
+        let crc = radio.get_crc()?;
+        let rate = radio.get_data_rate()?;
+        let send_us = transaction_time_us(PAYLOAD_LEN, PAYLOAD_LEN, 5, crc, rate);
+        let guard_us = SETTLING_US + DEFAULT_GUARD_US;
+        assert!(guard_us + send_us <= window_us);
+        Ok(HalfDuplexLink {
+            radio,
+            role,
+            peer_address,
+            window_us,
+            guard_us,
+            send_us,
+            estimate: None,
+            last_sent: None,
+        })
+    }
+
+    /// Allow for `guard_us` of clock error between the peers
+    pub fn set_guard_us(&mut self, guard_us: u32) {
+        assert!(SETTLING_US + guard_us + self.send_us <= self.window_us);
+        self.guard_us = SETTLING_US + guard_us;
+    }
+
+    /// Follow the leader's clock, see the module documentation
+    pub fn set_estimate(&mut self, estimate: Option<Estimate>) {
+        self.estimate = estimate;
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn peer_address(&self) -> &[u8; 5] {
+        &self.peer_address
+    }
+
+    /// Current window, `None` while a follower has no estimate
+    pub fn window<C: Clock>(&self, clock: &C) -> Option<Window> {
+        let (_, window) = self.position(clock)?;
+        Some(window)
+    }
+
+    /// Poll for a packet from the peer
+    ///
+    /// Listens throughout the peer's windows, and in this side's
+    /// windows while there is nothing to send.
+    pub fn poll(&mut self) -> Result<Option<Payload>, SpiE> {
+        match self.radio.wait_rx_ready() {
+            Ok(_) => Ok(Some(nb::block!(self.radio.read())?)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(e)) => Err(e),
+        }
+    }
+
+    /// Send `packet` in this side's next window
+    ///
+    /// Returns `WouldBlock` until there is room for it, at most one
+    /// packet per window, then whether the peer acknowledged it.
+    pub fn send<C: Clock>(&mut self, clock: &C, packet: &[u8]) -> nb::Result<bool, SpiE> {
+        let (index, window) = self.position(clock).ok_or(nb::Error::WouldBlock)?;
+        let remaining_us = match window {
+            Window::Tx { remaining_us } => remaining_us,
+            Window::Rx { .. } => return Err(nb::Error::WouldBlock),
+        };
+        let started_us = self.window_us - remaining_us;
+        if started_us < self.guard_us || remaining_us < self.send_us {
+            return Err(nb::Error::WouldBlock);
+        }
+        if self.last_sent == Some(index) {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.last_sent = Some(index);
+        // Leaves RX, and returns to Standby-I once the ack is in
+        nb::block!(self.radio.send(packet))?;
+        Ok(nb::block!(self.radio.wait_tx_done())?)
+    }
+
+    /// Window number and window on the leader's clock
+    fn position<C: Clock>(&self, clock: &C) -> Option<(u64, Window)> {
+        let now = clock.now_us();
+        let now = match self.role {
+            Role::Leader => now,
+            Role::Follower => self.estimate?.remote_us(now),
+        };
+        let window_us = u64::from(self.window_us);
+        let index = now / window_us;
+        let remaining_us = (window_us - now % window_us) as u32;
+        let ours = match self.role {
+            Role::Leader => index % 2 == 0,
+            Role::Follower => index % 2 == 1,
+        };
+        let window = match ours {
+            true => Window::Tx { remaining_us },
+            false => Window::Rx { remaining_us },
+        };
+        Some((index, window))
+    }
+}
@@ -0,0 +1,152 @@
+//! Heartbeats and peer liveness tracking
+//!
+//! Every node sends a two-byte heartbeat with its node id at a fixed
+//! interval to whatever TX address is set, typically a shared no-ack
+//! address, and remembers when it last heard each peer. Peers silent
+//! for longer than the timeout are reported once through the callback
+//! passed to [`poll()`](struct.Keepalive.html#method.poll).
+
+use crate::{Clock, Nrf24l01, Payload};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_HEARTBEAT: u8 = 0xD1;
+/// Number of peers tracked
+pub const MAX_PEERS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct Peer {
+    node_id: u8,
+    last_seen_us: u64,
+    alive: bool,
+}
+
+/// Sends heartbeats and tracks those of up to
+/// [`MAX_PEERS`](constant.MAX_PEERS.html) peers
+pub struct Keepalive<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    node_id: u8,
+    interval_us: u64,
+    timeout_us: u64,
+    next_heartbeat_us: u64,
+    peers: [Option<Peer>; MAX_PEERS],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Keepalive<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// The first heartbeat goes out on the first [`poll()`](#method.poll)
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        node_id: u8,
+        interval_us: u64,
+        timeout_us: u64,
+    ) -> Self {
+        Keepalive {
+            radio,
+            node_id,
+            interval_us,
+            timeout_us,
+            next_heartbeat_us: 0,
+            peers: [None; MAX_PEERS],
+        }
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Whether a heartbeat of `node_id` arrived within the timeout
+    pub fn peer_alive(&self, node_id: u8) -> bool {
+        self.peer(node_id).is_some_and(|peer| peer.alive)
+    }
+
+    /// When the last heartbeat of `node_id` arrived
+    pub fn last_seen_us(&self, node_id: u8) -> Option<u64> {
+        self.peer(node_id).map(|peer| peer.last_seen_us)
+    }
+
+    /// Stop tracking `node_id`, freeing its slot
+    pub fn forget(&mut self, node_id: u8) {
+        for slot in self.peers.iter_mut() {
+            if slot.is_some_and(|peer| peer.node_id == node_id) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn peer(&self, node_id: u8) -> Option<&Peer> {
+        self.peers
+            .iter()
+            .flatten()
+            .find(|peer| peer.node_id == node_id)
+    }
+
+    /// Send a heartbeat when due, track received ones, and report
+    /// peers that timed out to `on_timeout`
+    ///
+    /// Returns the first packet that is not a heartbeat.
+    pub fn poll<C: Clock, F: FnMut(u8)>(
+        &mut self,
+        clock: &C,
+        mut on_timeout: F,
+    ) -> Result<Option<Payload>, SpiE> {
+        let now = clock.now_us();
+        if now >= self.next_heartbeat_us {
+            nb::block!(self.radio.send(&[TYPE_HEARTBEAT, self.node_id]))?;
+            nb::block!(self.radio.wait_tx_done())?;
+            self.next_heartbeat_us = now + self.interval_us;
+        }
+        for peer in self.peers.iter_mut().flatten() {
+            if peer.alive && now >= peer.last_seen_us + self.timeout_us {
+                peer.alive = false;
+                on_timeout(peer.node_id);
+            }
+        }
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            match *packet {
+                [TYPE_HEARTBEAT, node_id] => self.seen(node_id, now),
+                _ => return Ok(Some(packet)),
+            }
+        }
+    }
+
+    fn seen(&mut self, node_id: u8, now: u64) {
+        let peer = Peer {
+            node_id,
+            last_seen_us: now,
+            alive: true,
+        };
+        let slot = match self
+            .peers
+            .iter()
+            .position(|slot| slot.is_some_and(|peer| peer.node_id == node_id))
+        {
+            Some(index) => Some(index),
+            None => self.peers.iter().position(Option::is_none),
+        };
+        // Peers beyond `MAX_PEERS` are not tracked
+        if let Some(index) = slot {
+            self.peers[index] = Some(peer);
+        }
+    }
+}
@@ -1,25 +1,129 @@
 //! nRF24L01+ driver for use with [embedded-hal](https://crates.io/crates/embedded-hal)
+//!
+//! The SPI bus may be shared with other devices through a bus proxy
+//! such as `shared-bus`. Every command is exactly one `transfer()`
+//! framed by CSN, so the bus is only held for the duration of a single
+//! command and CSN never stays low across calls, even when the transfer
+//! fails. CSN must be a dedicated pin driven by this driver.
 
 #![no_std]
 #[macro_use]
 extern crate bitfield;
 
+/// Report a [`TraceEvent`] if the `trace` feature is enabled
+macro_rules! trace {
+    ($device:expr, $event:expr) => {
+        #[cfg(feature = "trace")]
+        $device.trace($event);
+    };
+}
+
+pub mod activity;
+pub mod adaptive;
+pub mod allocation;
+pub mod asynch;
+pub mod beacon;
+pub mod benchmark;
+pub mod binding;
+pub mod ble;
+pub mod bulk;
+pub mod channels;
+pub mod command;
+#[cfg(feature = "compression")]
+pub mod compress;
+pub mod crazyflie;
+pub mod dedup;
+pub mod dispatch;
+pub mod diversity;
+pub mod duty_cycle;
+pub mod framing;
+pub mod gazell;
+pub mod half_duplex;
+pub mod keepalive;
+pub mod linktest;
+pub mod low_power;
+pub mod mesh;
+pub mod message;
+pub mod multiceiver;
+pub mod mux;
+pub mod mysensors;
+pub mod network;
+pub mod ota;
+pub mod pair;
+pub mod ping;
+pub mod pubsub;
+pub mod queue;
+pub mod registers;
+#[cfg(feature = "remote-config")]
+pub mod remote_config;
+pub mod rpc;
+pub mod session;
 pub mod setup;
+pub mod shared;
+pub mod sniffer;
+pub mod tdma;
+pub mod time_sync;
+pub mod timing;
 
-mod command;
+mod address;
+#[cfg(feature = "blocking")]
+mod blocking;
+mod broadcast;
+mod clock;
 mod config;
+mod csma;
 mod device;
+mod event;
+mod fifo;
+mod fixed;
+mod link_quality;
 mod payload;
-mod registers;
+mod pin;
+mod power;
+mod retry;
+mod state;
+mod stats;
+mod test_mode;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "ufmt")]
+mod ufmt_impls;
 
-pub use crate::config::{Configuration, CrcMode, DataRate};
-pub use crate::payload::Payload;
+pub use crate::address::{Address, AddressError, AddressFamily};
+pub use crate::clock::Clock;
+pub use crate::config::{ChipVariant, Configuration, CrcMode, DataRate, OutputPower, Pipe};
+pub use crate::csma::Csma;
+pub use crate::event::{Event, InterruptFlags, IrqPin};
+pub use crate::fifo::FifoLevel;
+pub use crate::fixed::unpad_fixed;
+pub use crate::link_quality::LINK_QUALITY_WINDOW;
+pub use crate::payload::{Payload, PayloadTooLong};
+pub use crate::pin::{CsnTiming, NoPin, ShortDelay};
+pub use crate::power::PowerState;
+pub use crate::registers::Status;
+pub use crate::retry::{RetryError, RetryPolicy};
+pub use crate::state::{Divergence, SavedState, SAVED_STATE_LEN};
+pub use crate::stats::{PipeStats, Stats};
+pub use crate::test_mode::PllLockTest;
+#[cfg(feature = "defmt")]
+pub use crate::trace::DefmtTrace;
+#[cfg(feature = "log")]
+pub use crate::trace::LogTrace;
+#[cfg(feature = "trace")]
+pub use crate::trace::{Trace, TraceEvent};
 
-use crate::command::{FlushTx, ReadRxPayload, ReadRxPayloadWidth, WriteTxPayload};
+use crate::channels::{Channel, ChannelMask, CHANNEL_COUNT};
+use crate::command::{
+    Activate, FlushRx, FlushTx, Nop, ReadRawRegister, ReadRxPayload, ReadRxPayloadWidth,
+    WriteAckPayload, WriteRawRegister, WriteTxPayload, WriteTxPayloadNoAck,
+};
 use crate::device::{Device, DeviceImpl};
-use crate::registers::{FifoStatus, Status};
-use core::fmt::Debug;
-use embedded_hal::blocking::spi::Transfer;
+use crate::link_quality::LinkWindow;
+use crate::registers::{Feature, FifoStatus, ObserveTx, RfSetup, CD};
+use crate::shared::{InterruptFree, RadioRx, RadioTx, SharedNrf24};
+use core::fmt::{self, Debug};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::{Transfer, Write};
 use embedded_hal::digital::v2::OutputPin;
 
 /// Number of RX pipes with configurable addresses
@@ -33,12 +137,27 @@ pub const NUM_PIPES: usize = 6;
 pub const RX_ADDR_LEN: usize = 5;
 pub const RX_ADDR_PREFIX_LEN: usize = 4;
 pub const PAYLOAD_LEN: usize = 32;
+/// Packets the TX FIFO can hold
+pub const TX_FIFO_LEN: usize = 3;
+/// Start-up time from power down to standby, `Tpd2stby`
+pub const POWER_UP_DELAY_US: u32 = 1500;
+/// Start-up time with an external PA/LNA, see [`Config::pa_lna()`](struct.Config.html#method.pa_lna)
+pub const PA_LNA_POWER_UP_DELAY_US: u32 = 5000;
+/// Size of the buffers for [`send_from()`](struct.Nrf24l01.html#method.send_from)
+/// and [`read_into()`](struct.Nrf24l01.html#method.read_into): command
+/// byte and payload
+pub const DMA_BUFFER_LEN: usize = 1 + PAYLOAD_LEN;
+/// Minimum CE high time to start a transmission, `Thce`
+pub const CE_PULSE_US: u32 = 10;
+/// Spacing of the samples taken by [`Nrf24l01::sample_rpd()`](struct.Nrf24l01.html#method.sample_rpd),
+/// the time a signal has to be present for `RPD` to rise
+pub const RPD_SAMPLE_INTERVAL_US: u32 = 40;
 
 pub trait Nrf24l01Rx {
     type Error;
 
-    fn set_address(&mut self, pipe: usize, address: &[u8]) -> Result<(), Self::Error>;
-    fn ready(&mut self) -> Result<u8, nb::Error<Self::Error>>;
+    fn set_address(&mut self, pipe: Pipe, address: &[u8]) -> Result<(), Self::Error>;
+    fn ready(&mut self) -> Result<Pipe, nb::Error<Self::Error>>;
     fn recv(&mut self) -> Result<Payload, nb::Error<Self::Error>>;
 }
 
@@ -76,10 +195,10 @@ where
 {
     type Error = SpiE;
 
-    fn set_address(&mut self, pipe: usize, address: &[u8]) -> Result<(), Self::Error> {
+    fn set_address(&mut self, pipe: Pipe, address: &[u8]) -> Result<(), Self::Error> {
         self.nrf24l01.borrow_mut().set_rx_addr(pipe, address)
     }
-    fn ready(&mut self) -> Result<u8, nb::Error<Self::Error>> {
+    fn ready(&mut self) -> Result<Pipe, nb::Error<Self::Error>> {
         self.nrf24l01.borrow_mut().wait_rx_ready()
     }
     fn recv(&mut self) -> Result<Payload, nb::Error<Self::Error>> {
@@ -144,7 +263,8 @@ pub struct Config {
     auto_retransmit_delay: u8,
     auto_retransmit_count: u8,
     data_rate: DataRate,
-    power: u8,
+    power: Option<u8>,
+    output_power: Option<OutputPower>,
     crc_mode: CrcMode,
     frequency: u8,
     rx_prefix: Option<[u8; RX_ADDR_PREFIX_LEN]>,
@@ -152,15 +272,25 @@ pub struct Config {
     rx_length: [Option<u8>; NUM_PIPES],
     rx_auto_ack: [bool; NUM_PIPES],
     rx_addr: [u8; NUM_PIPES],
+    chip_variant: ChipVariant,
+    verify_writes: bool,
+    pa_lna: bool,
+    csn_timing: Option<CsnTiming>,
+    dynamic_payloads: bool,
+    ack_payloads: bool,
+    /// Length of pipes without one of their own, ShockBurst classic only
+    fixed_length: Option<u8>,
 }
 
 impl Config {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Self {
         Self {
             auto_retransmit_delay: 1,
             auto_retransmit_count: 10,
             data_rate: DataRate::R250Kbps,
-            power: 3,
+            power: None,
+            output_power: None,
             crc_mode: CrcMode::TwoBytes,
             frequency: 42,
             rx_prefix: None,
@@ -168,12 +298,33 @@ impl Config {
             rx_length: [None; NUM_PIPES],
             rx_auto_ack: [true; NUM_PIPES],
             rx_addr: [0; NUM_PIPES],
+            chip_variant: ChipVariant::Plus,
+            verify_writes: false,
+            pa_lna: false,
+            csn_timing: None,
+            dynamic_payloads: true,
+            ack_payloads: false,
+            fixed_length: None,
         }
     }
+    /// Raw `SETUP_RETR.ARD` value, the delay is `250 * (delay + 1)` µs
     pub fn auto_retransmit_delay(mut self, delay: u8) -> Self {
         self.auto_retransmit_delay = delay;
         self
     }
+    /// Retransmit after at least `delay_us`, rounded up to 250 µs steps
+    ///
+    /// Panics beyond 4000 µs.
+    pub fn auto_retransmit_delay_us(mut self, delay_us: u32) -> Self {
+        self.auto_retransmit_delay =
+            timing::retransmit_delay_ard(delay_us).expect("retransmit delay too long");
+        self
+    }
+    /// Like [`auto_retransmit_delay_us()`](#method.auto_retransmit_delay_us)
+    #[cfg(feature = "fugit")]
+    pub fn auto_retransmit_delay_duration(self, delay: fugit::MicrosDurationU32) -> Self {
+        self.auto_retransmit_delay_us(delay.ticks())
+    }
     pub fn auto_retransmit_count(mut self, count: u8) -> Self {
         self.auto_retransmit_count = count;
         self
@@ -182,51 +333,224 @@ impl Config {
         self.data_rate = rate;
         self
     }
+    /// Raw level as taken by [`set_rf()`](trait.Configuration.html#method.set_rf)
+    ///
+    /// Defaults to `3`, which is 0 dBm, or to -12 dBm with
+    /// [`pa_lna()`](#method.pa_lna).
     pub fn power(mut self, power: u8) -> Self {
-        self.power = power;
+        self.power = Some(power);
+        self.output_power = None;
+        self
+    }
+    /// Overrides [`power()`](#method.power)
+    pub fn output_power(mut self, power: OutputPower) -> Self {
+        self.output_power = Some(power);
         self
     }
     pub fn crc_mode(mut self, mode: CrcMode) -> Self {
         self.crc_mode = mode;
         self
     }
+    /// RF channel number, see [`Channel`](channels/struct.Channel.html)
+    ///
+    /// [`validate()`](#method.validate) reports channels out of range.
     pub fn frequency(mut self, freq: u8) -> Self {
         self.frequency = freq;
         self
     }
+    /// Full address of pipe 1, which pipes 2 to 5 share except for the first byte
+    ///
+    /// Panics unless `address` is 5 bytes long.
+    pub fn rx_address(mut self, address: Address) -> Self {
+        assert_eq!(address.len(), RX_ADDR_LEN);
+        let bytes = address.as_bytes();
+        self.rx_addr[1] = bytes[0];
+        let mut prefix = [0; RX_ADDR_PREFIX_LEN];
+        prefix.copy_from_slice(&bytes[1..]);
+        self.rx_prefix = Some(prefix);
+        self
+    }
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.frequency = channel.number();
+        self
+    }
+    pub fn chip_variant(mut self, variant: ChipVariant) -> Self {
+        self.chip_variant = variant;
+        self
+    }
+    /// See [`Nrf24l01::set_verify_writes()`](struct.Nrf24l01.html#method.set_verify_writes)
+    pub fn verify_writes(mut self, verify_writes: bool) -> Self {
+        self.verify_writes = verify_writes;
+        self
+    }
+    /// Module with an external PA and LNA, like the E01-ML01DP5 or the
+    /// nRF24L01+PA+LNA boards
+    ///
+    /// Lowers the default output power to -12 dBm, as at 0 dBm the
+    /// amplified signal saturates the receiving LNA at short range, and
+    /// waits [`PA_LNA_POWER_UP_DELAY_US`](constant.PA_LNA_POWER_UP_DELAY_US.html)
+    /// for the supply to settle when powering up. An explicit
+    /// [`power()`](#method.power) or [`output_power()`](#method.output_power)
+    /// still wins. The LNA gain itself is fixed in hardware: if two such
+    /// modules sit within a meter of each other, lower the power on both
+    /// sides or move them apart.
+    pub fn pa_lna(mut self, pa_lna: bool) -> Self {
+        self.pa_lna = pa_lna;
+        self
+    }
+    /// Delays around CSN edges, see [`CsnTiming`](struct.CsnTiming.html)
+    pub fn csn_timing(mut self, timing: CsnTiming) -> Self {
+        self.csn_timing = Some(timing);
+        self
+    }
     pub fn rx_prefix(mut self, prefix: [u8; RX_ADDR_PREFIX_LEN]) -> Self {
         self.rx_prefix = Some(prefix);
         self
     }
-    pub fn rx_full(mut self, pipe: u8, address: u8, length: u8, auto_ack: bool) -> Self {
-        assert!(pipe >= 1);
-        assert!(pipe < 6);
-        let pipe = pipe as usize;
+    /// Pipe 0 is reserved for receiving acks
+    pub fn rx_full(mut self, pipe: Pipe, address: u8, length: u8, auto_ack: bool) -> Self {
+        assert!(pipe != Pipe::P0);
+        let pipe = pipe.index();
         self.rx_enabled[pipe] = true;
         self.rx_addr[pipe] = address;
         self.rx_length[pipe] = Some(length);
         self.rx_auto_ack[pipe] = auto_ack;
         self
     }
-    pub fn rx(mut self, pipe: u8, address: u8) -> Self {
-        assert!(pipe >= 1);
-        assert!(pipe < 6);
-        let pipe = pipe as usize;
+    /// Pipe 0 is reserved for receiving acks
+    pub fn rx(mut self, pipe: Pipe, address: u8) -> Self {
+        assert!(pipe != Pipe::P0);
+        let pipe = pipe.index();
         self.rx_enabled[pipe] = true;
         self.rx_addr[pipe] = address;
         self
     }
+    /// Enabled on all pipes by default
+    pub fn auto_ack(mut self, pipe: Pipe, auto_ack: bool) -> Self {
+        self.rx_auto_ack[pipe.index()] = auto_ack;
+        self
+    }
+    /// `EN_DPL`, on by default
+    ///
+    /// Without it, every enabled pipe needs a fixed length from
+    /// [`rx_full()`](#method.rx_full).
+    pub fn dynamic_payloads(mut self, enable: bool) -> Self {
+        self.dynamic_payloads = enable;
+        self
+    }
+    /// `EN_ACK_PAY`, which needs dynamic payloads on pipe 0 and on all
+    /// pipes that acknowledge
+    pub fn ack_payloads(mut self, enable: bool) -> Self {
+        self.ack_payloads = enable;
+        self
+    }
+    /// Turn Enhanced ShockBurst off entirely, sending and receiving
+    /// `payload_len` bytes on every pipe not given a length with
+    /// [`rx_full()`](#method.rx_full)
+    ///
+    /// Clears auto-ack on all pipes, auto-retransmit, dynamic payloads
+    /// and ack payloads. The packets then lack the packet control field
+    /// and match the ShockBurst format of the nRF2401 and nRF24E1, and
+    /// of nRF24L01 firmwares written for it. Nothing is acknowledged,
+    /// so a transmission completes right after it left the antenna,
+    /// which also makes this the fastest way to stream in one
+    /// direction. CRC stays as configured with
+    /// [`crc_mode()`](#method.crc_mode) and may be disabled.
+    pub fn shockburst_classic(mut self, payload_len: u8) -> Self {
+        assert!(payload_len > 0 && usize::from(payload_len) <= PAYLOAD_LEN);
+        self.rx_auto_ack = [false; NUM_PIPES];
+        self.auto_retransmit_delay = 0;
+        self.auto_retransmit_count = 0;
+        self.dynamic_payloads = false;
+        self.ack_payloads = false;
+        self.fixed_length = Some(payload_len);
+        self
+    }
+    /// Check for settings the chip cannot honor together
+    ///
+    /// [`Nrf24l01::new()`](struct.Nrf24l01.html#method.new) refuses such
+    /// a configuration instead of leaving the hardware to silently
+    /// override one of them.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if Channel::try_new(self.frequency).is_none() {
+            return Err(ConfigError::InvalidChannel {
+                channel: self.frequency,
+            });
+        }
+        let variant = self.chip_variant;
+        if !variant.supports_data_rate(self.data_rate) {
+            return Err(ConfigError::UnsupportedDataRate);
+        }
+        if self
+            .output_power
+            .is_some_and(|power| power.level(variant).is_none())
+            || self
+                .power
+                .is_some_and(|power| power >= variant.power_levels())
+        {
+            return Err(ConfigError::UnsupportedOutputPower);
+        }
+        // `EN_AA` forces `EN_CRC` high
+        if self.crc_mode == CrcMode::Disabled {
+            if let Some(pipe) = self.rx_auto_ack.iter().position(|&auto_ack| auto_ack) {
+                return Err(ConfigError::AutoAckWithoutCrc {
+                    pipe: Pipe::ALL[pipe],
+                });
+            }
+        }
+        for pipe in Pipe::ALL {
+            let index = pipe.index();
+            let dynamic = self.rx_length(index).is_none();
+            let enabled = self.rx_enabled[index];
+            if enabled && dynamic && !self.dynamic_payloads {
+                return Err(ConfigError::MissingLength { pipe });
+            }
+            // Pipe 0 receives the acks, and their payloads
+            let acking = index == 0 || (enabled && self.rx_auto_ack[index]);
+            if self.ack_payloads && acking && !(dynamic && self.dynamic_payloads) {
+                return Err(ConfigError::AckPayloadWithoutDynamicLength { pipe });
+            }
+        }
+        Ok(())
+    }
+    fn rx_length(&self, pipe: usize) -> Option<u8> {
+        self.rx_length[pipe].or(self.fixed_length)
+    }
     fn configure<T: Configuration>(
         self,
         device: &mut T,
     ) -> Result<(), <<T as Configuration>::Inner as Device>::Error> {
         device.set_auto_retransmit(self.auto_retransmit_delay, self.auto_retransmit_count)?;
-        device.set_rf(&self.data_rate, self.power)?;
+        let power = match self.output_power {
+            Some(power) => power
+                .level(self.chip_variant)
+                .expect("unsupported output power"),
+            None => match (self.power, self.pa_lna) {
+                (Some(power), _) => power,
+                (None, true) => OutputPower::Neg12dBm
+                    .level(self.chip_variant)
+                    .expect("unsupported output power"),
+                (None, false) => 3,
+            },
+        };
+        device.set_rf(&self.data_rate, power)?;
         device.set_crc(self.crc_mode)?;
         device.set_frequency(self.frequency)?;
         device.set_pipes_rx_enable(&self.rx_enabled)?;
-        device.set_pipes_rx_lengths(&self.rx_length)?;
+        let mut rx_length = [None; NUM_PIPES];
+        for (pipe, length) in rx_length.iter_mut().enumerate() {
+            *length = self.rx_length(pipe);
+        }
+        device.set_pipes_rx_lengths(&rx_length)?;
         device.set_auto_ack(&self.rx_auto_ack)?;
+        if !self.dynamic_payloads {
+            device
+                .device()
+                .update_register::<Feature, _, _>(|feature| feature.set_en_dpl(false))?;
+        }
+        if self.ack_payloads {
+            device.set_ack_payloads(true)?;
+        }
 
         // This improves the error rate, not sure why or if this is the best place for a wait
         wait(100);
@@ -239,11 +563,11 @@ impl Config {
                 rx_prefix[2],
                 rx_prefix[3],
             ];
-            device.set_rx_addr(1, &address)?;
+            device.set_rx_addr(Pipe::P1, &address)?;
 
-            for i in 2..NUM_PIPES {
-                if self.rx_enabled[i] {
-                    device.set_rx_addr(i, &[self.rx_addr[i]])?;
+            for &pipe in &Pipe::ALL[2..] {
+                if self.rx_enabled[pipe.index()] {
+                    device.set_rx_addr(pipe, &[self.rx_addr[pipe.index()]])?;
                 }
             }
         }
@@ -262,6 +586,18 @@ where
 {
     mode: Mode,
     device: DeviceImpl<Ce, Csn, Spi, E>,
+    csma: Option<Csma>,
+    rx_clock: Option<&'static dyn Clock>,
+    /// Taken by `capture_rx_timestamp()` for the next packet read
+    irq_timestamp_us: Option<u64>,
+    pa_lna: bool,
+    stats: Stats,
+    /// A full RX FIFO has been counted, until the next read
+    rx_overflow_counted: bool,
+    /// Ack payloads written per pipe and not yet sent
+    ack_queued: [u8; PIPES_COUNT],
+    link: LinkWindow,
+    channel_mask: ChannelMask,
 }
 impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
 where
@@ -272,19 +608,454 @@ where
     SpiE: Debug,
 {
     pub fn new(ce: Ce, csn: Csn, spi: Spi, config: Config) -> Result<Self, Error<SpiE>> {
+        config.validate().map_err(Error::InvalidConfig)?;
         let mut result = Self {
             mode: Mode::Standby,
-            device: DeviceImpl::new(ce, csn, spi)?,
+            device: DeviceImpl::new(ce, csn, spi, config.chip_variant, config.csn_timing)?,
+            csma: None,
+            rx_clock: None,
+            irq_timestamp_us: None,
+            pa_lna: false,
+            stats: Stats::default(),
+            rx_overflow_counted: false,
+            ack_queued: [0; PIPES_COUNT],
+            link: LinkWindow::new(),
+            channel_mask: ChannelMask::all(),
         };
+        result.device.set_verify_writes(config.verify_writes);
+        result.pa_lna = config.pa_lna;
+        if config.chip_variant == ChipVariant::NonPlus {
+            result.activate_features()?;
+        }
         config.configure(&mut result)?;
         result
             .device
             .update_config(|config| config.set_pwr_up(true))?;
+        result.check_writes()?;
         Ok(result)
     }
     pub fn config() -> Config {
         Config::default()
     }
+    /// Give back the pins and the SPI bus, leaving the chip as it is
+    pub fn release(self) -> (Ce, Csn, Spi) {
+        self.device.release()
+    }
+    /// Estimate the charge drawn from now on
+    ///
+    /// Integrates typical nRF24L01+ datasheet currents over the time
+    /// spent in each [`PowerState`](enum.PowerState.html).
+    pub fn track_power(&mut self, clock: &'static dyn Clock) -> Result<(), SpiE> {
+        self.device.track_power(clock)
+    }
+    /// Estimated charge in µC since [`track_power()`](#method.track_power)
+    #[allow(non_snake_case)]
+    pub fn estimated_charge_uC(&self) -> Option<u64> {
+        self.device.charge_pc().map(|charge| charge / 1_000_000)
+    }
+    pub fn power_state(&self) -> PowerState {
+        self.device.power_state()
+    }
+    /// Split into a TX and an RX handle that can be used from different
+    /// interrupt priorities
+    ///
+    /// The driver is moved into `storage`, which usually is a `static`.
+    /// See the [`shared`](shared/index.html) module.
+    #[allow(clippy::type_complexity)]
+    pub fn split<'a, CS: InterruptFree>(
+        self,
+        storage: &'a mut Option<SharedNrf24<CS, Ce, Csn, Spi, E, SpiE>>,
+    ) -> (
+        RadioTx<'a, CS, Ce, Csn, Spi, E, SpiE>,
+        RadioRx<'a, CS, Ce, Csn, Spi, E, SpiE>,
+    ) {
+        let shared: &'a SharedNrf24<CS, Ce, Csn, Spi, E, SpiE> =
+            storage.insert(SharedNrf24::new(self));
+        shared.split()
+    }
+    /// Capture the configuration as a baseline for
+    /// [`health_check()`](#method.health_check)
+    pub fn snapshot(&mut self) -> Result<SavedState, SpiE> {
+        SavedState::read(&mut self.device)
+    }
+    /// Compare the chip against the driver's `CONFIG` and against
+    /// `baseline` from [`snapshot()`](#method.snapshot)
+    ///
+    /// Call this periodically: a chip that browned out comes back with
+    /// reset values and stops hearing its peers. Returns the first
+    /// register that differs, if any; [`recover()`](#method.recover)
+    /// and [`resume()`](#method.resume) restore them.
+    pub fn health_check(&mut self, baseline: &SavedState) -> Result<Option<Divergence>, SpiE> {
+        let (_, config) = self.device.read_register_uncached::<registers::Config>()?;
+        let expected = self.device.config().0;
+        if config.0 != expected {
+            return Ok(Some(Divergence {
+                register: 0x00,
+                expected,
+                actual: config.0,
+            }));
+        }
+        let actual = SavedState::read(&mut self.device)?;
+        Ok(baseline.diff(&actual))
+    }
+    /// Capture the configuration and power down
+    ///
+    /// Waits for pending transmissions first.
+    pub fn sleep(&mut self) -> Result<SavedState, SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        let state = SavedState::read(&mut self.device)?;
+        self.power_down()?;
+        Ok(state)
+    }
+    /// Recreate the driver after the MCU has slept, restoring `state`
+    ///
+    /// All registers are written again in case the radio lost power
+    /// too. Allow [`POWER_UP_DELAY_US`](constant.POWER_UP_DELAY_US.html)
+    /// before sending or receiving. Call [`set_pa_lna()`](#method.set_pa_lna)
+    /// again for modules with an external PA/LNA,
+    /// [`set_csn_timing()`](#method.set_csn_timing) for CSN delays and
+    /// [`set_channel_mask()`](#method.set_channel_mask) for a channel mask.
+    pub fn resume(ce: Ce, csn: Csn, spi: Spi, state: &SavedState) -> Result<Self, Error<SpiE>> {
+        let mut result = Self {
+            mode: Mode::Standby,
+            device: DeviceImpl::new(ce, csn, spi, state.chip_variant(), None)?,
+            csma: None,
+            rx_clock: None,
+            irq_timestamp_us: None,
+            pa_lna: false,
+            stats: Stats::default(),
+            rx_overflow_counted: false,
+            ack_queued: [0; PIPES_COUNT],
+            link: LinkWindow::new(),
+            channel_mask: ChannelMask::all(),
+        };
+        if state.chip_variant() == ChipVariant::NonPlus {
+            result.activate_features()?;
+        }
+        let saved_config = state.write(&mut result.device)?;
+        result.device.update_config(|config| {
+            *config = saved_config;
+            config.set_pwr_up(true);
+            config.set_prim_rx(false);
+        })?;
+        Ok(result)
+    }
+    /// Enter power down mode, keeping the register contents
+    pub fn power_down(&mut self) -> Result<(), SpiE> {
+        self.device.ce_disable();
+        self.device
+            .update_config(|config| config.set_pwr_up(false))?;
+        self.mode = Mode::Standby;
+        trace!(self.device, TraceEvent::PowerDown);
+        Ok(())
+    }
+    /// Leave power down mode and wait for the oscillator to start up
+    pub fn power_up<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), SpiE> {
+        let powered_up = self.device.update_config(|config| {
+            let powered_up = config.pwr_up();
+            config.set_pwr_up(true);
+            powered_up
+        })?;
+        if !powered_up {
+            trace!(self.device, TraceEvent::PowerUp);
+            delay.delay_us(self.power_up_delay_us());
+        }
+        Ok(())
+    }
+    /// See [`Config::pa_lna()`](struct.Config.html#method.pa_lna), only
+    /// changes the power up delay here
+    pub fn set_pa_lna(&mut self, pa_lna: bool) {
+        self.pa_lna = pa_lna;
+    }
+    /// Repeat register accesses that fail with a transient SPI error
+    ///
+    /// Once `policy.attempts` are used up, the last error is returned as
+    /// before, [`retry_error()`](#method.retry_error) tells it apart.
+    /// `None` turns retrying off, which is the default.
+    pub fn set_spi_retry(&mut self, policy: Option<RetryPolicy<SpiE>>) {
+        self.device.set_retry(policy);
+    }
+    /// Classify `error`, just returned by a failed call, as
+    /// `RetryError::Exhausted` if the [retry policy](#method.set_spi_retry)
+    /// used up its attempts on it
+    pub fn retry_error(&mut self, error: SpiE) -> RetryError<SpiE> {
+        match self.device.take_exhausted_attempts() {
+            Some(attempts) => RetryError::Exhausted {
+                attempts,
+                last: error,
+            },
+            None => RetryError::Failed(error),
+        }
+    }
+    /// SPI transactions repeated under [`set_spi_retry()`](#method.set_spi_retry)
+    pub fn spi_retries(&self) -> u32 {
+        self.device.spi_retries()
+    }
+    /// See [`Config::csn_timing()`](struct.Config.html#method.csn_timing)
+    pub fn set_csn_timing(&mut self, timing: Option<CsnTiming>) {
+        self.device.set_csn_timing(timing);
+    }
+    /// Time [`power_up()`](#method.power_up) waits for the chip
+    pub fn power_up_delay_us(&self) -> u32 {
+        match self.pa_lna {
+            true => PA_LNA_POWER_UP_DELAY_US,
+            false => POWER_UP_DELAY_US,
+        }
+    }
+    pub fn chip_variant(&self) -> ChipVariant {
+        self.device.chip_variant()
+    }
+    /// Bring a latched-up chip back into a known state
+    ///
+    /// Lowers CE, flushes both FIFOs, clears all interrupts, writes
+    /// `CONFIG`, `EN_AA`, `EN_RXADDR`, `FEATURE` and `DYNPD` again as the
+    /// driver knows them, and power cycles via `PWR_UP`. Fails with
+    /// `NotConnected` if the chip still does not answer sensibly.
+    pub fn recover<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<SpiE>> {
+        trace!(self.device, TraceEvent::Recover);
+        self.device.ce_disable();
+        self.mode = Mode::Standby;
+        self.device.send_command(&FlushTx)?;
+        self.device.send_command(&FlushRx)?;
+        self.clear_interrupts()?;
+        if self.chip_variant() == ChipVariant::NonPlus {
+            self.activate_features()?;
+        }
+        self.device.rewrite_registers()?;
+        self.check_writes()?;
+        delay.delay_us(self.power_up_delay_us());
+        match self.device.is_connected()? {
+            true => Ok(()),
+            false => Err(Error::NotConnected),
+        }
+    }
+    /// Read back every register after writing it
+    ///
+    /// Meant for hunting down wiring and signal integrity faults, it
+    /// doubles the SPI traffic of register writes. Mismatches are
+    /// reported by [`check_writes()`](#method.check_writes).
+    pub fn set_verify_writes(&mut self, verify_writes: bool) {
+        self.device.set_verify_writes(verify_writes);
+    }
+    /// Report the first register write that did not read back as
+    /// written since the last call, as `Error::VerificationFailed`
+    ///
+    /// Always `Ok` unless [`set_verify_writes()`](#method.set_verify_writes)
+    /// is on. [`new()`](#method.new) and [`recover()`](#method.recover)
+    /// check on their own.
+    pub fn check_writes(&mut self) -> Result<(), Error<SpiE>> {
+        match self.device.take_verification_failure() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+    /// Read the first byte of the register at `addr`, bypassing the
+    /// typed register layer and the cache
+    ///
+    /// For experimenting with undocumented or clone-specific registers.
+    /// Panics if `addr` is not below `0x20`.
+    pub fn read_raw_register(&mut self, addr: u8) -> Result<u8, SpiE> {
+        let (_, value) = self.device.send_command(&ReadRawRegister::new(addr))?;
+        Ok(value)
+    }
+    /// Write one byte to the register at `addr`, bypassing the typed
+    /// register layer
+    ///
+    /// The register cache is invalidated, but the driver's own idea of
+    /// `CONFIG` and its mode are not updated: leave `CONFIG` alone.
+    /// Panics if `addr` is not below `0x20`.
+    pub fn write_raw_register(&mut self, addr: u8, value: u8) -> Result<(), SpiE> {
+        self.device
+            .send_command(&WriteRawRegister::new(addr, value))?;
+        self.device.invalidate_cache();
+        Ok(())
+    }
+    /// Read `EN_AA`, `EN_RXADDR`, `DYNPD` and `FEATURE` from the chip
+    /// again next time
+    ///
+    /// Needed only if they may have changed behind the driver's back,
+    /// like after a reset of the chip.
+    pub fn invalidate_cache(&mut self) {
+        self.device.invalidate_cache();
+    }
+    /// Send `ACTIVATE` unless `FEATURE` is already writable
+    ///
+    /// Returns whether `ACTIVATE` was needed.
+    fn activate_features(&mut self) -> Result<bool, SpiE> {
+        let (_, saved) = self.device.read_register::<Feature>()?;
+        let mut probe = Feature(0);
+        probe.set_en_dpl(true);
+        // The probe is expected to fail on the nRF24L01
+        let verify_writes = self.device.set_verify_writes(false);
+        let written = self.device.write_register(probe);
+        self.device.set_verify_writes(verify_writes);
+        written?;
+        // Check whether the write took effect
+        self.device.invalidate_cache();
+        let (_, feature) = self.device.read_register::<Feature>()?;
+        if !feature.en_dpl() {
+            self.device.send_command(&Activate)?;
+        }
+        self.device.write_register(saved)?;
+        Ok(!feature.en_dpl())
+    }
+    /// Whether `RF_DR_LOW` and bit 0 of `RF_SETUP` stick
+    fn probe_rf_setup(device: &mut DeviceImpl<Ce, Csn, Spi, E>) -> Result<(bool, bool), SpiE> {
+        let mut probe = RfSetup(0);
+        probe.set_rf_dr_low(true);
+        device.write_register(probe)?;
+        let (_, readback) = device.read_register::<RfSetup>()?;
+        let supports_250kbps = readback.rf_dr_low();
+
+        let mut probe = RfSetup(0);
+        probe.set_lna_hcurr(true);
+        device.write_register(probe)?;
+        let (_, readback) = device.read_register::<RfSetup>()?;
+        Ok((supports_250kbps, readback.lna_hcurr()))
+    }
+    /// Probe which chip is connected and switch to its quirks
+    ///
+    /// The nRF24L01 lacks 250 Kbps, the SI24R1 keeps bit 0 of
+    /// `RF_SETUP` which the nRF24L01+ ignores. This is a heuristic:
+    /// set the variant in [`Config`](struct.Config.html) when it is known.
+    pub fn detect_chip(&mut self) -> Result<ChipInfo, SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        let (_, saved) = self.device.read_register::<RfSetup>()?;
+        // The probes are expected not to read back as written
+        let verify_writes = self.device.set_verify_writes(false);
+        let probes = Self::probe_rf_setup(&mut self.device);
+        self.device.set_verify_writes(verify_writes);
+        let (supports_250kbps, keeps_bit_0) = probes?;
+        self.device.write_register(saved)?;
+
+        let variant = if !supports_250kbps {
+            ChipVariant::NonPlus
+        } else if keeps_bit_0 {
+            ChipVariant::Si24r1
+        } else {
+            ChipVariant::Plus
+        };
+        self.device.set_chip_variant(variant);
+        let needed_activate = self.activate_features()?;
+        Ok(ChipInfo {
+            variant,
+            supports_250kbps,
+            needed_activate,
+        })
+    }
+    /// Listen for a carrier on the current channel
+    ///
+    /// Enters RX mode and waits until the `CD` (nRF24L01) or `RPD`
+    /// (nRF24L01+) register is valid before sampling it.
+    pub fn has_carrier<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<bool, nb::Error<SpiE>> {
+        self.rx()?;
+        delay.delay_us(self.chip_variant().carrier_detect_delay_us());
+        let (_, cd) = self.device.read_register::<CD>()?;
+        Ok(cd.0 & 1 == 1)
+    }
+    /// Sample `CD`/`RPD` `n` times on the current channel
+    ///
+    /// Returns the share of samples that saw a carrier in percent, as
+    /// a measure of how busy the channel is. Enters RX mode and waits
+    /// for the register to become valid first, like
+    /// [`has_carrier()`](#method.has_carrier).
+    pub fn sample_rpd<D: DelayUs<u32>>(
+        &mut self,
+        n: u16,
+        delay: &mut D,
+    ) -> Result<u8, nb::Error<SpiE>> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.rx()?;
+        delay.delay_us(self.chip_variant().carrier_detect_delay_us());
+        let mut busy = 0u32;
+        for i in 0..n {
+            if i > 0 {
+                delay.delay_us(RPD_SAMPLE_INTERVAL_US);
+            }
+            let (_, cd) = self.device.read_register::<CD>()?;
+            busy += u32::from(cd.0 & 1);
+        }
+        Ok((busy * 100 / u32::from(n)) as u8)
+    }
+    /// Clear channel assessment: whether `channel` is free of carriers
+    ///
+    /// Waits for the TX FIFO to drain, retunes, listens for the RX
+    /// settling time plus `dwell_us` and samples `CD`/`RPD` once, then
+    /// returns to the previous channel and mode. A channel blocked by
+    /// the [channel mask](#method.set_channel_mask) is never tuned to
+    /// and never free.
+    pub fn cca<D: DelayUs<u32>>(
+        &mut self,
+        channel: Channel,
+        dwell_us: u32,
+        delay: &mut D,
+    ) -> Result<bool, SpiE> {
+        if !self.channel_mask.is_allowed(channel.number()) {
+            return Ok(false);
+        }
+        nb::block!(self.wait_tx_empty())?;
+        let previous_mode = self.mode;
+        let previous_channel = self.get_frequency()?;
+        // Restart RX so that the PLL settles on the new channel
+        self.standby();
+        self.set_channel(channel)?;
+        nb::block!(self.rx())?;
+        delay.delay_us(self.chip_variant().carrier_detect_delay_us() + dwell_us);
+        let (_, cd) = self.device.read_register::<CD>()?;
+        self.standby();
+        self.set_frequency(previous_channel)?;
+        match previous_mode {
+            Mode::Rx => nb::block!(self.rx())?,
+            Mode::Tx => self.tx()?,
+            Mode::Standby => {}
+        }
+        Ok(cd.0 & 1 == 0)
+    }
+    /// Sweep all 126 channels `passes` times, counting carriers
+    ///
+    /// `occupancy[n]` ends up with the number of passes that saw a
+    /// carrier on channel `n`, saturating at 255. Every channel gets the
+    /// full RX settling time before `CD`/`RPD` is sampled, so a sweep
+    /// takes about 22 ms on the nRF24L01+. Waits for the TX FIFO to
+    /// drain first and returns to the previous channel and mode.
+    /// Channels blocked by the [channel mask](#method.set_channel_mask)
+    /// are skipped and reported as always busy, `255`.
+    pub fn scan_spectrum<D: DelayUs<u32>>(
+        &mut self,
+        passes: u16,
+        occupancy: &mut [u8; CHANNEL_COUNT as usize],
+        delay: &mut D,
+    ) -> Result<(), SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        let previous_mode = self.mode;
+        let previous_channel = self.get_frequency()?;
+        let settle_us = self.chip_variant().carrier_detect_delay_us();
+        *occupancy = [0; CHANNEL_COUNT as usize];
+        for _ in 0..passes {
+            for (channel, count) in (0..CHANNEL_COUNT).zip(occupancy.iter_mut()) {
+                if !self.channel_mask.is_allowed(channel) {
+                    *count = u8::MAX;
+                    continue;
+                }
+                self.standby();
+                self.set_frequency(channel)?;
+                nb::block!(self.rx())?;
+                delay.delay_us(settle_us);
+                let (_, cd) = self.device.read_register::<CD>()?;
+                *count = count.saturating_add(cd.0 & 1);
+            }
+        }
+        self.standby();
+        self.set_frequency(previous_channel)?;
+        match previous_mode {
+            Mode::Rx => nb::block!(self.rx())?,
+            Mode::Tx => self.tx()?,
+            Mode::Standby => {}
+        }
+        Ok(())
+    }
     fn clear(&mut self, interrupts: Interrupts) -> Result<(), SpiE> {
         let mut clear = Status(0);
         clear.set_rx_dr(interrupts.rx_dr);
@@ -293,6 +1064,22 @@ where
         self.device.write_register(clear)?;
         Ok(())
     }
+    /// Read `STATUS` with a `NOP`, without touching any register
+    ///
+    /// Shows the `RX_DR`, `TX_DS` and `MAX_RT` interrupt flags, the pipe
+    /// of the next packet in the RX FIFO, and whether the TX FIFO is full.
+    pub fn status(&mut self) -> Result<Status, SpiE> {
+        let (status, ()) = self.device.send_command(&Nop)?;
+        Ok(status)
+    }
+    /// `STATUS` as sent along with the most recent SPI transaction
+    ///
+    /// Costs nothing, but may be stale: flags raised since, or packets
+    /// that arrived, only show with the next command. See
+    /// [`status()`](#method.status) for a fresh one.
+    pub fn last_status(&self) -> Status {
+        self.device.last_status()
+    }
     pub fn clear_interrupts(&mut self) -> Result<(), SpiE> {
         self.clear(Interrupts::new().set_rx_dr().set_tx_ds().set_max_rt())?;
         Ok(())
@@ -303,9 +1090,9 @@ where
         }
         self.wait_tx_empty()?;
         self.device.ce_enable();
-        self.device
-            .update_config(|config| config.set_prim_rx(true))?;
+        self.set_prim_rx(true)?;
         self.mode = Mode::Rx;
+        trace!(self.device, TraceEvent::Rx);
         Ok(())
     }
     fn tx(&mut self) -> Result<(), SpiE> {
@@ -313,24 +1100,192 @@ where
             return Ok(());
         }
         self.device.ce_disable();
-        self.device
-            .update_config(|config| config.set_prim_rx(false))?;
+        self.set_prim_rx(false)?;
         self.mode = Mode::Tx;
+        trace!(self.device, TraceEvent::Tx);
+        Ok(())
+    }
+    fn set_prim_rx(&mut self, prim_rx: bool) -> Result<(), SpiE> {
+        if !self.device.ce_tied_high() {
+            return self
+                .device
+                .update_config(|config| config.set_prim_rx(prim_rx));
+        }
+        // Without CE the only way through standby is a power cycle
+        let powered_up = self.device.update_config(|config| {
+            let powered_up = config.pwr_up();
+            if config.prim_rx() != prim_rx {
+                config.set_pwr_up(false);
+                config.set_prim_rx(prim_rx);
+            }
+            powered_up
+        })?;
+        self.device
+            .update_config(|config| config.set_pwr_up(powered_up))
+    }
+    /// Enable or disable listen-before-talk in [`send()`](#method.send)
+    ///
+    /// `send()` then returns `WouldBlock` until the channel has been
+    /// sampled after the carrier detect delay and found clear, see
+    /// [`Csma`](struct.Csma.html) for the timing.
+    pub fn set_csma(&mut self, csma: Option<Csma>) {
+        self.csma = csma;
+    }
+    /// Keep off the channels that `mask` blocks
+    ///
+    /// Retunes to the nearest allowed channel if the current one is
+    /// blocked. [`cca()`](#method.cca), [`scan_spectrum()`](#method.scan_spectrum),
+    /// the sniffer [`Follower`](sniffer/struct.Follower.html) and the
+    /// [`tdma`](tdma/index.html) and [`beacon`](beacon/index.html)
+    /// helpers honour it too. Panics if `mask` allows no channel.
+    pub fn set_channel_mask(&mut self, mask: ChannelMask) -> Result<(), SpiE> {
+        assert!(mask.count() > 0, "no channel allowed");
+        self.channel_mask = mask;
+        self.enforce_channel_mask()
+    }
+    pub fn channel_mask(&self) -> &ChannelMask {
+        &self.channel_mask
+    }
+    /// Retune to the nearest allowed channel if the current one is blocked
+    pub(crate) fn enforce_channel_mask(&mut self) -> Result<(), SpiE> {
+        let channel = self.get_frequency()?;
+        match self.channel_mask.nearest(channel) {
+            Some(allowed) if allowed != channel => self.set_frequency(allowed),
+            _ => Ok(()),
+        }
+    }
+    /// Sample the channel before transmitting, deferring while it is busy
+    fn listen_before_talk(&mut self) -> Result<(), nb::Error<SpiE>> {
+        let mut csma = match self.csma.take() {
+            Some(csma) => csma,
+            None => return Ok(()),
+        };
+        let result = self.check_channel(&mut csma);
+        self.csma = Some(csma);
+        result
+    }
+    fn check_channel(&mut self, csma: &mut Csma) -> Result<(), nb::Error<SpiE>> {
+        let now_us = csma.now_us();
+        if csma.defer(now_us) {
+            return Err(nb::Error::WouldBlock);
+        }
+        if self.mode != Mode::Rx {
+            self.rx()?;
+            csma.listening(now_us);
+        }
+        // CD/RPD is only valid once the carrier detection has settled
+        let settle_us = self.chip_variant().carrier_detect_delay_us();
+        if !csma.settled(now_us, settle_us) {
+            return Err(nb::Error::WouldBlock);
+        }
+        let (_, cd) = self.device.read_register::<CD>()?;
+        if cd.0 & 1 == 1 && csma.busy(now_us, settle_us) {
+            return Err(nb::Error::WouldBlock);
+        }
+        csma.reset();
         Ok(())
     }
+    /// Queue `packet` for transmission
+    ///
+    /// With [`set_csma()`](#method.set_csma) this first listens on the
+    /// channel and returns `WouldBlock` until it is clear.
     pub fn send(&mut self, packet: &[u8]) -> Result<(), nb::Error<SpiE>> {
+        self.listen_before_talk()?;
+        self.tx()?;
+        self.wait_tx_empty()?;
+        self.device.send_command(&WriteTxPayload::new(packet))?;
+        self.device.ce_enable();
+        Ok(())
+    }
+    /// Queue `packet` without asking for an acknowledgement
+    ///
+    /// The receiver neither acknowledges nor expects retransmits, and
+    /// [`wait_tx_done()`](#method.wait_tx_done) reports success as soon
+    /// as the packet is out. Requires
+    /// [`set_dyn_ack(true)`](trait.Configuration.html#method.set_dyn_ack).
+    pub fn send_no_ack(&mut self, packet: &[u8]) -> Result<(), nb::Error<SpiE>> {
+        self.listen_before_talk()?;
+        self.tx()?;
+        self.wait_tx_empty()?;
+        self.device
+            .send_command(&WriteTxPayloadNoAck::new(packet))?;
+        self.device.ce_enable();
+        Ok(())
+    }
+    /// Queue `packet` and pulse CE instead of holding it high
+    ///
+    /// The chip sends exactly this packet, including retransmits and
+    /// waiting for the ack, and then drops back to Standby-I on its
+    /// own instead of idling in Standby-II with the PLL running. Use
+    /// [`wait_tx_done()`](#method.wait_tx_done) for the outcome.
+    pub fn send_pulsed<D: DelayUs<u32>>(
+        &mut self,
+        packet: &[u8],
+        delay: &mut D,
+    ) -> Result<(), nb::Error<SpiE>> {
+        self.listen_before_talk()?;
         self.tx()?;
         self.wait_tx_empty()?;
         self.device.send_command(&WriteTxPayload::new(packet))?;
         self.device.ce_enable();
+        delay.delay_us(CE_PULSE_US);
+        self.device.ce_disable();
         Ok(())
     }
+    /// Send up to [`TX_FIFO_LEN`](constant.TX_FIFO_LEN.html) packets in
+    /// order, queuing all of them or none
+    ///
+    /// Waits for the TX FIFO to drain, queues every packet before any
+    /// goes out, then releases them one by one with a CE pulse. Returns
+    /// `None` once all have been acknowledged, or the index of the first
+    /// packet that reached the maximum number of retransmits, in which
+    /// case it and the ones after it are flushed unsent. An SPI error
+    /// while queuing flushes what was queued so far. Panics with more
+    /// packets than fit into the FIFO.
+    ///
+    /// With [`set_csma()`](#method.set_csma), blocks until the channel
+    /// is clear first.
+    pub fn send_ordered<D: DelayUs<u32>>(
+        &mut self,
+        packets: &[&[u8]],
+        delay: &mut D,
+    ) -> Result<Option<usize>, SpiE> {
+        assert!(packets.len() <= TX_FIFO_LEN);
+        nb::block!(self.listen_before_talk())?;
+        nb::block!(self.wait_tx_empty())?;
+        for packet in packets {
+            if let Err(e) = self.device.send_command(&WriteTxPayload::new(packet)) {
+                // Leave no partial batch behind, the error is what counts
+                let _ = self.flush_tx();
+                return Err(e);
+            }
+        }
+        for index in 0..packets.len() {
+            self.device.ce_enable();
+            delay.delay_us(CE_PULSE_US);
+            self.device.ce_disable();
+            if !nb::block!(self.wait_tx_done())? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+    /// Poll for room in the TX FIFO
+    ///
+    /// Costs no SPI transaction if the `STATUS` of the last command
+    /// already showed room, and a single-byte `NOP` otherwise. Only
+    /// after `MAX_RT` do `FLUSH_TX` and the interrupt clear follow.
     pub fn wait_tx_ready(&mut self) -> Result<(), nb::Error<SpiE>> {
         self.tx()?;
-        let (mut status, _) = self.device.read_register::<FifoStatus>()?;
+        if self.device.tx_ready() {
+            return Ok(());
+        }
+        // `TX_FULL` and `MAX_RT` are both in `STATUS`
+        let (mut status, ()) = self.device.send_command(&Nop)?;
         if status.max_rt() {
             (status, ()) = self.device.send_command(&FlushTx)?;
             self.clear(Interrupts::new().set_max_rt())?;
+            self.record_tx_failed();
         }
         match status.tx_full() {
             true => Err(nb::Error::WouldBlock),
@@ -343,6 +1298,7 @@ where
         if status.max_rt() {
             self.device.send_command(&FlushTx)?;
             self.clear(Interrupts::new().set_max_rt())?;
+            self.record_tx_failed();
         }
         match fifo_status.tx_empty() {
             true => {
@@ -352,25 +1308,252 @@ where
             false => Err(nb::Error::WouldBlock),
         }
     }
-    pub fn wait_rx_ready(&mut self) -> Result<u8, nb::Error<SpiE>> {
-        self.rx()?;
+    /// Send one packet to `address` and wait for its outcome
+    ///
+    /// Pipe 0 is pointed at `address` as well so that the auto-ack can
+    /// be received. Returns whether the packet has been acknowledged.
+    pub fn send_to(&mut self, address: &[u8], packet: &[u8]) -> Result<bool, SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        self.set_tx_addr(address)?;
+        self.set_rx_addr(Pipe::P0, address)?;
+        nb::block!(self.send(packet))?;
+        nb::block!(self.wait_tx_done())
+    }
+    /// Wait for the outcome of the packet in flight
+    ///
+    /// Returns `true` once it has been acknowledged, `false` if it has
+    /// been dropped after reaching the maximum number of retransmits.
+    ///
+    /// Once the TX FIFO has run empty, CE is lowered so that the chip
+    /// rests in Standby-I instead of Standby-II.
+    pub fn wait_tx_done(&mut self) -> Result<bool, nb::Error<SpiE>> {
+        self.tx()?;
         let (status, fifo_status) = self.device.read_register::<FifoStatus>()?;
-        match fifo_status.rx_empty() {
-            true => Err(nb::Error::WouldBlock),
-            false => Ok(status.rx_p_no()),
+        if status.max_rt() {
+            self.device.send_command(&FlushTx)?;
+            self.clear(Interrupts::new().set_max_rt())?;
+            self.standby();
+            self.record_tx_failed();
+            return Ok(false);
         }
+        match status.tx_ds() {
+            true => {
+                let (_, observe_tx) = self.device.read_register::<ObserveTx>()?;
+                self.record_tx_done(observe_tx.arc_cnt());
+                self.clear(Interrupts::new().set_tx_ds())?;
+                if fifo_status.tx_empty() {
+                    self.standby();
+                }
+                Ok(true)
+            }
+            false => Err(nb::Error::WouldBlock),
+        }
+    }
+    fn standby(&mut self) {
+        self.device.ce_disable();
+        self.mode = Mode::Standby;
+        trace!(self.device, TraceEvent::Standby);
     }
+    /// Poll for a received packet, returning its pipe
+    ///
+    /// Costs no SPI transaction at all if the `STATUS` of the last
+    /// command already showed a packet, and a single-byte `NOP`
+    /// otherwise.
+    pub fn wait_rx_ready(&mut self) -> Result<Pipe, nb::Error<SpiE>> {
+        self.rx()?;
+        if let Some(pipe) = self.device.pending_rx_pipe() {
+            return Ok(pipe);
+        }
+        // `RX_P_NO` reads as empty when the RX FIFO is
+        let (status, ()) = self.device.send_command(&Nop)?;
+        status.rx_p_no().ok_or(nb::Error::WouldBlock)
+    }
+    /// Take one packet out of the RX FIFO
+    ///
+    /// With [`set_rx_clock()`](#method.set_rx_clock), the payload
+    /// carries a timestamp. A packet with a corrupt length, over 32
+    /// bytes, is dropped along with the rest of the RX FIFO as the
+    /// datasheet requires, and `WouldBlock` returned.
     pub fn read(&mut self) -> Result<Payload, nb::Error<SpiE>> {
         self.rx()?;
-        let (_, payload_width) = self.device.send_command(&ReadRxPayloadWidth)?;
-        let (_, payload) = self
-            .device
-            .send_command(&ReadRxPayload::new(payload_width as usize))?;
+        let (status, len) = self.rx_payload_width()?;
+        let len = len.ok_or(nb::Error::WouldBlock)?;
+        let (_, mut payload) = self.device.send_command(&ReadRxPayload::new(len))?;
+        if let Some(clock) = self.rx_clock {
+            let timestamp_us = self
+                .irq_timestamp_us
+                .take()
+                .unwrap_or_else(|| clock.now_us());
+            payload.set_timestamp_us(Some(timestamp_us));
+        }
+        self.record_rx(status.rx_p_no(), payload.len());
         Ok(payload)
     }
+    /// `R_RX_PL_WID`, at most [`PAYLOAD_LEN`](constant.PAYLOAD_LEN.html)
+    ///
+    /// A larger value means the packet at the head of the RX FIFO is
+    /// corrupt. The datasheet requires flushing the RX FIFO then, which
+    /// this does before returning `None`.
+    fn rx_payload_width(&mut self) -> Result<(Status, Option<usize>), SpiE> {
+        let (status, payload_width) = self.device.send_command(&ReadRxPayloadWidth)?;
+        let len = usize::from(payload_width);
+        if len <= PAYLOAD_LEN {
+            return Ok((status, Some(len)));
+        }
+        self.device.send_command(&FlushRx)?;
+        self.clear(Interrupts::new().set_rx_dr())?;
+        Ok((status, None))
+    }
+    /// Timestamp received packets with `clock`, or stop with `None`
+    ///
+    /// Packets are stamped when [`read()`](#method.read) takes them out
+    /// of the FIFO, unless
+    /// [`capture_rx_timestamp()`](#method.capture_rx_timestamp) ran first.
+    pub fn set_rx_clock(&mut self, clock: Option<&'static dyn Clock>) {
+        self.rx_clock = clock;
+        self.irq_timestamp_us = None;
+    }
+    /// Remember the current time for the next packet read
+    ///
+    /// Call it first thing in the IRQ handler to stamp packets with
+    /// their arrival rather than with when they are read.
+    pub fn capture_rx_timestamp(&mut self) {
+        if let Some(clock) = self.rx_clock {
+            self.irq_timestamp_us = Some(clock.now_us());
+        }
+    }
+    /// Like [`send()`](#method.send), with the SPI transfer running on
+    /// `buf`
+    ///
+    /// With a `'static` buffer, the SPI implementation can move the
+    /// payload by DMA and sleep meanwhile.
+    pub fn send_from(
+        &mut self,
+        buf: &mut [u8; DMA_BUFFER_LEN],
+        packet: &[u8],
+    ) -> Result<(), nb::Error<SpiE>> {
+        self.listen_before_talk()?;
+        self.tx()?;
+        self.wait_tx_empty()?;
+        self.device
+            .send_command_in(&WriteTxPayload::new(packet), buf)?;
+        self.device.ce_enable();
+        Ok(())
+    }
+    /// Like [`read()`](#method.read), with the SPI transfer running on
+    /// `buf`
+    ///
+    /// Returns the pipe and the payload, which is `buf[1..]` up to its
+    /// length.
+    pub fn read_into<'b>(
+        &mut self,
+        buf: &'b mut [u8; DMA_BUFFER_LEN],
+    ) -> Result<(Pipe, &'b [u8]), nb::Error<SpiE>> {
+        let pipe = self.wait_rx_ready()?;
+        let (_, len) = self.rx_payload_width()?;
+        let len = len.ok_or(nb::Error::WouldBlock)?;
+        self.device.send_command_in(&ReadRxPayload::new(len), buf)?;
+        self.record_rx(Some(pipe), len);
+        Ok((pipe, &buf[1..1 + len]))
+    }
+    /// Queue `data` to be sent along with the next acknowledgement on `pipe`
+    ///
+    /// Requires [`set_ack_payloads(true)`](trait.Configuration.html#method.set_ack_payloads).
+    pub fn write_ack_payload(&mut self, pipe: Pipe, data: &[u8]) -> Result<(), SpiE> {
+        self.device
+            .send_command(&WriteAckPayload::new(pipe.into(), data))?;
+        self.record_ack_payload(pipe);
+        Ok(())
+    }
+    /// Read a payload that arrived with an acknowledgement, without leaving TX mode
+    ///
+    /// `None` also for a payload with a corrupt length, which is
+    /// flushed like in [`read()`](#method.read).
+    pub fn read_ack_payload(&mut self) -> Result<Option<Payload>, SpiE> {
+        let (_, fifo_status) = self.device.read_register::<FifoStatus>()?;
+        if fifo_status.rx_empty() {
+            return Ok(None);
+        }
+        let (status, len) = match self.rx_payload_width()? {
+            (status, Some(len)) => (status, len),
+            (_, None) => return Ok(None),
+        };
+        let (_, payload) = self.device.send_command(&ReadRxPayload::new(len))?;
+        self.clear(Interrupts::new().set_rx_dr())?;
+        self.record_rx(status.rx_p_no(), payload.len());
+        Ok(Some(payload))
+    }
+}
+
+/// Result of [`Nrf24l01::detect_chip()`](struct.Nrf24l01.html#method.detect_chip)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChipInfo {
+    pub variant: ChipVariant,
+    /// 250 Kbps can be selected
+    pub supports_250kbps: bool,
+    /// `FEATURE` had to be unlocked with `ACTIVATE`
+    pub needed_activate: bool,
+}
+
+/// Write-only fast paths, for SPI peripherals that also implement
+/// `blocking::spi::Write`
+///
+/// These skip reading `STATUS`, so they do not check for room in the
+/// TX FIFO: poll [`wait_tx_ready()`](#method.wait_tx_ready) first.
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Queue `packet` with a plain SPI write and start sending it
+    ///
+    /// Must be in TX mode already, as after
+    /// [`wait_tx_ready()`](#method.wait_tx_ready).
+    pub fn send_write_only(&mut self, packet: &[u8]) -> Result<(), SpiE> {
+        debug_assert!(self.mode == Mode::Tx);
+        self.device.write_command(&WriteTxPayload::new(packet))?;
+        self.device.ce_enable();
+        Ok(())
+    }
+    /// Queue an ack payload with a plain SPI write
+    pub fn write_ack_payload_write_only(&mut self, pipe: Pipe, data: &[u8]) -> Result<(), SpiE> {
+        self.device
+            .write_command(&WriteAckPayload::new(pipe.into(), data))?;
+        self.record_ack_payload(pipe);
+        Ok(())
+    }
+    /// Discard all unsent packets with a plain SPI write
+    pub fn flush_tx_write_only(&mut self) -> Result<(), SpiE> {
+        self.device.write_command(&FlushTx)
+    }
 }
 
-#[derive(PartialEq)]
+impl<Csn, Spi, E, SpiE> Nrf24l01<NoPin<E>, Csn, Spi, E, SpiE>
+where
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// For boards with CE tied high
+    ///
+    /// The chip is active whenever it is powered up, so it sits in
+    /// Standby-II (about 320 µA) instead of Standby-I (26 µA) between
+    /// transmissions and draws RX current while listening. Switching
+    /// between RX and TX has to go through power down, which adds the
+    /// 1.5 ms oscillator start-up to every turnaround. Call
+    /// [`power_down()`](#method.power_down) to save power while idle.
+    pub fn new_without_ce(csn: Csn, spi: Spi, config: Config) -> Result<Self, Error<SpiE>> {
+        let mut radio = Self::new(NoPin::new(), csn, spi, config)?;
+        radio.device.set_ce_tied_high();
+        Ok(radio)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 enum Mode {
     Standby,
     Rx,
@@ -424,9 +1607,73 @@ fn wait(mut count: u32) {
     }
 }
 
+/// Settings of a [`Config`](struct.Config.html) that do not go together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// An RF channel beyond 125
+    InvalidChannel { channel: u8 },
+    /// 250 kbps on the original nRF24L01
+    UnsupportedDataRate,
+    /// A power level the chip variant does not have
+    UnsupportedOutputPower,
+    /// Auto-ack needs CRC, the chip would turn it back on
+    AutoAckWithoutCrc { pipe: Pipe },
+    /// An enabled pipe without fixed length while dynamic payloads are off
+    MissingLength { pipe: Pipe },
+    /// Ack payloads only work on pipes with dynamic payload length
+    AckPayloadWithoutDynamicLength { pipe: Pipe },
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::InvalidChannel { channel } => {
+                write!(f, "RF channel {} out of range", channel)
+            }
+            ConfigError::UnsupportedDataRate => f.write_str("data rate not supported by the chip"),
+            ConfigError::UnsupportedOutputPower => {
+                f.write_str("output power not supported by the chip")
+            }
+            ConfigError::AutoAckWithoutCrc { pipe } => {
+                write!(f, "auto-ack on pipe {} needs CRC", pipe.index())
+            }
+            ConfigError::MissingLength { pipe } => write!(
+                f,
+                "pipe {} needs a fixed length without dynamic payloads",
+                pipe.index()
+            ),
+            ConfigError::AckPayloadWithoutDynamicLength { pipe } => write!(
+                f,
+                "ack payloads need dynamic payload length on pipe {}",
+                pipe.index()
+            ),
+        }
+    }
+}
+impl core::error::Error for ConfigError {}
+
 #[derive(Debug)]
 pub enum Error<E: Debug> {
     NotConnected,
+    /// The configuration contradicts itself or the chip variant, see
+    /// [`Config::validate()`](struct.Config.html#method.validate)
+    InvalidConfig(ConfigError),
+    /// The transmit budget of the [`DutyCycle`](duty_cycle/struct.DutyCycle.html) limiter is used up
+    DutyCycleExceeded,
+    /// `len` bytes of data where at most `max` fit, see
+    /// [`send_fixed()`](struct.Nrf24l01.html#method.send_fixed)
+    TooLong {
+        len: usize,
+        max: usize,
+    },
+    /// A register did not read back as written, see
+    /// [`Nrf24l01::set_verify_writes()`](struct.Nrf24l01.html#method.set_verify_writes)
+    ///
+    /// For addresses, `wrote` and `read` are the first differing byte.
+    VerificationFailed {
+        reg: u8,
+        wrote: u8,
+        read: u8,
+    },
     Spi(E),
 }
 impl<SpiE: Debug> From<SpiE> for Error<SpiE> {
@@ -434,3 +1681,34 @@ impl<SpiE: Debug> From<SpiE> for Error<SpiE> {
         Error::Spi(e)
     }
 }
+impl<E: Debug> fmt::Display for Error<E> {
+    /// The SPI error is shown with its `Debug` output, which is all
+    /// embedded-hal requires of it
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotConnected => f.write_str("no nRF24L01 answering on SPI"),
+            Error::InvalidConfig(e) => write!(f, "invalid configuration: {}", e),
+            Error::DutyCycleExceeded => f.write_str("duty cycle budget used up"),
+            Error::TooLong { len, max } => {
+                write!(f, "{} bytes of data where at most {} fit", len, max)
+            }
+            Error::VerificationFailed { reg, wrote, read } => write!(
+                f,
+                "register {:#04x} read back as {:#04x} after writing {:#04x}",
+                reg, read, wrote
+            ),
+            Error::Spi(e) => write!(f, "SPI error: {:?}", e),
+        }
+    }
+}
+/// Only for SPI errors that implement `core::error::Error` themselves,
+/// which [`source()`](#method.source) then hands out
+impl<E: core::error::Error + 'static> core::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::InvalidConfig(e) => Some(e),
+            Error::Spi(e) => Some(e),
+            _ => None,
+        }
+    }
+}
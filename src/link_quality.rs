@@ -0,0 +1,84 @@
+use crate::Nrf24l01;
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Packets the score of [`Nrf24l01::link_quality()`](struct.Nrf24l01.html#method.link_quality)
+/// is taken over
+pub const LINK_QUALITY_WINDOW: usize = 16;
+/// Score lost per retransmit, 15 retransmits still score above a loss
+const RETRANSMIT_COST: u8 = 16;
+
+/// Scores of the last packets sent, 0 for a lost one
+#[derive(Debug, Clone)]
+pub(crate) struct LinkWindow {
+    scores: [u8; LINK_QUALITY_WINDOW],
+    len: usize,
+    next: usize,
+}
+
+impl LinkWindow {
+    pub(crate) fn new() -> Self {
+        LinkWindow {
+            scores: [0; LINK_QUALITY_WINDOW],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, score: u8) {
+        self.scores[self.next] = score;
+        self.next = (self.next + 1) % LINK_QUALITY_WINDOW;
+        self.len = (self.len + 1).min(LINK_QUALITY_WINDOW);
+    }
+
+    fn average(&self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let total: usize = self.scores[0..self.len]
+            .iter()
+            .map(|&s| usize::from(s))
+            .sum();
+        Some((total / self.len) as u8)
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Quality of the link from 0 (nothing gets through) to 255 (no
+    /// retransmits), `None` before the first packet has been sent
+    ///
+    /// Averaged over the outcome of the last
+    /// [`LINK_QUALITY_WINDOW`](constant.LINK_QUALITY_WINDOW.html)
+    /// packets seen by [`wait_tx_done()`](#method.wait_tx_done) or
+    /// [`poll()`](#method.poll): each retransmit costs a little, a
+    /// packet dropped after `MAX_RT` counts as zero. Packets sent
+    /// without acknowledgement count as clean.
+    pub fn link_quality(&self) -> Option<u8> {
+        self.link.average()
+    }
+
+    /// Start the score over, e.g. after switching to another peer
+    pub fn reset_link_quality(&mut self) {
+        self.link = LinkWindow::new();
+    }
+
+    /// Count a packet that has been acknowledged after `retransmits`
+    pub(crate) fn record_tx_done(&mut self, retransmits: u8) {
+        let cost = retransmits.min(15) * RETRANSMIT_COST;
+        self.link.push(u8::MAX - cost);
+    }
+
+    /// Count a packet that reached `MAX_RT`
+    pub(crate) fn record_tx_failed(&mut self) {
+        trace!(self.device, crate::TraceEvent::MaxRt);
+        self.link.push(0);
+    }
+}
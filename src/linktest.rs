@@ -0,0 +1,247 @@
+//! Packet and bit error rate measurement for site surveys
+//!
+//! A [`Generator`](struct.Generator.html) sends numbered packets
+//! filled with a known pattern at a fixed rate, an
+//! [`Analyzer`](struct.Analyzer.html) counts lost, duplicated and
+//! corrupted packets. Bit errors only get through with CRC disabled
+//! on both sides; disable auto-retransmit as well to see raw
+//! channel losses.
+
+use crate::{Configuration, Nrf24l01, Pipe, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_TEST: u8 = 0xE5;
+const HEADER_LEN: usize = 5;
+
+/// Expected content of byte `index` of packet `seq`
+fn pattern(seq: u32, index: usize) -> u8 {
+    (seq.wrapping_mul(0x9E37_79B9) >> (8 * (index % 4))) as u8 ^ index as u8
+}
+
+/// Sends numbered test packets
+pub struct Generator<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    address: [u8; 5],
+    len: usize,
+    seq: u32,
+    acked: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Generator<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Send `len` byte packets (`5..=32`) to `address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: [u8; 5],
+        len: usize,
+    ) -> Self {
+        assert!((HEADER_LEN..=PAYLOAD_LEN).contains(&len));
+        Generator {
+            radio,
+            address,
+            len,
+            seq: 0,
+            acked: 0,
+        }
+    }
+
+    /// Packets sent so far
+    pub fn sent(&self) -> u32 {
+        self.seq
+    }
+
+    /// Packets acknowledged so far, if auto-ack is enabled
+    pub fn acked(&self) -> u32 {
+        self.acked
+    }
+
+    /// Send the next packet, returns whether it has been acknowledged
+    pub fn send_next(&mut self) -> Result<bool, SpiE> {
+        let mut packet = [0; PAYLOAD_LEN];
+        packet[0] = TYPE_TEST;
+        packet[1..HEADER_LEN].copy_from_slice(&self.seq.to_le_bytes());
+        for (i, byte) in packet[HEADER_LEN..self.len].iter_mut().enumerate() {
+            *byte = pattern(self.seq, i);
+        }
+        let acked = self.radio.send_to(&self.address, &packet[0..self.len])?;
+        self.seq = self.seq.wrapping_add(1);
+        if acked {
+            self.acked += 1;
+        }
+        Ok(acked)
+    }
+
+    /// Send `count` packets, one every `interval_us`
+    pub fn run<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        count: u32,
+        interval_us: u32,
+    ) -> Result<(), SpiE> {
+        for _ in 0..count {
+            self.send_next()?;
+            delay.delay_us(interval_us);
+        }
+        Ok(())
+    }
+}
+
+/// Counters of an [`Analyzer`](struct.Analyzer.html)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub received: u32,
+    /// Packets missing from the sequence
+    pub lost: u32,
+    /// Number of holes in the sequence
+    pub gaps: u32,
+    pub max_gap: u32,
+    pub duplicates: u32,
+    /// Packets with at least one wrong bit
+    pub corrupted: u32,
+    pub bit_errors: u32,
+    /// Pattern bits checked
+    pub bits: u32,
+}
+
+impl LinkStats {
+    /// Fraction of packets lost or corrupted
+    pub fn packet_error_rate(&self) -> f32 {
+        let total = self.received + self.lost;
+        match total {
+            0 => 0.0,
+            _ => (self.lost + self.corrupted) as f32 / total as f32,
+        }
+    }
+
+    pub fn bit_error_rate(&self) -> f32 {
+        match self.bits {
+            0 => 0.0,
+            bits => self.bit_errors as f32 / bits as f32,
+        }
+    }
+
+    fn count_gap(&mut self, gap: u32) {
+        self.lost += gap;
+        self.gaps += 1;
+        self.max_gap = self.max_gap.max(gap);
+    }
+}
+
+/// Receives test packets and accumulates statistics
+pub struct Analyzer<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    total: LinkStats,
+    interval: LinkStats,
+    next_seq: Option<u32>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Analyzer<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen on pipe 1 at `address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+    ) -> Result<Self, SpiE> {
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[1] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_rx_addr(Pipe::P1, address)?;
+        Ok(Analyzer {
+            radio,
+            total: LinkStats::default(),
+            interval: LinkStats::default(),
+            next_seq: None,
+        })
+    }
+
+    /// Statistics since creation or [`reset()`](#method.reset)
+    pub fn stats(&self) -> &LinkStats {
+        &self.total
+    }
+
+    /// Statistics since the previous call
+    pub fn take_interval(&mut self) -> LinkStats {
+        core::mem::take(&mut self.interval)
+    }
+
+    pub fn reset(&mut self) {
+        self.total = LinkStats::default();
+        self.interval = LinkStats::default();
+        self.next_seq = None;
+    }
+
+    /// Process all received packets
+    pub fn poll(&mut self) -> Result<(), SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            if packet.len() >= HEADER_LEN && packet[0] == TYPE_TEST {
+                self.analyze(&packet);
+            }
+        }
+    }
+
+    fn analyze(&mut self, packet: &[u8]) {
+        let seq = u32::from_le_bytes([packet[1], packet[2], packet[3], packet[4]]);
+        let mut bit_errors = 0;
+        for (i, byte) in packet[HEADER_LEN..].iter().enumerate() {
+            bit_errors += (byte ^ pattern(seq, i)).count_ones();
+        }
+        let bits = 8 * (packet.len() - HEADER_LEN) as u32;
+
+        for stats in [&mut self.total, &mut self.interval] {
+            match self.next_seq {
+                // A restarted generator begins again at 0
+                Some(next) if seq < next && seq != 0 => {
+                    stats.duplicates += 1;
+                    continue;
+                }
+                Some(next) if seq > next => stats.count_gap(seq - next),
+                _ => {}
+            }
+            stats.received += 1;
+            stats.bit_errors += bit_errors;
+            stats.bits += bits;
+            if bit_errors > 0 {
+                stats.corrupted += 1;
+            }
+        }
+        if self.next_seq.is_none_or(|next| seq >= next || seq == 0) {
+            self.next_seq = Some(seq.wrapping_add(1));
+        }
+    }
+}
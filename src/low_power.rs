@@ -0,0 +1,144 @@
+//! Wake-on-radio for battery powered receivers
+//!
+//! The receiver sleeps most of the time and only listens for a short
+//! window every period. The sender repeats its packet for at least
+//! one full period with [`send_wakeup()`](fn.send_wakeup.html) so that
+//! one of the repetitions falls into a listening window.
+
+use crate::{Nrf24l01, Payload, Pipe};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Polling interval while listening
+const POLL_US: u32 = 100;
+
+/// Waits while the radio is powered down, e.g. in an RTC-driven MCU sleep mode
+pub trait Sleep {
+    fn sleep_us(&mut self, us: u32);
+}
+
+impl<D: DelayUs<u32>> Sleep for D {
+    fn sleep_us(&mut self, us: u32) {
+        self.delay_us(us)
+    }
+}
+
+/// Duty-cycled receiver
+pub struct WakeOnRadio<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    listen_us: u32,
+    sleep_us: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> WakeOnRadio<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen for `listen_us` after every `sleep_us` powered down
+    ///
+    /// The window must cover the sender's repeat interval, i.e. the
+    /// time for one packet with all its retransmits.
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        listen_us: u32,
+        sleep_us: u32,
+    ) -> Self {
+        WakeOnRadio {
+            radio,
+            listen_us,
+            sleep_us,
+        }
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Power up, listen for one window, and power down again
+    ///
+    /// Sleeps for the rest of the period unless a packet has been
+    /// caught, which is returned along with its pipe.
+    pub fn cycle<D: DelayUs<u32>, S: Sleep>(
+        &mut self,
+        delay: &mut D,
+        sleep: &mut S,
+    ) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        self.radio.power_up(delay)?;
+        let mut listened_us = 0;
+        let received = loop {
+            match self.radio.wait_rx_ready() {
+                Ok(pipe) => break Some((pipe, nb::block!(self.radio.read())?)),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            if listened_us >= self.listen_us {
+                break None;
+            }
+            delay.delay_us(POLL_US);
+            listened_us += POLL_US;
+        };
+        self.radio.power_down()?;
+        if received.is_none() {
+            sleep.sleep_us(self.sleep_us);
+        }
+        Ok(received)
+    }
+
+    /// Run up to `cycles` cycles until a packet arrives
+    pub fn listen<D: DelayUs<u32>, S: Sleep>(
+        &mut self,
+        delay: &mut D,
+        sleep: &mut S,
+        cycles: u32,
+    ) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        for _ in 0..cycles {
+            if let Some(received) = self.cycle(delay, sleep)? {
+                return Ok(Some(received));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Repeat `packet` to `address` until acknowledged, for at least `duration_us`
+///
+/// Use the receiver's full period, sleep plus listen time, as
+/// duration. Time spent in retransmits is not counted. Returns whether the packet has been acknowledged.
+pub fn send_wakeup<Ce, Csn, Spi, E, SpiE, D>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    delay: &mut D,
+    address: &[u8],
+    packet: &[u8],
+    duration_us: u32,
+) -> Result<bool, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    D: DelayUs<u32>,
+{
+    let mut elapsed_us = 0;
+    while elapsed_us < duration_us {
+        if radio.send_to(address, packet)? {
+            return Ok(true);
+        }
+        delay.delay_us(POLL_US);
+        elapsed_us += POLL_US;
+    }
+    Ok(false)
+}
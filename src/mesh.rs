@@ -0,0 +1,390 @@
+//! RF24Mesh-style dynamic addressing on top of [`network`](../network/index.html)
+//!
+//! Nodes are identified by a fixed `node_id` and obtain their network
+//! address from the [`MeshMaster`](struct.MeshMaster.html) at runtime,
+//! attaching to whichever parent answers first.
+
+use crate::network::{
+    child_address, child_digit, is_valid_address, level, Header, Network, MASTER_ADDRESS,
+    MAX_LEVELS, PARENT_PIPE,
+};
+use crate::{Nrf24l01, Payload};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Address used by nodes that have not been assigned one yet
+pub const DEFAULT_ADDRESS: u16 = 0o4444;
+/// Highest `msg_type` that is passed through to the application
+pub const MAX_USER_MSG_TYPE: u8 = 127;
+/// Master to parent to requesting node, `reserved`: node ID, data: address
+pub const MSG_ADDR_RESPONSE: u8 = 128;
+/// Requesting node to parent to master, `reserved`: node ID
+pub const MSG_REQ_ADDRESS: u8 = 195;
+/// `reserved`: node ID, response data: address or empty
+pub const MSG_ADDR_LOOKUP: u8 = 196;
+/// Node to master, giving up its address
+pub const MSG_ADDR_RELEASE: u8 = 197;
+/// Data: address, response data: node ID or empty
+pub const MSG_ID_LOOKUP: u8 = 198;
+
+/// Parents deeper than this are not tried when requesting an address
+const MAX_PARENT_LEVEL: u8 = 2;
+/// Unanswered connection checks before re-attaching
+const MAX_FAILURES: u8 = 3;
+
+/// Root of the mesh, keeping the node ID to address table
+pub struct MeshMaster<'a, Ce, Csn, Spi, E, SpiE, const N: usize>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    network: Network<'a, Ce, Csn, Spi, E, SpiE>,
+    table: [Option<(u8, u16)>; N],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE, const N: usize> MeshMaster<'a, Ce, Csn, Spi, E, SpiE, N>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> Result<Self, SpiE> {
+        Ok(MeshMaster {
+            network: Network::new(radio, MASTER_ADDRESS)?,
+            table: [None; N],
+        })
+    }
+
+    pub fn network(&mut self) -> &mut Network<'a, Ce, Csn, Spi, E, SpiE> {
+        &mut self.network
+    }
+
+    pub fn address_of(&self, node_id: u8) -> Option<u16> {
+        self.table
+            .iter()
+            .flatten()
+            .find(|(id, _)| *id == node_id)
+            .map(|(_, address)| *address)
+    }
+
+    pub fn node_id_of(&self, address: u16) -> Option<u8> {
+        self.table
+            .iter()
+            .flatten()
+            .find(|(_, a)| *a == address)
+            .map(|(id, _)| *id)
+    }
+
+    /// Forget the address of `node_id`
+    pub fn release(&mut self, node_id: u8) {
+        for entry in self.table.iter_mut() {
+            if matches!(entry, Some((id, _)) if *id == node_id) {
+                *entry = None;
+            }
+        }
+    }
+
+    fn allocate(&mut self, node_id: u8, parent: u16) -> Option<u16> {
+        if level(parent) >= MAX_LEVELS {
+            return None;
+        }
+        self.release(node_id);
+        let address = (1..=5)
+            .map(|digit| child_address(parent, digit))
+            .find(|&address| address != DEFAULT_ADDRESS && self.node_id_of(address).is_none())?;
+        let entry = self.table.iter_mut().find(|entry| entry.is_none())?;
+        *entry = Some((node_id, address));
+        Some(address)
+    }
+
+    /// Serve address requests and lookups, returning application frames
+    pub fn update(&mut self) -> Result<Option<(Header, Payload)>, SpiE> {
+        while let Some((header, data)) = self.network.update()? {
+            match header.msg_type {
+                MSG_REQ_ADDRESS => {
+                    // Requests straight from an unaddressed node come from its parent: us
+                    let parent = match header.from_node {
+                        DEFAULT_ADDRESS => MASTER_ADDRESS,
+                        from_node => from_node,
+                    };
+                    if let Some(address) = self.allocate(header.reserved, parent) {
+                        let mut response = Header::new(parent, MSG_ADDR_RESPONSE);
+                        response.reserved = header.reserved;
+                        let address = address.to_le_bytes();
+                        if parent == MASTER_ADDRESS {
+                            response.to_node = DEFAULT_ADDRESS;
+                            response.from_node = MASTER_ADDRESS;
+                            self.network.write_direct(
+                                DEFAULT_ADDRESS,
+                                PARENT_PIPE,
+                                &response,
+                                &address,
+                            )?;
+                        } else {
+                            self.network.write(&mut response, &address)?;
+                        }
+                    }
+                }
+                MSG_ADDR_LOOKUP => {
+                    let mut response = Header::new(header.from_node, MSG_ADDR_LOOKUP);
+                    response.reserved = header.reserved;
+                    match self.address_of(header.reserved) {
+                        Some(address) => {
+                            self.network.write(&mut response, &address.to_le_bytes())?
+                        }
+                        None => self.network.write(&mut response, &[])?,
+                    };
+                }
+                MSG_ID_LOOKUP if data.len() >= 2 => {
+                    let address = u16::from_le_bytes([data[0], data[1]]);
+                    let mut response = Header::new(header.from_node, MSG_ID_LOOKUP);
+                    match self.node_id_of(address) {
+                        Some(node_id) => self.network.write(&mut response, &[node_id])?,
+                        None => self.network.write(&mut response, &[])?,
+                    };
+                }
+                MSG_ADDR_RELEASE => {
+                    if let Some(node_id) = self.node_id_of(header.from_node) {
+                        self.release(node_id);
+                    }
+                }
+                msg_type if msg_type <= MAX_USER_MSG_TYPE => return Ok(Some((header, data))),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Mesh member that obtains its address from the master
+pub struct MeshNode<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    network: Network<'a, Ce, Csn, Spi, E, SpiE>,
+    node_id: u8,
+    failures: u8,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> MeshNode<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Starts out unattached, call [`request_address()`](#method.request_address)
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>, node_id: u8) -> Result<Self, SpiE> {
+        Ok(MeshNode {
+            network: Network::new(radio, DEFAULT_ADDRESS)?,
+            node_id,
+            failures: 0,
+        })
+    }
+
+    pub fn network(&mut self) -> &mut Network<'a, Ce, Csn, Spi, E, SpiE> {
+        &mut self.network
+    }
+
+    pub fn node_id(&self) -> u8 {
+        self.node_id
+    }
+
+    /// Assigned network address, `None` while unattached
+    pub fn address(&self) -> Option<u16> {
+        match self.network.node_address() {
+            DEFAULT_ADDRESS => None,
+            address => Some(address),
+        }
+    }
+
+    /// Ask every reachable parent, nearest to the master first, for an address
+    ///
+    /// Waits up to `timeout_ms` for each answer. Returns whether an
+    /// address has been assigned.
+    pub fn request_address<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, SpiE> {
+        self.network.set_node_address(DEFAULT_ADDRESS)?;
+        for parent_level in 0..=MAX_PARENT_LEVEL {
+            let parents = (0..=0o555)
+                .filter(|&parent| is_valid_address(parent) && level(parent) == parent_level);
+            for parent in parents {
+                let mut request = Header::new(parent, MSG_REQ_ADDRESS);
+                request.from_node = DEFAULT_ADDRESS;
+                request.reserved = self.node_id;
+                let pipe = child_digit(DEFAULT_ADDRESS);
+                if !self.network.write_direct(parent, pipe, &request, &[])? {
+                    continue;
+                }
+                let node_id = self.node_id;
+                let response = self.wait_for(delay, timeout_ms, |header, data| {
+                    header.msg_type == MSG_ADDR_RESPONSE
+                        && header.reserved == node_id
+                        && data.len() >= 2
+                })?;
+                if let Some((_, data)) = response {
+                    let address = u16::from_le_bytes([data[0], data[1]]);
+                    if is_valid_address(address) {
+                        self.network.set_node_address(address)?;
+                        self.failures = 0;
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Drop the current address and attach again
+    pub fn renew_address<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, SpiE> {
+        self.release_address()?;
+        self.request_address(delay, timeout_ms)
+    }
+
+    /// Tell the master that this node's address is free
+    pub fn release_address(&mut self) -> Result<bool, SpiE> {
+        let released = match self.address() {
+            Some(_) => {
+                let mut header = Header::new(MASTER_ADDRESS, MSG_ADDR_RELEASE);
+                self.network.write(&mut header, &[])?
+            }
+            None => false,
+        };
+        self.network.set_node_address(DEFAULT_ADDRESS)?;
+        Ok(released)
+    }
+
+    /// Verify that the master still knows this node under its current address
+    ///
+    /// After several failed checks the parent is considered lost and
+    /// the node re-attaches through [`renew_address()`](#method.renew_address).
+    pub fn check_connection<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, SpiE> {
+        let address = match self.address() {
+            Some(address) => address,
+            None => return self.request_address(delay, timeout_ms),
+        };
+        if self.lookup_address(self.node_id, delay, timeout_ms)? == Some(address) {
+            self.failures = 0;
+            return Ok(true);
+        }
+        self.failures += 1;
+        if self.failures >= MAX_FAILURES {
+            self.renew_address(delay, timeout_ms)?;
+        }
+        Ok(false)
+    }
+
+    /// Ask the master for the address of `node_id`
+    pub fn lookup_address<D: DelayUs<u32>>(
+        &mut self,
+        node_id: u8,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<u16>, SpiE> {
+        let mut request = Header::new(MASTER_ADDRESS, MSG_ADDR_LOOKUP);
+        request.reserved = node_id;
+        if !self.network.write(&mut request, &[])? {
+            return Ok(None);
+        }
+        let response = self.wait_for(delay, timeout_ms, |header, _| {
+            header.msg_type == MSG_ADDR_LOOKUP && header.reserved == node_id
+        })?;
+        Ok(response
+            .filter(|(_, data)| data.len() >= 2)
+            .map(|(_, data)| u16::from_le_bytes([data[0], data[1]])))
+    }
+
+    /// Ask the master which node ID owns `address`
+    pub fn lookup_node_id<D: DelayUs<u32>>(
+        &mut self,
+        address: u16,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<u8>, SpiE> {
+        let mut request = Header::new(MASTER_ADDRESS, MSG_ID_LOOKUP);
+        if !self.network.write(&mut request, &address.to_le_bytes())? {
+            return Ok(None);
+        }
+        let response = self.wait_for(delay, timeout_ms, |header, _| {
+            header.msg_type == MSG_ID_LOOKUP
+        })?;
+        Ok(response.and_then(|(_, data)| data.first().cloned()))
+    }
+
+    /// Send an application frame, `msg_type` must not exceed [`MAX_USER_MSG_TYPE`](constant.MAX_USER_MSG_TYPE.html)
+    pub fn write(&mut self, to_node: u16, msg_type: u8, data: &[u8]) -> Result<bool, SpiE> {
+        assert!(msg_type <= MAX_USER_MSG_TYPE);
+        if self.address().is_none() {
+            return Ok(false);
+        }
+        let mut header = Header::new(to_node, msg_type);
+        self.network.write(&mut header, data)
+    }
+
+    /// Relay address requests for child nodes, returning application frames
+    pub fn update(&mut self) -> Result<Option<(Header, Payload)>, SpiE> {
+        while let Some((header, data)) = self.network.update()? {
+            match header.msg_type {
+                MSG_REQ_ADDRESS if self.address().is_some() => {
+                    let mut request = Header::new(MASTER_ADDRESS, MSG_REQ_ADDRESS);
+                    request.reserved = header.reserved;
+                    self.network.write(&mut request, &[])?;
+                }
+                MSG_ADDR_RESPONSE if self.address().is_some() => {
+                    let mut response = header;
+                    response.to_node = DEFAULT_ADDRESS;
+                    self.network
+                        .write_direct(DEFAULT_ADDRESS, PARENT_PIPE, &response, &data)?;
+                }
+                msg_type if msg_type <= MAX_USER_MSG_TYPE => return Ok(Some((header, data))),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Poll in 1 ms steps for a frame matching `f`, discarding others
+    fn wait_for<D, F>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+        f: F,
+    ) -> Result<Option<(Header, Payload)>, SpiE>
+    where
+        D: DelayUs<u32>,
+        F: Fn(&Header, &Payload) -> bool,
+    {
+        for _ in 0..timeout_ms {
+            while let Some((header, data)) = self.network.update()? {
+                if f(&header, &data) {
+                    return Ok(Some((header, data)));
+                }
+            }
+            delay.delay_us(1000);
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,114 @@
+//! Typed messages
+//!
+//! Anything implementing [`Message`](trait.Message.html) can be sent
+//! with [`Nrf24l01::send_msg()`](../struct.Nrf24l01.html#method.send_msg)
+//! and received with
+//! [`Nrf24l01::recv_msg()`](../struct.Nrf24l01.html#method.recv_msg).
+//! Messages are COBS framed, see [`framing`](../framing/index.html), so
+//! they may span several packets. With the `postcard` feature,
+//! [`Postcard`](struct.Postcard.html) turns any serde type into a
+//! `Message`.
+
+use crate::framing::{self, Deframer};
+use crate::Nrf24l01;
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Longest encoded message
+pub const MAX_MESSAGE_LEN: usize = 128;
+
+/// A value with a byte encoding
+pub trait Message: Sized {
+    /// Returns the encoded length, or `None` if `buf` is too short
+    fn encode(&self, buf: &mut [u8]) -> Option<usize>;
+    /// `None` if `data` is not a valid encoding
+    fn decode(data: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_message {
+    ($($ty: ty),*) => {
+        $(
+            /// Little-endian
+            impl Message for $ty {
+                fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+                    let bytes = self.to_le_bytes();
+                    buf.get_mut(0..bytes.len())?.copy_from_slice(&bytes);
+                    Some(bytes.len())
+                }
+
+                fn decode(data: &[u8]) -> Option<Self> {
+                    let mut bytes = [0; core::mem::size_of::<$ty>()];
+                    if data.len() != bytes.len() {
+                        return None;
+                    }
+                    bytes.copy_from_slice(data);
+                    Some(<$ty>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_message!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// A serde type encoded with [postcard](https://crates.io/crates/postcard)
+///
+/// `radio.send_msg(&Postcard(reading))` sends it, and
+/// `radio.recv_msg::<Postcard<Reading>>(&mut deframer)` receives it.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Postcard<T>(pub T);
+
+#[cfg(feature = "postcard")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Message for Postcard<T> {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        postcard::to_slice(&self.0, buf)
+            .ok()
+            .map(|encoded| encoded.len())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        postcard::from_bytes(data).ok().map(Postcard)
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Send `msg`, returning whether every packet was acknowledged
+    ///
+    /// Panics if it encodes to more than
+    /// [`MAX_MESSAGE_LEN`](message/constant.MAX_MESSAGE_LEN.html) bytes.
+    pub fn send_msg<T: Message>(&mut self, msg: &T) -> Result<bool, SpiE> {
+        let mut data = [0; MAX_MESSAGE_LEN];
+        let len = msg.encode(&mut data).expect("message too long");
+        let mut scratch = [0; framing::max_encoded_len(MAX_MESSAGE_LEN)];
+        framing::send_frame(self, &data[0..len], &mut scratch)
+    }
+
+    /// Poll for a message
+    ///
+    /// `deframer` collects messages spanning several packets between
+    /// calls. Messages that do not decode as `T` are dropped.
+    pub fn recv_msg<T: Message>(&mut self, deframer: &mut Deframer) -> Result<Option<T>, SpiE> {
+        loop {
+            match self.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.read())?;
+            let mut msg = None;
+            deframer.feed(&packet, |frame| msg = T::decode(frame));
+            if msg.is_some() {
+                return Ok(msg);
+            }
+        }
+    }
+}
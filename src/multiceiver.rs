@@ -0,0 +1,197 @@
+//! Star topology with up to six transmitters
+//!
+//! The hub listens on all six pipes. Each transmitter sends to its own
+//! [`pipe_address()`](fn.pipe_address.html) and gets answers back in
+//! ack payloads, which the hub queues per pipe.
+
+use crate::{Configuration, Nrf24l01, Payload, Pipe, PAYLOAD_LEN, PIPES_COUNT, TX_FIFO_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Ack payloads queued per pipe in software
+pub const ACK_QUEUE_LEN: usize = 2;
+/// First address byte of each pipe, the remaining bytes are shared
+const PIPE_LSB: [u8; PIPES_COUNT] = [0xC3, 0x3C, 0x33, 0xCE, 0x3E, 0xE3];
+
+/// Address a transmitter uses to reach `pipe` of the hub
+pub fn pipe_address(base: &[u8; 4], pipe: usize) -> [u8; 5] {
+    let mut address = [0; 5];
+    address[0] = PIPE_LSB[pipe];
+    address[1..].copy_from_slice(base);
+    address
+}
+
+/// What the hub knows about the transmitter on one pipe
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Peer {
+    /// Packets received from it
+    pub received: u32,
+    /// An ack payload for it is waiting in the TX FIFO
+    loaded: bool,
+}
+
+impl Peer {
+    pub fn is_active(&self) -> bool {
+        self.received > 0
+    }
+}
+
+struct AckQueue {
+    data: [[u8; PAYLOAD_LEN]; ACK_QUEUE_LEN],
+    lens: [usize; ACK_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl AckQueue {
+    const fn new() -> Self {
+        AckQueue {
+            data: [[0; PAYLOAD_LEN]; ACK_QUEUE_LEN],
+            lens: [0; ACK_QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> bool {
+        if self.len == ACK_QUEUE_LEN {
+            return false;
+        }
+        let index = (self.head + self.len) % ACK_QUEUE_LEN;
+        self.data[index][0..data.len()].copy_from_slice(data);
+        self.lens[index] = data.len();
+        self.len += 1;
+        true
+    }
+
+    fn front(&self) -> Option<&[u8]> {
+        match self.len {
+            0 => None,
+            _ => Some(&self.data[self.head][0..self.lens[self.head]]),
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.len > 0 {
+            self.head = (self.head + 1) % ACK_QUEUE_LEN;
+            self.len -= 1;
+        }
+    }
+}
+
+/// Receives from six pipes and answers through per-pipe ack payloads
+pub struct MulticeiverHub<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    peers: [Peer; PIPES_COUNT],
+    queues: [AckQueue; PIPES_COUNT],
+    /// Next pipe to consider when filling the TX FIFO
+    next_pipe: usize,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> MulticeiverHub<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen on all pipes at [`pipe_address(base, pipe)`](fn.pipe_address.html)
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        base: [u8; 4],
+    ) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_address_width(5)?;
+        radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+        radio.set_ack_payloads(true)?;
+        radio.set_auto_ack(&[true; PIPES_COUNT])?;
+        radio.set_pipes_rx_enable(&[true; PIPES_COUNT])?;
+        radio.set_rx_addr(Pipe::P0, &pipe_address(&base, 0))?;
+        radio.set_rx_addr(Pipe::P1, &pipe_address(&base, 1))?;
+        for (&pipe, lsb) in Pipe::ALL.iter().zip(PIPE_LSB.iter()).skip(2) {
+            radio.set_rx_addr(pipe, &[*lsb])?;
+        }
+        // Enter RX mode before ack payloads start occupying the TX FIFO
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        Ok(MulticeiverHub {
+            radio,
+            peers: [Peer::default(); PIPES_COUNT],
+            queues: [
+                AckQueue::new(),
+                AckQueue::new(),
+                AckQueue::new(),
+                AckQueue::new(),
+                AckQueue::new(),
+                AckQueue::new(),
+            ],
+            next_pipe: 0,
+        })
+    }
+
+    pub fn peer(&self, pipe: usize) -> &Peer {
+        &self.peers[pipe]
+    }
+
+    /// Queue `data` for the next acknowledgement on `pipe`
+    ///
+    /// Returns `false` if the queue of that pipe is full.
+    pub fn queue_ack(&mut self, pipe: usize, data: &[u8]) -> Result<bool, SpiE> {
+        assert!(data.len() <= PAYLOAD_LEN);
+        if !self.queues[pipe].push(data) {
+            return Ok(false);
+        }
+        self.load_ack_payloads()?;
+        Ok(true)
+    }
+
+    /// Poll for a packet, along with the pipe it arrived on
+    pub fn receive(&mut self) -> Result<Option<(usize, Payload)>, SpiE> {
+        let pipe = match self.radio.wait_rx_ready() {
+            Ok(pipe) => usize::from(pipe),
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        };
+        let payload = nb::block!(self.radio.read())?;
+        let peer = &mut self.peers[pipe];
+        peer.received = peer.received.wrapping_add(1);
+        if peer.loaded {
+            // The loaded ack payload went out with this packet's ack
+            peer.loaded = false;
+            self.queues[pipe].pop();
+            self.load_ack_payloads()?;
+        }
+        Ok(Some((pipe, payload)))
+    }
+
+    /// Move queued ack payloads into the TX FIFO, one per pipe, round-robin
+    fn load_ack_payloads(&mut self) -> Result<(), SpiE> {
+        let mut loaded = self.peers.iter().filter(|peer| peer.loaded).count();
+        for _ in 0..PIPES_COUNT {
+            if loaded == TX_FIFO_LEN {
+                break;
+            }
+            let pipe = self.next_pipe;
+            self.next_pipe = (self.next_pipe + 1) % PIPES_COUNT;
+            if self.peers[pipe].loaded {
+                continue;
+            }
+            if let Some(data) = self.queues[pipe].front() {
+                self.radio.write_ack_payload(Pipe::ALL[pipe], data)?;
+                self.peers[pipe].loaded = true;
+                loaded += 1;
+            }
+        }
+        Ok(())
+    }
+}
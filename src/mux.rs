@@ -0,0 +1,197 @@
+//! Virtual channels multiplexed over a single pipe
+//!
+//! Every packet starts with a one-byte virtual channel id. Each of the
+//! [`VIRTUAL_CHANNELS`](constant.VIRTUAL_CHANNELS.html) has its own
+//! send and receive queue, so independent streams neither need a pipe
+//! of their own nor block each other. Queued packets go out one at a
+//! time, taking turns between the channels.
+
+use crate::{Nrf24l01, Payload, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Number of virtual channels
+pub const VIRTUAL_CHANNELS: usize = 8;
+/// Packets queued per virtual channel and direction
+pub const QUEUE_LEN: usize = 2;
+/// Maximum number of data bytes per packet
+pub const MAX_DATA_LEN: usize = PAYLOAD_LEN - 1;
+
+struct Queue {
+    data: [[u8; MAX_DATA_LEN]; QUEUE_LEN],
+    lens: [usize; QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Queue {
+            data: [[0; MAX_DATA_LEN]; QUEUE_LEN],
+            lens: [0; QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> bool {
+        if self.len == QUEUE_LEN {
+            return false;
+        }
+        let index = (self.head + self.len) % QUEUE_LEN;
+        self.data[index][0..data.len()].copy_from_slice(data);
+        self.lens[index] = data.len();
+        self.len += 1;
+        true
+    }
+
+    fn front(&self) -> Option<&[u8]> {
+        match self.len {
+            0 => None,
+            _ => Some(&self.data[self.head][0..self.lens[self.head]]),
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.len > 0 {
+            self.head = (self.head + 1) % QUEUE_LEN;
+            self.len -= 1;
+        }
+    }
+}
+
+/// Send and receive queues for each virtual channel on one radio
+pub struct Mux<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    tx: [Queue; VIRTUAL_CHANNELS],
+    rx: [Queue; VIRTUAL_CHANNELS],
+    /// Next virtual channel to consider for sending
+    next_tx: usize,
+    in_flight: bool,
+    lost: u32,
+    dropped: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Mux<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Addresses and pipes are set up on `radio` beforehand
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> Self {
+        const EMPTY: Queue = Queue::new();
+        Mux {
+            radio,
+            tx: [EMPTY; VIRTUAL_CHANNELS],
+            rx: [EMPTY; VIRTUAL_CHANNELS],
+            next_tx: 0,
+            in_flight: false,
+            lost: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Queue `data` on virtual channel `channel`
+    ///
+    /// Returns `false` if its send queue is full. Panics if `channel`
+    /// is out of range or `data` longer than
+    /// [`MAX_DATA_LEN`](constant.MAX_DATA_LEN.html).
+    pub fn send(&mut self, channel: u8, data: &[u8]) -> bool {
+        assert!(data.len() <= MAX_DATA_LEN);
+        self.tx[usize::from(channel)].push(data)
+    }
+
+    /// Take the next packet received on virtual channel `channel`
+    pub fn receive(&mut self, channel: u8) -> Option<Payload> {
+        let queue = &mut self.rx[usize::from(channel)];
+        let payload = queue.front().map(Payload::new);
+        queue.pop();
+        payload
+    }
+
+    /// Packets waiting in the receive queue of `channel`
+    pub fn pending(&self, channel: u8) -> usize {
+        self.rx[usize::from(channel)].len
+    }
+
+    /// Packets sent but never acknowledged
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+
+    /// Packets received for an unknown or full virtual channel
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Send the next queued packet, or sort received packets into the
+    /// receive queues while there is nothing to send
+    pub fn poll(&mut self) -> Result<(), SpiE> {
+        if self.in_flight {
+            match self.radio.wait_tx_done() {
+                Ok(true) => {}
+                Ok(false) => self.lost = self.lost.wrapping_add(1),
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            self.in_flight = false;
+        }
+        if self.send_next()? {
+            return Ok(());
+        }
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            let queued = match packet.split_first() {
+                Some((&channel, data)) => self
+                    .rx
+                    .get_mut(usize::from(channel))
+                    .is_some_and(|queue| queue.push(data)),
+                None => false,
+            };
+            if !queued {
+                self.dropped = self.dropped.wrapping_add(1);
+            }
+        }
+    }
+
+    /// Returns whether a packet went out
+    fn send_next(&mut self) -> Result<bool, SpiE> {
+        for i in 0..VIRTUAL_CHANNELS {
+            let channel = (self.next_tx + i) % VIRTUAL_CHANNELS;
+            let data = match self.tx[channel].front() {
+                Some(data) => data,
+                None => continue,
+            };
+            let mut packet = [0; PAYLOAD_LEN];
+            packet[0] = channel as u8;
+            packet[1..=data.len()].copy_from_slice(data);
+            let len = 1 + data.len();
+            nb::block!(self.radio.send(&packet[0..len]))?;
+            self.tx[channel].pop();
+            self.in_flight = true;
+            self.next_tx = (channel + 1) % VIRTUAL_CHANNELS;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
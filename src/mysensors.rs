@@ -0,0 +1,565 @@
+//! [MySensors](https://www.mysensors.org/) 2.x radio messages
+//!
+//! Lets a sensor node built on this crate present itself to and talk
+//! with a stock MySensors gateway using the default RF24 transport
+//! settings.
+
+use crate::{Config, Configuration, DataRate, Nrf24l01, Pipe, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Protocol version carried in every header
+pub const PROTOCOL_VERSION: u8 = 2;
+/// Length of the message header
+pub const HEADER_LEN: usize = 7;
+/// Maximum payload length
+pub const MAX_PAYLOAD_LEN: usize = PAYLOAD_LEN - HEADER_LEN;
+/// Node ID of the gateway
+pub const GATEWAY_ID: u8 = 0;
+/// Node ID before one has been assigned
+pub const AUTO_ID: u8 = 255;
+/// Destination for broadcasts
+pub const BROADCAST_ID: u8 = 255;
+/// Child sensor ID used for messages about the node itself
+pub const NODE_SENSOR_ID: u8 = 255;
+/// Default RF channel
+pub const CHANNEL: u8 = 76;
+/// Library version reported when presenting the node
+pub const LIBRARY_VERSION: &str = "2.3.2";
+
+/// Radio address bytes, least significant (node ID) byte first
+const BASE_ADDRESS: [u8; 5] = [0x00, 0xFC, 0xE1, 0xA8, 0xA8];
+const NODE_PIPE: Pipe = Pipe::P1;
+const BROADCAST_PIPE: Pipe = Pipe::P2;
+/// Pipe 0 follows the destination of `send_to()`, so it is only
+/// enabled while sending to catch the ack
+const LISTEN_PIPES: [bool; PIPES_COUNT] = [false, true, true, false, false, false];
+const SEND_PIPES: [bool; PIPES_COUNT] = [true, true, true, false, false, false];
+
+/// Message command
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Command {
+    /// Sent by a node when it presents its sensors
+    Presentation = 0,
+    /// Set a value
+    Set = 1,
+    /// Request a value
+    Req = 2,
+    /// Internal message, see [`internal`](internal/index.html)
+    Internal = 3,
+    /// Firmware and other larger chunks of data
+    Stream = 4,
+}
+
+impl Command {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(Command::Presentation),
+            1 => Some(Command::Set),
+            2 => Some(Command::Req),
+            3 => Some(Command::Internal),
+            4 => Some(Command::Stream),
+            _ => None,
+        }
+    }
+}
+
+/// Encoding of the payload bytes
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PayloadType {
+    String = 0,
+    Byte = 1,
+    Int16 = 2,
+    UInt16 = 3,
+    Long32 = 4,
+    ULong32 = 5,
+    Custom = 6,
+    /// Value followed by the number of decimals
+    Float32 = 7,
+}
+
+impl PayloadType {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0 => PayloadType::String,
+            1 => PayloadType::Byte,
+            2 => PayloadType::Int16,
+            3 => PayloadType::UInt16,
+            4 => PayloadType::Long32,
+            5 => PayloadType::ULong32,
+            6 => PayloadType::Custom,
+            _ => PayloadType::Float32,
+        }
+    }
+}
+
+/// `msg_type` values for [`Command::Presentation`](enum.Command.html#variant.Presentation)
+pub mod sensor {
+    pub const S_DOOR: u8 = 0;
+    pub const S_MOTION: u8 = 1;
+    pub const S_SMOKE: u8 = 2;
+    pub const S_BINARY: u8 = 3;
+    pub const S_DIMMER: u8 = 4;
+    pub const S_COVER: u8 = 5;
+    pub const S_TEMP: u8 = 6;
+    pub const S_HUM: u8 = 7;
+    pub const S_BARO: u8 = 8;
+    pub const S_WIND: u8 = 9;
+    pub const S_RAIN: u8 = 10;
+    pub const S_UV: u8 = 11;
+    pub const S_WEIGHT: u8 = 12;
+    pub const S_POWER: u8 = 13;
+    pub const S_HEATER: u8 = 14;
+    pub const S_DISTANCE: u8 = 15;
+    pub const S_LIGHT_LEVEL: u8 = 16;
+    pub const S_ARDUINO_NODE: u8 = 17;
+    pub const S_ARDUINO_REPEATER_NODE: u8 = 18;
+    pub const S_LOCK: u8 = 19;
+    pub const S_IR: u8 = 20;
+    pub const S_WATER: u8 = 21;
+    pub const S_AIR_QUALITY: u8 = 22;
+    pub const S_CUSTOM: u8 = 23;
+    pub const S_DUST: u8 = 24;
+    pub const S_SCENE_CONTROLLER: u8 = 25;
+    pub const S_MULTIMETER: u8 = 30;
+    pub const S_MOISTURE: u8 = 35;
+}
+
+/// `msg_type` values for [`Command::Set`](enum.Command.html#variant.Set) and [`Command::Req`](enum.Command.html#variant.Req)
+pub mod value {
+    pub const V_TEMP: u8 = 0;
+    pub const V_HUM: u8 = 1;
+    pub const V_STATUS: u8 = 2;
+    pub const V_PERCENTAGE: u8 = 3;
+    pub const V_PRESSURE: u8 = 4;
+    pub const V_FORECAST: u8 = 5;
+    pub const V_RAIN: u8 = 6;
+    pub const V_RAINRATE: u8 = 7;
+    pub const V_WIND: u8 = 8;
+    pub const V_GUST: u8 = 9;
+    pub const V_DIRECTION: u8 = 10;
+    pub const V_UV: u8 = 11;
+    pub const V_WEIGHT: u8 = 12;
+    pub const V_DISTANCE: u8 = 13;
+    pub const V_IMPEDANCE: u8 = 14;
+    pub const V_ARMED: u8 = 15;
+    pub const V_TRIPPED: u8 = 16;
+    pub const V_WATT: u8 = 17;
+    pub const V_KWH: u8 = 18;
+    pub const V_SCENE_ON: u8 = 19;
+    pub const V_SCENE_OFF: u8 = 20;
+    pub const V_LIGHT_LEVEL: u8 = 23;
+    pub const V_VAR1: u8 = 24;
+    pub const V_VAR2: u8 = 25;
+    pub const V_VAR3: u8 = 26;
+    pub const V_VAR4: u8 = 27;
+    pub const V_VAR5: u8 = 28;
+    pub const V_UP: u8 = 29;
+    pub const V_DOWN: u8 = 30;
+    pub const V_STOP: u8 = 31;
+    pub const V_LOCK_STATUS: u8 = 36;
+    pub const V_LEVEL: u8 = 37;
+    pub const V_VOLTAGE: u8 = 38;
+    pub const V_CURRENT: u8 = 39;
+    pub const V_TEXT: u8 = 47;
+    pub const V_CUSTOM: u8 = 48;
+}
+
+/// `msg_type` values for [`Command::Internal`](enum.Command.html#variant.Internal)
+pub mod internal {
+    pub const I_BATTERY_LEVEL: u8 = 0;
+    pub const I_TIME: u8 = 1;
+    pub const I_VERSION: u8 = 2;
+    pub const I_ID_REQUEST: u8 = 3;
+    pub const I_ID_RESPONSE: u8 = 4;
+    pub const I_INCLUSION_MODE: u8 = 5;
+    pub const I_CONFIG: u8 = 6;
+    pub const I_FIND_PARENT_REQUEST: u8 = 7;
+    pub const I_FIND_PARENT_RESPONSE: u8 = 8;
+    pub const I_LOG_MESSAGE: u8 = 9;
+    pub const I_CHILDREN: u8 = 10;
+    pub const I_SKETCH_NAME: u8 = 11;
+    pub const I_SKETCH_VERSION: u8 = 12;
+    pub const I_REBOOT: u8 = 13;
+    pub const I_GATEWAY_READY: u8 = 14;
+    pub const I_HEARTBEAT_REQUEST: u8 = 18;
+    pub const I_PRESENTATION: u8 = 19;
+    pub const I_DISCOVER_REQUEST: u8 = 20;
+    pub const I_DISCOVER_RESPONSE: u8 = 21;
+    pub const I_HEARTBEAT_RESPONSE: u8 = 22;
+    pub const I_PING: u8 = 24;
+    pub const I_PONG: u8 = 25;
+}
+
+/// One MySensors message
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// Node that last relayed the message
+    pub last: u8,
+    pub sender: u8,
+    pub destination: u8,
+    pub command: Command,
+    /// Ask the destination to echo the message back
+    pub request_echo: bool,
+    /// This message is an echo
+    pub echo: bool,
+    /// Meaning depends on `command`, see [`sensor`](sensor/index.html),
+    /// [`value`](value/index.html), and [`internal`](internal/index.html)
+    pub msg_type: u8,
+    /// Child sensor ID
+    pub sensor: u8,
+    payload_type: PayloadType,
+    payload: [u8; MAX_PAYLOAD_LEN],
+    len: usize,
+}
+
+impl Message {
+    /// Empty string payload
+    pub fn new(destination: u8, sensor: u8, command: Command, msg_type: u8) -> Self {
+        Message {
+            last: AUTO_ID,
+            sender: AUTO_ID,
+            destination,
+            command,
+            request_echo: false,
+            echo: false,
+            msg_type,
+            sensor,
+            payload_type: PayloadType::String,
+            payload: [0; MAX_PAYLOAD_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn payload_type(&self) -> PayloadType {
+        self.payload_type
+    }
+
+    /// Raw payload bytes
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[0..self.len]
+    }
+
+    fn set(mut self, payload_type: PayloadType, data: &[u8]) -> Self {
+        let len = data.len().min(MAX_PAYLOAD_LEN);
+        self.payload[0..len].copy_from_slice(&data[0..len]);
+        self.payload_type = payload_type;
+        self.len = len;
+        self
+    }
+
+    /// Truncated to [`MAX_PAYLOAD_LEN`](constant.MAX_PAYLOAD_LEN.html)
+    pub fn with_str(self, value: &str) -> Self {
+        self.set(PayloadType::String, value.as_bytes())
+    }
+
+    pub fn with_u8(self, value: u8) -> Self {
+        self.set(PayloadType::Byte, &[value])
+    }
+
+    pub fn with_i16(self, value: i16) -> Self {
+        self.set(PayloadType::Int16, &value.to_le_bytes())
+    }
+
+    pub fn with_u16(self, value: u16) -> Self {
+        self.set(PayloadType::UInt16, &value.to_le_bytes())
+    }
+
+    pub fn with_i32(self, value: i32) -> Self {
+        self.set(PayloadType::Long32, &value.to_le_bytes())
+    }
+
+    pub fn with_u32(self, value: u32) -> Self {
+        self.set(PayloadType::ULong32, &value.to_le_bytes())
+    }
+
+    /// `decimals` tells the controller how to display the value
+    pub fn with_f32(self, value: f32, decimals: u8) -> Self {
+        let bytes = value.to_le_bytes();
+        self.set(
+            PayloadType::Float32,
+            &[bytes[0], bytes[1], bytes[2], bytes[3], decimals],
+        )
+    }
+
+    pub fn with_custom(self, data: &[u8]) -> Self {
+        self.set(PayloadType::Custom, data)
+    }
+
+    /// Payload as text, if it is a valid UTF-8 string
+    pub fn as_str(&self) -> Option<&str> {
+        core::str::from_utf8(self.payload()).ok()
+    }
+
+    /// Integer payload of any width, or a decimal string
+    pub fn as_i32(&self) -> Option<i32> {
+        let p = self.payload();
+        match (self.payload_type, p.len()) {
+            (PayloadType::Byte, 1) => Some(p[0].into()),
+            (PayloadType::Int16, 2) => Some(i16::from_le_bytes([p[0], p[1]]).into()),
+            (PayloadType::UInt16, 2) => Some(u16::from_le_bytes([p[0], p[1]]).into()),
+            (PayloadType::Long32, 4) | (PayloadType::ULong32, 4) => {
+                Some(i32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+            }
+            (PayloadType::String, _) => self.as_str()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        let p = self.payload();
+        match (self.payload_type, p.len()) {
+            (PayloadType::Float32, 5) => Some(f32::from_le_bytes([p[0], p[1], p[2], p[3]])),
+            (PayloadType::String, _) => self.as_str()?.parse().ok(),
+            _ => self.as_i32().map(|value| value as f32),
+        }
+    }
+
+    /// Serialize into `buf`, returning the length
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.last;
+        buf[1] = self.sender;
+        buf[2] = self.destination;
+        buf[3] = ((self.len as u8) << 3) | PROTOCOL_VERSION;
+        buf[4] = ((self.payload_type as u8) << 5)
+            | (u8::from(self.echo) << 4)
+            | (u8::from(self.request_echo) << 3)
+            | self.command as u8;
+        buf[5] = self.msg_type;
+        buf[6] = self.sensor;
+        buf[HEADER_LEN..HEADER_LEN + self.len].copy_from_slice(self.payload());
+        HEADER_LEN + self.len
+    }
+
+    /// `None` on a version mismatch or truncated packet
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN || buf[3] & 0b11 != PROTOCOL_VERSION {
+            return None;
+        }
+        let len = usize::from(buf[3] >> 3);
+        if len > MAX_PAYLOAD_LEN || buf.len() < HEADER_LEN + len {
+            return None;
+        }
+        let payload_type = PayloadType::from_bits(buf[4] >> 5);
+        let message = Message {
+            last: buf[0],
+            sender: buf[1],
+            destination: buf[2],
+            command: Command::from_bits(buf[4] & 0b111)?,
+            request_echo: buf[4] & (1 << 3) != 0,
+            echo: buf[4] & (1 << 4) != 0,
+            msg_type: buf[5],
+            sensor: buf[6],
+            payload_type,
+            payload: [0; MAX_PAYLOAD_LEN],
+            len: 0,
+        };
+        Some(message.set(payload_type, &buf[HEADER_LEN..][..len]))
+    }
+}
+
+/// Radio address of `node_id`
+pub fn node_address(node_id: u8) -> [u8; 5] {
+    let mut address = BASE_ADDRESS;
+    address[0] = node_id;
+    address
+}
+
+/// Settings matching the MySensors RF24 defaults
+pub fn config() -> Config {
+    Config::default()
+        .frequency(CHANNEL)
+        .data_rate(DataRate::R250Kbps)
+        .auto_retransmit_delay(5)
+        .auto_retransmit_count(15)
+}
+
+/// Sensor node talking to its parent, which is usually the gateway
+pub struct Node<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    node_id: u8,
+    parent_id: u8,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Node<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Use [`AUTO_ID`](constant.AUTO_ID.html) and
+    /// [`request_id()`](#method.request_id) to have the controller
+    /// assign a node ID.
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>, node_id: u8) -> Result<Self, SpiE> {
+        let mut node = Node {
+            radio,
+            node_id,
+            parent_id: GATEWAY_ID,
+        };
+        node.set_node_id(node_id)?;
+        Ok(node)
+    }
+
+    pub fn node_id(&self) -> u8 {
+        self.node_id
+    }
+
+    pub fn parent_id(&self) -> u8 {
+        self.parent_id
+    }
+
+    /// Route through a repeater instead of talking to the gateway directly
+    pub fn set_parent_id(&mut self, parent_id: u8) {
+        self.parent_id = parent_id;
+    }
+
+    fn set_node_id(&mut self, node_id: u8) -> Result<(), SpiE> {
+        self.node_id = node_id;
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_pipes_rx_enable(&LISTEN_PIPES)?;
+        self.radio.set_rx_addr(NODE_PIPE, &node_address(node_id))?;
+        self.radio.set_rx_addr(BROADCAST_PIPE, &[BROADCAST_ID])?;
+        Ok(())
+    }
+
+    /// Fill in the routing fields and send `message` to the parent
+    ///
+    /// Returns whether the parent acknowledged it.
+    pub fn send(&mut self, message: &mut Message) -> Result<bool, SpiE> {
+        message.last = self.node_id;
+        message.sender = self.node_id;
+        let mut buf = [0; PAYLOAD_LEN];
+        let len = message.encode(&mut buf);
+        let to = match message.destination {
+            BROADCAST_ID => BROADCAST_ID,
+            _ => self.parent_id,
+        };
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_pipes_rx_enable(&SEND_PIPES)?;
+        let acked = self.radio.send_to(&node_address(to), &buf[0..len])?;
+        self.radio.set_pipes_rx_enable(&LISTEN_PIPES)?;
+        Ok(acked)
+    }
+
+    /// Present this node with its sketch name and version
+    pub fn present_node(&mut self, sketch_name: &str, sketch_version: &str) -> Result<bool, SpiE> {
+        let mut message = Message::new(
+            GATEWAY_ID,
+            NODE_SENSOR_ID,
+            Command::Presentation,
+            sensor::S_ARDUINO_NODE,
+        )
+        .with_str(LIBRARY_VERSION);
+        if !self.send(&mut message)? {
+            return Ok(false);
+        }
+        let mut message = Message::new(
+            GATEWAY_ID,
+            NODE_SENSOR_ID,
+            Command::Internal,
+            internal::I_SKETCH_NAME,
+        )
+        .with_str(sketch_name);
+        if !self.send(&mut message)? {
+            return Ok(false);
+        }
+        let mut message = Message::new(
+            GATEWAY_ID,
+            NODE_SENSOR_ID,
+            Command::Internal,
+            internal::I_SKETCH_VERSION,
+        )
+        .with_str(sketch_version);
+        self.send(&mut message)
+    }
+
+    /// Present child `sensor` of type `sensor_type`, see [`sensor`](sensor/index.html)
+    pub fn present(
+        &mut self,
+        sensor: u8,
+        sensor_type: u8,
+        description: &str,
+    ) -> Result<bool, SpiE> {
+        let mut message = Message::new(GATEWAY_ID, sensor, Command::Presentation, sensor_type)
+            .with_str(description);
+        self.send(&mut message)
+    }
+
+    /// Report the battery level in percent
+    pub fn send_battery_level(&mut self, percent: u8) -> Result<bool, SpiE> {
+        let mut message = Message::new(
+            GATEWAY_ID,
+            NODE_SENSOR_ID,
+            Command::Internal,
+            internal::I_BATTERY_LEVEL,
+        )
+        .with_u8(percent);
+        self.send(&mut message)
+    }
+
+    /// Ask the controller for a node ID, waiting up to `timeout_ms`
+    pub fn request_id<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, SpiE> {
+        self.set_node_id(AUTO_ID)?;
+        let mut message = Message::new(
+            GATEWAY_ID,
+            NODE_SENSOR_ID,
+            Command::Internal,
+            internal::I_ID_REQUEST,
+        );
+        if !self.send(&mut message)? {
+            return Ok(false);
+        }
+        for _ in 0..timeout_ms {
+            while let Some(message) = self.receive()? {
+                if message.command == Command::Internal
+                    && message.msg_type == internal::I_ID_RESPONSE
+                {
+                    if let Some(node_id) = message.as_i32() {
+                        if (1..i32::from(AUTO_ID)).contains(&node_id) {
+                            self.set_node_id(node_id as u8)?;
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+            delay.delay_us(1000);
+        }
+        Ok(false)
+    }
+
+    /// Poll for a message addressed to this node or broadcast
+    pub fn receive(&mut self) -> Result<Option<Message>, SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let payload = nb::block!(self.radio.read())?;
+            match Message::decode(&payload) {
+                Some(message)
+                    if message.destination == self.node_id
+                        || message.destination == BROADCAST_ID =>
+                {
+                    return Ok(Some(message))
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! RF24Network-style tree addressing and frame routing
+//!
+//! Nodes are identified by octal addresses: `0` is the master, `0o1`
+//! to `0o5` are its children, `0o12` is the first child of node `0o2`,
+//! `0o312` the third child of `0o12`, and so on, up to four levels
+//! deep: each level adds a digit on the left. Every frame starts with a
+//! [`Header`](struct.Header.html) and is relayed hop by hop along the
+//! tree until it reaches `to_node`.
+
+use crate::{Configuration, Nrf24l01, Payload, Pipe, NUM_PIPES, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Length of the encoded [`Header`](struct.Header.html)
+pub const HEADER_LEN: usize = 8;
+/// Maximum number of user data bytes per frame
+pub const MAX_PAYLOAD_LEN: usize = PAYLOAD_LEN - HEADER_LEN;
+/// Address of the root of the tree
+pub const MASTER_ADDRESS: u16 = 0;
+/// Maximum depth of the tree
+pub const MAX_LEVELS: u8 = 4;
+/// Pipe on which a node listens to its parent
+pub const PARENT_PIPE: u8 = 5;
+
+/// Address bytes chosen for good preamble/address separation
+const ADDRESS_TRANSLATION: [u8; 7] = [0xc3, 0x3c, 0x33, 0xce, 0x3e, 0xe3, 0xec];
+
+/// Frame header, little-endian on the air
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub from_node: u16,
+    pub to_node: u16,
+    /// Sequence number, assigned by [`Network::write()`](struct.Network.html#method.write)
+    pub id: u16,
+    pub msg_type: u8,
+    pub reserved: u8,
+}
+
+impl Header {
+    pub fn new(to_node: u16, msg_type: u8) -> Self {
+        Header {
+            from_node: 0,
+            to_node,
+            id: 0,
+            msg_type,
+            reserved: 0,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) {
+        buf[0..2].copy_from_slice(&self.from_node.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.to_node.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.id.to_le_bytes());
+        buf[6] = self.msg_type;
+        buf[7] = self.reserved;
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        Some(Header {
+            from_node: u16::from_le_bytes([buf[0], buf[1]]),
+            to_node: u16::from_le_bytes([buf[2], buf[3]]),
+            id: u16::from_le_bytes([buf[4], buf[5]]),
+            msg_type: buf[6],
+            reserved: buf[7],
+        })
+    }
+}
+
+/// Every octal digit must be `1..=5`, at most [`MAX_LEVELS`](constant.MAX_LEVELS.html) digits
+pub fn is_valid_address(node: u16) -> bool {
+    let mut rest = node;
+    let mut level = 0;
+    while rest != 0 {
+        let digit = rest & 0o7;
+        if !(1..=5).contains(&digit) || level == MAX_LEVELS {
+            return false;
+        }
+        rest >>= 3;
+        level += 1;
+    }
+    true
+}
+
+/// Depth in the tree, `0` for the master
+pub fn level(node: u16) -> u8 {
+    let mut rest = node;
+    let mut level = 0;
+    while rest != 0 {
+        rest >>= 3;
+        level += 1;
+    }
+    level
+}
+
+fn node_mask(node: u16) -> u16 {
+    (1 << (3 * level(node))) - 1
+}
+
+pub fn parent_of(node: u16) -> u16 {
+    node & (node_mask(node) >> 3)
+}
+
+/// Last octal digit, which is the pipe this node uses on its parent
+pub fn child_digit(node: u16) -> u8 {
+    match level(node) {
+        0 => 0,
+        level => (node >> (3 * (level - 1))) as u8,
+    }
+}
+
+/// Address of child number `digit` (`1..=5`) of `parent`
+pub fn child_address(parent: u16, digit: u8) -> u16 {
+    parent | (u16::from(digit) << (3 * level(parent)))
+}
+
+/// Is `node` somewhere below `ancestor`?
+pub fn is_descendant(ancestor: u16, node: u16) -> bool {
+    node != ancestor && node & node_mask(ancestor) == ancestor
+}
+
+/// Radio address of `pipe` on `node`
+///
+/// Pipes 1 to 5 share all but the first byte, as required by the
+/// chip. Pipe 0 is shared by all nodes on the same level.
+pub fn pipe_address(node: u16, pipe: u8) -> [u8; 5] {
+    let mut out = [0xcc; 5];
+    let mut count = 1;
+    let mut rest = node;
+    while rest != 0 {
+        if pipe != 0 || node == 0 {
+            out[count] = ADDRESS_TRANSLATION[usize::from(rest & 0o7)];
+        }
+        rest >>= 3;
+        count += 1;
+    }
+    if pipe != 0 || node == 0 {
+        out[0] = ADDRESS_TRANSLATION[usize::from(pipe)];
+    } else {
+        out[1] = ADDRESS_TRANSLATION[count - 1];
+    }
+    out
+}
+
+/// One node in the tree
+pub struct Network<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    node_address: u16,
+    next_id: u16,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Network<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        node_address: u16,
+    ) -> Result<Self, SpiE> {
+        let mut network = Network {
+            radio,
+            node_address,
+            next_id: 0,
+        };
+        network.set_node_address(node_address)?;
+        Ok(network)
+    }
+
+    pub fn node_address(&self) -> u16 {
+        self.node_address
+    }
+
+    /// Reconfigure all RX pipes for a new address
+    pub fn set_node_address(&mut self, node_address: u16) -> Result<(), SpiE> {
+        assert!(is_valid_address(node_address));
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.node_address = node_address;
+        self.radio.set_pipes_rx_enable(&[true; NUM_PIPES])?;
+        self.radio
+            .set_rx_addr(Pipe::P0, &pipe_address(node_address, 0))?;
+        self.radio
+            .set_rx_addr(Pipe::P1, &pipe_address(node_address, 1))?;
+        for &pipe in &Pipe::ALL[2..] {
+            let address = pipe_address(node_address, pipe.into());
+            self.radio.set_rx_addr(pipe, &address[0..1])?;
+        }
+        Ok(())
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    /// Send `data` towards `header.to_node`
+    ///
+    /// Fills in `from_node` and `id`. Returns whether the first hop
+    /// acknowledged the frame, `false` without sending if there is no
+    /// route to `to_node`.
+    pub fn write(&mut self, header: &mut Header, data: &[u8]) -> Result<bool, SpiE> {
+        header.from_node = self.node_address;
+        header.id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.forward(header, data)
+    }
+
+    fn forward(&mut self, header: &Header, data: &[u8]) -> Result<bool, SpiE> {
+        let (node, pipe) = if is_descendant(self.node_address, header.to_node) {
+            let mask = (node_mask(self.node_address) << 3) | 0o7;
+            (header.to_node & mask, PARENT_PIPE)
+        } else if self.node_address != 0 {
+            (parent_of(self.node_address), child_digit(self.node_address))
+        } else {
+            // The master has no parent to hand the frame up to
+            return Ok(false);
+        };
+        self.write_direct(node, pipe, header, data)
+    }
+
+    /// Send one frame to `pipe` of the neighbouring `node`, bypassing routing
+    pub fn write_direct(
+        &mut self,
+        node: u16,
+        pipe: u8,
+        header: &Header,
+        data: &[u8],
+    ) -> Result<bool, SpiE> {
+        assert!(data.len() <= MAX_PAYLOAD_LEN);
+        let mut frame = [0; PAYLOAD_LEN];
+        header.encode(&mut frame);
+        frame[HEADER_LEN..HEADER_LEN + data.len()].copy_from_slice(data);
+
+        let address = pipe_address(node, pipe);
+        let acked = self
+            .radio
+            .send_to(&address, &frame[0..HEADER_LEN + data.len()])?;
+        self.radio
+            .set_rx_addr(Pipe::P0, &pipe_address(self.node_address, 0))?;
+        Ok(acked)
+    }
+
+    /// Poll for a frame addressed to this node
+    ///
+    /// Frames for other nodes are relayed on the way.
+    pub fn update(&mut self) -> Result<Option<(Header, Payload)>, SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let frame = nb::block!(self.radio.read())?;
+            let header = match Header::decode(&frame) {
+                Some(header) => header,
+                None => continue,
+            };
+            let data = Payload::new(&frame[HEADER_LEN..]);
+            if header.to_node == self.node_address {
+                return Ok(Some((header, data)));
+            }
+            self.forward(&header, &data)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_addressing() {
+        assert_eq!(child_address(MASTER_ADDRESS, 2), 0o2);
+        assert_eq!(child_address(0o2, 1), 0o12);
+        assert_eq!(child_address(0o12, 3), 0o312);
+        assert_eq!(parent_of(0o2), MASTER_ADDRESS);
+        assert_eq!(parent_of(0o12), 0o2);
+        assert_eq!(parent_of(0o21), 0o1);
+        assert_eq!(parent_of(0o312), 0o12);
+        assert_eq!(child_digit(0o12), 1);
+        assert_eq!(child_digit(0o312), 3);
+        assert_eq!(level(0o312), 3);
+        assert!(is_descendant(0o2, 0o312));
+        assert!(!is_descendant(0o1, 0o312));
+        assert!(is_valid_address(0o5555));
+        assert!(!is_valid_address(0o15555));
+        assert!(!is_valid_address(0o16));
+        for parent in [MASTER_ADDRESS, 0o3, 0o45, 0o123] {
+            for digit in 1..=5 {
+                let child = child_address(parent, digit);
+                assert_eq!(parent_of(child), parent);
+                assert_eq!(child_digit(child), digit);
+                assert_eq!(level(child), level(parent) + 1);
+            }
+        }
+    }
+}
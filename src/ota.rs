@@ -0,0 +1,395 @@
+//! Firmware image transfer for over-the-air updates
+//!
+//! A [`Sender`](struct.Sender.html) announces an image by its length
+//! and CRC-32, then sends it in chunks tagged with their offset. The
+//! [`Receiver`](struct.Receiver.html) hands each chunk to an
+//! [`ImageSink`](trait.ImageSink.html), usually a flash writer, and
+//! keeps its progress loaded as the ack payload. The sender waits for
+//! every chunk to show up in that progress before sending the next, and
+//! goes back to whatever offset the receiver reports.
+//!
+//! Transfers resume: when the same image is announced again, the sink
+//! tells how much of it it already holds and the sender carries on from
+//! there. Once complete, the receiver reads the image back from the
+//! sink and checks its CRC-32 before reporting success.
+
+use crate::framing::{crc32, crc32_update};
+use crate::{Configuration, Nrf24l01, Pipe, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_BEGIN: u8 = 0xC1;
+const TYPE_CHUNK: u8 = 0xC2;
+const TYPE_END: u8 = 0xC3;
+const TYPE_POLL: u8 = 0xC4;
+const TYPE_STATUS: u8 = 0xC5;
+const BEGIN_LEN: usize = 9;
+const CHUNK_HEADER_LEN: usize = 5;
+const STATUS_LEN: usize = 10;
+/// Image bytes per chunk
+pub const CHUNK_LEN: usize = PAYLOAD_LEN - CHUNK_HEADER_LEN;
+/// Pause between polls for the receiver's progress
+const POLL_INTERVAL_US: u32 = 1000;
+
+/// Where the receiver is with an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// No image announced yet
+    Idle,
+    Receiving,
+    /// Received in full and verified
+    Complete,
+    /// Did not match its CRC-32, or the sink failed
+    Failed,
+}
+
+impl State {
+    fn from_u8(state: u8) -> Option<Self> {
+        match state {
+            0 => Some(State::Idle),
+            1 => Some(State::Receiving),
+            2 => Some(State::Complete),
+            3 => Some(State::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Progress report in the receiver's ack payload
+#[derive(Debug, Clone, Copy)]
+struct Status {
+    state: State,
+    next: u32,
+    crc: u32,
+}
+
+impl Status {
+    fn encode(&self) -> [u8; STATUS_LEN] {
+        let mut buf = [0; STATUS_LEN];
+        buf[0] = TYPE_STATUS;
+        buf[1] = self.state as u8;
+        buf[2..6].copy_from_slice(&self.next.to_le_bytes());
+        buf[6..10].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < STATUS_LEN || buf[0] != TYPE_STATUS {
+            return None;
+        }
+        Some(Status {
+            state: State::from_u8(buf[1])?,
+            next: read_u32(&buf[2..6]),
+            crc: read_u32(&buf[6..10]),
+        })
+    }
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+/// Where received images go, e.g. the update slot of the flash
+pub trait ImageSink {
+    type Error;
+
+    /// Prepare for an image of `len` bytes
+    ///
+    /// Returns how many bytes of this very image, as told by `crc`, are
+    /// already held from an interrupted transfer, `0` to start over.
+    fn begin(&mut self, len: u32, crc: u32) -> Result<u32, Self::Error>;
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+    /// Read back what has been written, for verification
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+    /// `received` of `len` bytes are there
+    fn progress(&mut self, _received: u32, _len: u32) {}
+    /// The image is complete, and whether it matched its CRC-32
+    fn finish(&mut self, verified: bool) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum Error<SpiE, SinkE> {
+    Spi(SpiE),
+    Sink(SinkE),
+}
+
+impl<SpiE, SinkE> From<SpiE> for Error<SpiE, SinkE> {
+    fn from(e: SpiE) -> Self {
+        Error::Spi(e)
+    }
+}
+
+/// Receiving end of an update, feeding an [`ImageSink`](trait.ImageSink.html)
+pub struct Receiver<'a, Ce, Csn, Spi, E, SpiE, S>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    S: ImageSink,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    pipe: Pipe,
+    sink: S,
+    status: Status,
+    len: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE, S> Receiver<'a, Ce, Csn, Spi, E, SpiE, S>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    S: ImageSink,
+{
+    /// Receive on `pipe`, which must have auto-ack enabled
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        pipe: Pipe,
+        sink: S,
+    ) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_ack_payloads(true)?;
+        // Enter RX mode before the status ack payload occupies the TX FIFO
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        let mut receiver = Receiver {
+            radio,
+            pipe,
+            sink,
+            status: Status {
+                state: State::Idle,
+                next: 0,
+                crc: 0,
+            },
+            len: 0,
+        };
+        receiver.load_status()?;
+        Ok(receiver)
+    }
+
+    pub fn state(&self) -> State {
+        self.status.state
+    }
+
+    /// Bytes of the current image received so far
+    pub fn received(&self) -> u32 {
+        self.status.next
+    }
+
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Handle what has arrived, returning the state afterwards
+    ///
+    /// Sink errors fail the transfer before they are returned, the
+    /// sender learns about it with its next poll.
+    pub fn poll(&mut self) -> Result<State, Error<SpiE, S::Error>> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(self.status.state),
+                Err(nb::Error::Other(e)) => return Err(Error::Spi(e)),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            let result = self.handle(&packet);
+            if result.is_err() {
+                self.status.state = State::Failed;
+            }
+            self.load_status()?;
+            result.map_err(Error::Sink)?;
+        }
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Result<(), S::Error> {
+        match packet.first() {
+            Some(&TYPE_BEGIN) if packet.len() >= BEGIN_LEN => {
+                let len = read_u32(&packet[1..5]);
+                let crc = read_u32(&packet[5..9]);
+                let repeated = self.status.state == State::Receiving
+                    && self.status.crc == crc
+                    && self.len == len;
+                if !repeated {
+                    let held = self.sink.begin(len, crc)?.min(len);
+                    self.status = Status {
+                        state: State::Receiving,
+                        next: held,
+                        crc,
+                    };
+                    self.len = len;
+                }
+            }
+            Some(&TYPE_CHUNK) if packet.len() > CHUNK_HEADER_LEN => {
+                let offset = read_u32(&packet[1..5]);
+                let data = &packet[CHUNK_HEADER_LEN..];
+                let fits = (offset as usize)
+                    .checked_add(data.len())
+                    .is_some_and(|end| end <= self.len as usize);
+                if self.status.state == State::Receiving && offset == self.status.next && fits {
+                    self.sink.write(offset, data)?;
+                    self.status.next += data.len() as u32;
+                    self.sink.progress(self.status.next, self.len);
+                }
+            }
+            Some(&TYPE_END)
+                if self.status.state == State::Receiving && self.status.next == self.len =>
+            {
+                let verified = self.verify()?;
+                self.status.state = match verified {
+                    true => State::Complete,
+                    false => State::Failed,
+                };
+                self.sink.finish(verified)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Compare the CRC-32 of what the sink holds with the announced one
+    fn verify(&mut self) -> Result<bool, S::Error> {
+        let mut crc = 0;
+        let mut buf = [0; PAYLOAD_LEN];
+        let mut offset = 0;
+        while offset < self.len {
+            let len = (self.len - offset).min(PAYLOAD_LEN as u32) as usize;
+            self.sink.read(offset, &mut buf[0..len])?;
+            crc = crc32_update(crc, &buf[0..len]);
+            offset += len as u32;
+        }
+        Ok(crc == self.status.crc)
+    }
+
+    /// Replace the ack payload with the current status
+    fn load_status(&mut self) -> Result<(), SpiE> {
+        self.radio.flush_tx()?;
+        self.radio
+            .write_ack_payload(self.pipe, &self.status.encode())
+    }
+}
+
+/// Sending end of an update
+pub struct Sender<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    peer_address: [u8; 5],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Sender<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        peer_address: [u8; 5],
+    ) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_ack_payloads(true)?;
+        Ok(Sender {
+            radio,
+            peer_address,
+        })
+    }
+
+    /// Transfer `image`, resuming where the receiver left off
+    ///
+    /// Gives up when the receiver has not made progress for
+    /// `timeout_ms`. Returns whether the receiver verified the image;
+    /// call again to resume after a timeout.
+    pub fn send_image<D: DelayUs<u32>>(
+        &mut self,
+        image: &[u8],
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, SpiE> {
+        let len = image.len() as u32;
+        let crc = crc32(image);
+        let mut begin = [0; BEGIN_LEN];
+        begin[0] = TYPE_BEGIN;
+        begin[1..5].copy_from_slice(&len.to_le_bytes());
+        begin[5..9].copy_from_slice(&crc.to_le_bytes());
+        let started = |status: &Status| status.state == State::Receiving;
+        let mut status = match self.request(&begin, crc, started, delay, timeout_ms)? {
+            Some(status) => status,
+            None => return Ok(false),
+        };
+        while status.state == State::Receiving && status.next < len {
+            let offset = status.next;
+            let start = offset as usize;
+            let end = (start + CHUNK_LEN).min(image.len());
+            let mut chunk = [0; PAYLOAD_LEN];
+            chunk[0] = TYPE_CHUNK;
+            chunk[1..5].copy_from_slice(&offset.to_le_bytes());
+            chunk[CHUNK_HEADER_LEN..CHUNK_HEADER_LEN + end - start]
+                .copy_from_slice(&image[start..end]);
+            let packet = &chunk[0..CHUNK_HEADER_LEN + end - start];
+            let moved = |status: &Status| status.state != State::Receiving || status.next != offset;
+            status = match self.request(packet, crc, moved, delay, timeout_ms)? {
+                Some(status) => status,
+                None => return Ok(false),
+            };
+        }
+        if status.state == State::Receiving {
+            let done = |status: &Status| status.state != State::Receiving;
+            status = match self.request(&[TYPE_END], crc, done, delay, timeout_ms)? {
+                Some(status) => status,
+                None => return Ok(false),
+            };
+        }
+        Ok(status.state == State::Complete)
+    }
+
+    /// Send `packet`, then poll until the status of the image with `crc`
+    /// satisfies `done`
+    fn request<F, D>(
+        &mut self,
+        packet: &[u8],
+        crc: u32,
+        done: F,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<Status>, SpiE>
+    where
+        F: Fn(&Status) -> bool,
+        D: DelayUs<u32>,
+    {
+        let mut sent = false;
+        for _ in 0..timeout_ms {
+            let next = match sent {
+                false => packet,
+                true => &[TYPE_POLL],
+            };
+            if self.radio.send_to(&self.peer_address, next)? {
+                sent = true;
+                let status = self
+                    .radio
+                    .read_ack_payload()?
+                    .and_then(|ack| Status::decode(&ack));
+                if let Some(status) = status {
+                    if status.crc == crc && done(&status) {
+                        return Ok(Some(status));
+                    }
+                }
+            }
+            delay.delay_us(POLL_INTERVAL_US);
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,139 @@
+//! Two radios working as one full-duplex-ish link
+//!
+//! A [`RadioPair`](struct.RadioPair.html) keeps one module listening
+//! all the time while the other one transmits, so nothing arriving
+//! during a transmission is missed. Both modules usually hang off the
+//! same SPI bus, hence the shared SPI error type.
+//!
+//! The two radios can sit on the same channel, or on channels far
+//! enough apart (a few MHz at 1 Mbps) that the transmitter does not
+//! desensitize the receiver next to it.
+
+use crate::channels::Channel;
+use crate::{Address, Configuration, DataRate, Nrf24l01, Payload, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// A dedicated transmitter and a dedicated receiver
+pub struct RadioPair<TCe, TCsn, TSpi, TE, RCe, RCsn, RSpi, RE, SpiE>
+where
+    TCe: OutputPin<Error = TE>,
+    TCsn: OutputPin<Error = TE>,
+    TSpi: Transfer<u8, Error = SpiE>,
+    TE: Debug,
+    RCe: OutputPin<Error = RE>,
+    RCsn: OutputPin<Error = RE>,
+    RSpi: Transfer<u8, Error = SpiE>,
+    RE: Debug,
+    SpiE: Debug,
+{
+    tx: Nrf24l01<TCe, TCsn, TSpi, TE, SpiE>,
+    rx: Nrf24l01<RCe, RCsn, RSpi, RE, SpiE>,
+}
+
+impl<TCe, TCsn, TSpi, TE, RCe, RCsn, RSpi, RE, SpiE>
+    RadioPair<TCe, TCsn, TSpi, TE, RCe, RCsn, RSpi, RE, SpiE>
+where
+    TCe: OutputPin<Error = TE>,
+    TCsn: OutputPin<Error = TE>,
+    TSpi: Transfer<u8, Error = SpiE>,
+    TE: Debug,
+    RCe: OutputPin<Error = RE>,
+    RCsn: OutputPin<Error = RE>,
+    RSpi: Transfer<u8, Error = SpiE>,
+    RE: Debug,
+    SpiE: Debug,
+{
+    /// Put `rx` into RX mode for good
+    ///
+    /// The data rate of `rx` is changed to match `tx`.
+    pub fn new(
+        mut tx: Nrf24l01<TCe, TCsn, TSpi, TE, SpiE>,
+        mut rx: Nrf24l01<RCe, RCsn, RSpi, RE, SpiE>,
+    ) -> Result<Self, SpiE> {
+        let rate = tx.get_data_rate()?;
+        rx.set_data_rate(rate)?;
+        rx.flush_tx()?;
+        if let Err(nb::Error::Other(e)) = rx.wait_rx_ready() {
+            return Err(e);
+        }
+        Ok(RadioPair { tx, rx })
+    }
+
+    pub fn tx(&mut self) -> &mut Nrf24l01<TCe, TCsn, TSpi, TE, SpiE> {
+        &mut self.tx
+    }
+
+    pub fn rx(&mut self) -> &mut Nrf24l01<RCe, RCsn, RSpi, RE, SpiE> {
+        &mut self.rx
+    }
+
+    /// Give back both radios
+    #[allow(clippy::type_complexity)]
+    pub fn release(
+        self,
+    ) -> (
+        Nrf24l01<TCe, TCsn, TSpi, TE, SpiE>,
+        Nrf24l01<RCe, RCsn, RSpi, RE, SpiE>,
+    ) {
+        (self.tx, self.rx)
+    }
+
+    /// Move both radios to `channel`
+    pub fn set_channel(&mut self, channel: Channel) -> Result<(), SpiE> {
+        self.set_channels(channel, channel)
+    }
+
+    /// Transmit on `tx` and listen on `rx`, with the peer doing the
+    /// opposite
+    pub fn set_channels(&mut self, tx: Channel, rx: Channel) -> Result<(), SpiE> {
+        nb::block!(self.tx.wait_tx_empty())?;
+        self.tx.set_channel(tx)?;
+        self.rx.set_channel(rx)
+    }
+
+    pub fn set_data_rate(&mut self, rate: DataRate) -> Result<(), SpiE> {
+        nb::block!(self.tx.wait_tx_empty())?;
+        self.tx.set_data_rate(rate)?;
+        self.rx.set_data_rate(rate)
+    }
+
+    /// Listen on `address` with pipe 1
+    ///
+    /// Pipe 0 stays free for acks on the transmitter and so the
+    /// receiver uses the same pipes as a single radio would.
+    pub fn set_rx_address(&mut self, address: &Address) -> Result<(), SpiE> {
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[Pipe::P1.index()] = true;
+        self.rx.set_pipes_rx_enable(&pipes)?;
+        self.rx.set_rx_addr(Pipe::P1, address)
+    }
+
+    /// Queue `packet` for `address` on the transmitter
+    ///
+    /// Pipe 0 of the transmitter follows `address` for the auto-ack.
+    pub fn send_to(&mut self, address: &Address, packet: &[u8]) -> nb::Result<(), SpiE> {
+        self.tx.wait_tx_empty()?;
+        self.tx.set_tx_addr(address)?;
+        self.tx.set_rx_addr(Pipe::P0, address)?;
+        self.tx.send(packet)
+    }
+
+    /// Outcome of the last packet, see
+    /// [`Nrf24l01::wait_tx_done()`](../struct.Nrf24l01.html#method.wait_tx_done)
+    pub fn wait_tx_done(&mut self) -> nb::Result<bool, SpiE> {
+        self.tx.wait_tx_done()
+    }
+
+    /// Poll the receiver
+    pub fn receive(&mut self) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        let pipe = match self.rx.wait_rx_ready() {
+            Ok(pipe) => pipe,
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        };
+        let payload = nb::block!(self.rx.read())?;
+        Ok(Some((pipe, payload)))
+    }
+}
@@ -1,6 +1,14 @@
 use crate::PAYLOAD_LEN;
+use core::convert::TryFrom;
 use core::ops::Deref;
 
+/// More than [`PAYLOAD_LEN`](constant.PAYLOAD_LEN.html) bytes were
+/// given for a [`Payload`](struct.Payload.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadTooLong {
+    pub len: usize,
+}
+
 /// Represents a received packet. Stores 32 bytes and the actual length.
 ///
 /// Use [`as_ref()`](#method.as_ref) or [`Deref`](#impl-Deref) to
@@ -8,15 +16,33 @@ use core::ops::Deref;
 pub struct Payload {
     data: [u8; PAYLOAD_LEN],
     len: usize,
+    timestamp_us: Option<u64>,
 }
 
 impl Payload {
-    /// Copy a slice
+    /// Copy a slice, cut off after [`PAYLOAD_LEN`](constant.PAYLOAD_LEN.html) bytes
     pub fn new(source: &[u8]) -> Self {
         let mut data = [0; PAYLOAD_LEN];
         let len = source.len().min(data.len());
         data[0..len].copy_from_slice(&source[0..len]);
-        Payload { data, len }
+        Payload {
+            data,
+            len,
+            timestamp_us: None,
+        }
+    }
+
+    /// Copy a slice that must fit
+    pub fn try_new(source: &[u8]) -> Result<Self, PayloadTooLong> {
+        match source.len() {
+            len if len > PAYLOAD_LEN => Err(PayloadTooLong { len }),
+            _ => Ok(Payload::new(source)),
+        }
+    }
+
+    /// The buffer and the length of the content at its start
+    pub fn into_inner(self) -> ([u8; PAYLOAD_LEN], usize) {
+        (self.data, self.len)
     }
 
     /// Read length
@@ -27,6 +53,16 @@ impl Payload {
     pub fn is_empty(&self) -> bool {
         self.len == 0
     }
+
+    /// When the packet arrived, see
+    /// [`Nrf24l01::set_rx_clock()`](struct.Nrf24l01.html#method.set_rx_clock)
+    pub fn timestamp_us(&self) -> Option<u64> {
+        self.timestamp_us
+    }
+
+    pub(crate) fn set_timestamp_us(&mut self, timestamp_us: Option<u64>) {
+        self.timestamp_us = timestamp_us;
+    }
 }
 
 impl AsRef<[u8]> for Payload {
@@ -41,3 +77,32 @@ impl Deref for Payload {
         self.as_ref()
     }
 }
+
+impl AsMut<[u8]> for Payload {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.data[0..self.len]
+    }
+}
+
+/// Same as [`Payload::new()`](struct.Payload.html#method.new)
+impl From<&[u8]> for Payload {
+    fn from(source: &[u8]) -> Self {
+        Payload::new(source)
+    }
+}
+
+/// The inverse of [`into_inner()`](struct.Payload.html#method.into_inner)
+impl TryFrom<([u8; PAYLOAD_LEN], usize)> for Payload {
+    type Error = PayloadTooLong;
+
+    fn try_from((data, len): ([u8; PAYLOAD_LEN], usize)) -> Result<Self, PayloadTooLong> {
+        if len > PAYLOAD_LEN {
+            return Err(PayloadTooLong { len });
+        }
+        Ok(Payload {
+            data,
+            len,
+            timestamp_us: None,
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Placeholder for a CE pin that is tied high on the board
+///
+/// See [`Nrf24l01::new_without_ce()`](struct.Nrf24l01.html#method.new_without_ce).
+/// `E` is the error type of the CSN pin.
+pub struct NoPin<E>(PhantomData<E>);
+
+impl<E> NoPin<E> {
+    pub fn new() -> Self {
+        NoPin(PhantomData)
+    }
+}
+
+impl<E> Default for NoPin<E> {
+    fn default() -> Self {
+        NoPin::new()
+    }
+}
+
+impl<E: Debug> OutputPin for NoPin<E> {
+    type Error = E;
+
+    fn set_low(&mut self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), E> {
+        Ok(())
+    }
+}
+
+/// Short busy-wait for [`CsnTiming`](struct.CsnTiming.html)
+///
+/// embedded-hal 0.2 has no nanosecond delay, wrap a cycle counter or a
+/// calibrated `nop` loop. Rounding up is fine. With the
+/// `embedded-hal-1` feature, every `Clone` implementation of
+/// `embedded_hal::delay::DelayNs` from embedded-hal 1.0 is one.
+pub trait ShortDelay {
+    fn delay_ns(&self, ns: u32);
+}
+
+/// Delays on a clone, as the trait objects are shared
+#[cfg(feature = "embedded-hal-1")]
+impl<D: embedded_hal_1::delay::DelayNs + Clone> ShortDelay for D {
+    fn delay_ns(&self, ns: u32) {
+        embedded_hal_1::delay::DelayNs::delay_ns(&mut self.clone(), ns)
+    }
+}
+
+/// Extra time around CSN edges for slow level shifters or long cables
+///
+/// `setup_ns` is waited after pulling CSN low and before clocking,
+/// `hold_ns` after the last clock and before raising CSN, and again
+/// after raising it before the next transaction can start.
+#[derive(Clone, Copy)]
+pub struct CsnTiming {
+    pub delay: &'static dyn ShortDelay,
+    pub setup_ns: u32,
+    pub hold_ns: u32,
+}
+
+impl CsnTiming {
+    pub(crate) fn setup(&self) {
+        if self.setup_ns > 0 {
+            self.delay.delay_ns(self.setup_ns);
+        }
+    }
+
+    pub(crate) fn hold(&self) {
+        if self.hold_ns > 0 {
+            self.delay.delay_ns(self.hold_ns);
+        }
+    }
+}
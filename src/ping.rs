@@ -0,0 +1,229 @@
+//! Round-trip time and loss measurement
+//!
+//! [`Nrf24l01::ping()`](../struct.Nrf24l01.html#method.ping) times
+//! each packet from queuing until its auto-ack arrives, including
+//! retransmits. Any node listening with auto-ack on the address
+//! answers; a [`Responder`](struct.Responder.html) additionally counts
+//! what arrives.
+//!
+//! [`Nrf24l01::link_margin()`](../struct.Nrf24l01.html#method.link_margin)
+//! repeats the pings at decreasing output power to find out how much
+//! weaker the link could get.
+
+use crate::{Configuration, Nrf24l01, OutputPower, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_PING: u8 = 0xE1;
+/// Resolution of the time measurement
+const STEP_US: u32 = 10;
+/// Pause between pings
+const INTERVAL_US: u32 = 10_000;
+/// Give up on a ping that has neither been acked nor dropped
+const TIMEOUT_US: u32 = 100_000;
+
+/// Results of a [`ping()`](../struct.Nrf24l01.html#method.ping)
+///
+/// Times are rounded up to 10 µs and include SPI overhead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PingStats {
+    pub sent: u16,
+    pub received: u16,
+    pub min_us: u32,
+    pub avg_us: u32,
+    pub max_us: u32,
+}
+
+impl PingStats {
+    /// Lost pings in percent
+    pub fn loss_percent(&self) -> u8 {
+        match self.sent {
+            0 => 0,
+            sent => (u32::from(sent - self.received) * 100 / u32::from(sent)) as u8,
+        }
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Send `count` pings to `peer_addr`, 10 ms apart
+    pub fn ping<D: DelayUs<u32>>(
+        &mut self,
+        peer_addr: &[u8],
+        count: u16,
+        delay: &mut D,
+    ) -> Result<PingStats, SpiE> {
+        let mut stats = PingStats {
+            min_us: u32::MAX,
+            ..PingStats::default()
+        };
+        let mut total_us = 0u64;
+        nb::block!(self.wait_tx_empty())?;
+        self.set_tx_addr(peer_addr)?;
+        self.set_rx_addr(Pipe::P0, peer_addr)?;
+        for seq in 0..count {
+            let [lo, hi] = seq.to_le_bytes();
+            nb::block!(self.send(&[TYPE_PING, lo, hi]))?;
+            stats.sent += 1;
+            let mut elapsed_us = 0;
+            let acked = loop {
+                match self.wait_tx_done() {
+                    Ok(acked) => break acked,
+                    Err(nb::Error::WouldBlock) => {}
+                    Err(nb::Error::Other(e)) => return Err(e),
+                }
+                if elapsed_us >= TIMEOUT_US {
+                    self.flush_tx()?;
+                    break false;
+                }
+                delay.delay_us(STEP_US);
+                elapsed_us += STEP_US;
+            };
+            if acked {
+                let rtt_us = elapsed_us.max(STEP_US);
+                stats.received += 1;
+                stats.min_us = stats.min_us.min(rtt_us);
+                stats.max_us = stats.max_us.max(rtt_us);
+                total_us += u64::from(rtt_us);
+            }
+            delay.delay_us(INTERVAL_US);
+        }
+        if stats.received == 0 {
+            stats.min_us = 0;
+        } else {
+            stats.avg_us = (total_us / u64::from(stats.received)) as u32;
+        }
+        Ok(stats)
+    }
+
+    /// Step the output power down from 0 dBm while pinging `peer_addr`
+    ///
+    /// Runs `count` pings per power level and stops at the first one
+    /// that delivers less than `target_percent`. The chip has no RSSI,
+    /// so the distance to that power is the best estimate of how much
+    /// headroom the link has. The previous output power is restored.
+    pub fn link_margin<D: DelayUs<u32>>(
+        &mut self,
+        peer_addr: &[u8],
+        count: u16,
+        target_percent: u8,
+        delay: &mut D,
+    ) -> Result<LinkMargin, SpiE> {
+        let variant = self.chip_variant();
+        let previous = self.get_output_power()?;
+        let start = OutputPower::ZeroDbm.level(variant).unwrap_or(0);
+        let mut margin = LinkMargin {
+            lowest: None,
+            stats: PingStats::default(),
+        };
+        for level in (0..=start).rev() {
+            let power = match OutputPower::from_level(variant, level) {
+                Some(power) => power,
+                None => break,
+            };
+            self.set_output_power(power)?;
+            let stats = self.ping(peer_addr, count, delay)?;
+            if 100 - stats.loss_percent() < target_percent {
+                if margin.lowest.is_none() {
+                    margin.stats = stats;
+                }
+                break;
+            }
+            margin.lowest = Some(power);
+            margin.stats = stats;
+        }
+        self.set_output_power(previous)?;
+        Ok(margin)
+    }
+}
+
+/// Result of [`link_margin()`](../struct.Nrf24l01.html#method.link_margin)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkMargin {
+    /// Lowest power that achieved the target, `None` if not even 0 dBm did
+    pub lowest: Option<OutputPower>,
+    /// Pings at `lowest`, or at 0 dBm if it failed
+    pub stats: PingStats,
+}
+
+impl LinkMargin {
+    /// Power in dB that could be lost at 0 dBm before the link fails
+    pub fn margin_db(&self) -> Option<u8> {
+        Some(self.lowest?.dbm().unsigned_abs())
+    }
+}
+
+/// Answers pings and counts them
+pub struct Responder<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    received: u32,
+    last_seq: Option<u16>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Responder<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen with auto-ack on pipe 1 at `address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+    ) -> Result<Self, SpiE> {
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[1] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_rx_addr(Pipe::P1, address)?;
+        Ok(Responder {
+            radio,
+            received: 0,
+            last_seq: None,
+        })
+    }
+
+    /// Pings received so far
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Sequence number of the last ping
+    pub fn last_seq(&self) -> Option<u16> {
+        self.last_seq
+    }
+
+    /// Drain received pings, other packets are discarded
+    pub fn poll(&mut self) -> Result<(), SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            if let [TYPE_PING, lo, hi] = *packet {
+                self.received = self.received.wrapping_add(1);
+                self.last_seq = Some(u16::from_le_bytes([lo, hi]));
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::registers::RfSetup;
+use crate::Clock;
+
+/// Operating state for the [power estimate](struct.Nrf24l01.html#method.track_power)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    PowerDown,
+    /// Powered up, CE low
+    Standby,
+    /// PTX with CE high, counted as transmitting even while idle
+    Tx,
+    /// PRX with CE high
+    Rx,
+}
+
+/// Typical nRF24L01+ supply current in TX mode per `RF_PWR`, in nA
+const TX_NA: [u64; 4] = [7_000_000, 7_500_000, 9_000_000, 11_300_000];
+const RX_250KBPS_NA: u64 = 12_600_000;
+const RX_1MBPS_NA: u64 = 13_100_000;
+const RX_2MBPS_NA: u64 = 13_500_000;
+const STANDBY_NA: u64 = 26_000;
+const POWER_DOWN_NA: u64 = 900;
+
+/// Integrates datasheet currents over the time spent in each state
+pub(crate) struct PowerTracker {
+    clock: &'static dyn Clock,
+    state: PowerState,
+    since_us: u64,
+    /// Charge of all completed states, in pC
+    charge_pc: u64,
+    tx_na: u64,
+    rx_na: u64,
+}
+
+impl PowerTracker {
+    pub fn new(clock: &'static dyn Clock, state: PowerState, rf_setup: &RfSetup) -> Self {
+        let mut tracker = PowerTracker {
+            clock,
+            state,
+            since_us: clock.now_us(),
+            charge_pc: 0,
+            tx_na: 0,
+            rx_na: 0,
+        };
+        tracker.set_rf(rf_setup);
+        tracker
+    }
+
+    /// Pick the currents for new RF settings
+    pub fn set_rf(&mut self, rf_setup: &RfSetup) {
+        self.close_state();
+        self.tx_na = TX_NA[usize::from(rf_setup.rf_pwr())];
+        self.rx_na = match (rf_setup.rf_dr_low(), rf_setup.rf_dr_high()) {
+            (true, _) => RX_250KBPS_NA,
+            (false, false) => RX_1MBPS_NA,
+            (false, true) => RX_2MBPS_NA,
+        };
+    }
+
+    pub fn transition(&mut self, state: PowerState) {
+        if state != self.state {
+            self.close_state();
+            self.state = state;
+        }
+    }
+
+    /// Charge so far, including the current state, in pC
+    pub fn charge_pc(&self) -> u64 {
+        self.charge_pc + self.open_charge_pc(self.clock.now_us())
+    }
+
+    fn close_state(&mut self) {
+        let now = self.clock.now_us();
+        self.charge_pc += self.open_charge_pc(now);
+        self.since_us = now;
+    }
+
+    fn open_charge_pc(&self, now: u64) -> u64 {
+        let current_na = match self.state {
+            PowerState::PowerDown => POWER_DOWN_NA,
+            PowerState::Standby => STANDBY_NA,
+            PowerState::Tx => self.tx_na,
+            PowerState::Rx => self.rx_na,
+        };
+        let elapsed_us = now.saturating_sub(self.since_us);
+        (u128::from(current_na) * u128::from(elapsed_us) / 1000) as u64
+    }
+}
@@ -0,0 +1,233 @@
+//! Topic-based publish/subscribe
+//!
+//! Every payload starts with a topic id: topics below `0x80` take one
+//! byte, larger ones up to [`MAX_TOPIC`](constant.MAX_TOPIC.html) two
+//! bytes with the top bit of the first byte set. Subscribers listen on
+//! whatever RX pipes are enabled and filter by topic.
+
+use crate::{Nrf24l01, Payload, Pipe, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+pub const MAX_TOPIC: u16 = 0x7FFF;
+const LONG_TOPIC: u8 = 0x80;
+
+/// Length of the encoded `topic`
+pub fn topic_len(topic: u16) -> usize {
+    if topic < u16::from(LONG_TOPIC) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Prepend `topic` to `data` in `buf`, returning the length
+pub fn encode(topic: u16, data: &[u8], buf: &mut [u8]) -> usize {
+    assert!(topic <= MAX_TOPIC);
+    let len = topic_len(topic);
+    assert!(len + data.len() <= PAYLOAD_LEN);
+    if len == 1 {
+        buf[0] = topic as u8;
+    } else {
+        buf[0] = LONG_TOPIC | (topic >> 8) as u8;
+        buf[1] = topic as u8;
+    }
+    buf[len..len + data.len()].copy_from_slice(data);
+    len + data.len()
+}
+
+/// Split a payload into topic and data
+pub fn decode(buf: &[u8]) -> Option<(u16, &[u8])> {
+    let (&first, rest) = buf.split_first()?;
+    if first & LONG_TOPIC == 0 {
+        return Some((u16::from(first), rest));
+    }
+    let (&second, rest) = rest.split_first()?;
+    Some((
+        u16::from(first & !LONG_TOPIC) << 8 | u16::from(second),
+        rest,
+    ))
+}
+
+/// Matches topics whose bits under `mask` equal those of `topic`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    pub topic: u16,
+    pub mask: u16,
+}
+
+impl Filter {
+    pub fn exact(topic: u16) -> Self {
+        Filter {
+            topic,
+            mask: 0xFFFF,
+        }
+    }
+
+    /// Any topic
+    pub fn all() -> Self {
+        Filter { topic: 0, mask: 0 }
+    }
+
+    pub fn matches(&self, topic: u16) -> bool {
+        topic & self.mask == self.topic & self.mask
+    }
+}
+
+/// Called with the topic and data of each matching message
+pub type Callback = fn(u16, &[u8]);
+
+/// A received message
+pub struct Message {
+    pub topic: u16,
+    /// Pipe it arrived on
+    pub pipe: Pipe,
+    /// Data after the topic id
+    pub data: Payload,
+}
+
+/// Sends messages to one address
+pub struct Publisher<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    address: [u8; 5],
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Publisher<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>, address: [u8; 5]) -> Self {
+        Publisher { radio, address }
+    }
+
+    /// Returns whether the message has been acknowledged
+    pub fn publish(&mut self, topic: u16, data: &[u8]) -> Result<bool, SpiE> {
+        let mut buf = [0; PAYLOAD_LEN];
+        let len = encode(topic, data, &mut buf);
+        self.radio.send_to(&self.address, &buf[0..len])
+    }
+}
+
+/// Receives messages matching up to `N` filters
+pub struct Subscriber<'a, Ce, Csn, Spi, E, SpiE, const N: usize>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    filters: [Option<(Filter, Option<Callback>)>; N],
+    bridge: Option<[u8; 5]>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE, const N: usize> Subscriber<'a, Ce, Csn, Spi, E, SpiE, N>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> Self {
+        Subscriber {
+            radio,
+            filters: [None; N],
+            bridge: None,
+        }
+    }
+
+    /// Return matching messages from [`poll()`](#method.poll)
+    ///
+    /// Returns `false` if all `N` filters are in use.
+    pub fn subscribe(&mut self, filter: Filter) -> bool {
+        self.add(filter, None)
+    }
+
+    /// Hand matching messages to `callback` instead of returning them
+    pub fn subscribe_with(&mut self, filter: Filter, callback: Callback) -> bool {
+        self.add(filter, Some(callback))
+    }
+
+    fn add(&mut self, filter: Filter, callback: Option<Callback>) -> bool {
+        match self.filters.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some((filter, callback));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove all subscriptions with this filter
+    pub fn unsubscribe(&mut self, filter: Filter) {
+        for slot in self.filters.iter_mut() {
+            if matches!(slot, Some((f, _)) if *f == filter) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Republish every matching message to `address`, e.g. to link two networks
+    ///
+    /// Sending repoints pipe 0 at `address`: listen on pipes 1 to 5.
+    pub fn set_bridge(&mut self, address: Option<[u8; 5]>) {
+        self.bridge = address;
+    }
+
+    /// Poll for a matching message
+    ///
+    /// Callbacks are run on the way. Messages are returned only when
+    /// they match a filter without a callback.
+    pub fn poll(&mut self) -> Result<Option<Message>, SpiE> {
+        loop {
+            let pipe = match self.radio.wait_rx_ready() {
+                Ok(pipe) => pipe,
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            };
+            let payload = nb::block!(self.radio.read())?;
+            let (topic, data) = match decode(&payload) {
+                Some(message) => message,
+                None => continue,
+            };
+            let mut matched = false;
+            let mut wanted = false;
+            for (filter, callback) in self.filters.iter().flatten() {
+                if !filter.matches(topic) {
+                    continue;
+                }
+                matched = true;
+                match callback {
+                    Some(callback) => callback(topic, data),
+                    None => wanted = true,
+                }
+            }
+            if matched {
+                if let Some(bridge) = self.bridge {
+                    self.radio.send_to(&bridge, &payload)?;
+                }
+            }
+            if wanted {
+                return Ok(Some(Message {
+                    topic,
+                    pipe,
+                    data: Payload::new(data),
+                }));
+            }
+        }
+    }
+}
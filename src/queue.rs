@@ -0,0 +1,140 @@
+//! A software RX queue behind the three-packet hardware FIFO
+//!
+//! [`Nrf24l01::pump()`](../struct.Nrf24l01.html#method.pump) moves
+//! received packets from the chip into an [`RxQueue`](struct.RxQueue.html),
+//! from where the application takes them whenever it gets around to
+//! it. Call it from the IRQ handler, through
+//! [`SharedNrf24::lock()`](../shared/struct.SharedNrf24.html#method.lock)
+//! with the queue kept in the same critical section, so that bursts
+//! longer than three packets are not lost.
+//!
+//! With the `heapless` feature,
+//! [`pump_deque()`](../struct.Nrf24l01.html#method.pump_deque) fills a
+//! `heapless::Deque` instead.
+
+use crate::{Nrf24l01, Payload, Pipe, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Up to `N` received packets with their pipes, oldest first
+pub struct RxQueue<const N: usize> {
+    data: [[u8; PAYLOAD_LEN]; N],
+    lens: [u8; N],
+    pipes: [Pipe; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RxQueue<N> {
+    /// Usable in a `static`
+    pub const fn new() -> Self {
+        RxQueue {
+            data: [[0; PAYLOAD_LEN]; N],
+            lens: [0; N],
+            pipes: [Pipe::P0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns `false` if the queue is full
+    pub fn push(&mut self, pipe: Pipe, packet: &[u8]) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let index = (self.head + self.len) % N;
+        self.data[index][0..packet.len()].copy_from_slice(packet);
+        self.lens[index] = packet.len() as u8;
+        self.pipes[index] = pipe;
+        self.len += 1;
+        true
+    }
+
+    /// Take the oldest packet
+    pub fn pop(&mut self) -> Option<(Pipe, Payload)> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = self.head;
+        let payload = Payload::new(&self.data[index][0..usize::from(self.lens[index])]);
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some((self.pipes[index], payload))
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for RxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Move received packets into `queue` until either runs empty
+    ///
+    /// Returns the number of packets moved. Packets that do not fit
+    /// stay in the hardware FIFO for the next call.
+    pub fn pump<const N: usize>(&mut self, queue: &mut RxQueue<N>) -> Result<usize, SpiE> {
+        let mut moved = 0;
+        while !queue.is_full() {
+            match self.pump_one()? {
+                Some((pipe, packet)) => queue.push(pipe, &packet),
+                None => break,
+            };
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    /// Like [`pump()`](#method.pump), into a `heapless::Deque`
+    #[cfg(feature = "heapless")]
+    pub fn pump_deque<const N: usize>(
+        &mut self,
+        queue: &mut heapless::Deque<(Pipe, Payload), N>,
+    ) -> Result<usize, SpiE> {
+        let mut moved = 0;
+        while !queue.is_full() {
+            match self.pump_one()? {
+                Some(entry) => queue.push_back(entry).ok(),
+                None => break,
+            };
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    fn pump_one(&mut self) -> Result<Option<(Pipe, Payload)>, SpiE> {
+        let pipe = match self.wait_rx_ready() {
+            Ok(pipe) => pipe,
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        };
+        let packet = nb::block!(self.read())?;
+        Ok(Some((pipe, packet)))
+    }
+}
@@ -1,6 +1,14 @@
+//! The register map as typed bitfields
+//!
+//! What [`Nrf24l01::status()`](../struct.Nrf24l01.html#method.status)
+//! and friends return, and what
+//! [`command::ReadRegister`](../command/struct.ReadRegister.html) and
+//! [`command::WriteRegister`](../command/struct.WriteRegister.html)
+//! transfer. Address registers keep as many bytes as were written.
+
 #![allow(unused)]
 
-use crate::{MAX_ADDR_BYTES, MIN_ADDR_BYTES, PIPES_COUNT};
+use crate::{Pipe, MAX_ADDR_BYTES, MIN_ADDR_BYTES, PIPES_COUNT};
 
 pub trait Register {
     /// Address in the register map
@@ -225,6 +233,10 @@ bitfield! {
     pub struct RfSetup(u8);
     impl Debug;
 
+    /// Continuous carrier transmit, test mode
+    pub cont_wave, set_cont_wave: 7;
+    /// Force the PLL lock signal, test mode
+    pub pll_lock, set_pll_lock: 4;
     /// Set for 250 kbps
     pub rf_dr_low, set_rf_dr_low: 5;
     /// Set for 2 Mbps
@@ -235,6 +247,13 @@ bitfield! {
     /// * `10`: -6 dBm
     /// * `11`: 0 dBm
     pub u8, rf_pwr, set_rf_pwr: 2, 1;
+    /// LNA gain (nRF24L01 only)
+    pub lna_hcurr, set_lna_hcurr: 0;
+    /// RF output power on the SI24R1
+    /// * `000`: -12 dBm
+    /// * `011`: 0 dBm
+    /// * `111`: 7 dBm
+    pub u8, si24r1_pwr, set_si24r1_pwr: 2, 0;
 }
 impl_register!(RfSetup, 0x06);
 
@@ -250,13 +269,20 @@ bitfield! {
     pub tx_ds, set_tx_ds: 5;
     /// Maximum number of TX retransmits interrupt. Write `true` to clear.
     pub max_rt, set_max_rt: 4;
-    /// Data pipe number for reading from RX FIFO
-    pub u8, rx_p_no, _: 3, 1;
+    /// Data pipe number for reading from RX FIFO, `7` if empty
+    pub u8, rx_p_no_raw, _: 3, 1;
     /// TX FIFO full flag
     pub tx_full, _: 0;
 }
 impl_register!(Status, 0x07);
 
+impl Status {
+    /// Data pipe number for reading from RX FIFO, `None` if empty
+    pub fn rx_p_no(&self) -> Option<Pipe> {
+        Pipe::from_index(self.rx_p_no_raw().into())
+    }
+}
+
 bitfield! {
     pub struct ObserveTx(u8);
     impl Debug;
@@ -0,0 +1,524 @@
+//! Changing radio settings over the air
+//!
+//! An [`Agent`](struct.Agent.html) on the node answers requests from a
+//! [`Manager`](struct.Manager.html) on pipe 1: its current
+//! [`Settings`](struct.Settings.html) are always loaded as the ack
+//! payload, and a change comes back accepted or rejected in the same
+//! way. The agent only switches once that answer has left the TX FIFO,
+//! so the manager learns about the change before the node is gone.
+//! The manager then follows and checks that the node can still be
+//! reached, falling back to the old settings otherwise.
+//!
+//! Requests carry a key that both sides share. It is sent in plain
+//! text, so it only keeps other managers from changing a node by
+//! mistake.
+
+use crate::channels::{Channel, CHANNEL_COUNT};
+use crate::device::Device;
+use crate::registers::{Register, RxAddrP1};
+use crate::{Configuration, DataRate, FifoLevel, Nrf24l01, OutputPower, Pipe};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_GET: u8 = 0x71;
+const TYPE_SET: u8 = 0x72;
+const TYPE_POLL: u8 = 0x73;
+const TYPE_STATUS: u8 = 0x74;
+const REQUEST_HEADER_LEN: usize = 5;
+const STATUS_LEN: usize = 11;
+const KIND_CHANNEL: u8 = 0;
+const KIND_OUTPUT_POWER: u8 = 1;
+const KIND_DATA_RATE: u8 = 2;
+const KIND_ADDRESS: u8 = 3;
+const RESULT_NONE: u8 = 0;
+const RESULT_ACCEPTED: u8 = 1;
+const RESULT_REJECTED: u8 = 2;
+/// Pause between polls for the agent's answer
+const POLL_INTERVAL_US: u32 = 1000;
+const OUTPUT_POWERS: [OutputPower; 9] = [
+    OutputPower::Neg18dBm,
+    OutputPower::Neg12dBm,
+    OutputPower::Neg6dBm,
+    OutputPower::Neg4dBm,
+    OutputPower::ZeroDbm,
+    OutputPower::Pos1dBm,
+    OutputPower::Pos3dBm,
+    OutputPower::Pos4dBm,
+    OutputPower::Pos7dBm,
+];
+
+fn encode_rate(rate: DataRate) -> u8 {
+    match rate {
+        DataRate::R250Kbps => 0,
+        DataRate::R1Mbps => 1,
+        DataRate::R2Mbps => 2,
+    }
+}
+
+fn decode_rate(rate: u8) -> Option<DataRate> {
+    match rate {
+        0 => Some(DataRate::R250Kbps),
+        1 => Some(DataRate::R1Mbps),
+        2 => Some(DataRate::R2Mbps),
+        _ => None,
+    }
+}
+
+fn decode_power(dbm: u8) -> Option<OutputPower> {
+    OUTPUT_POWERS
+        .iter()
+        .copied()
+        .find(|power| power.dbm() == dbm as i8)
+}
+
+/// Radio settings of a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub channel: Channel,
+    pub output_power: OutputPower,
+    pub data_rate: DataRate,
+    /// Address of pipe 1, in SPI order
+    pub address: [u8; 5],
+}
+
+/// One setting to change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Channel(Channel),
+    OutputPower(OutputPower),
+    DataRate(DataRate),
+    /// New address of pipe 1, in SPI order
+    Address([u8; 5]),
+}
+
+impl Change {
+    /// Kind byte and value, returning the length
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        match *self {
+            Change::Channel(channel) => {
+                buf[0] = KIND_CHANNEL;
+                buf[1] = channel.number();
+                2
+            }
+            Change::OutputPower(power) => {
+                buf[0] = KIND_OUTPUT_POWER;
+                buf[1] = power.dbm() as u8;
+                2
+            }
+            Change::DataRate(rate) => {
+                buf[0] = KIND_DATA_RATE;
+                buf[1] = encode_rate(rate);
+                2
+            }
+            Change::Address(address) => {
+                buf[0] = KIND_ADDRESS;
+                buf[1..6].copy_from_slice(&address);
+                6
+            }
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        match *buf {
+            [KIND_CHANNEL, channel, ..] => Channel::try_new(channel).map(Change::Channel),
+            [KIND_OUTPUT_POWER, dbm, ..] => decode_power(dbm).map(Change::OutputPower),
+            [KIND_DATA_RATE, rate, ..] => decode_rate(rate).map(Change::DataRate),
+            [KIND_ADDRESS, ref address @ ..] if address.len() >= 5 => {
+                let mut bytes = [0; 5];
+                bytes.copy_from_slice(&address[0..5]);
+                Some(Change::Address(bytes))
+            }
+            _ => None,
+        }
+    }
+
+    /// Switch `radio` over
+    fn apply<Ce, Csn, Spi, E, SpiE>(
+        &self,
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        pipe: Pipe,
+    ) -> Result<(), SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        nb::block!(radio.wait_tx_empty())?;
+        match *self {
+            Change::Channel(channel) => radio.set_channel(channel),
+            Change::OutputPower(power) => radio.set_output_power(power),
+            Change::DataRate(rate) => radio.set_data_rate(rate),
+            Change::Address(address) => match pipe {
+                Pipe::P0 => {
+                    radio.set_tx_addr(&address)?;
+                    radio.set_rx_addr(Pipe::P0, &address)
+                }
+                pipe => radio.set_rx_addr(pipe, &address),
+            },
+        }
+    }
+}
+
+/// What the agent keeps in its ack payload
+#[derive(Debug, Clone, Copy)]
+struct Status {
+    seq: u8,
+    result: u8,
+    settings: Settings,
+}
+
+impl Status {
+    fn encode(&self) -> [u8; STATUS_LEN] {
+        let settings = &self.settings;
+        let mut buf = [0; STATUS_LEN];
+        buf[0] = TYPE_STATUS;
+        buf[1] = self.seq;
+        buf[2] = self.result;
+        buf[3] = settings.channel.number();
+        buf[4] = settings.output_power.dbm() as u8;
+        buf[5] = encode_rate(settings.data_rate);
+        buf[6..11].copy_from_slice(&settings.address);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < STATUS_LEN || buf[0] != TYPE_STATUS {
+            return None;
+        }
+        let mut address = [0; 5];
+        address.copy_from_slice(&buf[6..11]);
+        Some(Status {
+            seq: buf[1],
+            result: buf[2],
+            settings: Settings {
+                channel: Channel::try_new(buf[3])?,
+                output_power: decode_power(buf[4])?,
+                data_rate: decode_rate(buf[5])?,
+                address,
+            },
+        })
+    }
+}
+
+/// Whether `packet` is a request for an [`Agent`](struct.Agent.html)
+pub fn is_request(packet: &[u8]) -> bool {
+    packet.len() >= REQUEST_HEADER_LEN && matches!(packet[0], TYPE_GET | TYPE_SET | TYPE_POLL)
+}
+
+/// The node side
+///
+/// Does not hold on to the radio: the application reads packets as
+/// usual and passes those on pipe 1 that are
+/// [`is_request()`](fn.is_request.html) to
+/// [`handle()`](#method.handle).
+pub struct Agent {
+    key: u32,
+    status: Status,
+    /// Accepted, switching once the answer is out
+    pending: Option<Change>,
+}
+
+impl Agent {
+    /// Answer requests with `key` on pipe 1
+    ///
+    /// Enables ack payloads and loads the current settings. A channel
+    /// beyond the last valid one is moved down to it first.
+    pub fn new<Ce, Csn, Spi, E, SpiE>(
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        key: u32,
+    ) -> Result<Self, SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_ack_payloads(true)?;
+        let (_, address) = radio.device().read_register_uncached::<RxAddrP1>()?;
+        let mut bytes = [0; 5];
+        address.encode(&mut bytes);
+        let channel = match radio.get_channel()? {
+            Some(channel) => channel,
+            None => {
+                let last = Channel::new(CHANNEL_COUNT - 1);
+                radio.set_channel(last)?;
+                last
+            }
+        };
+        let settings = Settings {
+            channel,
+            output_power: radio.get_output_power()?,
+            data_rate: radio.get_data_rate()?,
+            address: bytes,
+        };
+        // Enter RX mode before the status ack payload occupies the TX FIFO
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        let mut agent = Agent {
+            key,
+            status: Status {
+                seq: 0,
+                result: RESULT_NONE,
+                settings,
+            },
+            pending: None,
+        };
+        agent.load_status(radio)?;
+        Ok(agent)
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.status.settings
+    }
+
+    /// Handle a request read from pipe 1
+    ///
+    /// Returns the change once it has been applied, which happens with
+    /// the first request after the one that asked for it.
+    pub fn handle<Ce, Csn, Spi, E, SpiE>(
+        &mut self,
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        packet: &[u8],
+    ) -> Result<Option<Change>, SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        if !is_request(packet) || packet[1..5] != self.key.to_le_bytes() {
+            return Ok(None);
+        }
+        // The answer went out with the acknowledgement of this packet
+        if let Some(change) = self.pending {
+            if radio.tx_fifo_level()? == FifoLevel::Empty {
+                self.pending = None;
+                change.apply(radio, Pipe::P1)?;
+                self.update_settings(&change);
+                self.load_status(radio)?;
+                return Ok(Some(change));
+            }
+        }
+        if let [TYPE_SET, _, _, _, _, seq, ref change @ ..] = *packet {
+            if seq != self.status.seq {
+                let change = Change::decode(change);
+                let supported = change.is_some_and(|change| match change {
+                    Change::OutputPower(power) => power.level(radio.chip_variant()).is_some(),
+                    Change::DataRate(rate) => radio.chip_variant().supports_data_rate(rate),
+                    _ => true,
+                });
+                self.status.seq = seq;
+                self.status.result = match supported {
+                    true => RESULT_ACCEPTED,
+                    false => RESULT_REJECTED,
+                };
+                self.pending = change.filter(|_| supported);
+            }
+        }
+        // Each acknowledgement takes the status along, reload it for the next
+        self.load_status(radio)?;
+        Ok(None)
+    }
+
+    fn update_settings(&mut self, change: &Change) {
+        let settings = &mut self.status.settings;
+        match *change {
+            Change::Channel(channel) => settings.channel = channel,
+            Change::OutputPower(power) => settings.output_power = power,
+            Change::DataRate(rate) => settings.data_rate = rate,
+            Change::Address(address) => settings.address = address,
+        }
+    }
+
+    /// Replace the ack payload with the current status
+    fn load_status<Ce, Csn, Spi, E, SpiE>(
+        &mut self,
+        radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    ) -> Result<(), SpiE>
+    where
+        Ce: OutputPin<Error = E>,
+        Csn: OutputPin<Error = E>,
+        Spi: Transfer<u8, Error = SpiE>,
+        E: Debug,
+        SpiE: Debug,
+    {
+        radio.flush_tx()?;
+        radio.write_ack_payload(Pipe::P1, &self.status.encode())
+    }
+}
+
+/// The managing side, talking to one node
+pub struct Manager<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    node_address: [u8; 5],
+    key: u32,
+    seq: u8,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Manager<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        node_address: [u8; 5],
+        key: u32,
+    ) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_ack_payloads(true)?;
+        Ok(Manager {
+            radio,
+            node_address,
+            key,
+            seq: 0,
+        })
+    }
+
+    pub fn node_address(&self) -> &[u8; 5] {
+        &self.node_address
+    }
+
+    /// Ask for the node's settings
+    pub fn query<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<Settings>, SpiE> {
+        let get = self.header(TYPE_GET);
+        let status = self.request(&get, delay, timeout_ms, |_| true)?;
+        Ok(status.map(|status| status.settings))
+    }
+
+    /// Change one setting of the node and follow it
+    ///
+    /// Returns `false` if the node rejected the change, did not answer,
+    /// or could not be reached with the new setting. In the last case
+    /// the manager goes back to the old one.
+    pub fn set<D: DelayUs<u32>>(
+        &mut self,
+        change: Change,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, SpiE> {
+        let previous = match self.query(delay, timeout_ms)? {
+            Some(settings) => settings,
+            None => return Ok(false),
+        };
+        self.seq = self.seq.wrapping_add(1).max(1);
+        let seq = self.seq;
+        let mut set = [0; 32];
+        set[0..REQUEST_HEADER_LEN].copy_from_slice(&self.header(TYPE_SET));
+        set[REQUEST_HEADER_LEN] = seq;
+        let len = REQUEST_HEADER_LEN + 1 + change.encode(&mut set[REQUEST_HEADER_LEN + 1..]);
+        let answered = |status: &Status| status.seq == seq && status.result != RESULT_NONE;
+        match self.request(&set[0..len], delay, timeout_ms, answered)? {
+            Some(status) if status.result == RESULT_ACCEPTED => {}
+            _ => return Ok(false),
+        }
+        // Let the agent see that the answer is out
+        let poll = self.header(TYPE_POLL);
+        self.radio.send_to(&self.node_address, &poll)?;
+        self.follow(&change)?;
+        if self.query(delay, timeout_ms)? == Some(updated(previous, &change)) {
+            return Ok(true);
+        }
+        self.follow(&revert(&previous, &change))?;
+        Ok(false)
+    }
+
+    /// Make the same change on this side, where it matters
+    fn follow(&mut self, change: &Change) -> Result<(), SpiE> {
+        match *change {
+            Change::Channel(_) | Change::DataRate(_) => change.apply(self.radio, Pipe::P0),
+            Change::Address(address) => {
+                self.node_address = address;
+                Ok(())
+            }
+            Change::OutputPower(_) => Ok(()),
+        }
+    }
+
+    fn header(&self, kind: u8) -> [u8; REQUEST_HEADER_LEN] {
+        let mut header = [0; REQUEST_HEADER_LEN];
+        header[0] = kind;
+        header[1..5].copy_from_slice(&self.key.to_le_bytes());
+        header
+    }
+
+    /// Send `packet`, then poll until the status satisfies `done`
+    fn request<F, D>(
+        &mut self,
+        packet: &[u8],
+        delay: &mut D,
+        timeout_ms: u32,
+        done: F,
+    ) -> Result<Option<Status>, SpiE>
+    where
+        F: Fn(&Status) -> bool,
+        D: DelayUs<u32>,
+    {
+        let poll = self.header(TYPE_POLL);
+        let mut sent = false;
+        for _ in 0..timeout_ms {
+            let next = match sent {
+                false => packet,
+                true => &poll,
+            };
+            if self.radio.send_to(&self.node_address, next)? {
+                // The first ack payload predates the request
+                if sent {
+                    let ack = self.radio.read_ack_payload()?;
+                    if let Some(status) = ack.and_then(|ack| Status::decode(&ack)) {
+                        if done(&status) {
+                            return Ok(Some(status));
+                        }
+                    }
+                } else {
+                    self.radio.read_ack_payload()?;
+                }
+                sent = true;
+            }
+            delay.delay_us(POLL_INTERVAL_US);
+        }
+        Ok(None)
+    }
+}
+
+fn updated(mut settings: Settings, change: &Change) -> Settings {
+    match *change {
+        Change::Channel(channel) => settings.channel = channel,
+        Change::OutputPower(power) => settings.output_power = power,
+        Change::DataRate(rate) => settings.data_rate = rate,
+        Change::Address(address) => settings.address = address,
+    }
+    settings
+}
+
+/// The change that undoes `change`
+fn revert(previous: &Settings, change: &Change) -> Change {
+    match *change {
+        Change::Channel(_) => Change::Channel(previous.channel),
+        Change::OutputPower(_) => Change::OutputPower(previous.output_power),
+        Change::DataRate(_) => Change::DataRate(previous.data_rate),
+        Change::Address(_) => Change::Address(previous.address),
+    }
+}
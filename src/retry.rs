@@ -0,0 +1,89 @@
+use crate::ShortDelay;
+use core::fmt::{self, Debug};
+
+/// Repeat SPI transactions that failed with a transient error, see
+/// [`Nrf24l01::set_spi_retry()`](struct.Nrf24l01.html#method.set_spi_retry)
+///
+/// embedded-hal 0.2 errors carry no kind, so `is_transient` decides,
+/// e.g. returning `true` for overrun and arbitration errors. Waits
+/// `backoff_ns` before the first retry and twice as long before each
+/// further one. Commands that read from or write to a FIFO are never
+/// repeated, as they may have taken effect before the error.
+pub struct RetryPolicy<SpiE> {
+    pub is_transient: fn(&SpiE) -> bool,
+    /// Attempts in total, including the first
+    pub attempts: u8,
+    pub delay: &'static dyn ShortDelay,
+    pub backoff_ns: u32,
+}
+
+impl<SpiE> Clone for RetryPolicy<SpiE> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<SpiE> Copy for RetryPolicy<SpiE> {}
+
+impl<SpiE> RetryPolicy<SpiE> {
+    /// Whether attempt number `attempt` failing with `error` is worth
+    /// another, waiting for the backoff if so
+    pub(crate) fn retry(&self, attempt: u8, error: &SpiE) -> bool {
+        if attempt >= self.attempts || !(self.is_transient)(error) {
+            return false;
+        }
+        let backoff_ns = self.backoff_ns.saturating_mul(1 << (attempt - 1).min(31));
+        if backoff_ns > 0 {
+            self.delay.delay_ns(backoff_ns);
+        }
+        true
+    }
+
+    /// Whether `error` was transient still when attempt number
+    /// `attempt` was the last one allowed
+    pub(crate) fn exhausted(&self, attempt: u8, error: &SpiE) -> bool {
+        attempt >= self.attempts && (self.is_transient)(error)
+    }
+}
+
+/// An SPI error, told apart by whether the [`RetryPolicy`](struct.RetryPolicy.html)
+/// gave up on it, see
+/// [`Nrf24l01::retry_error()`](struct.Nrf24l01.html#method.retry_error)
+#[derive(Debug)]
+pub enum RetryError<SpiE> {
+    /// Transient every time until all `attempts` were used up
+    Exhausted { attempts: u8, last: SpiE },
+    /// Not retried to the end: no policy, a permanent error, or a FIFO
+    /// command that must not be repeated
+    Failed(SpiE),
+}
+
+impl<SpiE> RetryError<SpiE> {
+    /// The SPI error returned by the last attempt
+    pub fn into_inner(self) -> SpiE {
+        match self {
+            RetryError::Exhausted { last, .. } => last,
+            RetryError::Failed(e) => e,
+        }
+    }
+}
+
+impl<SpiE: Debug> fmt::Display for RetryError<SpiE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetryError::Exhausted { attempts, last } => {
+                write!(f, "SPI error after {} attempts: {:?}", attempts, last)
+            }
+            RetryError::Failed(e) => write!(f, "SPI error: {:?}", e),
+        }
+    }
+}
+
+impl<SpiE: core::error::Error + 'static> core::error::Error for RetryError<SpiE> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            RetryError::Exhausted { last, .. } => Some(last),
+            RetryError::Failed(e) => Some(e),
+        }
+    }
+}
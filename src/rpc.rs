@@ -0,0 +1,310 @@
+//! Request/response calls with correlation ids
+//!
+//! The [`Client`](struct.Client.html) sends a request, then polls the
+//! [`Server`](struct.Server.html) until the response arrives. Short
+//! responses travel back in the ack payload of a poll, longer ones as
+//! a separate reply packet: at 1 Mbps the chip only fits 15 bytes of
+//! ack payload into the shortest retransmit delay.
+
+use crate::{Configuration, Nrf24l01, Payload, Pipe, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_REQUEST: u8 = 0xC1;
+const TYPE_POLL: u8 = 0xC2;
+const TYPE_RESPONSE: u8 = 0xC3;
+const HEADER_LEN: usize = 3;
+/// Maximum length of the arguments of a call
+pub const MAX_ARGS_LEN: usize = PAYLOAD_LEN - HEADER_LEN;
+/// Maximum length of a result
+pub const MAX_RESULT_LEN: usize = PAYLOAD_LEN - HEADER_LEN;
+/// Status returned for methods without a handler
+pub const STATUS_UNKNOWN_METHOD: u8 = 0xFF;
+const STATUS_OK: u8 = 0;
+
+/// Client pipe 0 receives acks and is only enabled while sending, pipe 1 replies
+const LISTEN_PIPES: [bool; PIPES_COUNT] = [false, true, false, false, false, false];
+const SEND_PIPES: [bool; PIPES_COUNT] = [true, true, false, false, false, false];
+
+/// Fills the result buffer, returning its length or a non-zero status
+pub type Handler = fn(args: &[u8], result: &mut [u8]) -> Result<usize, u8>;
+
+#[derive(Debug)]
+pub enum Error<SpiE> {
+    /// No response within the timeout
+    Timeout,
+    /// The handler returned this status
+    Remote(u8),
+    Spi(SpiE),
+}
+
+impl<SpiE> From<SpiE> for Error<SpiE> {
+    fn from(e: SpiE) -> Self {
+        Error::Spi(e)
+    }
+}
+
+fn configure<Ce, Csn, Spi, E, SpiE>(
+    radio: &mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    listen_address: &[u8],
+) -> Result<(), SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    nb::block!(radio.wait_tx_empty())?;
+    radio.set_pipes_rx_lengths(&[None; PIPES_COUNT])?;
+    radio.set_ack_payloads(true)?;
+    radio.set_auto_ack(&SEND_PIPES)?;
+    radio.set_pipes_rx_enable(&LISTEN_PIPES)?;
+    radio.set_rx_addr(Pipe::P1, listen_address)
+}
+
+/// Calls methods on a server
+pub struct Client<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    server_address: [u8; 5],
+    next_id: u8,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Client<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Long responses are sent to `reply_address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        server_address: [u8; 5],
+        reply_address: [u8; 5],
+    ) -> Result<Self, SpiE> {
+        configure(radio, &reply_address)?;
+        Ok(Client {
+            radio,
+            server_address,
+            next_id: 0,
+        })
+    }
+
+    /// Call `method` and wait up to `timeout_ms` for its result
+    pub fn call<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        method: u8,
+        args: &[u8],
+        timeout_ms: u32,
+    ) -> Result<Payload, Error<SpiE>> {
+        assert!(args.len() <= MAX_ARGS_LEN);
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut request = [0; PAYLOAD_LEN];
+        request[0] = TYPE_REQUEST;
+        request[1] = id;
+        request[2] = method;
+        request[HEADER_LEN..HEADER_LEN + args.len()].copy_from_slice(args);
+
+        let mut sent = false;
+        for _ in 0..timeout_ms {
+            if !sent {
+                // Retried with the same id, the server answers duplicates from its cache
+                sent = self.send(&request[0..HEADER_LEN + args.len()])?;
+            } else if self.send(&[TYPE_POLL, id])? {
+                if let Some(response) = self.radio.read_ack_payload()? {
+                    if let Some(result) = decode_response(&response, id) {
+                        return result;
+                    }
+                }
+            }
+            delay.delay_us(1000);
+            loop {
+                match self.radio.wait_rx_ready() {
+                    Ok(_) => {}
+                    Err(nb::Error::WouldBlock) => break,
+                    Err(nb::Error::Other(e)) => return Err(Error::Spi(e)),
+                }
+                let reply = nb::block!(self.radio.read())?;
+                if let Some(result) = decode_response(&reply, id) {
+                    return result;
+                }
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    fn send(&mut self, packet: &[u8]) -> Result<bool, SpiE> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_pipes_rx_enable(&SEND_PIPES)?;
+        let acked = self.radio.send_to(&self.server_address, packet)?;
+        self.radio.set_pipes_rx_enable(&LISTEN_PIPES)?;
+        Ok(acked)
+    }
+}
+
+fn decode_response<SpiE>(packet: &[u8], id: u8) -> Option<Result<Payload, Error<SpiE>>> {
+    if packet.len() < HEADER_LEN || packet[0] != TYPE_RESPONSE || packet[1] != id {
+        return None;
+    }
+    Some(match packet[2] {
+        STATUS_OK => Ok(Payload::new(&packet[HEADER_LEN..])),
+        status => Err(Error::Remote(status)),
+    })
+}
+
+/// Dispatches calls to up to `N` handlers
+pub struct Server<'a, Ce, Csn, Spi, E, SpiE, const N: usize>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    reply_address: [u8; 5],
+    handlers: [Option<(u8, Handler)>; N],
+    max_ack_payload: usize,
+    /// Response to the last request, resent for duplicates
+    response: [u8; PAYLOAD_LEN],
+    response_len: usize,
+    last_id: Option<u8>,
+    ack_loaded: bool,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE, const N: usize> Server<'a, Ce, Csn, Spi, E, SpiE, N>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen on `address`, send long responses to `reply_address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: [u8; 5],
+        reply_address: [u8; 5],
+    ) -> Result<Self, SpiE> {
+        configure(radio, &address)?;
+        // Enter RX mode before ack payloads start occupying the TX FIFO
+        if let Err(nb::Error::Other(e)) = radio.wait_rx_ready() {
+            return Err(e);
+        }
+        Ok(Server {
+            radio,
+            reply_address,
+            handlers: [None; N],
+            max_ack_payload: 15,
+            response: [0; PAYLOAD_LEN],
+            response_len: 0,
+            last_id: None,
+            ack_loaded: false,
+        })
+    }
+
+    /// Longest response sent as an ack payload, 15 by default
+    ///
+    /// Up to 32 bytes fit with a long enough auto-retransmit delay.
+    pub fn set_max_ack_payload(&mut self, len: usize) {
+        assert!(len <= PAYLOAD_LEN);
+        self.max_ack_payload = len;
+    }
+
+    /// Returns `false` if all `N` handler slots are in use
+    pub fn register(&mut self, method: u8, handler: Handler) -> bool {
+        match self.handlers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some((method, handler));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Serve pending requests
+    ///
+    /// Returns the method of a newly handled call.
+    pub fn poll(&mut self) -> Result<Option<u8>, SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            // Any loaded response went out with the ack of this packet
+            let was_loaded = self.ack_loaded;
+            self.ack_loaded = false;
+            match packet.get(0..2) {
+                Some(&[TYPE_REQUEST, id]) if packet.len() >= HEADER_LEN => {
+                    if self.last_id == Some(id) {
+                        self.respond()?;
+                        continue;
+                    }
+                    let method = packet[2];
+                    self.handle(id, method, &packet[HEADER_LEN..]);
+                    self.respond()?;
+                    return Ok(Some(method));
+                }
+                // Load the response again if its ack got lost
+                Some(&[TYPE_POLL, id]) if self.last_id == Some(id) && !was_loaded => {
+                    self.respond()?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle(&mut self, id: u8, method: u8, args: &[u8]) {
+        let handler = self
+            .handlers
+            .iter()
+            .flatten()
+            .find(|(m, _)| *m == method)
+            .map(|(_, handler)| *handler);
+        let status = match handler {
+            Some(handler) => match handler(args, &mut self.response[HEADER_LEN..]) {
+                Ok(len) => {
+                    self.response_len = HEADER_LEN + len.min(MAX_RESULT_LEN);
+                    STATUS_OK
+                }
+                Err(status) => status,
+            },
+            None => STATUS_UNKNOWN_METHOD,
+        };
+        if status != STATUS_OK {
+            self.response_len = HEADER_LEN;
+        }
+        self.response[0] = TYPE_RESPONSE;
+        self.response[1] = id;
+        self.response[2] = status;
+        self.last_id = Some(id);
+    }
+
+    fn respond(&mut self) -> Result<(), SpiE> {
+        // Drop stale ack payloads
+        self.radio.flush_tx()?;
+        let response = &self.response[0..self.response_len];
+        if response.len() <= self.max_ack_payload {
+            self.radio.write_ack_payload(Pipe::P1, response)?;
+            self.ack_loaded = true;
+        } else {
+            self.radio.send_to(&self.reply_address, response)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Connections with negotiated link parameters
+//!
+//! [`connect()`](fn.connect.html) sends a connect request with the
+//! [`Capabilities`](struct.Capabilities.html) of the initiating node
+//! and its own address; the peer answers from
+//! [`accept()`](fn.accept.html) with its capabilities. Both sides end
+//! up with a [`Link`](struct.Link.html) to each other carrying what
+//! both of them support.
+//!
+//! Handshake packets are always padded to 32 bytes, so they get through
+//! to nodes without dynamic payloads, too.
+
+use crate::{Configuration, Nrf24l01, Payload, Pipe, PAYLOAD_LEN};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_CONNECT: u8 = 0xF1;
+const TYPE_ACCEPT: u8 = 0xF2;
+const FLAG_DYNAMIC_PAYLOAD: u8 = 0x01;
+const FLAG_ACK_PAYLOAD: u8 = 0x02;
+const ADDRESS_LEN: usize = 5;
+
+/// What a node supports, and after the handshake what both support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Packets shorter than 32 bytes
+    pub dynamic_payload: bool,
+    /// Data in acknowledgements
+    pub ack_payload: bool,
+    /// Longest packet the node accepts
+    pub max_fragment_len: u8,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            dynamic_payload: true,
+            ack_payload: true,
+            max_fragment_len: PAYLOAD_LEN as u8,
+        }
+    }
+}
+
+impl Capabilities {
+    /// What both `self` and `other` support
+    pub fn negotiate(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            dynamic_payload: self.dynamic_payload && other.dynamic_payload,
+            ack_payload: self.ack_payload && other.ack_payload,
+            max_fragment_len: self.max_fragment_len.min(other.max_fragment_len),
+        }
+    }
+
+    fn encode(&self) -> [u8; 2] {
+        let mut flags = 0;
+        if self.dynamic_payload {
+            flags |= FLAG_DYNAMIC_PAYLOAD;
+        }
+        if self.ack_payload {
+            flags |= FLAG_ACK_PAYLOAD;
+        }
+        [flags, self.max_fragment_len]
+    }
+
+    fn decode(data: [u8; 2]) -> Capabilities {
+        Capabilities {
+            dynamic_payload: data[0] & FLAG_DYNAMIC_PAYLOAD != 0,
+            ack_payload: data[0] & FLAG_ACK_PAYLOAD != 0,
+            max_fragment_len: data[1].min(PAYLOAD_LEN as u8),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<SpiE> {
+    /// The peer did not acknowledge the request or did not answer it
+    Timeout,
+    Spi(SpiE),
+}
+
+impl<SpiE> From<SpiE> for Error<SpiE> {
+    fn from(e: SpiE) -> Self {
+        Error::Spi(e)
+    }
+}
+
+/// An established connection to one peer
+pub struct Link<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    peer_address: [u8; ADDRESS_LEN],
+    params: Capabilities,
+}
+
+/// Connect to the node listening on pipe 1 at `peer_address`
+///
+/// Listens on pipe 1 at `local_address` for the answer, for up to
+/// `timeout_ms`.
+pub fn connect<'a, Ce, Csn, Spi, E, SpiE, D>(
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    local_address: [u8; ADDRESS_LEN],
+    peer_address: [u8; ADDRESS_LEN],
+    capabilities: Capabilities,
+    delay: &mut D,
+    timeout_ms: u32,
+) -> Result<Link<'a, Ce, Csn, Spi, E, SpiE>, Error<SpiE>>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    D: DelayUs<u32>,
+{
+    nb::block!(radio.wait_tx_empty())?;
+    radio.set_rx_addr(Pipe::P1, &local_address)?;
+    let mut request = [0; PAYLOAD_LEN];
+    request[0] = TYPE_CONNECT;
+    request[1..3].copy_from_slice(&capabilities.encode());
+    request[3..3 + ADDRESS_LEN].copy_from_slice(&local_address);
+    if !radio.send_to(&peer_address, &request)? {
+        return Err(Error::Timeout);
+    }
+    for _ in 0..timeout_ms {
+        delay.delay_us(1000);
+        loop {
+            match radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(Error::Spi(e)),
+            }
+            let packet = nb::block!(radio.read())?;
+            if packet.len() >= 3 && packet[0] == TYPE_ACCEPT {
+                let remote = Capabilities::decode([packet[1], packet[2]]);
+                return Ok(Link {
+                    radio,
+                    peer_address,
+                    params: capabilities.negotiate(&remote),
+                });
+            }
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Answer a pending connect request, if any
+///
+/// The radio must be listening on pipe 1 at the address peers connect
+/// to. Other packets are discarded.
+#[allow(clippy::type_complexity)]
+pub fn accept<'a, Ce, Csn, Spi, E, SpiE>(
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    capabilities: Capabilities,
+) -> Result<Option<Link<'a, Ce, Csn, Spi, E, SpiE>>, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    let (remote, peer_address) = loop {
+        match radio.wait_rx_ready() {
+            Ok(_) => {}
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(e)) => return Err(e),
+        }
+        let packet = nb::block!(radio.read())?;
+        if packet.len() >= 3 + ADDRESS_LEN && packet[0] == TYPE_CONNECT {
+            let mut peer_address = [0; ADDRESS_LEN];
+            peer_address.copy_from_slice(&packet[3..3 + ADDRESS_LEN]);
+            break (Capabilities::decode([packet[1], packet[2]]), peer_address);
+        }
+    };
+    let mut answer = [0; PAYLOAD_LEN];
+    answer[0] = TYPE_ACCEPT;
+    answer[1..3].copy_from_slice(&capabilities.encode());
+    if !radio.send_to(&peer_address, &answer)? {
+        return Ok(None);
+    }
+    Ok(Some(Link {
+        radio,
+        peer_address,
+        params: capabilities.negotiate(&remote),
+    }))
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Link<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    pub fn peer_address(&self) -> &[u8; ADDRESS_LEN] {
+        &self.peer_address
+    }
+
+    /// The negotiated parameters
+    pub fn params(&self) -> &Capabilities {
+        &self.params
+    }
+
+    /// Send `data`, returning whether it was acknowledged
+    ///
+    /// Without dynamic payloads the packet is padded to 32 bytes.
+    /// Panics if `data` is longer than the negotiated `max_fragment_len`.
+    pub fn send(&mut self, data: &[u8]) -> Result<bool, SpiE> {
+        assert!(data.len() <= usize::from(self.params.max_fragment_len));
+        let mut packet = [0; PAYLOAD_LEN];
+        packet[0..data.len()].copy_from_slice(data);
+        let len = match self.params.dynamic_payload {
+            true => data.len(),
+            false => PAYLOAD_LEN,
+        };
+        self.radio.send_to(&self.peer_address, &packet[0..len])
+    }
+
+    /// Poll for a packet
+    pub fn receive(&mut self) -> Result<Option<Payload>, SpiE> {
+        match self.radio.wait_rx_ready() {
+            Ok(_) => Ok(Some(nb::block!(self.radio.read())?)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(e)) => Err(e),
+        }
+    }
+}
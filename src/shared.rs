@@ -0,0 +1,318 @@
+//! Sharing one radio between the main loop and interrupt handlers
+//!
+//! [`Nrf24l01::split()`](../struct.Nrf24l01.html#method.split) moves the
+//! driver into a [`SharedNrf24`](struct.SharedNrf24.html) and hands out
+//! a [`RadioTx`](struct.RadioTx.html) and a [`RadioRx`](struct.RadioRx.html).
+//! Every operation on them runs inside a short critical section, so one
+//! handle may live in an interrupt handler or RTIC task while the other
+//! stays in the main loop.
+//!
+//! Receiving waits until the TX FIFO has drained before switching back
+//! to RX mode, so a packet in flight is never cut off by the RX side.
+//!
+//! An interrupt handler on the IRQ pin can work on the
+//! [`SharedNrf24`](struct.SharedNrf24.html) directly through its
+//! non-blocking methods, each of which takes a handful of SPI
+//! transactions at most.
+
+use crate::device::Device;
+use crate::registers::{FifoStatus, Status};
+use crate::{Configuration, Mode, Nrf24l01, Nrf24l01Rx, Nrf24l01Tx, Payload, Pipe};
+use core::cell::{Cell, UnsafeCell};
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Runs a closure with interrupts (or other contexts) locked out
+///
+/// With the `critical-section` feature, use
+/// [`CriticalSectionLock`](struct.CriticalSectionLock.html). With
+/// `cortex-m` alone it is `cortex_m::interrupt::free(|_| f())`.
+///
+/// # Safety
+///
+/// `with()` must keep every other context that can reach the same
+/// [`SharedNrf24`](struct.SharedNrf24.html) from running until `f`
+/// returns. [`SharedNrf24`](struct.SharedNrf24.html) is `Sync` on
+/// that promise alone.
+pub unsafe trait InterruptFree {
+    fn with<R, F: FnOnce() -> R>(f: F) -> R;
+}
+
+/// [`InterruptFree`](trait.InterruptFree.html) through
+/// `critical_section::with()`
+#[cfg(feature = "critical-section")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CriticalSectionLock;
+
+#[cfg(feature = "critical-section")]
+unsafe impl InterruptFree for CriticalSectionLock {
+    fn with<R, F: FnOnce() -> R>(f: F) -> R {
+        critical_section::with(|_| f())
+    }
+}
+
+/// Clears the flag of a `lock()` when it ends, even by unwinding
+struct Unlock<'a>(&'a Cell<bool>);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// A radio owned jointly by a [`RadioTx`](struct.RadioTx.html) and a
+/// [`RadioRx`](struct.RadioRx.html)
+pub struct SharedNrf24<CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: UnsafeCell<Nrf24l01<Ce, Csn, Spi, E, SpiE>>,
+    /// A `lock()` is running, only touched inside `CS::with()`
+    locked: Cell<bool>,
+    _cs: PhantomData<CS>,
+}
+
+// Every access to `radio` and `locked` happens inside `CS::with()`,
+// which the unsafe `InterruptFree` promises to be exclusive
+unsafe impl<CS, Ce, Csn, Spi, E, SpiE> Sync for SharedNrf24<CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+    Nrf24l01<Ce, Csn, Spi, E, SpiE>: Send,
+{
+}
+
+impl<CS, Ce, Csn, Spi, E, SpiE> SharedNrf24<CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn new(radio: Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> Self {
+        SharedNrf24 {
+            radio: UnsafeCell::new(radio),
+            locked: Cell::new(false),
+            _cs: PhantomData,
+        }
+    }
+
+    /// Run `f` on the radio inside a critical section
+    ///
+    /// Keep `f` short, it blocks interrupts for its whole duration.
+    /// Panics if `f` calls `lock()` again, directly or through one of
+    /// the handles.
+    pub fn lock<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Nrf24l01<Ce, Csn, Spi, E, SpiE>) -> R,
+    {
+        CS::with(|| {
+            assert!(!self.locked.replace(true), "nested SharedNrf24::lock()");
+            let _unlock = Unlock(&self.locked);
+            // No other context runs concurrently, and the flag rules out
+            // a second `&mut` from a nested call
+            f(unsafe { &mut *self.radio.get() })
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn split(
+        &self,
+    ) -> (
+        RadioTx<'_, CS, Ce, Csn, Spi, E, SpiE>,
+        RadioRx<'_, CS, Ce, Csn, Spi, E, SpiE>,
+    ) {
+        (RadioTx { shared: self }, RadioRx { shared: self })
+    }
+
+    /// Clear all interrupt flags, releasing the IRQ pin
+    pub fn clear_interrupts(&self) -> Result<(), SpiE> {
+        self.lock(|radio| radio.clear_interrupts())
+    }
+
+    /// Copy one received packet into `buf`
+    ///
+    /// Returns the pipe and the packet length, or `WouldBlock` if the
+    /// RX FIFO is empty. Packets longer than `buf` are truncated.
+    pub fn read_into(&self, buf: &mut [u8]) -> nb::Result<(Pipe, usize), SpiE> {
+        self.lock(|radio| {
+            let pipe = radio.wait_rx_ready()?;
+            let payload = radio.read()?;
+            let len = payload.len().min(buf.len());
+            buf[0..len].copy_from_slice(&payload[0..len]);
+            Ok((pipe, len))
+        })
+    }
+
+    /// Restart transmission of whatever is left in the TX FIFO
+    ///
+    /// Clears `MAX_RT`, which makes the chip retry the packet at the
+    /// head of the FIFO, and raises CE again. Returns whether anything
+    /// was pending.
+    pub fn kick_tx(&self) -> Result<bool, SpiE> {
+        self.lock(|radio| {
+            if radio.mode != Mode::Tx {
+                return Ok(false);
+            }
+            let device = radio.device();
+            let (status, fifo_status) = device.read_register::<FifoStatus>()?;
+            if fifo_status.tx_empty() {
+                return Ok(false);
+            }
+            if status.max_rt() {
+                let mut clear = Status(0);
+                clear.set_max_rt(true);
+                device.write_register(clear)?;
+            }
+            device.ce_enable();
+            Ok(true)
+        })
+    }
+
+    /// Take the radio back
+    pub fn into_inner(self) -> Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio.into_inner()
+    }
+}
+
+/// Transmitting half of a shared radio
+pub struct RadioTx<'a, CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    shared: &'a SharedNrf24<CS, Ce, Csn, Spi, E, SpiE>,
+}
+
+impl<'a, CS, Ce, Csn, Spi, E, SpiE> RadioTx<'a, CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn set_tx_addr(&mut self, address: &[u8]) -> Result<(), SpiE> {
+        self.shared.lock(|radio| radio.set_tx_addr(address))
+    }
+
+    /// Queue `packet`, see [`Nrf24l01::send()`](../struct.Nrf24l01.html#method.send)
+    pub fn send(&mut self, packet: &[u8]) -> nb::Result<(), SpiE> {
+        self.shared.lock(|radio| radio.send(packet))
+    }
+
+    /// See [`Nrf24l01::wait_tx_done()`](../struct.Nrf24l01.html#method.wait_tx_done)
+    pub fn wait_tx_done(&mut self) -> nb::Result<bool, SpiE> {
+        self.shared.lock(|radio| radio.wait_tx_done())
+    }
+
+    pub fn read_ack_payload(&mut self) -> Result<Option<Payload>, SpiE> {
+        self.shared.lock(|radio| radio.read_ack_payload())
+    }
+}
+
+impl<'a, CS, Ce, Csn, Spi, E, SpiE> Nrf24l01Tx for RadioTx<'a, CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    type Error = SpiE;
+
+    fn set_address(&mut self, address: &[u8]) -> Result<(), Self::Error> {
+        self.set_tx_addr(address)
+    }
+    fn ready(&mut self) -> Result<(), nb::Error<Self::Error>> {
+        self.shared.lock(|radio| radio.wait_tx_empty())
+    }
+    fn send(&mut self, packet: &[u8]) -> Result<(), nb::Error<Self::Error>> {
+        RadioTx::send(self, packet)
+    }
+}
+
+/// Receiving half of a shared radio
+pub struct RadioRx<'a, CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    shared: &'a SharedNrf24<CS, Ce, Csn, Spi, E, SpiE>,
+}
+
+impl<'a, CS, Ce, Csn, Spi, E, SpiE> RadioRx<'a, CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn set_rx_addr(&mut self, pipe: Pipe, address: &[u8]) -> Result<(), SpiE> {
+        self.shared.lock(|radio| radio.set_rx_addr(pipe, address))
+    }
+
+    /// Take one packet out of the RX FIFO
+    ///
+    /// Returns `WouldBlock` while the FIFO is empty or a packet is
+    /// still being transmitted.
+    pub fn receive(&mut self) -> nb::Result<(Pipe, Payload), SpiE> {
+        self.shared.lock(|radio| {
+            let pipe = radio.wait_rx_ready()?;
+            Ok((pipe, radio.read()?))
+        })
+    }
+
+    /// Queue `data` for the next acknowledgement on `pipe`
+    pub fn write_ack_payload(&mut self, pipe: Pipe, data: &[u8]) -> Result<(), SpiE> {
+        self.shared
+            .lock(|radio| radio.write_ack_payload(pipe, data))
+    }
+}
+
+impl<'a, CS, Ce, Csn, Spi, E, SpiE> Nrf24l01Rx for RadioRx<'a, CS, Ce, Csn, Spi, E, SpiE>
+where
+    CS: InterruptFree,
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    type Error = SpiE;
+
+    fn set_address(&mut self, pipe: Pipe, address: &[u8]) -> Result<(), Self::Error> {
+        self.set_rx_addr(pipe, address)
+    }
+    fn ready(&mut self) -> Result<Pipe, nb::Error<Self::Error>> {
+        self.shared.lock(|radio| radio.wait_rx_ready())
+    }
+    fn recv(&mut self) -> Result<Payload, nb::Error<Self::Error>> {
+        self.shared.lock(|radio| radio.read())
+    }
+}
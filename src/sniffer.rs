@@ -0,0 +1,499 @@
+//! Promiscuous Enhanced ShockBurst sniffer
+//!
+//! With an (illegal) 2-byte address of `0x00AA`/`0x0055`, disabled CRC
+//! and auto-ack, the chip locks onto noise followed by the preamble of
+//! any packet on the channel, and hands out the raw bits that follow:
+//! the actual address, packet control field, payload and CRC. These
+//! are checked against the CRC16 in software to discard the many false
+//! positives.
+//!
+//! A [`Follower`](struct.Follower.html) retunes through the hop set
+//! of a frequency-hopping transmitter and learns its sequence.
+//!
+//! Captured frames can be streamed in pcap format through a
+//! [`Capture`](struct.Capture.html), e.g. over a UART into Wireshark.
+
+use crate::device::Device;
+use crate::registers::Feature;
+use crate::{
+    Clock, Configuration, CrcMode, DataRate, Nrf24l01, Payload, Pipe, PAYLOAD_LEN, PIPES_COUNT,
+};
+use core::fmt::Debug;
+use embedded_hal::blocking::serial;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const CRC_LEN: usize = 2;
+/// Packet control field: 6 bits length, 2 bits PID, 1 bit no-ack
+const PCF_BITS: usize = 9;
+
+/// pcap link type `LINKTYPE_USER0`, map it to the `nordic_rf` dissector
+pub const PCAP_LINKTYPE: u32 = 147;
+/// Length of the record header preceding each captured packet
+pub const RECORD_HEADER_LEN: usize = 4;
+/// Largest record: header, address and payload
+pub const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + 5 + PAYLOAD_LEN;
+const FLAG_VALID: u8 = 1 << 0;
+const FLAG_NO_ACK: u8 = 1 << 1;
+
+/// A packet recovered from raw air data
+pub struct Frame {
+    /// RF channel the frame was received on
+    pub channel: u8,
+    /// Address as seen on the air, most significant byte first
+    pub address: [u8; 5],
+    pub address_len: usize,
+    /// Packet ID, incremented by the transmitter for each new packet
+    pub pid: u8,
+    pub no_ack: bool,
+    pub payload: Payload,
+}
+
+/// Receives raw air data on one channel
+pub struct Sniffer<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    channel: u8,
+    address_len: usize,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Sniffer<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Look for frames with `address_len` byte addresses (`3..=5`)
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        channel: u8,
+        rate: DataRate,
+        address_len: usize,
+    ) -> Result<Self, SpiE> {
+        assert!((3..=5).contains(&address_len));
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_crc(CrcMode::Disabled)?;
+        radio.set_auto_ack(&[false; PIPES_COUNT])?;
+        radio.set_auto_retransmit(0, 0)?;
+        radio.set_rf(&rate, 0)?;
+        radio.set_pipes_rx_lengths(&[Some(PAYLOAD_LEN as u8); PIPES_COUNT])?;
+        radio
+            .device()
+            .update_register::<Feature, _, _>(|feature| feature.set_en_dpl(false))?;
+        // SETUP_AW = 0
+        radio.set_address_width(2)?;
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        pipes[1] = true;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_rx_addr(Pipe::P0, &[0xAA, 0x00])?;
+        radio.set_rx_addr(Pipe::P1, &[0x55, 0x00])?;
+        radio.set_frequency(channel)?;
+        Ok(Sniffer {
+            radio,
+            channel,
+            address_len,
+        })
+    }
+
+    pub fn radio(&mut self) -> &mut Nrf24l01<Ce, Csn, Spi, E, SpiE> {
+        self.radio
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    pub fn set_channel(&mut self, channel: u8) -> Result<(), SpiE> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_frequency(channel)?;
+        self.channel = channel;
+        Ok(())
+    }
+
+    /// Poll for raw air data, mostly noise
+    pub fn receive_raw(&mut self) -> Result<Option<Payload>, SpiE> {
+        match self.radio.wait_rx_ready() {
+            Ok(_) => Ok(Some(nb::block!(self.radio.read())?)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(e)) => Err(e),
+        }
+    }
+
+    /// Poll for raw air data that passes the CRC check
+    pub fn receive(&mut self) -> Result<Option<Frame>, SpiE> {
+        while let Some(raw) = self.receive_raw()? {
+            if let Some(frame) = parse(&raw, self.address_len, self.channel) {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Largest hop set a [`Follower`](struct.Follower.html) can cover
+pub const MAX_HOP_CHANNELS: usize = 32;
+
+/// A frame caught by a [`Follower`](struct.Follower.html)
+pub struct Followed {
+    pub frame: Frame,
+    /// Time since the previous frame of the target
+    pub gap_us: Option<u64>,
+    /// Same PID and payload as the previous frame, i.e. a retransmit
+    pub repeat: bool,
+    /// The previous frame was caught on another channel
+    pub hopped: bool,
+}
+
+/// Follows a frequency-hopping transmitter through a suspected hop set
+///
+/// Sweeps the hop set, dwelling on each channel for a while. After a
+/// capture it stays on that channel as long as the target keeps
+/// sending there, then retunes straight to the channel where the
+/// target showed up after this one last time, learning the hop
+/// sequence as it goes. Without such history it continues the sweep.
+pub struct Follower<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    sniffer: Sniffer<'a, Ce, Csn, Spi, E, SpiE>,
+    hop_set: [u8; MAX_HOP_CHANNELS],
+    hop_len: usize,
+    /// Index into `hop_set` currently tuned to
+    index: usize,
+    dwell_us: u32,
+    hold_us: u32,
+    tuned_us: Option<u64>,
+    target: Option<([u8; 5], usize)>,
+    hits: [u16; MAX_HOP_CHANNELS],
+    /// Index of the channel the target moved to from each channel
+    successors: [Option<u8>; MAX_HOP_CHANNELS],
+    last_index: Option<usize>,
+    last_us: Option<u64>,
+    last_pid: u8,
+    last_payload: Payload,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Follower<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Sweep `hop_set`, `dwell_us` per channel
+    ///
+    /// Frames of any address are followed until
+    /// [`set_target()`](#method.set_target) narrows it down. Channels
+    /// blocked by the radio's
+    /// [channel mask](../struct.Nrf24l01.html#method.set_channel_mask)
+    /// are left out, panics if that leaves none.
+    pub fn new(
+        mut sniffer: Sniffer<'a, Ce, Csn, Spi, E, SpiE>,
+        hop_set: &[u8],
+        dwell_us: u32,
+    ) -> Result<Self, SpiE> {
+        assert!(hop_set.len() <= MAX_HOP_CHANNELS);
+        let mut channels = [0; MAX_HOP_CHANNELS];
+        let mut hop_len = 0;
+        for &channel in hop_set {
+            if sniffer.radio().channel_mask().is_allowed(channel) {
+                channels[hop_len] = channel;
+                hop_len += 1;
+            }
+        }
+        assert!(hop_len > 0, "no hop channel allowed");
+        sniffer.set_channel(channels[0])?;
+        Ok(Follower {
+            sniffer,
+            hop_set: channels,
+            hop_len,
+            index: 0,
+            dwell_us,
+            hold_us: dwell_us,
+            tuned_us: None,
+            target: None,
+            hits: [0; MAX_HOP_CHANNELS],
+            successors: [None; MAX_HOP_CHANNELS],
+            last_index: None,
+            last_us: None,
+            last_pid: 0,
+            last_payload: Payload::new(&[]),
+        })
+    }
+
+    /// Stay on a channel for `hold_us` after each capture there
+    pub fn set_hold_us(&mut self, hold_us: u32) {
+        self.hold_us = hold_us;
+    }
+
+    /// Only follow frames to `address`, most significant byte first,
+    /// or any address with `None`
+    pub fn set_target(&mut self, address: Option<&[u8]>) {
+        self.target = address.map(|address| {
+            let mut bytes = [0; 5];
+            bytes[0..address.len()].copy_from_slice(address);
+            (bytes, address.len())
+        });
+        self.forget();
+    }
+
+    /// Forget the learned hop sequence and capture counts
+    pub fn forget(&mut self) {
+        self.hits = [0; MAX_HOP_CHANNELS];
+        self.successors = [None; MAX_HOP_CHANNELS];
+        self.last_index = None;
+        self.last_us = None;
+    }
+
+    pub fn hop_set(&self) -> &[u8] {
+        &self.hop_set[0..self.hop_len]
+    }
+
+    /// Frames of the target caught on each channel of the hop set
+    pub fn hits(&self) -> &[u16] {
+        &self.hits[0..self.hop_len]
+    }
+
+    /// Channel the target last moved to from `channel`
+    pub fn successor(&self, channel: u8) -> Option<u8> {
+        let index = self.hop_set().iter().position(|&ch| ch == channel)?;
+        Some(self.hop_set[usize::from(self.successors[index]?)])
+    }
+
+    pub fn sniffer(&mut self) -> &mut Sniffer<'a, Ce, Csn, Spi, E, SpiE> {
+        &mut self.sniffer
+    }
+
+    pub fn release(self) -> Sniffer<'a, Ce, Csn, Spi, E, SpiE> {
+        self.sniffer
+    }
+
+    /// Poll for a frame of the target, retuning when the dwell is over
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<Option<Followed>, SpiE> {
+        while let Some(frame) = self.sniffer.receive()? {
+            let matches = self.target.is_none_or(|(address, len)| {
+                frame.address_len == len && frame.address[0..len] == address[0..len]
+            });
+            if matches {
+                return Ok(Some(self.record(frame, clock.now_us())));
+            }
+        }
+        let now = clock.now_us();
+        let tuned = *self.tuned_us.get_or_insert(now);
+        let held = self.last_index == Some(self.index)
+            && self
+                .last_us
+                .is_some_and(|last| now - last < u64::from(self.hold_us));
+        if !held && now - tuned >= u64::from(self.dwell_us) {
+            let next = match self.last_index {
+                Some(last) if last == self.index => self.successors[last].map(usize::from),
+                _ => None,
+            };
+            self.tune(next.unwrap_or((self.index + 1) % self.hop_len), now)?;
+        }
+        Ok(None)
+    }
+
+    fn tune(&mut self, index: usize, now: u64) -> Result<(), SpiE> {
+        self.sniffer.set_channel(self.hop_set[index])?;
+        self.index = index;
+        self.tuned_us = Some(now);
+        Ok(())
+    }
+
+    fn record(&mut self, frame: Frame, now: u64) -> Followed {
+        let index = self.index;
+        self.hits[index] = self.hits[index].saturating_add(1);
+        let repeat = self.last_us.is_some()
+            && frame.pid == self.last_pid
+            && *frame.payload == *self.last_payload;
+        let hopped = self.last_index.is_some_and(|last| last != index);
+        if let Some(last) = self.last_index.filter(|_| hopped) {
+            self.successors[last] = Some(index as u8);
+        }
+        let gap_us = self.last_us.map(|last| now - last);
+        self.last_index = Some(index);
+        self.last_us = Some(now);
+        self.last_pid = frame.pid;
+        self.last_payload = Payload::new(&frame.payload);
+        Followed {
+            frame,
+            gap_us,
+            repeat,
+            hopped,
+        }
+    }
+}
+
+/// Find an Enhanced ShockBurst frame in `raw`
+///
+/// The preamble may have been matched one bit early, so a shifted
+/// copy is tried as well.
+pub fn parse(raw: &[u8], address_len: usize, channel: u8) -> Option<Frame> {
+    let total_bits = raw.len() * 8;
+    for shift in 0..2 {
+        let header_bits = shift + address_len * 8 + PCF_BITS;
+        if header_bits > total_bits {
+            continue;
+        }
+        let len = usize::from(bits(raw, shift + address_len * 8, 6) as u8);
+        let payload_bits = len * 8;
+        let crc_offset = header_bits + payload_bits;
+        if len > PAYLOAD_LEN || crc_offset + CRC_LEN * 8 > total_bits {
+            continue;
+        }
+        let crc = bits(raw, crc_offset, 16) as u16;
+        if crc16(raw, shift, crc_offset - shift) != crc {
+            continue;
+        }
+
+        let mut address = [0; 5];
+        for (i, byte) in address.iter_mut().take(address_len).enumerate() {
+            *byte = bits(raw, shift + i * 8, 8) as u8;
+        }
+        let pcf = bits(raw, shift + address_len * 8, PCF_BITS);
+        let mut payload = [0; PAYLOAD_LEN];
+        for (i, byte) in payload.iter_mut().take(len).enumerate() {
+            *byte = bits(raw, header_bits + i * 8, 8) as u8;
+        }
+        return Some(Frame {
+            channel,
+            address,
+            address_len,
+            pid: ((pcf >> 1) & 0b11) as u8,
+            no_ack: pcf & 1 == 1,
+            payload: Payload::new(&payload[0..len]),
+        });
+    }
+    None
+}
+
+/// `count` (at most 32) bits starting at bit `offset`, MSB first
+fn bits(data: &[u8], offset: usize, count: usize) -> u32 {
+    (offset..offset + count).fold(0, |acc, i| {
+        let bit = (data[i / 8] >> (7 - i % 8)) & 1;
+        (acc << 1) | u32::from(bit)
+    })
+}
+
+/// CRC16-CCITT as computed by the chip over `count` bits at `offset`
+pub fn crc16(data: &[u8], offset: usize, count: usize) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for i in offset..offset + count {
+        let bit = (data[i / 8] >> (7 - i % 8)) & 1;
+        let msb = (crc >> 15) as u8;
+        crc <<= 1;
+        if msb ^ bit == 1 {
+            crc ^= 0x1021;
+        }
+    }
+    crc
+}
+
+/// Byte-oriented output for captured packets
+pub trait CaptureSink {
+    type Error;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl<S: serial::Write<u8>> CaptureSink for S {
+    type Error = S::Error;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.bwrite_all(data)
+    }
+}
+
+/// Serialize `frame` into `buf`, returning the length
+///
+/// Record layout: channel, flags (bit 0 CRC valid, bit 1 no-ack),
+/// address length, PID, then the address (most significant byte
+/// first) and the payload.
+pub fn encode_frame(frame: &Frame, buf: &mut [u8]) -> usize {
+    let mut flags = FLAG_VALID;
+    if frame.no_ack {
+        flags |= FLAG_NO_ACK;
+    }
+    buf[0] = frame.channel;
+    buf[1] = flags;
+    buf[2] = frame.address_len as u8;
+    buf[3] = frame.pid;
+    let mut len = RECORD_HEADER_LEN;
+    buf[len..len + frame.address_len].copy_from_slice(&frame.address[0..frame.address_len]);
+    len += frame.address_len;
+    buf[len..len + frame.payload.len()].copy_from_slice(&frame.payload);
+    len + frame.payload.len()
+}
+
+/// Serialize unparsed air data into `buf`, returning the length
+pub fn encode_raw(channel: u8, raw: &[u8], buf: &mut [u8]) -> usize {
+    buf[0..RECORD_HEADER_LEN].copy_from_slice(&[channel, 0, 0, 0]);
+    buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + raw.len()].copy_from_slice(raw);
+    RECORD_HEADER_LEN + raw.len()
+}
+
+/// Streams records as a pcap file
+pub struct Capture<S: CaptureSink> {
+    sink: S,
+}
+
+impl<S: CaptureSink> Capture<S> {
+    /// Writes the pcap file header
+    pub fn new(mut sink: S) -> Result<Self, S::Error> {
+        let mut header = [0; 24];
+        header[0..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        header[4..6].copy_from_slice(&2u16.to_le_bytes());
+        header[6..8].copy_from_slice(&4u16.to_le_bytes());
+        header[16..20].copy_from_slice(&(MAX_RECORD_LEN as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&PCAP_LINKTYPE.to_le_bytes());
+        sink.write(&header)?;
+        Ok(Capture { sink })
+    }
+
+    pub fn release(self) -> S {
+        self.sink
+    }
+
+    /// `timestamp_us` is any monotonic microsecond count
+    pub fn write_frame(&mut self, frame: &Frame, timestamp_us: u64) -> Result<(), S::Error> {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let len = encode_frame(frame, &mut buf);
+        self.write_record(&buf[0..len], timestamp_us)
+    }
+
+    pub fn write_raw(
+        &mut self,
+        channel: u8,
+        raw: &[u8],
+        timestamp_us: u64,
+    ) -> Result<(), S::Error> {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let len = encode_raw(channel, raw, &mut buf);
+        self.write_record(&buf[0..len], timestamp_us)
+    }
+
+    fn write_record(&mut self, record: &[u8], timestamp_us: u64) -> Result<(), S::Error> {
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(&((timestamp_us / 1_000_000) as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&((timestamp_us % 1_000_000) as u32).to_le_bytes());
+        header[8..12].copy_from_slice(&(record.len() as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(record.len() as u32).to_le_bytes());
+        self.sink.write(&header)?;
+        self.sink.write(record)
+    }
+}
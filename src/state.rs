@@ -0,0 +1,179 @@
+use crate::device::Device;
+use crate::registers::{
+    Config, Dynpd, EnAa, EnRxaddr, Feature, Register, RfCh, RfSetup, RxAddrP0, RxAddrP1, RxAddrP2,
+    RxAddrP3, RxAddrP4, RxAddrP5, RxPwP0, RxPwP1, RxPwP2, RxPwP3, RxPwP4, RxPwP5, SetupAw,
+    SetupRetr, TxAddr,
+};
+use crate::{ChipVariant, MAX_ADDR_BYTES};
+
+const REGISTERS_LEN: usize = 19;
+/// Length of [`SavedState::to_bytes()`](struct.SavedState.html#method.to_bytes)
+pub const SAVED_STATE_LEN: usize = 1 + REGISTERS_LEN + 3 * MAX_ADDR_BYTES;
+
+/// Register contents captured by [`Nrf24l01::sleep()`](struct.Nrf24l01.html#method.sleep)
+///
+/// Small enough to keep in retained RAM or a backup register file
+/// while the MCU sleeps, see [`to_bytes()`](#method.to_bytes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedState {
+    chip_variant: ChipVariant,
+    registers: [u8; REGISTERS_LEN],
+    rx_addr_p0: [u8; MAX_ADDR_BYTES],
+    rx_addr_p1: [u8; MAX_ADDR_BYTES],
+    tx_addr: [u8; MAX_ADDR_BYTES],
+}
+
+/// A register that does not hold what it should, see
+/// [`Nrf24l01::health_check()`](struct.Nrf24l01.html#method.health_check)
+///
+/// For addresses, `expected` and `actual` are the first differing byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// Register address
+    pub register: u8,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+/// Apply `$m!(index, Register)` to every single-byte register, `CONFIG` first
+macro_rules! for_each_register {
+    ($m: ident) => {
+        $m!(0, Config);
+        $m!(1, EnAa);
+        $m!(2, EnRxaddr);
+        $m!(3, SetupAw);
+        $m!(4, SetupRetr);
+        $m!(5, RfCh);
+        $m!(6, RfSetup);
+        $m!(7, RxAddrP2);
+        $m!(8, RxAddrP3);
+        $m!(9, RxAddrP4);
+        $m!(10, RxAddrP5);
+        $m!(11, RxPwP0);
+        $m!(12, RxPwP1);
+        $m!(13, RxPwP2);
+        $m!(14, RxPwP3);
+        $m!(15, RxPwP4);
+        $m!(16, RxPwP5);
+        $m!(17, Dynpd);
+        $m!(18, Feature);
+    };
+}
+
+impl SavedState {
+    pub(crate) fn read<D: Device>(device: &mut D) -> Result<Self, D::Error> {
+        let mut registers = [0; REGISTERS_LEN];
+        macro_rules! read {
+            ($i: expr, $reg: ident) => {
+                let (_, register) = device.read_register_uncached::<$reg>()?;
+                register.encode(&mut registers[$i..$i + 1]);
+            };
+        }
+        for_each_register!(read);
+        Ok(SavedState {
+            chip_variant: device.chip_variant(),
+            registers,
+            rx_addr_p0: read_address::<RxAddrP0, _>(device)?,
+            rx_addr_p1: read_address::<RxAddrP1, _>(device)?,
+            tx_addr: read_address::<TxAddr, _>(device)?,
+        })
+    }
+
+    /// Write everything but `CONFIG`, which is returned
+    pub(crate) fn write<D: Device>(&self, device: &mut D) -> Result<Config, D::Error> {
+        let registers = &self.registers;
+        macro_rules! write {
+            (0, Config) => {};
+            ($i: expr, $reg: ident) => {
+                device.write_register($reg::decode(&registers[$i..$i + 1]))?;
+            };
+        }
+        for_each_register!(write);
+        device.write_register(RxAddrP0::new(&self.rx_addr_p0))?;
+        device.write_register(RxAddrP1::new(&self.rx_addr_p1))?;
+        device.write_register(TxAddr::new(&self.tx_addr))?;
+        Ok(Config::decode(&registers[0..1]))
+    }
+
+    /// First register, `CONFIG` aside, that differs in `actual`
+    pub(crate) fn diff(&self, actual: &SavedState) -> Option<Divergence> {
+        let mut addrs = [0; REGISTERS_LEN];
+        macro_rules! addr {
+            ($i: expr, $reg: ident) => {
+                addrs[$i] = $reg::addr();
+            };
+        }
+        for_each_register!(addr);
+        let registers = addrs
+            .iter()
+            .zip(self.registers.iter().zip(actual.registers.iter()))
+            .skip(1);
+        let addresses = [
+            (RxAddrP0::addr(), &self.rx_addr_p0, &actual.rx_addr_p0),
+            (RxAddrP1::addr(), &self.rx_addr_p1, &actual.rx_addr_p1),
+            (TxAddr::addr(), &self.tx_addr, &actual.tx_addr),
+        ];
+        let addresses = addresses.iter().flat_map(|&(addr, expected, actual)| {
+            expected
+                .iter()
+                .zip(actual.iter())
+                .map(move |pair| (addr, pair))
+        });
+        registers
+            .map(|(&addr, pair)| (addr, pair))
+            .chain(addresses)
+            .find(|(_, (expected, actual))| expected != actual)
+            .map(|(register, (&expected, &actual))| Divergence {
+                register,
+                expected,
+                actual,
+            })
+    }
+
+    pub fn chip_variant(&self) -> ChipVariant {
+        self.chip_variant
+    }
+
+    pub fn to_bytes(&self) -> [u8; SAVED_STATE_LEN] {
+        let mut bytes = [0; SAVED_STATE_LEN];
+        bytes[0] = self.chip_variant as u8;
+        bytes[1..1 + REGISTERS_LEN].copy_from_slice(&self.registers);
+        let addresses = &mut bytes[1 + REGISTERS_LEN..];
+        addresses[0..5].copy_from_slice(&self.rx_addr_p0);
+        addresses[5..10].copy_from_slice(&self.rx_addr_p1);
+        addresses[10..15].copy_from_slice(&self.tx_addr);
+        bytes
+    }
+
+    /// `None` if the bytes do not come from [`to_bytes()`](#method.to_bytes)
+    pub fn from_bytes(bytes: &[u8; SAVED_STATE_LEN]) -> Option<Self> {
+        let chip_variant = match bytes[0] {
+            0 => ChipVariant::Plus,
+            1 => ChipVariant::NonPlus,
+            2 => ChipVariant::Si24r1,
+            _ => return None,
+        };
+        let mut state = SavedState {
+            chip_variant,
+            registers: [0; REGISTERS_LEN],
+            rx_addr_p0: [0; MAX_ADDR_BYTES],
+            rx_addr_p1: [0; MAX_ADDR_BYTES],
+            tx_addr: [0; MAX_ADDR_BYTES],
+        };
+        state
+            .registers
+            .copy_from_slice(&bytes[1..1 + REGISTERS_LEN]);
+        let addresses = &bytes[1 + REGISTERS_LEN..];
+        state.rx_addr_p0.copy_from_slice(&addresses[0..5]);
+        state.rx_addr_p1.copy_from_slice(&addresses[5..10]);
+        state.tx_addr.copy_from_slice(&addresses[10..15]);
+        Some(state)
+    }
+}
+
+fn read_address<R: Register, D: Device>(device: &mut D) -> Result<[u8; MAX_ADDR_BYTES], D::Error> {
+    let (_, register) = device.read_register_uncached::<R>()?;
+    let mut address = [0; MAX_ADDR_BYTES];
+    register.encode(&mut address);
+    Ok(address)
+}
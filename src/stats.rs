@@ -0,0 +1,111 @@
+use crate::device::Device;
+use crate::fifo::FifoLevel;
+use crate::registers::FifoStatus;
+use crate::{Nrf24l01, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Counters kept by the driver, see
+/// [`Nrf24l01::stats()`](struct.Nrf24l01.html#method.stats)
+///
+/// All counters wrap around.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Packets taken out of the RX FIFO
+    pub rx_packets: u32,
+    /// Times the RX FIFO was found full
+    ///
+    /// Packets arriving while it is full are dropped without a trace,
+    /// so any count here means packets are read out too slowly. Each
+    /// time counts once until a packet has been read.
+    pub rx_overflows: u32,
+    /// Indexed by pipe number
+    pub pipes: [PipeStats; PIPES_COUNT],
+}
+
+/// Traffic on one pipe, see [`Stats`](struct.Stats.html)
+///
+/// A pipe that stays at zero while others count points at a silent
+/// sender.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipeStats {
+    pub rx_packets: u32,
+    /// Payload bytes of `rx_packets`
+    pub rx_bytes: u32,
+    /// Ack payloads sent back on this pipe
+    ///
+    /// Queued with [`write_ack_payload()`](struct.Nrf24l01.html#method.write_ack_payload),
+    /// counted once a packet arrives on the pipe to carry them.
+    pub ack_payloads: u32,
+}
+
+impl Stats {
+    pub fn pipe(&self, pipe: Pipe) -> &PipeStats {
+        &self.pipes[pipe.index()]
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// How full the RX FIFO is
+    ///
+    /// The chip only flags empty and full, `One` stands for one or two
+    /// packets. Finding it full counts in
+    /// [`Stats::rx_overflows`](struct.Stats.html#structfield.rx_overflows).
+    pub fn rx_fifo_level(&mut self) -> Result<FifoLevel, SpiE> {
+        let (_, fifo_status) = self.device.read_register::<FifoStatus>()?;
+        if fifo_status.rx_empty() {
+            return Ok(FifoLevel::Empty);
+        }
+        if !fifo_status.rx_full() {
+            return Ok(FifoLevel::One);
+        }
+        if !self.rx_overflow_counted {
+            self.stats.rx_overflows = self.stats.rx_overflows.wrapping_add(1);
+            self.rx_overflow_counted = true;
+        }
+        Ok(FifoLevel::Full)
+    }
+
+    /// Count a packet taken out of the RX FIFO
+    pub(crate) fn record_rx(&mut self, pipe: Option<Pipe>, len: usize) {
+        self.stats.rx_packets = self.stats.rx_packets.wrapping_add(1);
+        self.rx_overflow_counted = false;
+        let pipe = match pipe {
+            Some(pipe) => pipe.index(),
+            None => return,
+        };
+        let stats = &mut self.stats.pipes[pipe];
+        stats.rx_packets = stats.rx_packets.wrapping_add(1);
+        stats.rx_bytes = stats.rx_bytes.wrapping_add(len as u32);
+        // An empty TX FIFO, e.g. after a flush, holds no ack payloads
+        if self.device.tx_queued() == 0 {
+            self.ack_queued = [0; PIPES_COUNT];
+        }
+        if self.ack_queued[pipe] > 0 {
+            self.ack_queued[pipe] -= 1;
+            stats.ack_payloads = stats.ack_payloads.wrapping_add(1);
+        }
+    }
+
+    /// Remember an ack payload waiting for a packet on `pipe`
+    pub(crate) fn record_ack_payload(&mut self, pipe: Pipe) {
+        let queued = &mut self.ack_queued[pipe.index()];
+        *queued = queued.saturating_add(1);
+    }
+}
@@ -0,0 +1,377 @@
+//! Time-division multiple access
+//!
+//! A [`Coordinator`](struct.Coordinator.html) broadcasts a beacon at
+//! the start of every frame, listing which node owns which of the
+//! following slots. [`Node`](struct.Node.html)s synchronize to the
+//! beacon and transmit only at the start of their own slot. A node
+//! without a slot asks for one in any free slot.
+//!
+//! Beacons travel on their own address without acknowledgement,
+//! uplink frames on a second address with auto-ack.
+
+use crate::{Clock, Configuration, Nrf24l01, Payload, Pipe, PAYLOAD_LEN, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_BEACON: u8 = 0xB1;
+const TYPE_JOIN: u8 = 0xB2;
+const TYPE_DATA: u8 = 0xB3;
+const BEACON_HEADER_LEN: usize = 6;
+const DATA_HEADER_LEN: usize = 2;
+/// Maximum number of data slots per frame
+pub const MAX_SLOTS: usize = PAYLOAD_LEN - BEACON_HEADER_LEN;
+/// Maximum number of user data bytes per uplink frame
+pub const MAX_DATA_LEN: usize = PAYLOAD_LEN - DATA_HEADER_LEN;
+/// Beacons missed before a node considers itself out of sync
+const MAX_MISSED_BEACONS: u64 = 3;
+/// Nodes listen on pipe 1 only so that they never ack uplink frames of others
+const LISTEN_PIPES: [bool; PIPES_COUNT] = [false, true, false, false, false, false];
+const SEND_PIPES: [bool; PIPES_COUNT] = [true, true, false, false, false, false];
+
+/// Frame layout announced in every beacon
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Sequence number of the current frame
+    pub frame: u16,
+    pub slot_us: u16,
+    /// Node id owning each data slot, `0` for free slots
+    owners: [u8; MAX_SLOTS],
+    slot_count: usize,
+}
+
+impl Schedule {
+    /// `slot_count` data slots of `slot_us` each, all free
+    ///
+    /// A slot must be long enough for one packet plus its
+    /// acknowledgement and retransmits.
+    pub fn new(slot_count: usize, slot_us: u16) -> Self {
+        assert!(slot_count > 0 && slot_count <= MAX_SLOTS);
+        assert!(slot_us > 0);
+        Schedule {
+            frame: 0,
+            slot_us,
+            owners: [0; MAX_SLOTS],
+            slot_count,
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// Length of a whole frame: the beacon slot and all data slots
+    pub fn frame_us(&self) -> u64 {
+        (self.slot_count as u64 + 1) * u64::from(self.slot_us)
+    }
+
+    pub fn owner(&self, slot: usize) -> Option<u8> {
+        match self.owners[0..self.slot_count][slot] {
+            0 => None,
+            node_id => Some(node_id),
+        }
+    }
+
+    /// Data slot assigned to `node_id`
+    pub fn slot_of(&self, node_id: u8) -> Option<usize> {
+        self.owners[0..self.slot_count]
+            .iter()
+            .position(|&owner| owner == node_id)
+    }
+
+    /// Give `node_id` (non-zero) a free slot unless it already has one
+    pub fn assign(&mut self, node_id: u8) -> Option<usize> {
+        assert!(node_id != 0);
+        if let Some(slot) = self.slot_of(node_id) {
+            return Some(slot);
+        }
+        let slot = self.slot_of(0)?;
+        self.owners[slot] = node_id;
+        Some(slot)
+    }
+
+    pub fn release(&mut self, node_id: u8) {
+        if let Some(slot) = self.slot_of(node_id) {
+            self.owners[slot] = 0;
+        }
+    }
+
+    /// Serialize into `buf`, returning the length
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = TYPE_BEACON;
+        buf[1..3].copy_from_slice(&self.frame.to_le_bytes());
+        buf[3..5].copy_from_slice(&self.slot_us.to_le_bytes());
+        buf[5] = self.slot_count as u8;
+        let len = BEACON_HEADER_LEN + self.slot_count;
+        buf[BEACON_HEADER_LEN..len].copy_from_slice(&self.owners[0..self.slot_count]);
+        len
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < BEACON_HEADER_LEN || buf[0] != TYPE_BEACON {
+            return None;
+        }
+        let slot_count = usize::from(buf[5]);
+        if slot_count == 0 || slot_count > MAX_SLOTS || buf.len() < BEACON_HEADER_LEN + slot_count {
+            return None;
+        }
+        // Any transmitter can send a beacon, never divide by its slot length
+        let slot_us = u16::from_le_bytes([buf[3], buf[4]]);
+        if slot_us == 0 {
+            return None;
+        }
+        let mut schedule = Schedule::new(slot_count, slot_us);
+        schedule.frame = u16::from_le_bytes([buf[1], buf[2]]);
+        schedule.owners[0..slot_count]
+            .copy_from_slice(&buf[BEACON_HEADER_LEN..BEACON_HEADER_LEN + slot_count]);
+        Some(schedule)
+    }
+}
+
+/// Sends beacons and collects uplink frames
+pub struct Coordinator<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    beacon_address: [u8; 5],
+    schedule: Schedule,
+    next_frame_us: Option<u64>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Coordinator<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Moves to the nearest channel the radio's
+    /// [channel mask](../struct.Nrf24l01.html#method.set_channel_mask)
+    /// allows, nodes with the same mask end up on the same one
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        beacon_address: [u8; 5],
+        uplink_address: [u8; 5],
+        schedule: Schedule,
+    ) -> Result<Self, SpiE> {
+        nb::block!(radio.wait_tx_empty())?;
+        radio.enforce_channel_mask()?;
+        // Pipe 0 unused: beacons are sent without waiting for acks
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[1] = true;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_rx_addr(Pipe::P1, &uplink_address)?;
+        radio.set_tx_addr(&beacon_address)?;
+        Ok(Coordinator {
+            radio,
+            beacon_address,
+            schedule,
+            next_frame_us: None,
+        })
+    }
+
+    pub fn schedule(&mut self) -> &mut Schedule {
+        &mut self.schedule
+    }
+
+    /// Send the beacon when a frame is due, and poll for an uplink frame
+    ///
+    /// Slot requests are granted on the fly. Returns the node id along
+    /// with the data.
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<Option<(u8, Payload)>, SpiE> {
+        let now = clock.now_us();
+        if self.next_frame_us.is_none_or(|next| now >= next) {
+            self.send_beacon()?;
+            self.next_frame_us = Some(now + self.schedule.frame_us());
+        }
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let frame = nb::block!(self.radio.read())?;
+            match frame.get(0..DATA_HEADER_LEN) {
+                Some(&[TYPE_JOIN, node_id]) if node_id != 0 => {
+                    self.schedule.assign(node_id);
+                }
+                Some(&[TYPE_DATA, node_id]) => {
+                    return Ok(Some((node_id, Payload::new(&frame[DATA_HEADER_LEN..]))));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn send_beacon(&mut self) -> Result<(), SpiE> {
+        let mut buf = [0; PAYLOAD_LEN];
+        let len = self.schedule.encode(&mut buf);
+        self.radio.set_tx_addr(&self.beacon_address)?;
+        nb::block!(self.radio.send(&buf[0..len]))?;
+        nb::block!(self.radio.wait_tx_done())?;
+        self.schedule.frame = self.schedule.frame.wrapping_add(1);
+        Ok(())
+    }
+}
+
+/// Transmits in its assigned slot
+pub struct Node<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    uplink_address: [u8; 5],
+    node_id: u8,
+    schedule: Option<Schedule>,
+    frame_start_us: u64,
+    /// Frame in which a packet has already been sent
+    last_sent: Option<u16>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Node<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// `node_id` must be non-zero and unique
+    ///
+    /// Moves off a channel blocked by the radio's
+    /// [channel mask](../struct.Nrf24l01.html#method.set_channel_mask),
+    /// like the [`Coordinator`](struct.Coordinator.html) does.
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        beacon_address: [u8; 5],
+        uplink_address: [u8; 5],
+        node_id: u8,
+    ) -> Result<Self, SpiE> {
+        assert!(node_id != 0);
+        nb::block!(radio.wait_tx_empty())?;
+        radio.enforce_channel_mask()?;
+        // Pipe 0 receives acks, pipe 1 beacons which must not be acked
+        let mut auto_ack = [false; PIPES_COUNT];
+        auto_ack[0] = true;
+        radio.set_auto_ack(&auto_ack)?;
+        radio.set_pipes_rx_enable(&LISTEN_PIPES)?;
+        radio.set_rx_addr(Pipe::P1, &beacon_address)?;
+        radio.set_tx_addr(&uplink_address)?;
+        Ok(Node {
+            radio,
+            uplink_address,
+            node_id,
+            schedule: None,
+            frame_start_us: 0,
+            last_sent: None,
+        })
+    }
+
+    /// Schedule of the last beacon, `None` until synchronized
+    pub fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    pub fn slot(&self) -> Option<usize> {
+        self.schedule.as_ref()?.slot_of(self.node_id)
+    }
+
+    /// Listen for beacons, call this often while not sending
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<(), SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let frame = nb::block!(self.radio.read())?;
+            if let Some(schedule) = Schedule::decode(&frame) {
+                self.frame_start_us = clock.now_us();
+                self.schedule = Some(schedule);
+            }
+        }
+        let now = clock.now_us();
+        let lost = match self.schedule {
+            Some(ref schedule) => {
+                now - self.frame_start_us > MAX_MISSED_BEACONS * schedule.frame_us()
+            }
+            None => false,
+        };
+        if lost {
+            self.schedule = None;
+        }
+        Ok(())
+    }
+
+    /// Send `data` in the next slot of this node
+    ///
+    /// Returns `WouldBlock` until the slot has come. Without an
+    /// assigned slot a request is sent in a free slot first. Returns
+    /// whether the coordinator acknowledged the data.
+    pub fn send<C: Clock>(&mut self, clock: &C, data: &[u8]) -> nb::Result<bool, SpiE> {
+        assert!(data.len() <= MAX_DATA_LEN);
+        self.poll(clock)?;
+        let (slot, frame) = match self.current_slot(clock) {
+            Some(current) => current,
+            None => return Err(nb::Error::WouldBlock),
+        };
+        if self.last_sent == Some(frame) {
+            return Err(nb::Error::WouldBlock);
+        }
+        let schedule = self.schedule.as_ref().unwrap();
+        let mut buf = [0; PAYLOAD_LEN];
+        match schedule.owner(slot) {
+            Some(owner) if owner == self.node_id => {
+                self.last_sent = Some(frame);
+                buf[0] = TYPE_DATA;
+                buf[1] = self.node_id;
+                buf[DATA_HEADER_LEN..DATA_HEADER_LEN + data.len()].copy_from_slice(data);
+                let len = DATA_HEADER_LEN + data.len();
+                Ok(self.send_uplink(&buf[0..len])?)
+            }
+            None if self.slot().is_none() => {
+                self.last_sent = Some(frame);
+                buf[0] = TYPE_JOIN;
+                buf[1] = self.node_id;
+                self.send_uplink(&buf[0..DATA_HEADER_LEN])?;
+                Err(nb::Error::WouldBlock)
+            }
+            _ => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    fn send_uplink(&mut self, frame: &[u8]) -> Result<bool, SpiE> {
+        nb::block!(self.radio.wait_tx_empty())?;
+        self.radio.set_pipes_rx_enable(&SEND_PIPES)?;
+        let acked = self.radio.send_to(&self.uplink_address, frame)?;
+        self.radio.set_pipes_rx_enable(&LISTEN_PIPES)?;
+        Ok(acked)
+    }
+
+    /// Data slot and frame number if now is early enough in a slot to send
+    fn current_slot<C: Clock>(&self, clock: &C) -> Option<(usize, u16)> {
+        let schedule = self.schedule.as_ref()?;
+        let slot_us = u64::from(schedule.slot_us);
+        let elapsed = clock.now_us() - self.frame_start_us;
+        let frames = elapsed / schedule.frame_us();
+        let offset = elapsed % schedule.frame_us();
+        let slot = (offset / slot_us) as usize;
+        // Keep the second half of each slot as a guard interval
+        if slot == 0 || offset % slot_us > slot_us / 2 {
+            return None;
+        }
+        // Slots counted from the beacon, starting at 1
+        Some((slot - 1, schedule.frame.wrapping_add(frames as u16)))
+    }
+}
@@ -0,0 +1,124 @@
+use crate::channels::Channel;
+use crate::device::Device;
+use crate::registers::RfSetup;
+use crate::{Configuration, Mode, Nrf24l01};
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// The synthesizer held on one channel by `PLL_LOCK`, see
+/// [`Nrf24l01::pll_lock_test()`](struct.Nrf24l01.html#method.pll_lock_test)
+///
+/// `CONT_WAVE` stays off, so nothing is transmitted. Leaving with
+/// [`finish()`](#method.finish), or dropping the guard, clears
+/// `PLL_LOCK` again and returns the radio to the mode it was in.
+pub struct PllLockTest<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    previous_mode: Mode,
+    previous_channel: u8,
+    active: bool,
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Lock the synthesizer on `channel` for conducted measurements
+    ///
+    /// Waits for the TX FIFO to drain, then sets `PLL_LOCK` in
+    /// `RF_SETUP` and raises CE in TX mode. The radio is borrowed by the
+    /// returned guard until the test ends.
+    pub fn pll_lock_test(
+        &mut self,
+        channel: Channel,
+    ) -> Result<PllLockTest<'_, Ce, Csn, Spi, E, SpiE>, SpiE> {
+        nb::block!(self.wait_tx_empty())?;
+        let previous_mode = self.mode;
+        let previous_channel = self.get_frequency()?;
+        self.standby();
+        self.set_channel(channel)?;
+        self.tx()?;
+        let mut test = PllLockTest {
+            radio: self,
+            previous_mode,
+            previous_channel,
+            active: true,
+        };
+        test.set_pll_lock(true)?;
+        test.radio.device.ce_enable();
+        Ok(test)
+    }
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> PllLockTest<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Move the locked synthesizer to another channel
+    pub fn set_channel(&mut self, channel: Channel) -> Result<(), SpiE> {
+        let device = self.radio.device();
+        device.ce_disable();
+        self.radio.set_channel(channel)?;
+        self.radio.device().ce_enable();
+        Ok(())
+    }
+
+    /// Clear `PLL_LOCK` and restore the previous channel and mode
+    pub fn finish(mut self) -> Result<(), SpiE> {
+        self.leave()
+    }
+
+    fn set_pll_lock(&mut self, pll_lock: bool) -> Result<(), SpiE> {
+        let device = self.radio.device();
+        let (_, mut rf_setup) = device.read_register::<RfSetup>()?;
+        rf_setup.set_pll_lock(pll_lock);
+        rf_setup.set_cont_wave(false);
+        device.write_register(rf_setup)?;
+        Ok(())
+    }
+
+    fn leave(&mut self) -> Result<(), SpiE> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+        self.radio.standby();
+        self.set_pll_lock(false)?;
+        self.radio.set_frequency(self.previous_channel)?;
+        match self.previous_mode {
+            Mode::Rx => nb::block!(self.radio.rx())?,
+            Mode::Tx => self.radio.tx()?,
+            Mode::Standby => {}
+        }
+        Ok(())
+    }
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Drop for PllLockTest<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    fn drop(&mut self) {
+        // Errors cannot be reported here, use `finish()` to see them
+        let _ = self.leave();
+    }
+}
@@ -0,0 +1,291 @@
+//! Two-way time transfer
+//!
+//! A [`Client`](struct.Client.html) sends a request stamped with its
+//! local time, a [`Server`](struct.Server.html) answers with the time
+//! it received the request and the time it sent the answer. From the
+//! four timestamps the client derives the offset of the server's
+//! clock and the round trip, assuming the path takes equally long in
+//! both directions. Repeated exchanges also give the drift between
+//! the two clocks.
+//!
+//! Packets are stamped when they are queued or read, so the accuracy
+//! depends on how promptly both sides poll. Set an
+//! [`rx clock`](../struct.Nrf24l01.html#method.set_rx_clock) to stamp
+//! received packets as early as possible.
+
+use crate::{Clock, Configuration, Nrf24l01, Pipe, PIPES_COUNT};
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+const TYPE_REQUEST: u8 = 0x51;
+const TYPE_RESPONSE: u8 = 0x52;
+const REQUEST_LEN: usize = 15;
+const RESPONSE_LEN: usize = 26;
+/// Resolution of the wait for a response
+const STEP_US: u32 = 100;
+
+fn read_u64(buf: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[0..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Outcome of one exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Local time the request was sent
+    pub local_us: u64,
+    /// Server time minus local time
+    pub offset_us: i64,
+    /// Time on air and in both drivers, without the server's turnaround
+    pub round_trip_us: u32,
+}
+
+/// What the client knows about the server's clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    /// Server time minus local time, as of `local_us`
+    pub offset_us: i64,
+    /// Local time of the last accepted sample
+    pub local_us: u64,
+    /// How much faster the server's clock runs, in parts per billion
+    pub drift_ppb: i32,
+}
+
+impl Estimate {
+    /// Server time at local time `local_us`
+    pub fn remote_us(&self, local_us: u64) -> u64 {
+        let elapsed = local_us.wrapping_sub(self.local_us) as i64;
+        let drift = (elapsed as i128 * i128::from(self.drift_ppb) / 1_000_000_000) as i64;
+        local_us.wrapping_add((self.offset_us + drift) as u64)
+    }
+
+    /// Local time at server time `remote_us`
+    pub fn local_us(&self, remote_us: u64) -> u64 {
+        // The drift correction is small enough to use the uncorrected guess
+        let guess = remote_us.wrapping_sub(self.offset_us as u64);
+        let error = self.remote_us(guess).wrapping_sub(remote_us) as i64;
+        guess.wrapping_sub(error as u64)
+    }
+}
+
+/// Answers time requests
+pub struct Server<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    answered: u32,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Server<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Listen with auto-ack on pipe 1 at `address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        address: &[u8],
+    ) -> Result<Self, SpiE> {
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        pipes[1] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_rx_addr(Pipe::P1, address)?;
+        Ok(Server { radio, answered: 0 })
+    }
+
+    /// Requests answered so far
+    pub fn answered(&self) -> u32 {
+        self.answered
+    }
+
+    /// Answer pending requests, other packets are discarded
+    pub fn poll<C: Clock>(&mut self, clock: &C) -> Result<(), SpiE> {
+        loop {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            let received_us = packet.timestamp_us().unwrap_or_else(|| clock.now_us());
+            if packet.len() != REQUEST_LEN || packet[0] != TYPE_REQUEST {
+                continue;
+            }
+            let mut response = [0; RESPONSE_LEN];
+            response[0] = TYPE_RESPONSE;
+            response[1] = packet[1];
+            response[2..10].copy_from_slice(&packet[2..10]);
+            response[10..18].copy_from_slice(&received_us.to_le_bytes());
+            response[18..26].copy_from_slice(&clock.now_us().to_le_bytes());
+            if self.radio.send_to(&packet[10..15], &response)? {
+                self.answered = self.answered.wrapping_add(1);
+            }
+        }
+    }
+}
+
+/// Keeps track of a server's clock
+pub struct Client<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+    server_address: [u8; 5],
+    address: [u8; 5],
+    seq: u8,
+    max_round_trip_us: u32,
+    /// First accepted sample, the base line for the drift
+    first: Option<Sample>,
+    estimate: Option<Estimate>,
+}
+
+impl<'a, Ce, Csn, Spi, E, SpiE> Client<'a, Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Ask `server_address`, receiving answers on pipe 1 at `address`
+    pub fn new(
+        radio: &'a mut Nrf24l01<Ce, Csn, Spi, E, SpiE>,
+        server_address: [u8; 5],
+        address: [u8; 5],
+    ) -> Result<Self, SpiE> {
+        let mut pipes = [false; PIPES_COUNT];
+        pipes[0] = true;
+        pipes[1] = true;
+        nb::block!(radio.wait_tx_empty())?;
+        radio.set_pipes_rx_enable(&pipes)?;
+        radio.set_auto_ack(&pipes)?;
+        radio.set_rx_addr(Pipe::P1, &address)?;
+        Ok(Client {
+            radio,
+            server_address,
+            address,
+            seq: 0,
+            max_round_trip_us: u32::MAX,
+            first: None,
+            estimate: None,
+        })
+    }
+
+    /// Discard samples whose round trip took longer
+    ///
+    /// A slow round trip usually means retransmits on one of the two
+    /// ways, which breaks the assumption of a symmetric path.
+    pub fn set_max_round_trip_us(&mut self, max_round_trip_us: u32) {
+        self.max_round_trip_us = max_round_trip_us;
+    }
+
+    /// Current estimate, `None` before the first successful exchange
+    pub fn estimate(&self) -> Option<&Estimate> {
+        self.estimate.as_ref()
+    }
+
+    /// Server time now, `None` before the first successful exchange
+    pub fn remote_us<C: Clock>(&self, clock: &C) -> Option<u64> {
+        Some(self.estimate?.remote_us(clock.now_us()))
+    }
+
+    /// Forget all samples, for example after the server restarted
+    pub fn reset(&mut self) {
+        self.first = None;
+        self.estimate = None;
+    }
+
+    /// Run one exchange and update the estimate
+    ///
+    /// Returns the sample, or `None` if the server did not answer
+    /// within `timeout_ms` or the round trip was too slow.
+    pub fn sync<C: Clock, D: DelayUs<u32>>(
+        &mut self,
+        clock: &C,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<Sample>, SpiE> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut request = [0; REQUEST_LEN];
+        request[0] = TYPE_REQUEST;
+        request[1] = self.seq;
+        let sent_us = clock.now_us();
+        request[2..10].copy_from_slice(&sent_us.to_le_bytes());
+        request[10..15].copy_from_slice(&self.address);
+        if !self.radio.send_to(&self.server_address, &request)? {
+            return Ok(None);
+        }
+        let mut waited_us = 0;
+        while waited_us < timeout_ms.saturating_mul(1000) {
+            match self.radio.wait_rx_ready() {
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) => {
+                    delay.delay_us(STEP_US);
+                    waited_us += STEP_US;
+                    continue;
+                }
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            let packet = nb::block!(self.radio.read())?;
+            let received_us = packet.timestamp_us().unwrap_or_else(|| clock.now_us());
+            if packet.len() != RESPONSE_LEN
+                || packet[0] != TYPE_RESPONSE
+                || packet[1] != self.seq
+                || read_u64(&packet[2..]) != sent_us
+            {
+                continue;
+            }
+            let server_received_us = read_u64(&packet[10..]);
+            let server_sent_us = read_u64(&packet[18..]);
+            return Ok(self.update(sent_us, server_received_us, server_sent_us, received_us));
+        }
+        Ok(None)
+    }
+
+    fn update(&mut self, t1: u64, t2: u64, t3: u64, t4: u64) -> Option<Sample> {
+        let there = t2.wrapping_sub(t1) as i64;
+        let back = t3.wrapping_sub(t4) as i64;
+        let round_trip = t4.wrapping_sub(t1).saturating_sub(t3.wrapping_sub(t2));
+        if round_trip > u64::from(self.max_round_trip_us) {
+            return None;
+        }
+        let sample = Sample {
+            local_us: t1,
+            offset_us: there / 2 + back / 2,
+            round_trip_us: round_trip as u32,
+        };
+        let first = *self.first.get_or_insert(sample);
+        let span = sample.local_us.wrapping_sub(first.local_us) as i128;
+        let drift_ppb = match span {
+            0 => self.estimate.map_or(0, |estimate| estimate.drift_ppb),
+            span => {
+                let gained = i128::from(sample.offset_us - first.offset_us);
+                (gained * 1_000_000_000 / span).clamp(i32::MIN.into(), i32::MAX.into()) as i32
+            }
+        };
+        self.estimate = Some(Estimate {
+            offset_us: sample.offset_us,
+            local_us: sample.local_us,
+            drift_ppb,
+        });
+        Some(sample)
+    }
+}
@@ -0,0 +1,201 @@
+//! Packet timing from the datasheet formulas
+//!
+//! All functions are `const` so that timeouts and retransmit delays
+//! can be derived at compile time. Results are in microseconds,
+//! rounded up, and assume Enhanced ShockBurst with its 9-bit packet
+//! control field.
+//!
+//! With the `fugit` feature, the functions without the `_us` suffix
+//! return `fugit::MicrosDurationU32` instead.
+
+use crate::{CrcMode, DataRate, PAYLOAD_LEN};
+
+/// TX/RX settling time, `Tstdby2a`
+pub const SETTLING_US: u32 = 130;
+/// Preamble length in bytes
+const PREAMBLE_LEN: u32 = 1;
+/// Packet control field length in bits
+const PCF_BITS: u32 = 9;
+
+pub const fn crc_len(crc: CrcMode) -> u32 {
+    match crc {
+        CrcMode::Disabled => 0,
+        CrcMode::OneByte => 1,
+        CrcMode::TwoBytes => 2,
+    }
+}
+
+pub const fn kbps(rate: DataRate) -> u32 {
+    match rate {
+        DataRate::R250Kbps => 250,
+        DataRate::R1Mbps => 1000,
+        DataRate::R2Mbps => 2000,
+    }
+}
+
+/// Delay between the last bit and the IRQ pin going low, `Tirq`
+pub const fn irq_delay_us(rate: DataRate) -> u32 {
+    match rate {
+        DataRate::R2Mbps => 6,
+        _ => 9,
+    }
+}
+
+/// Time on air of one packet with `payload_len` bytes, `Toa`
+pub const fn air_time_us(
+    payload_len: usize,
+    address_width: u8,
+    crc: CrcMode,
+    rate: DataRate,
+) -> u32 {
+    assert!(payload_len <= PAYLOAD_LEN);
+    let bits =
+        8 * (PREAMBLE_LEN + address_width as u32 + payload_len as u32 + crc_len(crc)) + PCF_BITS;
+    (bits * 1000).div_ceil(kbps(rate))
+}
+
+/// Time on air of an acknowledgement, `Tack`
+pub const fn ack_time_us(
+    ack_payload_len: usize,
+    address_width: u8,
+    crc: CrcMode,
+    rate: DataRate,
+) -> u32 {
+    air_time_us(ack_payload_len, address_width, crc, rate)
+}
+
+/// One acknowledged transmission without retransmits, `Tesb` minus
+/// the SPI upload
+pub const fn transaction_time_us(
+    payload_len: usize,
+    ack_payload_len: usize,
+    address_width: u8,
+    crc: CrcMode,
+    rate: DataRate,
+) -> u32 {
+    SETTLING_US
+        + air_time_us(payload_len, address_width, crc, rate)
+        + SETTLING_US
+        + ack_time_us(ack_payload_len, address_width, crc, rate)
+        + irq_delay_us(rate)
+}
+
+/// Auto-retransmit delay for a `SETUP_RETR.ARD` value
+pub const fn retransmit_delay_us(ard: u8) -> u32 {
+    assert!(ard < 16);
+    250 * (ard as u32 + 1)
+}
+
+/// Largest auto-retransmit delay, `ARD` 15
+pub const MAX_RETRANSMIT_DELAY_US: u32 = 4000;
+
+/// Smallest `SETUP_RETR.ARD` value waiting at least `delay_us`, `None`
+/// beyond [`MAX_RETRANSMIT_DELAY_US`](constant.MAX_RETRANSMIT_DELAY_US.html)
+pub const fn retransmit_delay_ard(delay_us: u32) -> Option<u8> {
+    if delay_us > MAX_RETRANSMIT_DELAY_US {
+        return None;
+    }
+    match delay_us.div_ceil(250) {
+        0 => Some(0),
+        steps => Some(steps as u8 - 1),
+    }
+}
+
+/// Smallest `ARD` that waits long enough for an ack carrying
+/// `ack_payload_len` bytes, `None` if none does
+pub const fn min_retransmit_delay(
+    ack_payload_len: usize,
+    address_width: u8,
+    crc: CrcMode,
+    rate: DataRate,
+) -> Option<u8> {
+    let needed = SETTLING_US + ack_time_us(ack_payload_len, address_width, crc, rate);
+    let mut ard = 0;
+    while ard < 16 {
+        if retransmit_delay_us(ard) >= needed {
+            return Some(ard);
+        }
+        ard += 1;
+    }
+    None
+}
+
+/// Worst case from starting a transmission until `MAX_RT`, after
+/// `arc` retransmits `ard` apart
+pub const fn max_retransmit_time_us(
+    payload_len: usize,
+    address_width: u8,
+    crc: CrcMode,
+    rate: DataRate,
+    ard: u8,
+    arc: u8,
+) -> u32 {
+    let attempt = SETTLING_US + air_time_us(payload_len, address_width, crc, rate);
+    (arc as u32 + 1) * (attempt + retransmit_delay_us(ard)) + irq_delay_us(rate)
+}
+
+#[cfg(feature = "fugit")]
+pub use self::durations::*;
+
+#[cfg(feature = "fugit")]
+mod durations {
+    use super::*;
+    use fugit::MicrosDurationU32;
+
+    /// [`air_time_us()`](fn.air_time_us.html) as a duration
+    pub const fn air_time(
+        payload_len: usize,
+        address_width: u8,
+        crc: CrcMode,
+        rate: DataRate,
+    ) -> MicrosDurationU32 {
+        MicrosDurationU32::from_ticks(air_time_us(payload_len, address_width, crc, rate))
+    }
+
+    /// [`transaction_time_us()`](fn.transaction_time_us.html) as a duration
+    pub const fn transaction_time(
+        payload_len: usize,
+        ack_payload_len: usize,
+        address_width: u8,
+        crc: CrcMode,
+        rate: DataRate,
+    ) -> MicrosDurationU32 {
+        MicrosDurationU32::from_ticks(transaction_time_us(
+            payload_len,
+            ack_payload_len,
+            address_width,
+            crc,
+            rate,
+        ))
+    }
+
+    /// Auto-retransmit delay for a `SETUP_RETR.ARD` value
+    pub const fn retransmit_delay(ard: u8) -> MicrosDurationU32 {
+        MicrosDurationU32::from_ticks(retransmit_delay_us(ard))
+    }
+
+    /// Smallest `SETUP_RETR.ARD` value waiting at least `delay`
+    pub const fn retransmit_delay_ard_for(delay: MicrosDurationU32) -> Option<u8> {
+        retransmit_delay_ard(delay.ticks())
+    }
+
+    /// [`max_retransmit_time_us()`](fn.max_retransmit_time_us.html) as a
+    /// duration
+    pub const fn max_retransmit_time(
+        payload_len: usize,
+        address_width: u8,
+        crc: CrcMode,
+        rate: DataRate,
+        ard: u8,
+        arc: u8,
+    ) -> MicrosDurationU32 {
+        MicrosDurationU32::from_ticks(max_retransmit_time_us(
+            payload_len,
+            address_width,
+            crc,
+            rate,
+            ard,
+            arc,
+        ))
+    }
+}
@@ -0,0 +1,85 @@
+use crate::Nrf24l01;
+use core::fmt::Debug;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// What the driver did, reported to a [`Trace`](trait.Trace.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TraceEvent {
+    /// Switched to RX mode and raised CE
+    Rx,
+    /// Switched to TX mode, CE low until a packet is sent
+    Tx,
+    /// Lowered CE
+    Standby,
+    PowerUp,
+    PowerDown,
+    /// A packet reached the maximum number of retransmits and the TX
+    /// FIFO was flushed
+    MaxRt,
+    /// `W_REGISTER`, with the first byte for addresses
+    ///
+    /// Writes to `STATUS`, which only clear interrupt flags, are left out.
+    RegisterWrite {
+        reg: u8,
+        value: u8,
+    },
+    /// An SPI transfer failed and is repeated, see
+    /// [`Nrf24l01::set_spi_retry()`](struct.Nrf24l01.html#method.set_spi_retry)
+    SpiRetry {
+        attempt: u8,
+    },
+    /// [`Nrf24l01::recover()`](struct.Nrf24l01.html#method.recover) started
+    Recover,
+}
+
+/// Receives the decisions of the driver for field debugging
+///
+/// Forward them to whatever logging the application has. Called from
+/// within driver methods, so keep it short.
+pub trait Trace {
+    fn event(&self, event: TraceEvent);
+}
+
+/// [`Trace`](trait.Trace.html) into `log::debug!()`
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogTrace;
+
+#[cfg(feature = "log")]
+impl Trace for LogTrace {
+    fn event(&self, event: TraceEvent) {
+        log::debug!("nrf24: {:?}", event);
+    }
+}
+
+/// [`Trace`](trait.Trace.html) into `defmt::debug!()`
+#[cfg(feature = "defmt")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefmtTrace;
+
+#[cfg(feature = "defmt")]
+impl Trace for DefmtTrace {
+    fn event(&self, event: TraceEvent) {
+        defmt::debug!("nrf24: {}", event);
+    }
+}
+
+impl<Ce, Csn, Spi, E, SpiE> Nrf24l01<Ce, Csn, Spi, E, SpiE>
+where
+    Ce: OutputPin<Error = E>,
+    Csn: OutputPin<Error = E>,
+    Spi: Transfer<u8, Error = SpiE>,
+    E: Debug,
+    SpiE: Debug,
+{
+    /// Report mode changes, register writes, `MAX_RT` flushes and
+    /// recovery actions to `trace`, or stop with `None`
+    ///
+    /// With the `log` or `defmt` feature, pass `Some(&LogTrace)` or
+    /// `Some(&DefmtTrace)` to forward them to that logger.
+    pub fn set_trace(&mut self, trace: Option<&'static dyn Trace>) {
+        self.device.set_trace(trace);
+    }
+}
@@ -0,0 +1,180 @@
+//! `ufmt` counterparts of the `core::fmt` output, for targets where
+//! `core::fmt` costs more flash than the rest of the driver
+
+use crate::registers::{FifoStatus, Status};
+use crate::{ConfigError, Error, SavedState};
+use core::fmt::Debug;
+use ufmt::{uDebug, uDisplay, uWrite, Formatter};
+
+fn write_hex<W: uWrite + ?Sized>(f: &mut Formatter<'_, W>, byte: u8) -> Result<(), W::Error> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    f.write_str("0x")?;
+    f.write_char(DIGITS[usize::from(byte >> 4)].into())?;
+    f.write_char(DIGITS[usize::from(byte & 0xF)].into())
+}
+
+fn write_decimal<W: uWrite + ?Sized>(
+    f: &mut Formatter<'_, W>,
+    mut n: usize,
+) -> Result<(), W::Error> {
+    let mut digits = [0; 20];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    for &digit in &digits[start..] {
+        f.write_char(char::from(digit))?;
+    }
+    Ok(())
+}
+
+/// Raw value, then the name of every flag that is set
+fn write_flags<W: uWrite + ?Sized>(
+    f: &mut Formatter<'_, W>,
+    name: &str,
+    raw: u8,
+    flags: &[(bool, &str)],
+) -> Result<(), W::Error> {
+    f.write_str(name)?;
+    f.write_str("(")?;
+    write_hex(f, raw)?;
+    for &(set, flag) in flags {
+        if set {
+            f.write_str(" ")?;
+            f.write_str(flag)?;
+        }
+    }
+    f.write_str(")")
+}
+
+impl uDebug for Status {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        write_flags(
+            f,
+            "Status",
+            self.0,
+            &[
+                (self.rx_dr(), "RX_DR"),
+                (self.tx_ds(), "TX_DS"),
+                (self.max_rt(), "MAX_RT"),
+                (self.tx_full(), "TX_FULL"),
+            ],
+        )?;
+        match self.rx_p_no() {
+            Some(pipe) => {
+                f.write_str(" pipe ")?;
+                f.write_char(char::from(b'0' + pipe.index() as u8))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl uDebug for FifoStatus {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        write_flags(
+            f,
+            "FifoStatus",
+            self.0,
+            &[
+                (self.tx_reuse(), "TX_REUSE"),
+                (self.tx_full(), "TX_FULL"),
+                (self.tx_empty(), "TX_EMPTY"),
+                (self.rx_full(), "RX_FULL"),
+                (self.rx_empty(), "RX_EMPTY"),
+            ],
+        )
+    }
+}
+
+/// The bytes of [`SavedState::to_bytes()`](struct.SavedState.html#method.to_bytes)
+impl uDebug for SavedState {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        f.write_str("SavedState[")?;
+        for (i, &byte) in self.to_bytes().iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write_hex(f, byte)?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl uDisplay for ConfigError {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        let (before, pipe, after) = match self {
+            ConfigError::InvalidChannel { channel } => {
+                f.write_str("RF channel ")?;
+                write_decimal(f, usize::from(*channel))?;
+                return f.write_str(" out of range");
+            }
+            ConfigError::UnsupportedDataRate => {
+                return f.write_str("data rate not supported by the chip")
+            }
+            ConfigError::UnsupportedOutputPower => {
+                return f.write_str("output power not supported by the chip")
+            }
+            ConfigError::AutoAckWithoutCrc { pipe } => ("auto-ack on pipe ", pipe, " needs CRC"),
+            ConfigError::MissingLength { pipe } => (
+                "pipe ",
+                pipe,
+                " needs a fixed length without dynamic payloads",
+            ),
+            ConfigError::AckPayloadWithoutDynamicLength { pipe } => (
+                "ack payloads need dynamic payload length on pipe ",
+                pipe,
+                "",
+            ),
+        };
+        f.write_str(before)?;
+        f.write_char(char::from(b'0' + pipe.index() as u8))?;
+        f.write_str(after)
+    }
+}
+
+impl uDebug for ConfigError {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uDisplay::fmt(self, f)
+    }
+}
+
+/// The SPI error has no `ufmt` impl to rely on, only its presence is shown
+impl<E: Debug> uDisplay for Error<E> {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            Error::NotConnected => f.write_str("no nRF24L01 answering on SPI"),
+            Error::InvalidConfig(e) => {
+                f.write_str("invalid configuration: ")?;
+                uDisplay::fmt(e, f)
+            }
+            Error::DutyCycleExceeded => f.write_str("duty cycle budget used up"),
+            Error::TooLong { len, max } => {
+                write_decimal(f, *len)?;
+                f.write_str(" bytes of data where at most ")?;
+                write_decimal(f, *max)?;
+                f.write_str(" fit")
+            }
+            Error::VerificationFailed { reg, wrote, read } => {
+                f.write_str("register ")?;
+                write_hex(f, *reg)?;
+                f.write_str(" read back as ")?;
+                write_hex(f, *read)?;
+                f.write_str(" after writing ")?;
+                write_hex(f, *wrote)
+            }
+            Error::Spi(_) => f.write_str("SPI error"),
+        }
+    }
+}
+
+impl<E: Debug> uDebug for Error<E> {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uDisplay::fmt(self, f)
+    }
+}
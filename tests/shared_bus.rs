@@ -0,0 +1,254 @@
+//! An nRF24L01+ sharing its SPI bus with an SD card through
+//! `shared-bus` proxies
+//!
+//! The bus is a model of both devices: it checks that only one chip
+//! select is low per transfer and answers nRF24 commands from a
+//! register file.
+
+use core::convert::Infallible;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+use embedded_nrf24l01::{Config, Nrf24l01, Pipe};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+const STATUS: usize = 0x07;
+const FIFO_STATUS: usize = 0x17;
+const RX_DR: u8 = 1 << 6;
+const TX_DS: u8 = 1 << 5;
+
+#[derive(Clone)]
+struct Pin(Rc<Cell<bool>>);
+
+impl Pin {
+    fn new() -> Self {
+        Pin(Rc::new(Cell::new(true)))
+    }
+
+    fn is_high(&self) -> bool {
+        self.0.get()
+    }
+}
+
+impl OutputPin for Pin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.set(true);
+        Ok(())
+    }
+}
+
+/// Register file and FIFOs of an nRF24L01+ that acks every packet
+struct Chip {
+    registers: [u8; 0x20],
+    /// `RX_ADDR_P0`, `RX_ADDR_P1` and `TX_ADDR`
+    addresses: [[u8; 5]; 3],
+    rx_fifo: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+    transfers: usize,
+}
+
+impl Chip {
+    fn new() -> Self {
+        let mut registers = [0; 0x20];
+        registers[0x00] = 0x08;
+        registers[0x01] = 0x3F;
+        registers[0x02] = 0x03;
+        registers[0x03] = 0x03;
+        registers[0x04] = 0x03;
+        registers[0x05] = 0x02;
+        registers[0x06] = 0x0E;
+        Chip {
+            registers,
+            addresses: [[0xE7; 5], [0xC2; 5], [0xE7; 5]],
+            rx_fifo: VecDeque::new(),
+            sent: Vec::new(),
+            transfers: 0,
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let rx_p_no = match self.rx_fifo.is_empty() {
+            true => 7,
+            false => 1,
+        };
+        self.registers[STATUS] & (RX_DR | TX_DS | 1 << 4) | rx_p_no << 1
+    }
+
+    fn fifo_status(&self) -> u8 {
+        let rx_empty = self.rx_fifo.is_empty() as u8;
+        let rx_full = (self.rx_fifo.len() >= 3) as u8;
+        // Packets go out as soon as they are written
+        1 << 4 | rx_full << 1 | rx_empty
+    }
+
+    fn address(&mut self, reg: usize) -> Option<&mut [u8; 5]> {
+        match reg {
+            0x0A => Some(&mut self.addresses[0]),
+            0x0B => Some(&mut self.addresses[1]),
+            0x10 => Some(&mut self.addresses[2]),
+            _ => None,
+        }
+    }
+
+    fn transfer(&mut self, words: &mut [u8]) {
+        self.transfers += 1;
+        let status = self.status();
+        let (command, data) = words.split_first_mut().unwrap();
+        let command = *command;
+        match command {
+            0x00..=0x1F => {
+                let reg = usize::from(command);
+                let value = match reg {
+                    FIFO_STATUS => self.fifo_status(),
+                    STATUS => self.status(),
+                    _ => self.registers[reg],
+                };
+                match self.address(reg) {
+                    Some(address) => {
+                        let len = data.len();
+                        data.copy_from_slice(&address[0..len]);
+                    }
+                    None => data.iter_mut().for_each(|byte| *byte = value),
+                }
+            }
+            0x20..=0x3F => {
+                let reg = usize::from(command & 0x1F);
+                match self.address(reg) {
+                    Some(address) => address[0..data.len()].copy_from_slice(data),
+                    // Interrupt flags are cleared by writing 1
+                    None if reg == STATUS => self.registers[STATUS] &= !data[0],
+                    None => self.registers[reg] = data[0],
+                }
+            }
+            0x50 => {}
+            0x60 => data[0] = self.rx_fifo.front().map_or(0, |packet| packet.len() as u8),
+            0x61 => {
+                let packet = self.rx_fifo.pop_front().expect("RX FIFO empty");
+                data[0..packet.len()].copy_from_slice(&packet);
+                if self.rx_fifo.is_empty() {
+                    self.registers[STATUS] &= !RX_DR;
+                }
+            }
+            0xA0 | 0xB0 => {
+                self.sent.push(data.to_vec());
+                self.registers[STATUS] |= TX_DS;
+            }
+            0xA8..=0xAD => {}
+            0xE1 => {}
+            0xE2 => self.rx_fifo.clear(),
+            0xFF => {}
+            _ => panic!("unexpected command {:#04x}", command),
+        }
+        words[0] = status;
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        self.rx_fifo.push_back(packet.to_vec());
+        self.registers[STATUS] |= RX_DR;
+    }
+}
+
+/// The physical bus, routing each transfer to the selected device
+struct Bus {
+    nrf_csn: Pin,
+    sd_cs: Pin,
+    chip: Rc<RefCell<Chip>>,
+    sd_bytes: Rc<Cell<usize>>,
+}
+
+impl Bus {
+    fn route(&mut self, words: &mut [u8]) {
+        match (self.nrf_csn.is_high(), self.sd_cs.is_high()) {
+            (false, true) => self.chip.borrow_mut().transfer(words),
+            (true, false) => {
+                self.sd_bytes.set(self.sd_bytes.get() + words.len());
+                words.iter_mut().for_each(|byte| *byte = 0xFF);
+            }
+            (nrf_csn, sd_cs) => panic!("CSN {} and SD CS {} on one transfer", nrf_csn, sd_cs),
+        }
+    }
+}
+
+impl Transfer<u8> for Bus {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+        self.route(words);
+        Ok(words)
+    }
+}
+
+impl Write<u8> for Bus {
+    type Error = Infallible;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        self.route(&mut words.to_vec());
+        Ok(())
+    }
+}
+
+/// One SD card command frame, with CS held low around it
+fn sd_command<S: Write<u8, Error = Infallible>>(sd: &mut S, cs: &mut Pin, command: u8) {
+    cs.set_low().unwrap();
+    sd.write(&[0x40 | command, 0, 0, 0, 0, 0x95]).unwrap();
+    cs.set_high().unwrap();
+}
+
+#[test]
+fn nrf24_shares_the_bus_with_an_sd_card() {
+    let nrf_csn = Pin::new();
+    let mut sd_cs = Pin::new();
+    let chip = Rc::new(RefCell::new(Chip::new()));
+    let sd_bytes = Rc::new(Cell::new(0));
+    let bus = shared_bus::BusManagerSimple::new(Bus {
+        nrf_csn: nrf_csn.clone(),
+        sd_cs: sd_cs.clone(),
+        chip: chip.clone(),
+        sd_bytes: sd_bytes.clone(),
+    });
+
+    let mut sd = bus.acquire_spi();
+    sd_command(&mut sd, &mut sd_cs, 0);
+    let config = Config::default().frequency(76);
+    let mut radio = Nrf24l01::new(Pin::new(), nrf_csn.clone(), bus.acquire_spi(), config)
+        .expect("radio not found");
+    assert!(nrf_csn.is_high(), "CSN left low after new()");
+    assert_eq!(chip.borrow().registers[0x05], 76);
+
+    // SD traffic between every radio call
+    sd_command(&mut sd, &mut sd_cs, 17);
+    nb::block!(radio.send(b"hello")).unwrap();
+    sd_command(&mut sd, &mut sd_cs, 17);
+    assert!(nb::block!(radio.wait_tx_done()).unwrap());
+    assert!(nrf_csn.is_high(), "CSN left low after sending");
+    assert_eq!(chip.borrow().sent, vec![b"hello".to_vec()]);
+
+    chip.borrow_mut().receive(b"world");
+    sd_command(&mut sd, &mut sd_cs, 24);
+    assert_eq!(nb::block!(radio.wait_rx_ready()).unwrap(), Pipe::P1);
+    let payload = nb::block!(radio.read()).unwrap();
+    assert_eq!(payload.as_ref(), b"world");
+    assert!(nrf_csn.is_high(), "CSN left low after receiving");
+
+    let transfers = chip.borrow().transfers;
+    sd_command(&mut sd, &mut sd_cs, 12);
+    assert_eq!(
+        chip.borrow().transfers,
+        transfers,
+        "SD command reached the radio"
+    );
+    assert_eq!(sd_bytes.get(), 5 * 6);
+
+    // The proxy comes back and keeps working for the SD card
+    let (_ce, _csn, _spi) = radio.release();
+    sd_command(&mut sd, &mut sd_cs, 0);
+    assert_eq!(sd_bytes.get(), 6 * 6);
+}